@@ -0,0 +1,62 @@
+//! The `assert` subcommand, checking a record without changing it.
+
+use crate::dns_type::DnsType;
+use crate::gandi::{get_gandi_record, GandiApi};
+use crate::ip::canonical;
+use clap::Args;
+use log::{error, info};
+
+pub const EXIT_MATCHES: i32 = 0;
+pub const EXIT_DIFFERS: i32 = 1;
+pub const EXIT_MISSING: i32 = 2;
+pub const EXIT_API_ERROR: i32 = 3;
+
+#[derive(Debug, Args)]
+pub struct AssertArgs {
+    /// Name of the record, `@` for the domain itself
+    #[arg(long)]
+    record: String,
+    /// Type of the record
+    #[arg(long = "type", value_name = "TYPE", default_value = "A")]
+    dns_type: DnsType,
+    /// Expected value; repeat it for rrsets with several values
+    #[arg(long, required = true)]
+    value: Vec<String>,
+}
+
+/// Checks that the rrset holds exactly the expected values, returning the
+/// exit code: 0 if it does, 1 if it holds other values, 2 if it does not
+/// exist and 3 if the API could not be asked.
+pub async fn run(args: &AssertArgs, domain: &str, api: &GandiApi) -> i32 {
+    let dns_type = args.dns_type;
+    let values = match get_gandi_record(domain, &args.record, dns_type, api).await {
+        Ok(Some(rrset)) if !rrset.values.is_empty() => rrset.values,
+        Ok(_) => {
+            error!("{}/{} does not exist", args.record, dns_type);
+            return EXIT_MISSING;
+        }
+        Err(e) => {
+            error!("Unable to get {}/{}: {}", args.record, dns_type, e);
+            return EXIT_API_ERROR;
+        }
+    };
+
+    let mut actual: Vec<String> = values.iter().map(|v| canonical(v)).collect();
+    let mut expected: Vec<String> = args.value.iter().map(|v| canonical(v)).collect();
+    actual.sort();
+    expected.sort();
+    expected.dedup();
+    if actual == expected {
+        info!("{}/{} is {}", args.record, dns_type, values.join(","));
+        EXIT_MATCHES
+    } else {
+        error!(
+            "{}/{} is {}, expected {}",
+            args.record,
+            dns_type,
+            values.join(","),
+            args.value.join(",")
+        );
+        EXIT_DIFFERS
+    }
+}
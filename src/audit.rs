@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// One change (or failed change) of a record, or a change made by someone
+/// else, stored as a JSON line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// RFC 3339 timestamp in UTC.
+    pub timestamp: String,
+    pub domain: String,
+    pub record: String,
+    pub dns_type: String,
+    /// Values of the rrset before the change, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<Vec<String>>,
+    /// TTL of the rrset before the change, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_ttl: Option<u32>,
+    pub new: String,
+    /// Detection source the new value was detected through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Value the client last wrote, for a record that drifted from it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written: Option<String>,
+    /// Comment of the record in the configuration, for reviewers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// What told the new value, e.g. `api.ipify.org` or `FRITZ!Box`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Changed,
+    Failed,
+    /// Someone else changed the record since the client last wrote it.
+    Drifted,
+    /// `rollback` put back the values the record held before a change.
+    #[serde(rename = "rolled_back")]
+    RolledBack,
+    /// The rrset was deleted as listed in `DNS.remove`.
+    Removed,
+}
+
+impl AuditEntry {
+    pub fn new(
+        domain: &str,
+        record: &str,
+        dns_type: &str,
+        old: Option<Vec<String>>,
+        new: &str,
+        outcome: Outcome,
+    ) -> Self {
+        AuditEntry {
+            timestamp: now_rfc3339(),
+            domain: domain.to_string(),
+            record: record.to_string(),
+            dns_type: dns_type.to_string(),
+            old,
+            old_ttl: None,
+            new: new.to_string(),
+            source: None,
+            outcome,
+            error: None,
+            written: None,
+            comment: None,
+            provider: None,
+        }
+    }
+
+    pub fn time(&self) -> Option<OffsetDateTime> {
+        OffsetDateTime::parse(&self.timestamp, &Rfc3339).ok()
+    }
+}
+
+fn now_rfc3339() -> String {
+    OffsetDateTime::now_utc()
+        .format(&Rfc3339)
+        .unwrap_or_default()
+}
+
+/// A line of the log that could not be parsed.
+#[derive(Debug)]
+pub struct CorruptLine {
+    pub line: usize,
+    pub error: String,
+}
+
+/// Append-only log of the record changes in the state directory.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(state_dir: &Path) -> Self {
+        AuditLog {
+            path: state_dir.join(AUDIT_FILE),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entries: &[AuditEntry]) -> io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())
+    }
+
+    /// Streams the entries in file order, yielding the line number and
+    /// parse error of corrupt lines instead of failing. Returns `None` if
+    /// the log does not exist yet.
+    #[allow(clippy::type_complexity)]
+    pub fn read(
+        &self,
+    ) -> io::Result<Option<impl Iterator<Item = io::Result<Result<AuditEntry, CorruptLine>>>>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let entries = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|(index, line)| {
+                line.map(|line| {
+                    serde_json::from_str(&line).map_err(|e| CorruptLine {
+                        line: index + 1,
+                        error: e.to_string(),
+                    })
+                })
+            });
+        Ok(Some(entries))
+    }
+}
@@ -0,0 +1,317 @@
+//! Asking the metadata service of a cloud provider for the public address
+//! of the instance, for virtual machines whose address changes when they
+//! are stopped and started: EC2 (IMDSv2), Google Compute Engine and Hetzner
+//! Cloud. The service is only reachable from the instance itself.
+
+use crate::ip::{error_chain, IpVersion};
+use crate::telemetry;
+use crate::timeouts::Timeouts;
+use reqwest::StatusCode;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Where the metadata services listen, on a link-local address so that no
+/// name has to be resolved.
+const DEFAULT_URL: &str = "http://169.254.169.254";
+/// Off the cloud, the link-local address drops the connection attempts
+/// rather than refusing them, so they are given up on early.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Bound of the requests unless `HTTP.ip_detection_timeout` is set.
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// Lifetime of the IMDSv2 session tokens, each used for a single request.
+const EC2_TOKEN_TTL: &str = "60";
+
+/// The cloud provider whose metadata service is asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloudProvider {
+    Ec2,
+    Gce,
+    Hcloud,
+}
+
+impl CloudProvider {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CloudProvider::Ec2 => "ec2",
+            CloudProvider::Gce => "gce",
+            CloudProvider::Hcloud => "hcloud",
+        }
+    }
+
+    /// The name of the provider, for the logs.
+    pub fn label(self) -> &'static str {
+        match self {
+            CloudProvider::Ec2 => "EC2",
+            CloudProvider::Gce => "GCE",
+            CloudProvider::Hcloud => "Hetzner Cloud",
+        }
+    }
+}
+
+impl FromStr for CloudProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ec2" | "aws" => Ok(CloudProvider::Ec2),
+            "gce" | "gcp" => Ok(CloudProvider::Gce),
+            "hcloud" | "hetzner" => Ok(CloudProvider::Hcloud),
+            other => Err(format!(
+                "unknown cloud provider '{}', expected ec2, gce or hcloud",
+                other
+            )),
+        }
+    }
+}
+
+/// The metadata service detecting the addresses of a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloudMetadata {
+    pub provider: CloudProvider,
+    /// Base URL of the service, e.g. a local server standing in for it in
+    /// tests, the link-local address if `None`.
+    pub url: Option<String>,
+}
+
+impl CloudMetadata {
+    fn base_url(&self) -> &str {
+        self.url
+            .as_deref()
+            .unwrap_or(DEFAULT_URL)
+            .trim_end_matches('/')
+    }
+
+    /// The public address of the family `version` the service reports,
+    /// unvalidated, or `None` if the instance has none of that family.
+    pub async fn public_ip(
+        &self,
+        version: IpVersion,
+        timeouts: Timeouts,
+    ) -> Result<Option<String>, String> {
+        // The service is on the local link, so the requests must not go
+        // through a proxy or the binding of the family.
+        let client = timeouts
+            .client(Some(timeouts.ip_detection.unwrap_or(TIMEOUT)))
+            .connect_timeout(CONNECT_TIMEOUT)
+            .no_proxy()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let base = self.base_url();
+        match (self.provider, version) {
+            (CloudProvider::Ec2, _) => {
+                let token = ec2_token(&client, base).await?;
+                let path = match version {
+                    IpVersion::V4 => "public-ipv4",
+                    IpVersion::V6 => "ipv6",
+                };
+                let request = client
+                    .get(format!("{}/latest/meta-data/{}", base, path))
+                    .header("X-aws-ec2-metadata-token", token);
+                fetch(request).await
+            }
+            (CloudProvider::Gce, _) => {
+                let path = match version {
+                    IpVersion::V4 => "access-configs/0/external-ip",
+                    IpVersion::V6 => "ipv6-access-configs/0/external-ipv6",
+                };
+                let url = format!(
+                    "{}/computeMetadata/v1/instance/network-interfaces/0/{}",
+                    base, path
+                );
+                fetch(client.get(url).header("Metadata-Flavor", "Google")).await
+            }
+            (CloudProvider::Hcloud, IpVersion::V4) => {
+                let url = format!("{}/hetzner/v1/metadata/public-ipv4", base);
+                fetch(client.get(url)).await
+            }
+            // There is no key of its own for the IPv6 address, only the
+            // network configuration cloud-init applies.
+            (CloudProvider::Hcloud, IpVersion::V6) => {
+                let url = format!("{}/hetzner/v1/metadata/network-config", base);
+                Ok(fetch(client.get(url))
+                    .await?
+                    .and_then(|config| hcloud_ipv6(&config)))
+            }
+        }
+    }
+}
+
+/// Starts an IMDSv2 session, without which EC2 instances requiring it
+/// answer 401.
+async fn ec2_token(client: &reqwest::Client, base: &str) -> Result<String, String> {
+    let response = client
+        .put(format!("{}/latest/api/token", base))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", EC2_TOKEN_TTL)
+        .send()
+        .await
+        .map_err(|e| error_chain(&e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("status code {} getting the IMDSv2 token", status));
+    }
+    response.text().await.map_err(|e| error_chain(&e))
+}
+
+/// The value at a metadata path, `None` if it is not set: the paths of
+/// the addresses are missing, or empty on GCE, when none is assigned.
+async fn fetch(request: reqwest::RequestBuilder) -> Result<Option<String>, String> {
+    let response = request.send().await.map_err(|e| error_chain(&e))?;
+    let status = response.status();
+    telemetry::record("http.response.status_code", status.as_u16());
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(format!("status code {}", status));
+    }
+    let body = response.text().await.map_err(|e| error_chain(&e))?;
+    Ok(Some(body.trim().to_string()).filter(|value| !value.is_empty()))
+}
+
+/// The first IPv6 address of the Hetzner Cloud network configuration, a
+/// YAML document listing it as `address: 2a01:4f8:c17:1234::1/64`.
+fn hcloud_ipv6(config: &str) -> Option<String> {
+    config
+        .lines()
+        .filter_map(|line| {
+            line.trim()
+                .trim_start_matches("- ")
+                .strip_prefix("address:")
+        })
+        .map(|value| value.trim().trim_matches(|c| c == '"' || c == '\''))
+        .map(|value| value.split('/').next().unwrap_or_default())
+        .find(|value| value.contains(':'))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Stands in for a metadata service, answering each request, given as
+    /// its lowercased head, with the status and body `answer` returns.
+    async fn service(answer: fn(&str) -> (&'static str, &'static str)) -> CloudMetadata {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let head = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+                let (status, body) = answer(&head);
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        CloudMetadata {
+            provider: CloudProvider::Ec2,
+            url: Some(format!("http://{}", addr)),
+        }
+    }
+
+    async fn public_ip(
+        metadata: CloudMetadata,
+        version: IpVersion,
+    ) -> Result<Option<String>, String> {
+        metadata.public_ip(version, Timeouts::default()).await
+    }
+
+    #[tokio::test]
+    async fn asks_ec2_with_an_imdsv2_token() {
+        let metadata = service(|head| {
+            if head.starts_with("put /latest/api/token ") {
+                if head.contains("x-aws-ec2-metadata-token-ttl-seconds: 60") {
+                    return ("200 OK", "t0ken");
+                }
+                return ("400 Bad Request", "");
+            }
+            if !head.contains("x-aws-ec2-metadata-token: t0ken") {
+                return ("401 Unauthorized", "");
+            }
+            match head.lines().next().unwrap_or_default() {
+                "get /latest/meta-data/public-ipv4 http/1.1" => ("200 OK", "203.0.113.7\n"),
+                _ => ("404 Not Found", ""),
+            }
+        })
+        .await;
+
+        let ipv4 = public_ip(metadata.clone(), IpVersion::V4).await;
+        assert_eq!(ipv4, Ok(Some("203.0.113.7".to_string())));
+        // No IPv6 address is assigned to the instance.
+        assert_eq!(public_ip(metadata, IpVersion::V6).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn reports_the_ec2_token_refusal() {
+        let metadata = service(|_| ("403 Forbidden", "")).await;
+        let error = public_ip(metadata, IpVersion::V4).await.unwrap_err();
+        assert!(error.contains("IMDSv2 token"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn asks_gce_with_the_metadata_flavor() {
+        let mut metadata = service(|head| {
+            if !head.contains("metadata-flavor: google") {
+                return ("403 Forbidden", "");
+            }
+            let path = "/computemetadata/v1/instance/network-interfaces/0/";
+            match head.lines().next().unwrap_or_default() {
+                line if line == format!("get {}access-configs/0/external-ip http/1.1", path) => {
+                    ("200 OK", "203.0.113.8")
+                }
+                // GCE answers an empty value when no address is assigned.
+                _ => ("200 OK", ""),
+            }
+        })
+        .await;
+        metadata.provider = CloudProvider::Gce;
+
+        let ipv4 = public_ip(metadata.clone(), IpVersion::V4).await;
+        assert_eq!(ipv4, Ok(Some("203.0.113.8".to_string())));
+        assert_eq!(public_ip(metadata, IpVersion::V6).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn asks_hcloud_for_both_families() {
+        let mut metadata = service(|head| match head.lines().next().unwrap_or_default() {
+            "get /hetzner/v1/metadata/public-ipv4 http/1.1" => ("200 OK", "203.0.113.9"),
+            "get /hetzner/v1/metadata/network-config http/1.1" => (
+                "200 OK",
+                "config:\n- name: eth0\n  subnets:\n  - type: dhcp\n  - address: 2a01:4f8:c17:1234::1/64\n    type: static\n",
+            ),
+            _ => ("404 Not Found", ""),
+        })
+        .await;
+        metadata.provider = CloudProvider::Hcloud;
+
+        let ipv4 = public_ip(metadata.clone(), IpVersion::V4).await;
+        assert_eq!(ipv4, Ok(Some("203.0.113.9".to_string())));
+        let ipv6 = public_ip(metadata, IpVersion::V6).await;
+        assert_eq!(ipv6, Ok(Some("2a01:4f8:c17:1234::1".to_string())));
+    }
+
+    #[tokio::test]
+    async fn gives_up_early_off_the_cloud() {
+        // Accepts the connections but never answers, as the link-local
+        // address of a host that is no cloud instance does not.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metadata = CloudMetadata {
+            provider: CloudProvider::Gce,
+            url: Some(format!("http://{}", listener.local_addr().unwrap())),
+        };
+
+        let started = Instant::now();
+        let result = public_ip(metadata, IpVersion::V4).await;
+        assert!(result.is_err(), "{:?}", result);
+        assert!(started.elapsed() < TIMEOUT + Duration::from_secs(1));
+        drop(listener);
+    }
+}
@@ -0,0 +1,3104 @@
+use crate::cloud::CloudMetadata;
+use crate::discovery::DomainDiscovery;
+use crate::dns_type::DnsType;
+use crate::drift::DriftPolicy;
+use crate::encryption;
+use crate::error::DdnsError;
+use crate::fritzbox::FritzBox;
+use crate::gandi::AuthScheme;
+use crate::gateway::Gateway;
+use crate::guard::GuardConfig;
+use crate::ip::{
+    parse_address_type, prefix_mask, provider_family, Binding, DetectionMethod, DetectionSource,
+    FamilyDetection, IpVersion, IpVersionFilter, PrefixSource,
+};
+use crate::message_templates::{self, Templates};
+use crate::notify::{
+    AppriseConfig, DiscordConfig, EventFilter, GotifyConfig, MatrixConfig, NotifyConfig,
+    NtfyConfig, NtfyPriority, TelegramConfig, WebhookConfig, DEFAULT_GOTIFY_PRIORITY,
+    DEFAULT_NTFY_SERVER,
+};
+use crate::pinning::SpkiPin;
+use crate::precheck::Precheck;
+use crate::records_file;
+use crate::redacted::Redacted;
+use crate::remote_config::RemoteConfig;
+use crate::schema::{self, SECTIONS, SECTION_ALIASES};
+use crate::secret_store::KeyringEntry;
+use crate::telemetry::TelemetryConfig;
+#[cfg(feature = "extended-records")]
+use crate::template::{check_value, Addresses};
+use crate::template::{Placeholder, TemplateRecord, EXTENDED_TYPES};
+use crate::timeouts::{Timeouts, DEFAULT_REQUEST_TIMEOUT};
+use crate::tls::TlsConfig;
+use crate::ttl::{AdaptiveTtl, GANDI_MAX_TTL, GANDI_MIN_TTL};
+use crate::watchdog::WatchdogConfig;
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, ConfigError, File, FileFormat};
+use ipnet::IpNet;
+use log::warn;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::Date;
+#[cfg(feature = "extended-records")]
+use time::OffsetDateTime;
+
+const DEFAULT_QUEUE_DEPTH: usize = 100;
+const DEFAULT_EXPIRY_WARNING_DAYS: u32 = 14;
+const DEFAULT_DAEMON_INTERVAL: Duration = Duration::from_secs(300);
+const DEFAULT_CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const CONTROL_SOCKET_FILE: &str = "control.sock";
+const PAUSE_FILE: &str = "pause";
+const DEFAULT_TTL_MIN: u32 = GANDI_MIN_TTL;
+const DEFAULT_TTL_MAX: u32 = 86400;
+const DEFAULT_TTL_WINDOW: Duration = Duration::from_secs(86400);
+const DEFAULT_WATCHDOG_GRACE: Duration = Duration::from_secs(1800);
+const DEFAULT_WATCHDOG_RESOLVER: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+const DATE_FORMAT: &[FormatItem<'_>] = format_description!("[year]-[month]-[day]");
+
+/// What to publish for a record following several sources when some of
+/// them detected no address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartialSet {
+    /// Leave the rrset as it is until every source detects an address.
+    #[default]
+    Keep,
+    /// Publish the addresses that were detected.
+    Publish,
+}
+
+impl PartialSet {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PartialSet::Keep => "keep",
+            PartialSet::Publish => "publish_partial",
+        }
+    }
+}
+
+impl FromStr for PartialSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(PartialSet::Keep),
+            "publish_partial" => Ok(PartialSet::Publish),
+            other => Err(format!("unknown policy '{}'", other)),
+        }
+    }
+}
+
+/// A record to update, with the detection source it follows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordEntry {
+    pub name: String,
+    /// Subsection of `DETECTION` the addresses are detected with, the
+    /// default detection settings if `None`.
+    pub source: Option<String>,
+    /// Subsections of `DETECTION` whose addresses the rrset holds together,
+    /// e.g. the WAN addresses of a multi-homed host, instead of `source`.
+    pub sources: Vec<String>,
+    pub on_source_failure: PartialSet,
+    /// Families of this record, overriding `DNS.ip_version`.
+    pub ip_version: Option<IpVersionFilter>,
+    /// What the record is for, only shown to the operators.
+    pub comment: Option<String>,
+    /// Whether the rrset may be written even if it holds several values or
+    /// one that is not an address of its family.
+    pub allow_overwrite: bool,
+    /// Interface identifier of the host the record names, published in
+    /// the prefix delegated to the network instead of the detected IPv6
+    /// address.
+    pub host_part: Option<Ipv6Addr>,
+}
+
+impl RecordEntry {
+    /// A record following the default detection settings.
+    pub fn named(name: String) -> Self {
+        RecordEntry {
+            name,
+            source: None,
+            sources: Vec::new(),
+            on_source_failure: PartialSet::default(),
+            ip_version: None,
+            comment: None,
+            allow_overwrite: false,
+            host_part: None,
+        }
+    }
+
+    /// Whether the record only has a name, e.g. in `DNS.records_file`.
+    fn is_plain(&self) -> bool {
+        *self == RecordEntry::named(self.name.clone())
+    }
+
+    /// Whether the rrset holds the addresses of several sources.
+    pub fn is_multi_source(&self) -> bool {
+        !self.sources.is_empty()
+    }
+
+    /// Whether the addresses detected through `source` are published in
+    /// the record.
+    pub fn follows(&self, source: Option<&str>) -> bool {
+        match source {
+            Some(name) if self.is_multi_source() => self.sources.iter().any(|s| s == name),
+            _ => !self.is_multi_source() && self.source.as_deref() == source,
+        }
+    }
+
+    /// Names the sources of the record, for the logs and error messages.
+    pub fn source_label(&self) -> Option<String> {
+        if self.is_multi_source() {
+            return Some(self.sources.join("+"));
+        }
+        self.source.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsConfig {
+    pub key: String,
+    /// File the key was read from, e.g. a mounted Kubernetes Secret.
+    pub key_file: Option<PathBuf>,
+    /// Entry of the OS secret store the key was read from.
+    pub keyring: Option<KeyringEntry>,
+    pub sharing_id: Option<String>,
+    /// Explicitly configured scheme, disabling the automatic fallback.
+    pub auth_method: Option<AuthScheme>,
+    /// Day the credential expires, which Gandi does not tell.
+    pub key_expires: Option<Date>,
+    /// How many days ahead of `key_expires` to warn about it.
+    pub expiry_warning_days: u32,
+    /// Empty if the domains are discovered.
+    pub domain: String,
+    /// Set when `DNS.domains.discover` is, updating the records in every
+    /// matching domain of the account instead of in `domain`.
+    pub discovery: Option<DomainDiscovery>,
+    /// The inline records followed by those of `records_files`.
+    pub records: Vec<RecordEntry>,
+    /// TXT rrsets of `DNS.records` rendered from a template.
+    pub templates: Vec<TemplateRecord>,
+    /// Paths or glob patterns of files listing further records.
+    pub records_files: Vec<String>,
+    /// Families updated for the records that do not set their own.
+    pub ip_version: IpVersionFilter,
+    /// Names or patterns of records whose A and AAAA rrsets are deleted,
+    /// e.g. of decommissioned hosts.
+    pub remove: Vec<String>,
+    /// Whether deleting them needs `--allow-delete`.
+    pub confirm_deletes: bool,
+    /// Name of the TXT rrset telling which host last changed the records,
+    /// when and with which addresses.
+    pub heartbeat_record: Option<String>,
+    pub max_run_duration: Option<Duration>,
+    /// Minimum spacing between two requests to the Gandi API.
+    pub gandi_request_interval: Option<Duration>,
+    /// Maximum number of requests to the Gandi API in flight at once.
+    pub max_concurrent_requests: Option<usize>,
+    /// Remaining API quota below which a warning is logged.
+    pub quota_warning_threshold: Option<u64>,
+    /// Prefix of the rate limit headers of the API, tried before the
+    /// usual spellings.
+    pub rate_limit_header_prefix: Option<String>,
+    pub timeouts: Timeouts,
+    pub tls: TlsConfig,
+    pub state_dir: PathBuf,
+    /// File the metrics of every pass are written to.
+    pub metrics_file: Option<PathBuf>,
+    pub retry_queue_depth: usize,
+    pub use_batch: bool,
+    /// What to do with records someone else changed since they were
+    /// last written.
+    pub on_drift: DriftPolicy,
+    pub change_debounce: Option<Duration>,
+    pub precheck: Precheck,
+    /// Resolver used by the DNS precheck instead of the authoritative
+    /// name servers.
+    pub precheck_resolver: Option<IpAddr>,
+    /// Set when any of the `UPDATE.ttl_*` keys is present.
+    pub adaptive_ttl: Option<AdaptiveTtl>,
+    pub daemon_interval: Duration,
+    /// How often the daemon checks the configuration file for changes,
+    /// `None` if only on signals and control requests.
+    pub config_poll_interval: Option<Duration>,
+    /// Unix socket the daemon is controlled through.
+    pub control_socket: PathBuf,
+    /// While this file exists, no updates are made.
+    pub pause_file: PathBuf,
+    /// Whether `--health-check` succeeds while paused.
+    pub healthy_while_paused: bool,
+    pub guard: GuardConfig,
+    pub notify: NotifyConfig,
+    /// Set when `WATCHDOG.interval` is.
+    pub watchdog: Option<WatchdogConfig>,
+    /// Set when `TELEMETRY.otlp_endpoint` is.
+    pub telemetry: Option<TelemetryConfig>,
+    /// Default detection settings, used by records without a source.
+    pub detection: DetectionSource,
+    /// Named detection sources, e.g. one per uplink.
+    pub sources: BTreeMap<String, DetectionSource>,
+    /// Addresses given on the command line, bypassing detection.
+    pub ipv4_override: Option<Ipv4Addr>,
+    pub ipv6_override: Option<Ipv6Addr>,
+    /// Family given on the command line, applying to all records.
+    pub ip_version_override: Option<IpVersion>,
+    /// Set on the command line to write even the rrsets that look like
+    /// they are used for something else.
+    pub force_overwrite: bool,
+    /// Set on the command line to delete the rrsets of `remove`.
+    pub allow_delete: bool,
+    /// Set by the `set` subcommand, whose values normal runs keep.
+    pub manual_set: bool,
+    /// Set on the command line to revert the values written by `set`.
+    pub revert_manual: bool,
+    /// Set on the command line to log only errors.
+    pub quiet: bool,
+}
+
+impl DnsConfig {
+    /// The detection sources used by the records in order of first use,
+    /// `None` standing for the default detection settings.
+    pub fn used_sources(&self) -> Vec<Option<&str>> {
+        let mut sources = Vec::new();
+        for record in &self.records {
+            let followed = if record.is_multi_source() {
+                record.sources.iter().map(|s| Some(s.as_str())).collect()
+            } else {
+                vec![record.source.as_deref()]
+            };
+            for source in followed {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+            }
+        }
+        for template in &self.templates {
+            let source = template.source.as_deref();
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        sources
+    }
+
+    /// The families updated for `record`.
+    pub fn versions(&self, record: &RecordEntry) -> IpVersionFilter {
+        match self.ip_version_override {
+            Some(version) => IpVersionFilter::Only(version),
+            None => record.ip_version.unwrap_or(self.ip_version),
+        }
+    }
+
+    /// Whether the rrsets of `record` may be written whatever they hold.
+    pub fn may_overwrite(&self, record: &RecordEntry) -> bool {
+        self.force_overwrite || record.allow_overwrite
+    }
+
+    /// The comment of the first entry of `record` that has one.
+    pub fn comment(&self, record: &str) -> Option<&str> {
+        self.records
+            .iter()
+            .filter(|entry| entry.name == record)
+            .find_map(|entry| entry.comment.as_deref())
+    }
+
+    pub fn detection(&self, source: Option<&str>) -> &DetectionSource {
+        match source {
+            Some(name) => &self.sources[name],
+            None => &self.detection,
+        }
+    }
+}
+
+/// Builds a [`DnsConfig`] without a configuration file. Everything but the
+/// key and the domain defaults to what a configuration file leaving it out
+/// gets.
+#[derive(Debug)]
+pub struct DnsConfigBuilder {
+    config: DnsConfig,
+    /// Set unless the socket is to follow the state directory.
+    control_socket: Option<PathBuf>,
+    pause_file: Option<PathBuf>,
+    /// Whether the apex is added to the records if missing.
+    include_apex: bool,
+    /// Whether the apex is taken out of the records.
+    exclude_apex: bool,
+}
+
+impl DnsConfigBuilder {
+    pub fn new(key: String, domain: String) -> Self {
+        DnsConfigBuilder {
+            config: DnsConfig {
+                key,
+                key_file: None,
+                keyring: None,
+                sharing_id: None,
+                auth_method: None,
+                key_expires: None,
+                expiry_warning_days: DEFAULT_EXPIRY_WARNING_DAYS,
+                domain,
+                records: Vec::new(),
+                templates: Vec::new(),
+                records_files: Vec::new(),
+                remove: Vec::new(),
+                confirm_deletes: true,
+                ip_version: IpVersionFilter::default(),
+                max_run_duration: None,
+                gandi_request_interval: None,
+                max_concurrent_requests: None,
+                quota_warning_threshold: None,
+                rate_limit_header_prefix: None,
+                timeouts: Timeouts::default(),
+                tls: TlsConfig::default(),
+                state_dir: default_state_dir(),
+                metrics_file: None,
+                retry_queue_depth: DEFAULT_QUEUE_DEPTH,
+                use_batch: false,
+                on_drift: DriftPolicy::default(),
+                change_debounce: None,
+                precheck: Precheck::default(),
+                precheck_resolver: None,
+                adaptive_ttl: None,
+                daemon_interval: DEFAULT_DAEMON_INTERVAL,
+                config_poll_interval: Some(DEFAULT_CONFIG_POLL_INTERVAL),
+                control_socket: PathBuf::new(),
+                pause_file: PathBuf::new(),
+                healthy_while_paused: true,
+                guard: GuardConfig::default(),
+                notify: NotifyConfig::default(),
+                watchdog: None,
+                telemetry: None,
+                discovery: None,
+                heartbeat_record: None,
+                detection: DetectionSource::default(),
+                sources: BTreeMap::new(),
+                ipv4_override: None,
+                ipv6_override: None,
+                ip_version_override: None,
+                force_overwrite: false,
+                allow_delete: false,
+                manual_set: false,
+                revert_manual: false,
+                quiet: false,
+            },
+            control_socket: None,
+            pause_file: None,
+            include_apex: false,
+            exclude_apex: false,
+        }
+    }
+
+    pub fn key_file(mut self, path: Option<PathBuf>) -> Self {
+        self.config.key_file = path;
+        self
+    }
+
+    pub fn keyring(mut self, entry: Option<KeyringEntry>) -> Self {
+        self.config.keyring = entry;
+        self
+    }
+
+    pub fn sharing_id(mut self, sharing_id: Option<String>) -> Self {
+        self.config.sharing_id = sharing_id;
+        self
+    }
+
+    pub fn auth_method(mut self, method: Option<AuthScheme>) -> Self {
+        self.config.auth_method = method;
+        self
+    }
+
+    pub fn key_expires(mut self, expires: Option<Date>, warning_days: u32) -> Self {
+        self.config.key_expires = expires;
+        self.config.expiry_warning_days = warning_days;
+        self
+    }
+
+    pub fn add_record(mut self, record: RecordEntry) -> Self {
+        self.config.records.push(record);
+        self
+    }
+
+    pub fn add_template(mut self, template: TemplateRecord) -> Self {
+        self.config.templates.push(template);
+        self
+    }
+
+    /// Remembers where the records read from files came from; the records
+    /// themselves are added with [`DnsConfigBuilder::add_record`].
+    pub fn records_files(mut self, patterns: Vec<String>) -> Self {
+        self.config.records_files = patterns;
+        self
+    }
+
+    /// Sets the records to delete, and whether that needs `--allow-delete`.
+    pub fn remove(mut self, patterns: Vec<String>, confirm_deletes: bool) -> Self {
+        self.config.remove = patterns;
+        self.config.confirm_deletes = confirm_deletes;
+        self
+    }
+
+    pub fn ip_version(mut self, filter: IpVersionFilter) -> Self {
+        self.config.ip_version = filter;
+        self
+    }
+
+    pub fn max_run_duration(mut self, limit: Option<Duration>) -> Self {
+        self.config.max_run_duration = limit;
+        self
+    }
+
+    pub fn request_limits(
+        mut self,
+        interval: Option<Duration>,
+        max_in_flight: Option<usize>,
+    ) -> Self {
+        self.config.gandi_request_interval = interval;
+        self.config.max_concurrent_requests = max_in_flight;
+        self
+    }
+
+    /// How the quota the API reports is read and watched.
+    pub fn quota_watch(
+        mut self,
+        header_prefix: Option<String>,
+        warning_threshold: Option<u64>,
+    ) -> Self {
+        self.config.rate_limit_header_prefix = header_prefix;
+        self.config.quota_warning_threshold = warning_threshold;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.config.timeouts = timeouts;
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.config.tls = tls;
+        self
+    }
+
+    pub fn state_dir(mut self, dir: PathBuf) -> Self {
+        self.config.state_dir = dir;
+        self
+    }
+
+    pub fn metrics_file(mut self, path: Option<PathBuf>) -> Self {
+        self.config.metrics_file = path;
+        self
+    }
+
+    pub fn retry_queue_depth(mut self, depth: usize) -> Self {
+        self.config.retry_queue_depth = depth;
+        self
+    }
+
+    pub fn use_batch(mut self, use_batch: bool) -> Self {
+        self.config.use_batch = use_batch;
+        self
+    }
+
+    pub fn on_drift(mut self, policy: DriftPolicy) -> Self {
+        self.config.on_drift = policy;
+        self
+    }
+
+    pub fn change_debounce(mut self, debounce: Option<Duration>) -> Self {
+        self.config.change_debounce = debounce;
+        self
+    }
+
+    pub fn precheck(mut self, precheck: Precheck, resolver: Option<IpAddr>) -> Self {
+        self.config.precheck = precheck;
+        self.config.precheck_resolver = resolver;
+        self
+    }
+
+    /// Adapts the TTLs instead of always writing
+    /// [`RRSET_TTL`](crate::gandi::RRSET_TTL).
+    pub fn ttl(mut self, policy: Option<AdaptiveTtl>) -> Self {
+        self.config.adaptive_ttl = policy;
+        self
+    }
+
+    pub fn daemon_interval(mut self, interval: Duration) -> Self {
+        self.config.daemon_interval = interval;
+        self
+    }
+
+    pub fn config_poll_interval(mut self, interval: Option<Duration>) -> Self {
+        self.config.config_poll_interval = interval;
+        self
+    }
+
+    /// Updates the apex, `@`, as the first record unless it is among the
+    /// records already.
+    pub fn with_apex(mut self) -> Self {
+        self.include_apex = true;
+        self
+    }
+
+    /// Never updates the apex, even if it is among the records, e.g. since
+    /// it points to a hosted website.
+    pub fn without_apex(mut self) -> Self {
+        self.exclude_apex = true;
+        self
+    }
+
+    /// Defaults to `control.sock` in the state directory.
+    pub fn control_socket(mut self, path: PathBuf) -> Self {
+        self.control_socket = Some(path);
+        self
+    }
+
+    /// Defaults to `pause` in the state directory.
+    pub fn pause_file(mut self, path: PathBuf) -> Self {
+        self.pause_file = Some(path);
+        self
+    }
+
+    pub fn healthy_while_paused(mut self, healthy: bool) -> Self {
+        self.config.healthy_while_paused = healthy;
+        self
+    }
+
+    pub fn guard(mut self, guard: GuardConfig) -> Self {
+        self.config.guard = guard;
+        self
+    }
+
+    pub fn notify(mut self, notify: NotifyConfig) -> Self {
+        self.config.notify = notify;
+        self
+    }
+
+    pub fn heartbeat_record(mut self, record: Option<String>) -> Self {
+        self.config.heartbeat_record = record;
+        self
+    }
+
+    pub fn discovery(mut self, discovery: Option<DomainDiscovery>) -> Self {
+        self.config.discovery = discovery;
+        self
+    }
+
+    pub fn watchdog(mut self, watchdog: Option<WatchdogConfig>) -> Self {
+        self.config.watchdog = watchdog;
+        self
+    }
+
+    pub fn telemetry(mut self, telemetry: Option<TelemetryConfig>) -> Self {
+        self.config.telemetry = telemetry;
+        self
+    }
+
+    pub fn detection(mut self, detection: DetectionSource) -> Self {
+        self.config.detection = detection;
+        self
+    }
+
+    pub fn add_source(mut self, name: String, source: DetectionSource) -> Self {
+        self.config.sources.insert(name, source);
+        self
+    }
+
+    /// Checks the configuration with [`validate_config`].
+    pub fn build(self) -> Result<DnsConfig, Vec<ConfigValidationError>> {
+        let mut config = self.config;
+        config.control_socket = self
+            .control_socket
+            .unwrap_or_else(|| config.state_dir.join(CONTROL_SOCKET_FILE));
+        config.pause_file = self
+            .pause_file
+            .unwrap_or_else(|| config.state_dir.join(PAUSE_FILE));
+        if self.include_apex && self.exclude_apex {
+            return Err(vec![ConfigValidationError {
+                key: "DNS.include_apex",
+                message: "conflicts with DNS.exclude_apex".to_string(),
+            }]);
+        }
+        if self.include_apex && !config.records.iter().any(|record| record.name == "@") {
+            config
+                .records
+                .insert(0, RecordEntry::named("@".to_string()));
+        }
+        if self.exclude_apex {
+            config.records.retain(|record| record.name != "@");
+        }
+        let errors = validate_config(&config);
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Parses a duration such as `"500ms"`, `"120s"`, `"5m"`, `"1h"` or `"7d"`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in duration '{}'", value))?;
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+
+    let secs_per_unit: u64 = match unit.trim() {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        other => return Err(format!("unknown unit '{}' in duration '{}'", other, value)),
+    };
+    amount
+        .checked_mul(secs_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too long", value))
+}
+
+/// Formats a duration in the largest unit that represents it exactly, so
+/// that the result round-trips through [`parse_duration`].
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if !millis.is_multiple_of(1000) {
+        return format!("{}ms", millis);
+    }
+    let secs = duration.as_secs();
+    if secs != 0 && secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs != 0 && secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Names `key` in the type errors of its value, which the typed getters
+/// of `Config` leave out.
+fn keyed(e: ConfigError, key: &str) -> ConfigError {
+    match e {
+        ConfigError::Type {
+            origin,
+            unexpected,
+            expected,
+            key: None,
+        } => ConfigError::Type {
+            origin,
+            unexpected,
+            expected,
+            key: Some(key.to_string()),
+        },
+        e => e,
+    }
+}
+
+fn get_optional_string(config: &Config, key: &str) -> Result<Option<String>, ConfigError> {
+    match config.get_string(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(keyed(e, key)),
+    }
+}
+
+/// Reads a list of strings, also accepting a single string.
+fn get_string_list(config: &Config, key: &str) -> Result<Vec<String>, ConfigError> {
+    match config.get_array(key) {
+        Ok(values) => values
+            .into_iter()
+            .map(|v| v.into_string().map_err(|e| keyed(e, key)))
+            .collect(),
+        Err(ConfigError::NotFound(_)) => Ok(Vec::new()),
+        Err(_) => Ok(vec![config.get_string(key).map_err(|e| keyed(e, key))?]),
+    }
+}
+
+fn get_optional_bool(config: &Config, key: &str) -> Result<Option<bool>, ConfigError> {
+    match config.get_bool(key) {
+        Ok(value) => Ok(Some(value)),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(keyed(e, key)),
+    }
+}
+
+fn get_optional_duration(config: &Config, key: &str) -> Result<Option<Duration>, ConfigError> {
+    match config.get_string(key) {
+        Ok(value) => parse_duration(&value)
+            .map(Some)
+            .map_err(|e| ConfigError::Message(format!("{}: {}", key, e))),
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(keyed(e, key)),
+    }
+}
+
+fn get_optional_ttl(config: &Config, key: &str) -> Result<Option<u32>, ConfigError> {
+    match config.get_int(key) {
+        Ok(ttl) => match u32::try_from(ttl) {
+            Ok(ttl) if (GANDI_MIN_TTL..=GANDI_MAX_TTL).contains(&ttl) => Ok(Some(ttl)),
+            _ => Err(ConfigError::Message(format!(
+                "{}: the TTL must be between {} and {} seconds, got {}",
+                key, GANDI_MIN_TTL, GANDI_MAX_TTL, ttl
+            ))),
+        },
+        Err(ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(keyed(e, key)),
+    }
+}
+
+fn get_adaptive_ttl(config: &Config) -> Result<Option<AdaptiveTtl>, ConfigError> {
+    let min = get_optional_ttl(config, "UPDATE.ttl_min")?;
+    let max = get_optional_ttl(config, "UPDATE.ttl_max")?;
+    let window = get_optional_duration(config, "UPDATE.ttl_window")?;
+    if min.is_none() && max.is_none() && window.is_none() {
+        return Ok(None);
+    }
+
+    let policy = AdaptiveTtl {
+        min: min.unwrap_or(DEFAULT_TTL_MIN),
+        max: max.unwrap_or(DEFAULT_TTL_MAX),
+        window: window.unwrap_or(DEFAULT_TTL_WINDOW),
+    };
+    if policy.min > policy.max {
+        return Err(ConfigError::Message(format!(
+            "UPDATE.ttl_min ({}) is larger than UPDATE.ttl_max ({})",
+            policy.min, policy.max
+        )));
+    }
+    if policy.window.as_secs() == 0 {
+        return Err(ConfigError::Message(
+            "UPDATE.ttl_window must be at least one second".to_string(),
+        ));
+    }
+    Ok(Some(policy))
+}
+
+fn get_watchdog(config: &Config) -> Result<Option<WatchdogConfig>, ConfigError> {
+    let Some(interval) = get_optional_duration(config, "WATCHDOG.interval")? else {
+        return Ok(None);
+    };
+    if interval.is_zero() {
+        return Err(ConfigError::Message(
+            "WATCHDOG.interval must be at least one second".to_string(),
+        ));
+    }
+    let resolver = get_optional_string(config, "WATCHDOG.resolver")?
+        .map(|resolver| resolver.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("WATCHDOG.resolver: {}", e)))?;
+    Ok(Some(WatchdogConfig {
+        interval,
+        grace: get_optional_duration(config, "WATCHDOG.grace")?.unwrap_or(DEFAULT_WATCHDOG_GRACE),
+        resolver: resolver.unwrap_or(DEFAULT_WATCHDOG_RESOLVER),
+        reassert: get_optional_bool(config, "WATCHDOG.reassert")?.unwrap_or(false),
+    }))
+}
+
+fn get_telemetry(config: &Config) -> Result<Option<TelemetryConfig>, ConfigError> {
+    let Some(otlp_endpoint) = get_optional_string(config, "TELEMETRY.otlp_endpoint")? else {
+        return Ok(None);
+    };
+    let url = reqwest::Url::parse(&otlp_endpoint).map_err(|e| {
+        ConfigError::Message(format!(
+            "TELEMETRY.otlp_endpoint: '{}': {}",
+            otlp_endpoint, e
+        ))
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ConfigError::Message(format!(
+            "TELEMETRY.otlp_endpoint: {} is not an HTTP URL, OTLP is exported over HTTP",
+            otlp_endpoint
+        )));
+    }
+    Ok(Some(TelemetryConfig { otlp_endpoint }))
+}
+
+/// Follows the XDG base directory specification, falling back to the
+/// current directory when no home directory is known. On Windows, where
+/// the service has neither, the directory is in `%ProgramData%`.
+fn default_state_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir).join("ddns-gandi");
+    }
+    #[cfg(windows)]
+    if let Some(dir) = program_data_dir() {
+        return dir;
+    }
+    match env::var_os("HOME").filter(|d| !d.is_empty()) {
+        Some(home) => PathBuf::from(home).join(".local/state/ddns-gandi"),
+        None => PathBuf::from("."),
+    }
+}
+
+/// `%ProgramData%\ddns-gandi`, shared by the service and the commands run
+/// by the administrators.
+#[cfg(windows)]
+pub fn program_data_dir() -> Option<PathBuf> {
+    env::var_os("ProgramData")
+        .filter(|d| !d.is_empty())
+        .map(|dir| PathBuf::from(dir).join("ddns-gandi"))
+}
+
+/// Default location of the configuration file, in the working directory.
+pub const DEFAULT_CONFIG_PATH: &str = ".gandi.toml";
+
+/// Overrides the keys of the configuration file with the matching
+/// `<SECTION>_<KEY>` environment variables, e.g. `GANDI_KEY` or
+/// `RUN_MAX_RUN_DURATION`. Only the first underscore separates the section
+/// from the key, since the keys contain underscores themselves.
+fn add_environment(
+    mut builder: ConfigBuilder<DefaultState>,
+) -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+    for (name, value) in env::vars() {
+        let Some((section, key)) = name.split_once('_') else {
+            continue;
+        };
+        if SECTIONS.iter().any(|(name, _)| *name == section) && !key.is_empty() {
+            let key = format!("{}.{}", section, key.to_ascii_lowercase());
+            builder = builder.set_override(key, value)?;
+        }
+    }
+    Ok(builder)
+}
+
+/// Reads where the key is kept in the OS secret store.
+fn get_keyring_entry(config: &Config) -> Result<KeyringEntry, ConfigError> {
+    let defaults = KeyringEntry::default();
+    Ok(KeyringEntry {
+        service: get_optional_string(config, "GANDI.keyring_service")?.unwrap_or(defaults.service),
+        account: get_optional_string(config, "GANDI.keyring_account")?.unwrap_or(defaults.account),
+    })
+}
+
+/// Reads the key from where `GANDI.key_source` says: the configuration,
+/// which may name a `key_file`, or the OS secret store.
+fn get_key(
+    config: &Config,
+) -> Result<(String, Option<PathBuf>, Option<KeyringEntry>), ConfigError> {
+    let key_file = get_optional_string(config, "GANDI.key_file")?.map(PathBuf::from);
+    let source = get_optional_string(config, "GANDI.key_source")?;
+    match source.as_deref().map(str::trim) {
+        None | Some("config") => {}
+        Some("keyring") => {
+            // The key must not be left in the file next to the keyring.
+            if key_file.is_some() || get_optional_string(config, "GANDI.key")?.is_some() {
+                return Err(ConfigError::Message(
+                    "GANDI.key_source: key and key_file cannot be set with \"keyring\"".to_string(),
+                ));
+            }
+            let entry = get_keyring_entry(config)?;
+            let key = entry
+                .read()
+                .map_err(|e| ConfigError::Message(format!("GANDI.key_source: {}", e)))?;
+            return Ok((key, None, Some(entry)));
+        }
+        Some(other) => {
+            return Err(ConfigError::Message(format!(
+                "GANDI.key_source: unknown source '{}', expected \"config\" or \"keyring\"",
+                other
+            )))
+        }
+    }
+
+    let key = match &key_file {
+        Some(path) => read_secret_file(path)
+            .map_err(|e| ConfigError::Message(format!("GANDI.key_file: {}", e)))?,
+        None => config.get_string("GANDI.key")?,
+    };
+    Ok((key, key_file, None))
+}
+
+/// Reads the `<prefix>bind_*` keys of one address family.
+fn get_binding(config: &Config, prefix: &str) -> Result<Binding, ConfigError> {
+    let key = format!("{}bind_address", prefix);
+    let address = get_optional_string(config, &key)?
+        .map(|address| address.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?;
+    Ok(Binding {
+        address,
+        interface: get_optional_string(config, &format!("{}bind_interface", prefix))?,
+    })
+}
+
+/// Reads the `<section>.ipv4` or `<section>.ipv6` table, falling back to
+/// the `<section>.<family>_bind_*` keys for the binding.
+fn get_family(
+    config: &Config,
+    section: &str,
+    version: IpVersion,
+) -> Result<FamilyDetection, ConfigError> {
+    let family = match version {
+        IpVersion::V4 => "ipv4",
+        IpVersion::V6 => "ipv6",
+    };
+    let table = format!("{}.{}", section, family);
+    let providers = get_string_list(config, &format!("{}.providers", table))?;
+    for url in &providers {
+        reqwest::Url::parse(url)
+            .map_err(|e| ConfigError::Message(format!("{}.providers: '{}': {}", table, url, e)))?;
+        match provider_family(url) {
+            Some(other) if other != version => {
+                return Err(ConfigError::Message(format!(
+                    "{}.providers: {} only answers over IP{}",
+                    table,
+                    url,
+                    other.as_str()
+                )))
+            }
+            _ => {}
+        }
+    }
+    let key = format!("{}.method", table);
+    let method = get_optional_string(config, &key)?
+        .map(|method| method.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?
+        .unwrap_or_default();
+    if let (IpVersion::V6, DetectionMethod::Gateway) = (version, method) {
+        return Err(ConfigError::Message(format!(
+            "{}: the gateway protocols only detect IPv4 addresses",
+            key
+        )));
+    }
+    let headers = get_headers(config, &table)?;
+    if providers.is_empty() && !headers.is_empty() {
+        return Err(ConfigError::Message(format!(
+            "{}.headers: only sent to the providers, which are not set",
+            table
+        )));
+    }
+    let key = format!("{}.prefix_len", table);
+    let prefix_len = match config.get_int(&key) {
+        Ok(_) if version == IpVersion::V4 => {
+            return Err(ConfigError::Message(format!(
+                "{}: only IPv6 prefixes are delegated",
+                key
+            )))
+        }
+        Ok(length) => Some(
+            u8::try_from(length)
+                .ok()
+                .filter(|length| (1..=128).contains(length))
+                .ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "{}: the prefix length must be between 1 and 128, got {}",
+                        key, length
+                    ))
+                })?,
+        ),
+        Err(ConfigError::NotFound(_)) => None,
+        Err(e) => return Err(keyed(e, &key)),
+    };
+    let delegated_prefix = get_delegated_prefix(config, &table)?;
+    let reason = match (&delegated_prefix, prefix_len) {
+        (Some(_), _) if version == IpVersion::V4 => Some("only IPv6 prefixes are delegated"),
+        (Some(_), None) => Some("the delegated prefix is read, but prefix_len is not set"),
+        _ => None,
+    };
+    if let Some(reason) = reason {
+        return Err(ConfigError::Message(format!("{}: {}", table, reason)));
+    }
+    let legacy = get_binding(config, &format!("{}.{}_", section, family))?;
+    let binding = get_binding(config, &format!("{}.", table))?;
+    Ok(FamilyDetection {
+        providers,
+        method,
+        timeout: get_optional_duration(config, &format!("{}.timeout", table))?
+            .filter(|t| !t.is_zero()),
+        binding: Binding {
+            address: binding.address.or(legacy.address),
+            interface: binding.interface.or(legacy.interface),
+        },
+        headers,
+        prefix_len,
+        delegated_prefix,
+    })
+}
+
+/// Reads `<table>.prefix_lease_file` or `<table>.prefix_interface`, where
+/// the delegated prefix is read.
+fn get_delegated_prefix(config: &Config, table: &str) -> Result<Option<PrefixSource>, ConfigError> {
+    let lease_file = get_optional_string(config, &format!("{}.prefix_lease_file", table))?;
+    let interface = get_optional_string(config, &format!("{}.prefix_interface", table))?;
+    match (lease_file, interface) {
+        (Some(_), Some(_)) => Err(ConfigError::Message(format!(
+            "{}: prefix_lease_file and prefix_interface are both set",
+            table
+        ))),
+        (Some(path), None) => Ok(Some(PrefixSource::LeaseFile(PathBuf::from(path)))),
+        (None, Some(name)) => Ok(Some(PrefixSource::Interface(name))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Reads the `<table>.headers` table, sent to the providers of a family.
+fn get_headers(config: &Config, table: &str) -> Result<HeaderMap, ConfigError> {
+    let key = format!("{}.headers", table);
+    let entries = match config.get_table(&key) {
+        Ok(entries) => entries,
+        Err(ConfigError::NotFound(_)) => return Ok(HeaderMap::new()),
+        Err(e) => return Err(e),
+    };
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+        let invalid =
+            |e: &dyn fmt::Display| ConfigError::Message(format!("{}.{}: {}", key, name, e));
+        let value = value.into_string().map_err(|e| invalid(&e))?;
+        let header = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+        let mut value = HeaderValue::from_str(&value).map_err(|e| invalid(&e))?;
+        value.set_sensitive(true);
+        headers.insert(header, value);
+    }
+    Ok(headers)
+}
+
+fn get_detection(config: &Config, section: &str) -> Result<DetectionSource, ConfigError> {
+    let source = DetectionSource {
+        ipv4: get_family(config, section, IpVersion::V4)?,
+        ipv6: get_family(config, section, IpVersion::V6)?,
+        fritzbox: get_fritzbox(config, section)?,
+        gateway: get_gateway(config, section)?,
+        cloud: get_cloud(config, section)?,
+    };
+    for (family, detection) in [("ipv4", &source.ipv4), ("ipv6", &source.ipv6)] {
+        let missing = match detection.method {
+            DetectionMethod::Fritzbox if source.fritzbox.is_none() => "fritzbox_address",
+            DetectionMethod::Gateway if source.gateway.is_none() => "gateway",
+            DetectionMethod::Cloud if source.cloud.is_none() => "cloud",
+            _ => continue,
+        };
+        return Err(ConfigError::Message(format!(
+            "{}.{}.method: {} is not set",
+            section, family, missing
+        )));
+    }
+    Ok(source)
+}
+
+/// Reads the webhook of `NOTIFY`, set if `webhook_url` is.
+fn get_webhook(config: &Config) -> Result<Option<WebhookConfig>, ConfigError> {
+    let Some(url) = get_optional_string(config, "NOTIFY.webhook_url")? else {
+        return Ok(None);
+    };
+    // Its keys are in NOTIFY rather than in a table of its own.
+    let defaults = EventFilter::default();
+    Ok(Some(WebhookConfig {
+        url: Redacted(url),
+        events: EventFilter {
+            on_change: get_optional_bool(config, "NOTIFY.webhook_on_change")?
+                .unwrap_or(defaults.on_change),
+            on_failure: get_optional_bool(config, "NOTIFY.webhook_on_failure")?
+                .or(get_optional_bool(config, "NOTIFY.webhook_on_error")?)
+                .unwrap_or(defaults.on_failure),
+        },
+    }))
+}
+
+/// Reads the `on_change` and `on_failure` (or `on_error`) keys of the
+/// `table` of a channel, along with which it holds its other keys.
+fn get_event_filter(config: &Config, table: &str) -> Result<EventFilter, ConfigError> {
+    match config.get(table) {
+        Err(ConfigError::NotFound(_)) => Ok(EventFilter::default()),
+        result => result,
+    }
+}
+
+/// Reads the `NOTIFY.ntfy` table, set if `topic` is.
+fn get_ntfy(config: &Config) -> Result<Option<NtfyConfig>, ConfigError> {
+    let Some(topic) = get_optional_string(config, "NOTIFY.ntfy.topic")? else {
+        if get_optional_string(config, "NOTIFY.ntfy.server")?.is_some() {
+            return Err(ConfigError::Message(
+                "NOTIFY.ntfy.server: NOTIFY.ntfy.topic is not set".to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+    let server = get_optional_string(config, "NOTIFY.ntfy.server")?
+        .unwrap_or_else(|| DEFAULT_NTFY_SERVER.to_string());
+    reqwest::Url::parse(&server)
+        .map_err(|e| ConfigError::Message(format!("NOTIFY.ntfy.server: '{}': {}", server, e)))?;
+    Ok(Some(NtfyConfig {
+        server,
+        topic,
+        token: get_optional_string(config, "NOTIFY.ntfy.token")?.map(Redacted),
+        priority: get_ntfy_priority(config, "NOTIFY.ntfy.priority")?,
+        events: get_event_filter(config, "NOTIFY.ntfy")?,
+    }))
+}
+
+fn get_ntfy_priority(config: &Config, key: &str) -> Result<NtfyPriority, ConfigError> {
+    match config.get_string(key) {
+        Ok(priority) => priority
+            .parse()
+            .map_err(|e| ConfigError::Message(format!("{}: {}", key, e))),
+        Err(ConfigError::NotFound(_)) => Ok(NtfyPriority::DEFAULT),
+        Err(e) => Err(keyed(e, key)),
+    }
+}
+
+/// Reads the `NOTIFY.gotify` table, set if `server` is.
+fn get_gotify(config: &Config) -> Result<Option<GotifyConfig>, ConfigError> {
+    let Some(server) = get_optional_string(config, "NOTIFY.gotify.server")? else {
+        if get_optional_string(config, "NOTIFY.gotify.token")?.is_some() {
+            return Err(ConfigError::Message(
+                "NOTIFY.gotify.token: NOTIFY.gotify.server is not set".to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+    reqwest::Url::parse(&server)
+        .map_err(|e| ConfigError::Message(format!("NOTIFY.gotify.server: '{}': {}", server, e)))?;
+    let token = get_optional_string(config, "NOTIFY.gotify.token")?.ok_or_else(|| {
+        ConfigError::Message("NOTIFY.gotify.server: NOTIFY.gotify.token is not set".to_string())
+    })?;
+    Ok(Some(GotifyConfig {
+        server,
+        token: Redacted(token),
+        priority: get_gotify_priority(config, "NOTIFY.gotify.priority")?,
+        events: get_event_filter(config, "NOTIFY.gotify")?,
+    }))
+}
+
+fn get_gotify_priority(config: &Config, key: &str) -> Result<u8, ConfigError> {
+    match config.get_int(key) {
+        Ok(priority) => u8::try_from(priority)
+            .ok()
+            .filter(|priority| *priority <= 10)
+            .ok_or_else(|| {
+                ConfigError::Message(format!("{}: {} is not between 0 and 10", key, priority))
+            }),
+        Err(ConfigError::NotFound(_)) => Ok(DEFAULT_GOTIFY_PRIORITY),
+        Err(e) => Err(keyed(e, key)),
+    }
+}
+
+/// The server a `NOTIFY.push` table pushes to.
+enum Push {
+    Ntfy(NtfyConfig),
+    Gotify(GotifyConfig),
+}
+
+/// Reads the `NOTIFY.push` table, the URL of an ntfy topic or of a Gotify
+/// server as chosen with `type`, set if `url` is.
+fn get_push(config: &Config) -> Result<Option<Push>, ConfigError> {
+    let Some(url) = get_optional_string(config, "NOTIFY.push.url")? else {
+        return Ok(None);
+    };
+    let message = |e: &dyn fmt::Display| ConfigError::Message(format!("NOTIFY.push.url: {}", e));
+    let parsed = reqwest::Url::parse(&url).map_err(|e| message(&format!("'{}': {}", url, e)))?;
+    let token = get_optional_string(config, "NOTIFY.push.token")?;
+    let events = get_event_filter(config, "NOTIFY.push")?;
+    let kind = get_optional_string(config, "NOTIFY.push.type")?.ok_or_else(|| {
+        ConfigError::Message("NOTIFY.push.url: NOTIFY.push.type is not set".to_string())
+    })?;
+    match kind.to_ascii_lowercase().as_str() {
+        "ntfy" => {
+            let topic = parsed
+                .path_segments()
+                .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+                .ok_or_else(|| message(&format!("'{}' names no topic", url)))?
+                .to_string();
+            let mut server = parsed.clone();
+            if let Ok(mut segments) = server.path_segments_mut() {
+                segments.pop_if_empty().pop();
+            }
+            Ok(Some(Push::Ntfy(NtfyConfig {
+                server: server.as_str().trim_end_matches('/').to_string(),
+                topic,
+                token: token.map(Redacted),
+                priority: get_ntfy_priority(config, "NOTIFY.push.priority")?,
+                events,
+            })))
+        }
+        "gotify" => Ok(Some(Push::Gotify(GotifyConfig {
+            server: url,
+            token: Redacted(token.ok_or_else(|| {
+                ConfigError::Message("NOTIFY.push.url: NOTIFY.push.token is not set".to_string())
+            })?),
+            priority: get_gotify_priority(config, "NOTIFY.push.priority")?,
+            events,
+        }))),
+        other => Err(ConfigError::Message(format!(
+            "NOTIFY.push.type: unknown type '{}', expected ntfy or gotify",
+            other
+        ))),
+    }
+}
+
+/// Reads the `DNS.domains` table, set if `discover` is true.
+fn get_discovery(config: &Config) -> Result<Option<DomainDiscovery>, ConfigError> {
+    if !get_optional_bool(config, "DNS.domains.discover")?.unwrap_or(false) {
+        return Ok(None);
+    }
+    let patterns = |key| -> Result<Vec<String>, ConfigError> {
+        Ok(get_string_list(config, key)?
+            .iter()
+            .map(|pattern| pattern.trim().trim_end_matches('.').to_ascii_lowercase())
+            .collect())
+    };
+    Ok(Some(DomainDiscovery {
+        include: patterns("DNS.domains.include")?,
+        exclude: patterns("DNS.domains.exclude")?,
+    }))
+}
+
+/// Reads `NOTIFY.desktop`, warning if it cannot be honored.
+fn get_desktop(config: &Config) -> Result<bool, ConfigError> {
+    let desktop = get_optional_bool(config, "NOTIFY.desktop")?.unwrap_or(false);
+    if desktop && cfg!(not(feature = "desktop-notifications")) {
+        warn!("NOTIFY.desktop: built without the desktop-notifications feature, ignoring it");
+    }
+    Ok(desktop)
+}
+
+/// Reads the `NOTIFY.telegram` table, set if `bot_token` is.
+fn get_telegram(config: &Config) -> Result<Option<TelegramConfig>, ConfigError> {
+    let chat_ids = get_string_list(config, "NOTIFY.telegram.chat_id")?;
+    let Some(bot_token) = get_optional_string(config, "NOTIFY.telegram.bot_token")? else {
+        if !chat_ids.is_empty() {
+            return Err(ConfigError::Message(
+                "NOTIFY.telegram.chat_id: NOTIFY.telegram.bot_token is not set".to_string(),
+            ));
+        }
+        return Ok(None);
+    };
+    if chat_ids.is_empty() {
+        return Err(ConfigError::Message(
+            "NOTIFY.telegram.bot_token: NOTIFY.telegram.chat_id is not set".to_string(),
+        ));
+    }
+    Ok(Some(TelegramConfig {
+        bot_token: Redacted(bot_token),
+        chat_ids,
+        events: get_event_filter(config, "NOTIFY.telegram")?,
+    }))
+}
+
+/// Reads the `NOTIFY.discord` table, set if `webhook_url` is.
+fn get_discord(config: &Config) -> Result<Option<DiscordConfig>, ConfigError> {
+    let Some(webhook_url) = get_optional_string(config, "NOTIFY.discord.webhook_url")? else {
+        return Ok(None);
+    };
+    reqwest::Url::parse(&webhook_url)
+        .map_err(|e| ConfigError::Message(format!("NOTIFY.discord.webhook_url: {}", e)))?;
+    Ok(Some(DiscordConfig {
+        webhook_url: Redacted(webhook_url),
+        username: get_optional_string(config, "NOTIFY.discord.username")?,
+        avatar_url: get_optional_string(config, "NOTIFY.discord.avatar_url")?,
+        events: get_event_filter(config, "NOTIFY.discord")?,
+    }))
+}
+
+/// Reads the `NOTIFY.matrix` table, set if `homeserver_url` is.
+fn get_matrix(config: &Config) -> Result<Option<MatrixConfig>, ConfigError> {
+    let Some(homeserver_url) = get_optional_string(config, "NOTIFY.matrix.homeserver_url")? else {
+        return Ok(None);
+    };
+    let url = reqwest::Url::parse(&homeserver_url)
+        .map_err(|e| ConfigError::Message(format!("NOTIFY.matrix.homeserver_url: {}", e)))?;
+    if url.cannot_be_a_base() {
+        return Err(ConfigError::Message(format!(
+            "NOTIFY.matrix.homeserver_url: {} is not an HTTP URL",
+            homeserver_url
+        )));
+    }
+    let required = |key: &str| {
+        get_optional_string(config, &format!("NOTIFY.matrix.{}", key))?.ok_or_else(|| {
+            ConfigError::Message(format!(
+                "NOTIFY.matrix.{}: not set, but NOTIFY.matrix.homeserver_url is",
+                key
+            ))
+        })
+    };
+    Ok(Some(MatrixConfig {
+        homeserver_url,
+        access_token: Redacted(required("access_token")?),
+        room_id: required("room_id")?,
+        events: get_event_filter(config, "NOTIFY.matrix")?,
+    }))
+}
+
+/// `NOTIFY.templates.<kind>.<part>`, compiled so that a syntax error stops
+/// the configuration from loading rather than a notification from being
+/// sent.
+fn get_message_templates(config: &Config) -> Result<Templates, ConfigError> {
+    let mut sources = BTreeMap::new();
+    for kind in message_templates::KINDS {
+        for part in message_templates::PARTS {
+            let name = format!("{}.{}", kind, part);
+            let key = format!("NOTIFY.templates.{}", name);
+            if let Some(source) = get_optional_string(config, &key)? {
+                sources.insert(name, source);
+            }
+        }
+    }
+    Templates::new(sources)
+        .map_err(|(name, e)| ConfigError::Message(format!("NOTIFY.templates.{}: {}", name, e)))
+}
+
+/// Reads the `NOTIFY.apprise` table, set if `urls` is.
+fn get_apprise(config: &Config) -> Result<Option<AppriseConfig>, ConfigError> {
+    let urls = get_string_list(config, "NOTIFY.apprise.urls")?;
+    let apprise_url = get_optional_string(config, "NOTIFY.apprise.apprise_url")?;
+    if urls.is_empty() {
+        if apprise_url.is_some() {
+            return Err(ConfigError::Message(
+                "NOTIFY.apprise.apprise_url: NOTIFY.apprise.urls is not set".to_string(),
+            ));
+        }
+        return Ok(None);
+    }
+    // The URLs are not shown, they hold the credentials.
+    if let Some(index) = urls.iter().position(|url| !url.contains("://")) {
+        return Err(ConfigError::Message(format!(
+            "NOTIFY.apprise.urls: entry {} is not a URL such as slack://tokenA/tokenB",
+            index
+        )));
+    }
+    if let Some(apprise_url) = &apprise_url {
+        reqwest::Url::parse(apprise_url).map_err(|e| {
+            ConfigError::Message(format!(
+                "NOTIFY.apprise.apprise_url: '{}': {}",
+                apprise_url, e
+            ))
+        })?;
+    }
+    Ok(Some(AppriseConfig {
+        urls: Redacted(urls),
+        apprise_url,
+        events: get_event_filter(config, "NOTIFY.apprise")?,
+    }))
+}
+
+/// Reads the `<section>.gateway*` keys, set if `gateway` is.
+fn get_gateway(config: &Config, section: &str) -> Result<Option<Gateway>, ConfigError> {
+    let key = format!("{}.gateway", section);
+    let Some(protocol) = get_optional_string(config, &key)? else {
+        return Ok(None);
+    };
+    let protocol = protocol
+        .parse()
+        .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?;
+    let key = format!("{}.gateway_address", section);
+    let address = get_optional_string(config, &key)?
+        .map(|address| address.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?;
+    Ok(Some(Gateway { protocol, address }))
+}
+
+/// Reads the `<section>.cloud*` keys, set if `cloud` is.
+fn get_cloud(config: &Config, section: &str) -> Result<Option<CloudMetadata>, ConfigError> {
+    let key = format!("{}.cloud_metadata_url", section);
+    let url = get_optional_string(config, &key)?;
+    if let Some(url) = &url {
+        reqwest::Url::parse(url)
+            .map_err(|e| ConfigError::Message(format!("{}: '{}': {}", key, url, e)))?;
+    }
+    let key = format!("{}.cloud", section);
+    let Some(provider) = get_optional_string(config, &key)? else {
+        if url.is_some() {
+            return Err(ConfigError::Message(format!(
+                "{}.cloud_metadata_url: {} is not set",
+                section, key
+            )));
+        }
+        return Ok(None);
+    };
+    let provider = provider
+        .parse()
+        .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?;
+    Ok(Some(CloudMetadata { provider, url }))
+}
+
+/// Reads the `<section>.fritzbox_*` keys, set if `fritzbox_address` is.
+fn get_fritzbox(config: &Config, section: &str) -> Result<Option<FritzBox>, ConfigError> {
+    let Some(address) = get_optional_string(config, &format!("{}.fritzbox_address", section))?
+    else {
+        return Ok(None);
+    };
+    let username = get_optional_string(config, &format!("{}.fritzbox_username", section))?;
+    let password = get_optional_string(config, &format!("{}.fritzbox_password", section))?;
+    if username.is_some() != password.is_some() {
+        return Err(ConfigError::Message(format!(
+            "{}: set both fritzbox_username and fritzbox_password",
+            section
+        )));
+    }
+    let key = format!("{}.fritzbox_ipv6_suffix", section);
+    let ipv6_suffix = get_optional_string(config, &key)?
+        .map(|suffix| suffix.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("{}: {}", key, e)))?;
+    Ok(Some(FritzBox {
+        address,
+        username,
+        password,
+        ipv6_suffix,
+    }))
+}
+
+/// Reads the named detection sources, the subsections of `DETECTION`.
+fn get_sources(config: &Config) -> Result<BTreeMap<String, DetectionSource>, ConfigError> {
+    let detection = match config.get_table("DETECTION") {
+        Ok(detection) => detection,
+        Err(ConfigError::NotFound(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut sources = BTreeMap::new();
+    for (name, value) in detection {
+        // The tables of the address families are not sources.
+        if name == "ipv4" || name == "ipv6" {
+            continue;
+        }
+        if value.into_table().is_ok() {
+            let source = get_detection(config, &format!("DETECTION.{}", name))?;
+            sources.insert(name, source);
+        }
+    }
+    Ok(sources)
+}
+
+/// Whether an entry of a `DNS.records` array is a template.
+fn is_template(value: &config::Value) -> bool {
+    value
+        .clone()
+        .into_table()
+        .is_ok_and(|table| table.contains_key("template"))
+}
+
+/// Parses an entry of a `DNS.records` array with a `template`.
+fn parse_template_entry(value: config::Value) -> Result<TemplateRecord, ConfigError> {
+    let table = value.into_table()?;
+    let name = table
+        .get("name")
+        .cloned()
+        .ok_or_else(|| ConfigError::Message("DNS.records: an entry has no name".to_string()))?
+        .into_string()?;
+    let name = name.trim().to_string();
+    let get = |key: &str| {
+        table
+            .get(key)
+            .cloned()
+            .map(|value| value.into_string())
+            .transpose()
+    };
+    let dns_type = match get("type")? {
+        Some(dns_type) => dns_type
+            .parse()
+            .map_err(|e| ConfigError::Message(format!("DNS.records: {}: {}", name, e)))?,
+        None => DnsType::Txt,
+    };
+    let extended = EXTENDED_TYPES.contains(&dns_type);
+    if dns_type != DnsType::Txt && !(extended && cfg!(feature = "extended-records")) {
+        let reason = if extended {
+            format!(
+                "{} records need ddns-gandi built with the extended-records feature",
+                dns_type
+            )
+        } else {
+            "only TXT, SRV, TLSA and SSHFP records are rendered from a template".to_string()
+        };
+        return Err(ConfigError::Message(format!(
+            "DNS.records: {}: {}",
+            name, reason
+        )));
+    }
+    for key in table.keys() {
+        if !matches!(
+            key.as_str(),
+            "name" | "type" | "template" | "source" | "comment"
+        ) {
+            return Err(ConfigError::Message(format!(
+                "DNS.records: {}: {} does not apply to templates",
+                name, key
+            )));
+        }
+    }
+    Ok(TemplateRecord {
+        dns_type,
+        template: get("template")?.unwrap_or_default(),
+        source: get("source")?,
+        comment: get("comment")?.filter(|comment| !comment.trim().is_empty()),
+        name,
+    })
+}
+
+/// Reads the templates of `DNS.records`, the entries with a `template`.
+fn get_templates(config: &Config) -> Result<Vec<TemplateRecord>, ConfigError> {
+    match config.get_array("DNS.records") {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(is_template)
+            .map(parse_template_entry)
+            .collect(),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Parses an entry of a `DNS.records` array, either the name of a record or
+/// a table with its `name` and `source` or `sources`.
+fn parse_record_entry(value: config::Value) -> Result<RecordEntry, ConfigError> {
+    let table = match value.clone().into_table() {
+        Ok(table) => table,
+        Err(_) => return Ok(RecordEntry::named(value.into_string()?.trim().to_string())),
+    };
+    let name = table
+        .get("name")
+        .cloned()
+        .ok_or_else(|| ConfigError::Message("DNS.records: an entry has no name".to_string()))?
+        .into_string()?;
+    let source = table
+        .get("source")
+        .cloned()
+        .map(|source| source.into_string())
+        .transpose()?;
+    let ip_version = table
+        .get("ip_version")
+        .cloned()
+        .map(|version| version.try_deserialize())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("DNS.records: {}: {}", name.trim(), e)))?;
+    let sources = match table.get("sources").cloned() {
+        Some(sources) => sources
+            .into_array()?
+            .into_iter()
+            .map(|source| source.into_string())
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+    if source.is_some() && !sources.is_empty() {
+        return Err(ConfigError::Message(format!(
+            "DNS.records: {}: set either source or sources",
+            name.trim()
+        )));
+    }
+    let on_source_failure = table
+        .get("on_source_failure")
+        .cloned()
+        .map(|policy| policy.into_string())
+        .transpose()?
+        .map(|policy| policy.parse())
+        .transpose()
+        .map_err(|e: String| {
+            ConfigError::Message(format!(
+                "DNS.records: {}: on_source_failure: {}",
+                name.trim(),
+                e
+            ))
+        })?
+        .unwrap_or_default();
+    let comment = table
+        .get("comment")
+        .cloned()
+        .map(|comment| comment.into_string())
+        .transpose()?
+        .filter(|comment| !comment.trim().is_empty());
+    let allow_overwrite = table
+        .get("allow_overwrite")
+        .cloned()
+        .map(|allow| allow.into_bool())
+        .transpose()?
+        .unwrap_or(false);
+    if table.contains_key("type") {
+        return Err(ConfigError::Message(format!(
+            "DNS.records: {}: type is only set with a template, the A and AAAA records follow DNS.ip_version",
+            name.trim()
+        )));
+    }
+    let host_part = table
+        .get("host_part")
+        .cloned()
+        .map(|host| host.into_string())
+        .transpose()?
+        .map(|host| host.trim().parse::<Ipv6Addr>())
+        .transpose()
+        .map_err(|e| {
+            ConfigError::Message(format!("DNS.records: {}: host_part: {}", name.trim(), e))
+        })?;
+    Ok(RecordEntry {
+        name: name.trim().to_string(),
+        source,
+        sources,
+        on_source_failure,
+        ip_version,
+        comment,
+        allow_overwrite,
+        host_part,
+    })
+}
+
+/// The name of a record as the API expects it: lowercased, relative to
+/// `domain` if written as a fully qualified name such as
+/// `www.example.com.`, and `@` for the domain itself.
+pub fn normalize_record_name(name: &str, domain: &str) -> String {
+    let name = name.trim().to_ascii_lowercase();
+    let Some(fqdn) = name.strip_suffix('.') else {
+        return name;
+    };
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    if fqdn == domain {
+        return "@".to_string();
+    }
+    match fqdn
+        .strip_suffix(&domain)
+        .and_then(|label| label.strip_suffix('.'))
+    {
+        Some(label) if !label.is_empty() => label.to_string(),
+        _ => fqdn.to_string(),
+    }
+}
+
+/// Normalizes the name of `record`, warning if that changed it.
+fn normalize_record(record: &mut RecordEntry, domain: &str) {
+    let name = normalize_record_name(&record.name, domain);
+    if name != record.name {
+        warn!("DNS.records: using {} for {}", name, record.name);
+        record.name = name;
+    }
+}
+
+/// Drops the entries listing a record again with the same sources, so that
+/// its rrsets are only updated once per pass. The first entry is kept, and
+/// updated for both families if the dropped one was for another family.
+fn dedup_records(records: Vec<RecordEntry>, ip_version: IpVersionFilter) -> Vec<RecordEntry> {
+    let mut unique: Vec<RecordEntry> = Vec::new();
+    for record in records {
+        let Some(first) = unique.iter_mut().find(|other| {
+            other.name == record.name && other.source_label() == record.source_label()
+        }) else {
+            unique.push(record);
+            continue;
+        };
+        warn!(
+            "DNS.records: {} is listed more than once, it is only updated once",
+            record.name
+        );
+        let versions = first.ip_version.unwrap_or(ip_version);
+        if versions != IpVersionFilter::Both && versions != record.ip_version.unwrap_or(ip_version)
+        {
+            first.ip_version = Some(IpVersionFilter::Both);
+        }
+    }
+    unique
+}
+
+/// Reads `DNS.records`, either a string with one record per line (or
+/// separated by commas) or an array of entries.
+fn get_records(config: &Config) -> Result<Vec<RecordEntry>, ConfigError> {
+    let entries = match config.get_string("DNS.records") {
+        Ok(records) => records
+            .split(['\n', ','])
+            .map(|name| RecordEntry::named(name.trim().to_string()))
+            .collect(),
+        Err(ConfigError::NotFound(key)) => return Err(ConfigError::NotFound(key)),
+        Err(_) => config
+            .get_array("DNS.records")?
+            .into_iter()
+            .filter(|entry| !is_template(entry))
+            .map(parse_record_entry)
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    Ok(entries
+        .into_iter()
+        .filter(|record| !record.name.is_empty())
+        .collect())
+}
+
+/// A problem with a configuration that is well-formed but inconsistent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigValidationError {
+    /// The key at fault, e.g. `DNS.records`.
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// Lists everything wrong with `config`: a missing key or domain, records
+/// using undefined sources, and records listed with two sources, which
+/// would make them flap between the uplinks.
+pub fn validate_config(config: &DnsConfig) -> Vec<ConfigValidationError> {
+    let mut errors = Vec::new();
+    let mut error = |key, message| errors.push(ConfigValidationError { key, message });
+    if config.key.is_empty() {
+        error("GANDI.key", "the key is empty".to_string());
+    }
+    match &config.discovery {
+        None if config.domain.trim().is_empty() => {
+            error("DNS.domain", "the domain is empty".to_string());
+        }
+        Some(_) if !config.domain.is_empty() => {
+            error("DNS.domains", "conflicts with DNS.domain".to_string());
+        }
+        Some(_) if config.watchdog.is_some() => {
+            error(
+                "DNS.domains",
+                "the watchdog needs a single DNS.domain".to_string(),
+            );
+        }
+        Some(_) if config.notify.digest_interval.is_some() => {
+            error(
+                "DNS.domains",
+                "NOTIFY.digest_interval needs a single DNS.domain".to_string(),
+            );
+        }
+        _ => {}
+    }
+
+    let records = &config.records;
+    for (index, record) in records.iter().enumerate() {
+        for source in record.source.iter().chain(&record.sources) {
+            if !config.sources.contains_key(source) {
+                error(
+                    "DNS.records",
+                    format!(
+                        "{} uses the undefined detection source '{}'",
+                        record.name, source
+                    ),
+                );
+            }
+        }
+        if let Some(host_part) = record.host_part {
+            let prefix_len = match &record.source {
+                Some(source) => config.sources.get(source),
+                None => Some(&config.detection),
+            }
+            .and_then(|source| source.ipv6.prefix_len);
+            match prefix_len {
+                _ if record.is_multi_source() => error(
+                    "DNS.records",
+                    format!("{}: host_part cannot be combined with sources", record.name),
+                ),
+                None => error(
+                    "DNS.records",
+                    format!(
+                        "{}: host_part needs the prefix_len of the IPv6 detection of {}",
+                        record.name,
+                        record.source.as_deref().unwrap_or("DETECTION")
+                    ),
+                ),
+                Some(length) if u128::from(host_part) & prefix_mask(length) != 0 => error(
+                    "DNS.records",
+                    format!(
+                        "{}: host_part {} overlaps the /{} prefix",
+                        record.name, host_part, length
+                    ),
+                ),
+                Some(_) => {}
+            }
+        }
+        if let Some(other) = records[..index].iter().find(|other| {
+            other.name == record.name && other.source_label() != record.source_label()
+        }) {
+            error(
+                "DNS.records",
+                format!(
+                    "{} is listed with the sources {} and {}",
+                    record.name,
+                    other.source_label().as_deref().unwrap_or("(default)"),
+                    record.source_label().as_deref().unwrap_or("(default)")
+                ),
+            );
+        }
+    }
+    for template in &config.templates {
+        if let Some(source) = &template.source {
+            if !config.sources.contains_key(source) {
+                error(
+                    "DNS.records",
+                    format!(
+                        "{} uses the undefined detection source '{}'",
+                        template.name, source
+                    ),
+                );
+            }
+        }
+        let placeholders = match template.placeholders() {
+            Ok(placeholders) => placeholders,
+            Err(e) => {
+                error("DNS.records", format!("{}: template: {}", template.name, e));
+                continue;
+            }
+        };
+        #[cfg(feature = "extended-records")]
+        if template.dns_type != DnsType::Txt {
+            // The addresses cannot make a value invalid, so any will do.
+            let addresses = Addresses {
+                ipv4: Some("192.0.2.1"),
+                ipv6: Some("2001:db8::1"),
+            };
+            let text = template
+                .render(addresses, OffsetDateTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            if let Err(e) = check_value(template.dns_type, &text) {
+                error("DNS.records", format!("{}: {}", template.name, e));
+            }
+        }
+        for (placeholder, version) in [
+            (Placeholder::Ipv4, IpVersion::V4),
+            (Placeholder::Ipv6, IpVersion::V6),
+        ] {
+            if placeholders.contains(&placeholder) && !config.ip_version.allows(version) {
+                error(
+                    "DNS.records",
+                    format!(
+                        "{}: the template embeds the IP{} address, which DNS.ip_version disables",
+                        template.name,
+                        version.as_str()
+                    ),
+                );
+            }
+        }
+    }
+    errors
+}
+
+/// Reads a secret mounted as a file holding nothing but the secret, as
+/// Kubernetes and Docker do, ignoring surrounding whitespace.
+fn read_secret_file(path: &Path) -> Result<String, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    let secret = contents.trim();
+    if secret.is_empty() {
+        return Err(format!("{} is empty", path.display()));
+    }
+    Ok(secret.to_string())
+}
+
+/// Rejects the configuration file if it has keys that are not read, all
+/// of them being reported at once. With `lax`, they are only warned about,
+/// e.g. for a file written for a newer version.
+fn check_unknown_keys(origin: &str, contents: &str, lax: bool) -> Result<(), ConfigError> {
+    let unknown = schema::unknown_keys(contents);
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    if lax {
+        for key in unknown {
+            warn!("{}: ignoring {}", origin, key);
+        }
+        return Ok(());
+    }
+
+    let mut message = format!("{} has unknown keys:", origin);
+    for key in unknown {
+        message.push_str(&format!("\n\t{}", key));
+    }
+    message.push_str("\n(use --lax-config to ignore them)");
+    Err(ConfigError::Message(message))
+}
+
+/// Reads a configuration document from `origin`, decrypting it if it was
+/// encrypted with age.
+fn load_contents(origin: &str, contents: &[u8], lax: bool) -> Result<Config, ConfigError> {
+    let contents = if encryption::is_encrypted(contents) {
+        encryption::decrypt(contents)
+            .map_err(|e| ConfigError::Message(format!("unable to decrypt {}: {}", origin, e)))?
+    } else {
+        String::from_utf8(contents.to_vec())
+            .map_err(|e| ConfigError::Message(format!("{}: {}", origin, e)))?
+    };
+    check_unknown_keys(origin, &contents, lax)?;
+    let contents = rename_aliases(contents)?;
+    let builder = Config::builder().add_source(File::from_str(&contents, FileFormat::Toml));
+    add_environment(builder)?.build()
+}
+
+/// Renames the sections given under another of their names, leaving
+/// syntax errors to the configuration reader.
+fn rename_aliases(contents: String) -> Result<String, ConfigError> {
+    let Ok(mut root) = contents.parse::<toml::Table>() else {
+        return Ok(contents);
+    };
+    let mut renamed = false;
+    for (alias, section) in SECTION_ALIASES {
+        if let Some(value) = root.remove(*alias) {
+            if root.contains_key(*section) {
+                return Err(ConfigError::Message(format!(
+                    "[{}] is another name of [{}], which is set too",
+                    alias, section
+                )));
+            }
+            root.insert(section.to_string(), value);
+            renamed = true;
+        }
+    }
+    if !renamed {
+        return Ok(contents);
+    }
+    toml::to_string(&root).map_err(|e| ConfigError::Message(e.to_string()))
+}
+
+fn load(path: &Path, lax: bool) -> Result<Config, ConfigError> {
+    match fs::read(path) {
+        Ok(contents) => load_contents(&path.display().to_string(), &contents, lax),
+        // Left to the configuration reader.
+        Err(_) => {
+            let required = path != Path::new(DEFAULT_CONFIG_PATH);
+            let file = File::from(path).format(FileFormat::Toml).required(required);
+            add_environment(Config::builder().add_source(file))?.build()
+        }
+    }
+}
+
+/// Reads where the key is kept in the OS secret store, for `set-key`.
+pub fn read_keyring_entry(path: &Path, lax: bool) -> Result<KeyringEntry, ConfigError> {
+    get_keyring_entry(&load(path, lax)?)
+}
+
+/// Reads the configuration file, decrypting it if it was encrypted with
+/// age, and the environment. The default configuration file may be
+/// missing, so that the client can be configured through the environment
+/// alone.
+pub fn read_config(path: &Path, lax: bool) -> Result<DnsConfig, ConfigError> {
+    from_config(load(path, lax)?)
+}
+
+/// Reads the configuration from a `file://`, `http(s)://` or
+/// `consul://kv/<key>` URL, and the environment. `timeout` bounds the
+/// fetch.
+pub async fn read_config_from_url(
+    url: &str,
+    headers: Option<&HeaderMap>,
+    lax: bool,
+    timeout: Duration,
+) -> Result<DnsConfig, DdnsError> {
+    let mut remote = RemoteConfig::new(url, headers, timeout)?;
+    let contents = remote.fetch().await?.unwrap_or_default();
+    load_contents(url, &contents, lax)
+        .and_then(from_config)
+        .map_err(|e| DdnsError::Config(e.to_string()))
+}
+
+fn from_config(config: Config) -> Result<DnsConfig, ConfigError> {
+    let (key, key_file, keyring) = get_key(&config)?;
+    let sharing_id = get_optional_string(&config, "GANDI.sharing_id")?;
+    let auth_method = match get_optional_string(&config, "GANDI.auth_method")? {
+        Some(method) => Some(method),
+        None => get_optional_string(&config, "GANDI.auth_scheme")?,
+    };
+    let auth_method = auth_method
+        .map(|method| method.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("GANDI.auth_method: {}", e)))?;
+    let key_expires = get_optional_string(&config, "GANDI.key_expires")?
+        .map(|date| Date::parse(date.trim(), DATE_FORMAT))
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("GANDI.key_expires: {}", e)))?;
+    let expiry_warning_days = match config.get_int("GANDI.expiry_warning_days") {
+        Ok(days) => u32::try_from(days).map_err(|_| {
+            ConfigError::Message(format!("GANDI.expiry_warning_days: invalid value {}", days))
+        })?,
+        Err(ConfigError::NotFound(_)) => DEFAULT_EXPIRY_WARNING_DAYS,
+        Err(e) => return Err(keyed(e, "GANDI.expiry_warning_days")),
+    };
+    let discovery = get_discovery(&config)?;
+    let domain = match discovery {
+        Some(_) => get_optional_string(&config, "DNS.domain")?.unwrap_or_default(),
+        None => config.get_string("DNS.domain")?,
+    };
+    let records_files = get_string_list(&config, "DNS.records_file")?;
+    let mut records = match get_records(&config) {
+        Err(ConfigError::NotFound(_)) if !records_files.is_empty() => Vec::new(),
+        records => records?,
+    };
+    for record in &mut records {
+        normalize_record(record, &domain);
+    }
+    for name in records_file::read(&records_files)
+        .map_err(|e| ConfigError::Message(format!("DNS.records_file: {}", e)))?
+    {
+        let mut record = RecordEntry::named(name);
+        normalize_record(&mut record, &domain);
+        if !records.iter().any(|other| other.name == record.name) {
+            records.push(record);
+        }
+    }
+    let ip_version: Option<IpVersionFilter> = get_optional_string(&config, "DNS.ip_version")?
+        .map(|version| version.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("DNS.ip_version: {}", e)))?;
+    let update_types = get_string_list(&config, "DNS.update_types")?
+        .iter()
+        .map(|dns_type| parse_address_type(dns_type))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ConfigError::Message(format!("DNS.update_types: {}", e)))?;
+    let ip_version = match (ip_version, update_types.is_empty()) {
+        (version, true) => version.unwrap_or_default(),
+        (version, false) => {
+            let types = IpVersionFilter::of_types(&update_types)
+                .map_err(|e| ConfigError::Message(format!("DNS.update_types: {}", e)))?;
+            if version.is_some_and(|version| version != types) {
+                return Err(ConfigError::Message(
+                    "DNS.update_types: contradicts DNS.ip_version".to_string(),
+                ));
+            }
+            types
+        }
+    };
+    let records = dedup_records(records, ip_version);
+    let mut templates = get_templates(&config)?;
+    for template in &mut templates {
+        let name = normalize_record_name(&template.name, &domain);
+        if name != template.name {
+            warn!("DNS.records: using {} for {}", name, template.name);
+            template.name = name;
+        }
+    }
+    let remove: Vec<String> = get_string_list(&config, "DNS.remove")?
+        .iter()
+        .map(|pattern| normalize_record_name(pattern, &domain))
+        .collect();
+    if let Some(record) = records.iter().find(|record| {
+        remove
+            .iter()
+            .any(|p| records_file::name_matches(p, &record.name))
+    }) {
+        return Err(ConfigError::Message(format!(
+            "DNS.remove: {} is also in DNS.records",
+            record.name
+        )));
+    }
+    let confirm_deletes = get_optional_bool(&config, "DNS.confirm_deletes")?.unwrap_or(true);
+    let heartbeat_record = get_optional_string(&config, "DNS.heartbeat_record")?
+        .map(|name| normalize_record_name(&name, &domain));
+    let max_run_duration = get_optional_duration(&config, "RUN.max_run_duration")?;
+    let timeouts = Timeouts {
+        connect: get_optional_duration(&config, "HTTP.connect_timeout")?.filter(|t| !t.is_zero()),
+        request: get_optional_duration(&config, "HTTP.request_timeout")?
+            .filter(|t| !t.is_zero())
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+        ip_detection: get_optional_duration(&config, "HTTP.ip_detection_timeout")?
+            .filter(|t| !t.is_zero()),
+        api_call: get_optional_duration(&config, "HTTP.api_call_timeout")?.filter(|t| !t.is_zero()),
+    };
+    let ca_certs = get_string_list(&config, "HTTP.ca_cert")?
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let accept_invalid_certs =
+        get_optional_bool(&config, "HTTP.danger_accept_invalid_certs")?.unwrap_or(false);
+    let mut tls = TlsConfig::load(ca_certs, accept_invalid_certs)
+        .map_err(|e| ConfigError::Message(format!("HTTP.ca_cert: {}", e)))?;
+    get_string_list(&config, "HTTP.gandi_spki_pins")?
+        .iter()
+        .map(|pin| SpkiPin::parse(pin))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|pins| tls.pin_gandi(pins))
+        .map_err(|e| ConfigError::Message(format!("HTTP.gandi_spki_pins: {}", e)))?;
+    let gandi_request_interval =
+        get_optional_duration(&config, "HTTP.gandi_request_interval")?.filter(|i| !i.is_zero());
+    let max_concurrent_requests = match config.get_int("HTTP.max_concurrent_requests") {
+        Ok(max) => Some(
+            usize::try_from(max)
+                .ok()
+                .filter(|max| *max > 0)
+                .ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "HTTP.max_concurrent_requests: invalid value {}",
+                        max
+                    ))
+                })?,
+        ),
+        Err(ConfigError::NotFound(_)) => None,
+        Err(e) => return Err(keyed(e, "HTTP.max_concurrent_requests")),
+    };
+
+    let quota_warning_threshold = match config.get_int("HTTP.quota_warning_threshold") {
+        Ok(threshold) => Some(u64::try_from(threshold).map_err(|_| {
+            ConfigError::Message(format!(
+                "HTTP.quota_warning_threshold: invalid value {}",
+                threshold
+            ))
+        })?),
+        Err(ConfigError::NotFound(_)) => None,
+        Err(e) => return Err(keyed(e, "HTTP.quota_warning_threshold")),
+    };
+    let rate_limit_header_prefix = get_optional_string(&config, "HTTP.rate_limit_header_prefix")?
+        .map(|prefix| prefix.trim().to_string())
+        .filter(|prefix| !prefix.is_empty());
+    let state_dir = get_optional_string(&config, "STATE.dir")?.map(PathBuf::from);
+    let metrics_file = get_optional_string(&config, "STATE.metrics_file")?.map(PathBuf::from);
+    let retry_queue_depth = match config.get_int("RETRY.queue_depth") {
+        Ok(depth) => usize::try_from(depth).map_err(|_| {
+            ConfigError::Message(format!("RETRY.queue_depth: invalid depth {}", depth))
+        })?,
+        Err(ConfigError::NotFound(_)) => DEFAULT_QUEUE_DEPTH,
+        Err(e) => return Err(keyed(e, "RETRY.queue_depth")),
+    };
+    let use_batch = get_optional_bool(&config, "UPDATE.use_batch")?.unwrap_or(false);
+    let on_drift = get_optional_string(&config, "UPDATE.on_drift")?
+        .map(|policy| policy.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("UPDATE.on_drift: {}", e)))?
+        .unwrap_or_default();
+    let change_debounce = match config.get_int("UPDATE.change_debounce_s") {
+        Ok(secs) => Some(Duration::from_secs(u64::try_from(secs).map_err(|_| {
+            ConfigError::Message(format!("UPDATE.change_debounce_s: invalid value {}", secs))
+        })?)),
+        Err(ConfigError::NotFound(_)) => None,
+        Err(e) => return Err(keyed(e, "UPDATE.change_debounce_s")),
+    };
+    let precheck = get_optional_string(&config, "UPDATE.precheck")?
+        .map(|mode| mode.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("UPDATE.precheck: {}", e)))?
+        .unwrap_or_default();
+    let precheck_resolver = get_optional_string(&config, "UPDATE.precheck_resolver")?
+        .map(|resolver| resolver.parse())
+        .transpose()
+        .map_err(|e| ConfigError::Message(format!("UPDATE.precheck_resolver: {}", e)))?;
+    let adaptive_ttl = get_adaptive_ttl(&config)?;
+    let daemon_interval =
+        get_optional_duration(&config, "DAEMON.interval")?.unwrap_or(DEFAULT_DAEMON_INTERVAL);
+    let config_poll_interval = get_optional_duration(&config, "DAEMON.config_poll_interval")?
+        .map_or(Some(DEFAULT_CONFIG_POLL_INTERVAL), |i| {
+            Some(i).filter(|i| !i.is_zero())
+        });
+    let control_socket = get_optional_string(&config, "DAEMON.control_socket")?.map(PathBuf::from);
+    let pause_file = get_optional_string(&config, "DAEMON.pause_file")?.map(PathBuf::from);
+    let healthy_while_paused =
+        get_optional_bool(&config, "DAEMON.healthy_while_paused")?.unwrap_or(true);
+
+    let guard = GuardConfig {
+        gateway_mac: get_optional_string(&config, "GUARD.gateway_mac")?,
+        prefixes: get_string_list(&config, "GUARD.prefixes")?
+            .iter()
+            .map(|prefix| {
+                prefix.parse::<IpNet>().map_err(|e| {
+                    ConfigError::Message(format!("GUARD.prefixes: '{}': {}", prefix, e))
+                })
+            })
+            .collect::<Result<_, _>>()?,
+        ssids: get_string_list(&config, "GUARD.ssids")?,
+    };
+
+    let (mut ntfy, mut gotify) = (get_ntfy(&config)?, get_gotify(&config)?);
+    match get_push(&config)? {
+        Some(Push::Ntfy(_)) if ntfy.is_some() => {
+            return Err(ConfigError::Message(
+                "NOTIFY.push: NOTIFY.ntfy is set too".to_string(),
+            ))
+        }
+        Some(Push::Gotify(_)) if gotify.is_some() => {
+            return Err(ConfigError::Message(
+                "NOTIFY.push: NOTIFY.gotify is set too".to_string(),
+            ))
+        }
+        Some(Push::Ntfy(push)) => ntfy = Some(push),
+        Some(Push::Gotify(push)) => gotify = Some(push),
+        None => {}
+    }
+    let notify = NotifyConfig {
+        webhook: get_webhook(&config)?,
+        ntfy,
+        gotify,
+        telegram: get_telegram(&config)?,
+        discord: get_discord(&config)?,
+        matrix: get_matrix(&config)?,
+        apprise: get_apprise(&config)?,
+        desktop: get_desktop(&config)?,
+        digest_interval: get_optional_duration(&config, "NOTIFY.digest_interval")?
+            .filter(|i| !i.is_zero()),
+        throttle_interval: get_optional_duration(&config, "NOTIFY.throttle_interval")?
+            .filter(|i| !i.is_zero()),
+        templates: get_message_templates(&config)?,
+    };
+
+    let watchdog = get_watchdog(&config)?;
+    let telemetry = get_telemetry(&config)?;
+    let detection = get_detection(&config, "DETECTION")?;
+    let sources = get_sources(&config)?;
+
+    let mut builder = DnsConfigBuilder::new(key, domain)
+        .key_file(key_file)
+        .keyring(keyring)
+        .sharing_id(sharing_id)
+        .auth_method(auth_method)
+        .key_expires(key_expires, expiry_warning_days)
+        .records_files(records_files)
+        .remove(remove, confirm_deletes)
+        .ip_version(ip_version)
+        .max_run_duration(max_run_duration)
+        .request_limits(gandi_request_interval, max_concurrent_requests)
+        .quota_watch(rate_limit_header_prefix, quota_warning_threshold)
+        .timeouts(timeouts)
+        .tls(tls)
+        .retry_queue_depth(retry_queue_depth)
+        .use_batch(use_batch)
+        .on_drift(on_drift)
+        .change_debounce(change_debounce)
+        .precheck(precheck, precheck_resolver)
+        .ttl(adaptive_ttl)
+        .daemon_interval(daemon_interval)
+        .config_poll_interval(config_poll_interval)
+        .healthy_while_paused(healthy_while_paused)
+        .guard(guard)
+        .notify(notify)
+        .watchdog(watchdog)
+        .telemetry(telemetry)
+        .discovery(discovery)
+        .heartbeat_record(heartbeat_record)
+        .metrics_file(metrics_file)
+        .detection(detection);
+    for template in templates {
+        builder = builder.add_template(template);
+    }
+    for record in records {
+        builder = builder.add_record(record);
+    }
+    for (name, source) in sources {
+        builder = builder.add_source(name, source);
+    }
+    if let Some(dir) = state_dir {
+        builder = builder.state_dir(dir);
+    }
+    if get_optional_bool(&config, "DNS.include_apex")?.unwrap_or(false) {
+        builder = builder.with_apex();
+    }
+    if get_optional_bool(&config, "DNS.exclude_apex")?.unwrap_or(false) {
+        builder = builder.without_apex();
+    }
+    if let Some(path) = control_socket {
+        builder = builder.control_socket(path);
+    }
+    if let Some(path) = pause_file {
+        builder = builder.pause_file(path);
+    }
+    builder.build().map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        ConfigError::Message(errors.join("; "))
+    })
+}
+
+/// Renders the effective configuration as a TOML document that can be used
+/// as a config file again, with the API key replaced by `****`.
+pub fn dump_config(config: &DnsConfig) -> Result<String, toml::ser::Error> {
+    let mut gandi = toml::Table::new();
+    match (&config.keyring, &config.key_file) {
+        (Some(entry), _) => {
+            gandi.insert("key_source".into(), "keyring".into());
+            gandi.insert("keyring_service".into(), entry.service.clone().into());
+            gandi.insert("keyring_account".into(), entry.account.clone().into())
+        }
+        (None, Some(path)) => gandi.insert(
+            "key_file".into(),
+            path.to_string_lossy().into_owned().into(),
+        ),
+        (None, None) => gandi.insert("key".into(), "****".into()),
+    };
+    if let Some(sharing_id) = &config.sharing_id {
+        gandi.insert("sharing_id".into(), sharing_id.clone().into());
+    }
+    if let Some(method) = config.auth_method {
+        gandi.insert("auth_method".into(), method.as_str().into());
+    }
+    if let Some(expires) = config.key_expires {
+        gandi.insert("key_expires".into(), expires.to_string().into());
+    }
+    if config.expiry_warning_days != DEFAULT_EXPIRY_WARNING_DAYS {
+        gandi.insert(
+            "expiry_warning_days".into(),
+            i64::from(config.expiry_warning_days).into(),
+        );
+    }
+
+    let mut dns = toml::Table::new();
+    if let Some(discovery) = &config.discovery {
+        let mut domains = toml::Table::new();
+        domains.insert("discover".into(), true.into());
+        if !discovery.include.is_empty() {
+            domains.insert("include".into(), discovery.include.clone().into());
+        }
+        if !discovery.exclude.is_empty() {
+            domains.insert("exclude".into(), discovery.exclude.clone().into());
+        }
+        dns.insert("domains".into(), domains.into());
+    } else {
+        dns.insert("domain".into(), config.domain.clone().into());
+    }
+    if config.records.iter().all(RecordEntry::is_plain) && config.templates.is_empty() {
+        let names: Vec<&str> = config.records.iter().map(|r| r.name.as_str()).collect();
+        dns.insert("records".into(), names.join("\n").into());
+    } else {
+        let entries: Vec<toml::Value> = config
+            .records
+            .iter()
+            .map(|record| {
+                if record.is_plain() {
+                    return record.name.clone().into();
+                }
+                let mut entry = toml::Table::new();
+                entry.insert("name".into(), record.name.clone().into());
+                if let Some(source) = &record.source {
+                    entry.insert("source".into(), source.clone().into());
+                }
+                if record.is_multi_source() {
+                    entry.insert("sources".into(), record.sources.clone().into());
+                    entry.insert(
+                        "on_source_failure".into(),
+                        record.on_source_failure.as_str().into(),
+                    );
+                }
+                if let Some(version) = record.ip_version {
+                    entry.insert("ip_version".into(), version.as_str().into());
+                }
+                if let Some(comment) = &record.comment {
+                    entry.insert("comment".into(), comment.clone().into());
+                }
+                if record.allow_overwrite {
+                    entry.insert("allow_overwrite".into(), true.into());
+                }
+                if let Some(host_part) = record.host_part {
+                    entry.insert("host_part".into(), host_part.to_string().into());
+                }
+                entry.into()
+            })
+            .chain(config.templates.iter().map(|template| {
+                let mut entry = toml::Table::new();
+                entry.insert("name".into(), template.name.clone().into());
+                entry.insert("type".into(), template.dns_type.as_str().into());
+                entry.insert("template".into(), template.template.clone().into());
+                if let Some(source) = &template.source {
+                    entry.insert("source".into(), source.clone().into());
+                }
+                if let Some(comment) = &template.comment {
+                    entry.insert("comment".into(), comment.clone().into());
+                }
+                entry.into()
+            }))
+            .collect();
+        dns.insert("records".into(), entries.into());
+    }
+    if !config.records_files.is_empty() {
+        dns.insert("records_file".into(), config.records_files.clone().into());
+    }
+    if config.ip_version != IpVersionFilter::Both {
+        dns.insert("ip_version".into(), config.ip_version.as_str().into());
+    }
+    if !config.remove.is_empty() {
+        dns.insert("remove".into(), config.remove.clone().into());
+    }
+    if !config.confirm_deletes {
+        dns.insert("confirm_deletes".into(), false.into());
+    }
+    if let Some(record) = &config.heartbeat_record {
+        dns.insert("heartbeat_record".into(), record.clone().into());
+    }
+
+    let mut root = toml::Table::new();
+    root.insert("GANDI".into(), gandi.into());
+    root.insert("DNS".into(), dns.into());
+
+    let mut run = toml::Table::new();
+    if let Some(limit) = config.max_run_duration {
+        run.insert("max_run_duration".into(), format_duration(limit).into());
+    }
+    if !run.is_empty() {
+        root.insert("RUN".into(), run.into());
+    }
+
+    let mut http = toml::Table::new();
+    if let Some(interval) = config.gandi_request_interval {
+        http.insert(
+            "gandi_request_interval".into(),
+            format_duration(interval).into(),
+        );
+    }
+    if let Some(max) = config.max_concurrent_requests {
+        http.insert("max_concurrent_requests".into(), (max as i64).into());
+    }
+    if let Some(threshold) = config.quota_warning_threshold {
+        http.insert(
+            "quota_warning_threshold".into(),
+            i64::try_from(threshold).unwrap_or(i64::MAX).into(),
+        );
+    }
+    if let Some(prefix) = &config.rate_limit_header_prefix {
+        http.insert("rate_limit_header_prefix".into(), prefix.clone().into());
+    }
+    let timeouts = [
+        ("connect_timeout", config.timeouts.connect),
+        ("ip_detection_timeout", config.timeouts.ip_detection),
+        ("api_call_timeout", config.timeouts.api_call),
+    ];
+    for (key, timeout) in timeouts {
+        if let Some(timeout) = timeout {
+            http.insert(key.into(), format_duration(timeout).into());
+        }
+    }
+    if config.timeouts.request != DEFAULT_REQUEST_TIMEOUT {
+        http.insert(
+            "request_timeout".into(),
+            format_duration(config.timeouts.request).into(),
+        );
+    }
+    if !config.tls.ca_certs.is_empty() {
+        let paths: Vec<String> = config
+            .tls
+            .ca_certs
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        http.insert("ca_cert".into(), paths.into());
+    }
+    if config.tls.accept_invalid_certs {
+        http.insert("danger_accept_invalid_certs".into(), true.into());
+    }
+    if !config.tls.gandi_pins.is_empty() {
+        let pins: Vec<String> = config
+            .tls
+            .gandi_pins
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        http.insert("gandi_spki_pins".into(), pins.into());
+    }
+    if !http.is_empty() {
+        root.insert("HTTP".into(), http.into());
+    }
+
+    let mut state = toml::Table::new();
+    state.insert(
+        "dir".into(),
+        config.state_dir.to_string_lossy().into_owned().into(),
+    );
+    if let Some(path) = &config.metrics_file {
+        state.insert(
+            "metrics_file".into(),
+            path.to_string_lossy().into_owned().into(),
+        );
+    }
+    root.insert("STATE".into(), state.into());
+
+    let mut retry = toml::Table::new();
+    retry.insert(
+        "queue_depth".into(),
+        (config.retry_queue_depth as i64).into(),
+    );
+    root.insert("RETRY".into(), retry.into());
+
+    let mut update = toml::Table::new();
+    update.insert("use_batch".into(), config.use_batch.into());
+    update.insert("on_drift".into(), config.on_drift.as_str().into());
+    if let Some(debounce) = config.change_debounce {
+        update.insert(
+            "change_debounce_s".into(),
+            (debounce.as_secs() as i64).into(),
+        );
+    }
+    update.insert("precheck".into(), config.precheck.as_str().into());
+    if let Some(resolver) = config.precheck_resolver {
+        update.insert("precheck_resolver".into(), resolver.to_string().into());
+    }
+    if let Some(policy) = config.adaptive_ttl {
+        update.insert("ttl_min".into(), i64::from(policy.min).into());
+        update.insert("ttl_max".into(), i64::from(policy.max).into());
+        update.insert("ttl_window".into(), format_duration(policy.window).into());
+    }
+    root.insert("UPDATE".into(), update.into());
+
+    let mut daemon = toml::Table::new();
+    daemon.insert(
+        "interval".into(),
+        format_duration(config.daemon_interval).into(),
+    );
+    daemon.insert(
+        "config_poll_interval".into(),
+        config
+            .config_poll_interval
+            .map_or_else(|| "0s".to_string(), format_duration)
+            .into(),
+    );
+    daemon.insert(
+        "control_socket".into(),
+        config.control_socket.to_string_lossy().into_owned().into(),
+    );
+    daemon.insert(
+        "pause_file".into(),
+        config.pause_file.to_string_lossy().into_owned().into(),
+    );
+    if !config.healthy_while_paused {
+        daemon.insert("healthy_while_paused".into(), false.into());
+    }
+    root.insert("DAEMON".into(), daemon.into());
+
+    let mut guard = toml::Table::new();
+    if let Some(mac) = &config.guard.gateway_mac {
+        guard.insert("gateway_mac".into(), mac.clone().into());
+    }
+    if !config.guard.prefixes.is_empty() {
+        let prefixes: Vec<String> = config
+            .guard
+            .prefixes
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        guard.insert("prefixes".into(), prefixes.into());
+    }
+    if !config.guard.ssids.is_empty() {
+        guard.insert("ssids".into(), config.guard.ssids.clone().into());
+    }
+    if !guard.is_empty() {
+        root.insert("GUARD".into(), guard.into());
+    }
+
+    let mut notify = toml::Table::new();
+    if let Some(webhook) = &config.notify.webhook {
+        notify.insert("webhook_url".into(), "****".into());
+        if !webhook.events.on_change {
+            notify.insert("webhook_on_change".into(), false.into());
+        }
+        if !webhook.events.on_failure {
+            notify.insert("webhook_on_failure".into(), false.into());
+        }
+    }
+    if let Some(ntfy) = &config.notify.ntfy {
+        let mut table = toml::Table::new();
+        table.insert("server".into(), ntfy.server.clone().into());
+        table.insert("topic".into(), ntfy.topic.clone().into());
+        if ntfy.token.is_some() {
+            table.insert("token".into(), "****".into());
+        }
+        if ntfy.priority != NtfyPriority::DEFAULT {
+            table.insert("priority".into(), ntfy.priority.as_str().into());
+        }
+        if !ntfy.events.on_change {
+            table.insert("on_change".into(), false.into());
+        }
+        if !ntfy.events.on_failure {
+            table.insert("on_failure".into(), false.into());
+        }
+        notify.insert("ntfy".into(), table.into());
+    }
+    if let Some(gotify) = &config.notify.gotify {
+        let mut table = toml::Table::new();
+        table.insert("server".into(), gotify.server.clone().into());
+        table.insert("token".into(), "****".into());
+        if gotify.priority != DEFAULT_GOTIFY_PRIORITY {
+            table.insert("priority".into(), i64::from(gotify.priority).into());
+        }
+        if !gotify.events.on_change {
+            table.insert("on_change".into(), false.into());
+        }
+        if !gotify.events.on_failure {
+            table.insert("on_failure".into(), false.into());
+        }
+        notify.insert("gotify".into(), table.into());
+    }
+    if let Some(telegram) = &config.notify.telegram {
+        let mut table = toml::Table::new();
+        table.insert("bot_token".into(), "****".into());
+        table.insert("chat_id".into(), telegram.chat_ids.clone().into());
+        if !telegram.events.on_change {
+            table.insert("on_change".into(), false.into());
+        }
+        if !telegram.events.on_failure {
+            table.insert("on_failure".into(), false.into());
+        }
+        notify.insert("telegram".into(), table.into());
+    }
+    if let Some(discord) = &config.notify.discord {
+        let mut table = toml::Table::new();
+        table.insert("webhook_url".into(), "****".into());
+        if let Some(username) = &discord.username {
+            table.insert("username".into(), username.clone().into());
+        }
+        if let Some(avatar_url) = &discord.avatar_url {
+            table.insert("avatar_url".into(), avatar_url.clone().into());
+        }
+        if !discord.events.on_change {
+            table.insert("on_change".into(), false.into());
+        }
+        if !discord.events.on_failure {
+            table.insert("on_failure".into(), false.into());
+        }
+        notify.insert("discord".into(), table.into());
+    }
+    if let Some(matrix) = &config.notify.matrix {
+        let mut table = toml::Table::new();
+        table.insert(
+            "homeserver_url".into(),
+            matrix.homeserver_url.clone().into(),
+        );
+        table.insert("access_token".into(), "****".into());
+        table.insert("room_id".into(), matrix.room_id.clone().into());
+        if !matrix.events.on_change {
+            table.insert("on_change".into(), false.into());
+        }
+        if !matrix.events.on_failure {
+            table.insert("on_failure".into(), false.into());
+        }
+        notify.insert("matrix".into(), table.into());
+    }
+    if let Some(apprise) = &config.notify.apprise {
+        let mut table = toml::Table::new();
+        table.insert("urls".into(), vec!["****"; apprise.urls.len()].into());
+        if let Some(apprise_url) = &apprise.apprise_url {
+            table.insert("apprise_url".into(), apprise_url.clone().into());
+        }
+        if !apprise.events.on_change {
+            table.insert("on_change".into(), false.into());
+        }
+        if !apprise.events.on_failure {
+            table.insert("on_failure".into(), false.into());
+        }
+        notify.insert("apprise".into(), table.into());
+    }
+    if config.notify.desktop {
+        notify.insert("desktop".into(), true.into());
+    }
+    if let Some(interval) = config.notify.digest_interval {
+        notify.insert("digest_interval".into(), format_duration(interval).into());
+    }
+    if let Some(interval) = config.notify.throttle_interval {
+        notify.insert("throttle_interval".into(), format_duration(interval).into());
+    }
+    let mut templates = toml::Table::new();
+    for (name, source) in config.notify.templates.sources() {
+        let (kind, part) = name.split_once('.').unwrap_or((name, ""));
+        if let toml::Value::Table(parts) = templates
+            .entry(kind)
+            .or_insert_with(|| toml::Table::new().into())
+        {
+            parts.insert(part.into(), source.clone().into());
+        }
+    }
+    if !templates.is_empty() {
+        notify.insert("templates".into(), templates.into());
+    }
+    if !notify.is_empty() {
+        root.insert("NOTIFY".into(), notify.into());
+    }
+
+    if let Some(watchdog) = config.watchdog {
+        let mut section = toml::Table::new();
+        section.insert("interval".into(), format_duration(watchdog.interval).into());
+        section.insert("grace".into(), format_duration(watchdog.grace).into());
+        section.insert("resolver".into(), watchdog.resolver.to_string().into());
+        section.insert("reassert".into(), watchdog.reassert.into());
+        root.insert("WATCHDOG".into(), section.into());
+    }
+    if let Some(telemetry) = &config.telemetry {
+        let mut section = toml::Table::new();
+        section.insert(
+            "otlp_endpoint".into(),
+            telemetry.otlp_endpoint.clone().into(),
+        );
+        root.insert("TELEMETRY".into(), section.into());
+    }
+
+    let mut detection = toml::Table::new();
+    insert_detection(&mut detection, &config.detection);
+    for (name, source) in &config.sources {
+        let mut section = toml::Table::new();
+        insert_detection(&mut section, source);
+        detection.insert(name.clone(), section.into());
+    }
+    if !detection.is_empty() {
+        root.insert("DETECTION".into(), detection.into());
+    }
+
+    toml::to_string(&root)
+}
+
+fn insert_detection(table: &mut toml::Table, source: &DetectionSource) {
+    for (name, family) in [("ipv4", &source.ipv4), ("ipv6", &source.ipv6)] {
+        let mut section = toml::Table::new();
+        if !family.providers.is_empty() {
+            section.insert("providers".into(), family.providers.clone().into());
+        }
+        if family.method != DetectionMethod::Auto {
+            section.insert("method".into(), family.method.as_str().into());
+        }
+        if let Some(timeout) = family.timeout {
+            section.insert("timeout".into(), format_duration(timeout).into());
+        }
+        if let Some(address) = family.binding.address {
+            section.insert("bind_address".into(), address.to_string().into());
+        }
+        if let Some(interface) = &family.binding.interface {
+            section.insert("bind_interface".into(), interface.clone().into());
+        }
+        if !family.headers.is_empty() {
+            let headers: toml::Table = family
+                .headers
+                .keys()
+                .map(|name| (name.to_string(), "****".into()))
+                .collect();
+            section.insert("headers".into(), headers.into());
+        }
+        if let Some(length) = family.prefix_len {
+            section.insert("prefix_len".into(), i64::from(length).into());
+        }
+        match &family.delegated_prefix {
+            Some(PrefixSource::LeaseFile(path)) => {
+                section.insert(
+                    "prefix_lease_file".into(),
+                    path.to_string_lossy().into_owned().into(),
+                );
+            }
+            Some(PrefixSource::Interface(name)) => {
+                section.insert("prefix_interface".into(), name.clone().into());
+            }
+            None => {}
+        }
+        if !section.is_empty() {
+            table.insert(name.into(), section.into());
+        }
+    }
+    if let Some(router) = &source.fritzbox {
+        table.insert("fritzbox_address".into(), router.address.clone().into());
+        if let Some(username) = &router.username {
+            table.insert("fritzbox_username".into(), username.clone().into());
+            table.insert("fritzbox_password".into(), "****".into());
+        }
+        if let Some(suffix) = router.ipv6_suffix {
+            table.insert("fritzbox_ipv6_suffix".into(), suffix.to_string().into());
+        }
+    }
+    if let Some(gateway) = &source.gateway {
+        table.insert("gateway".into(), gateway.protocol.as_str().into());
+        if let Some(address) = gateway.address {
+            table.insert("gateway_address".into(), address.to_string().into());
+        }
+    }
+    if let Some(cloud) = &source.cloud {
+        table.insert("cloud".into(), cloud.provider.as_str().into());
+        if let Some(url) = &cloud.url {
+            table.insert("cloud_metadata_url".into(), url.clone().into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> DnsConfig {
+        load_contents("test.toml", contents.as_bytes(), false)
+            .and_then(from_config)
+            .unwrap()
+    }
+
+    #[test]
+    fn accepts_the_wildcard_record() {
+        let config = parse(
+            r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = [{ name = "*" }, { name = "www" }]
+"#,
+        );
+        let names: Vec<&str> = config.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["*", "www"]);
+        assert!(validate_config(&config).is_empty());
+    }
+
+    #[test]
+    fn normalizes_record_names() {
+        assert_eq!(normalize_record_name("WWW", "example.com"), "www");
+        assert_eq!(
+            normalize_record_name("www.example.com.", "example.com"),
+            "www"
+        );
+        assert_eq!(
+            normalize_record_name("Home.Lab.Example.COM.", "example.com."),
+            "home.lab"
+        );
+        assert_eq!(normalize_record_name("example.com.", "example.com"), "@");
+        assert_eq!(normalize_record_name("@", "example.com"), "@");
+        assert_eq!(normalize_record_name("_dmarc", "example.com"), "_dmarc");
+        assert_eq!(normalize_record_name("*", "example.com"), "*");
+        assert_eq!(normalize_record_name("*.example.com.", "example.com"), "*");
+        // A name without the trailing dot is relative to the domain.
+        assert_eq!(
+            normalize_record_name("www.example.com", "example.com"),
+            "www.example.com"
+        );
+        // Outside of the domain, left for the API to reject.
+        assert_eq!(
+            normalize_record_name("www.example.org.", "example.com"),
+            "www.example.org"
+        );
+        assert_eq!(
+            normalize_record_name("notexample.com.", "example.com"),
+            "notexample.com"
+        );
+    }
+
+    #[test]
+    fn normalizes_the_configured_record_names() {
+        let config = parse(
+            r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = [{ name = "WWW" }, { name = "vpn.example.com." }, { name = "example.com." }]
+"#,
+        );
+        let names: Vec<&str> = config.records.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["www", "vpn", "@"]);
+    }
+
+    fn parse_error(contents: &str) -> String {
+        load_contents("test.toml", contents.as_bytes(), false)
+            .and_then(from_config)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn reads_where_the_delegated_prefix_is() {
+        let base = r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = ["router", { name = "nas", host_part = "::1:2:3:4" }]
+"#;
+        let with = |detection: &str| format!("{}\n[DETECTION.ipv6]\n{}\n", base, detection);
+
+        let config = parse(&with(
+            "prefix_len = 56\nprefix_lease_file = \"/var/lib/dhcp/dhclient6.leases\"",
+        ));
+        assert_eq!(
+            config.detection.ipv6.delegated_prefix,
+            Some(PrefixSource::LeaseFile(PathBuf::from(
+                "/var/lib/dhcp/dhclient6.leases"
+            )))
+        );
+        let config = parse(&with("prefix_len = 56\nprefix_interface = \"br-lan\""));
+        assert_eq!(
+            config.detection.ipv6.delegated_prefix,
+            Some(PrefixSource::Interface("br-lan".to_string()))
+        );
+
+        let error = parse_error(&with("prefix_interface = \"br-lan\""));
+        assert!(error.contains("prefix_len is not set"), "{}", error);
+        let error = parse_error(&with(
+            "prefix_len = 56\nprefix_interface = \"br-lan\"\nprefix_lease_file = \"leases\"",
+        ));
+        assert!(error.contains("both set"), "{}", error);
+    }
+
+    #[test]
+    fn renders_only_the_extended_types_besides_txt() {
+        let base = r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+"#;
+        let with = |entry: &str| format!("{}records = [{}]\n", base, entry);
+
+        let error = parse_error(&with(
+            r#"{ name = "@", type = "MX", template = "10 mail.example.com." }"#,
+        ));
+        assert!(error.contains("only TXT, SRV, TLSA and SSHFP"), "{}", error);
+
+        let srv = with(
+            r#"{ name = "_sip._udp", type = "SRV", template = "10 5 5060 sip.example.com." }"#,
+        );
+        if cfg!(feature = "extended-records") {
+            let config = parse(&srv);
+            assert_eq!(config.templates[0].dns_type, DnsType::Srv);
+            assert!(validate_config(&config).is_empty());
+        } else {
+            assert!(parse_error(&srv).contains("extended-records feature"));
+        }
+    }
+
+    #[cfg(feature = "extended-records")]
+    #[test]
+    fn checks_the_values_of_the_extended_types() {
+        let error = parse_error(
+            r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = [
+    { name = "_443._tcp.www", type = "TLSA", template = "3 1 1 0123abcd" },
+    { name = "www", type = "SSHFP", template = "4 2 {ipv4}" },
+    { name = "_sip._udp", type = "SRV", template = "10 5 sip.example.com." },
+]
+"#,
+        );
+        assert!(!error.contains("_443._tcp.www"), "{}", error);
+        assert!(error.contains("www: '4 2 192.0.2.1' is not a SSHFP value"));
+        assert!(error.contains("_sip._udp: '10 5 sip.example.com.' is not a SRV value"));
+    }
+
+    #[test]
+    fn names_the_key_of_values_of_the_wrong_type() {
+        let base = r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = ["www"]
+"#;
+        for (section, key, value) in [
+            ("RETRY", "queue_depth", r#""deep""#),
+            ("UPDATE", "use_batch", r#""sometimes""#),
+            ("HTTP", "max_concurrent_requests", "[4]"),
+            ("UPDATE", "ttl_min", r#""short""#),
+            ("NOTIFY", "webhook_url", "[1, 2]"),
+        ] {
+            let contents = format!("{}\n[{}]\n{} = {}\n", base, section, key, value);
+            let error = parse_error(&contents);
+            let path = format!("{}.{}", section, key);
+            assert!(error.contains(&path), "{}: {}", path, error);
+        }
+    }
+
+    #[test]
+    fn reads_the_channels_as_the_requests_name_them() {
+        let base = r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = ["www"]
+"#;
+        let config = parse(&format!(
+            "{}{}",
+            base,
+            r#"
+[notifications.push]
+type = "ntfy"
+url = "https://ntfy.example.com/ddns"
+token = "tk_secret"
+priority = "high"
+on_error = false
+
+[notifications.discord]
+webhook_url = "https://discord.com/api/webhooks/1/secret"
+on_change = false
+"#
+        ));
+        let ntfy = config.notify.ntfy.as_ref().unwrap();
+        assert_eq!(ntfy.server, "https://ntfy.example.com");
+        assert_eq!(ntfy.topic, "ddns");
+        assert_eq!(ntfy.token.as_deref().map(String::as_str), Some("tk_secret"));
+        assert_eq!(
+            ntfy.events,
+            EventFilter {
+                on_change: true,
+                on_failure: false
+            }
+        );
+        assert!(!config.notify.discord.as_ref().unwrap().events.on_change);
+
+        let config = parse(&format!(
+            "{}{}",
+            base,
+            r#"
+[NOTIFY.push]
+type = "gotify"
+url = "https://gotify.example.com"
+token = "secret"
+priority = 7
+"#
+        ));
+        let gotify = config.notify.gotify.as_ref().unwrap();
+        assert_eq!(gotify.server, "https://gotify.example.com");
+        assert_eq!(gotify.priority, 7);
+
+        let unknown_type = r#"
+[NOTIFY.push]
+type = "pushover"
+url = "https://example.com"
+"#;
+        let error = parse_error(&format!("{}{}", base, unknown_type));
+        assert!(error.contains("NOTIFY.push.type"), "{}", error);
+        let both = r#"
+[NOTIFY]
+webhook_url = "https://example.com"
+
+[notifications.ntfy]
+topic = "ddns"
+"#;
+        let error = parse_error(&format!("{}{}", base, both));
+        assert!(error.contains("[notifications]"), "{}", error);
+    }
+
+    #[test]
+    fn reads_the_event_filters_of_the_channels() {
+        let config = parse(
+            r#"
+[GANDI]
+key = "0123456789abcdef01234567"
+
+[DNS]
+domain = "example.com"
+records = ["www"]
+
+[NOTIFY]
+webhook_url = "https://example.com/hook?token=secret"
+webhook_on_failure = false
+
+[NOTIFY.ntfy]
+topic = "ddns"
+token = "tk_secret"
+priority = "high"
+on_change = false
+
+[NOTIFY.gotify]
+server = "https://gotify.example.com"
+token = "secret"
+"#,
+        );
+        let webhook = config.notify.webhook.as_ref().unwrap();
+        assert_eq!(
+            webhook.events,
+            EventFilter {
+                on_change: true,
+                on_failure: false
+            }
+        );
+        let ntfy = config.notify.ntfy.as_ref().unwrap();
+        assert_eq!(
+            ntfy.events,
+            EventFilter {
+                on_change: false,
+                on_failure: true
+            }
+        );
+        assert_eq!(
+            config.notify.gotify.as_ref().unwrap().events,
+            EventFilter::default()
+        );
+        let debug = format!("{:?}", config.notify);
+        assert!(!debug.contains("secret"), "{}", debug);
+    }
+}
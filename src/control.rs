@@ -0,0 +1,446 @@
+//! The control socket of the daemon, and the `ctl` subcommand talking to
+//! it. Requests and responses are single lines of JSON, e.g.
+//! `{"command":"status"}`.
+//!
+//! Pausing is kept in the pause file, so that it survives restarts and can
+//! also be toggled by creating and removing the file.
+
+use crate::health::unix_secs;
+use crate::pacing::RateLimitInfo;
+use crate::timing::Timings;
+use clap::{Args, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, oneshot};
+#[cfg(unix)]
+use {
+    log::warn,
+    std::os::unix::fs::PermissionsExt,
+    tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    tokio::net::{UnixListener, UnixStream},
+    tokio::task::JoinHandle,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Subcommand)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// Show the state of the daemon and its last pass
+    Status,
+    /// Start a pass right away
+    UpdateNow,
+    /// Read the configuration again
+    ReloadConfig,
+    /// Skip the scheduled passes until resumed, also across restarts
+    Pause,
+    /// Run the scheduled passes again
+    Resume,
+}
+
+#[derive(Debug, Args)]
+pub struct CtlArgs {
+    #[command(subcommand)]
+    request: Request,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Response {
+    pub fn message(message: &str) -> Self {
+        Response {
+            ok: true,
+            message: Some(message.to_string()),
+            status: None,
+        }
+    }
+
+    pub fn error(message: &str) -> Self {
+        Response {
+            ok: false,
+            message: Some(message.to_string()),
+            status: None,
+        }
+    }
+}
+
+/// The state of the daemon, times in seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Status {
+    pub paused: bool,
+    pub started: u64,
+    pub passes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_pass: Option<PassReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_pass: Option<u64>,
+    /// The quota of API requests last reported by the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_quota: Option<QuotaReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassReport {
+    pub finished: u64,
+    pub outcome: String,
+    pub changed: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<Timings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaReport {
+    pub remaining: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset: Option<u64>,
+}
+
+impl From<RateLimitInfo> for QuotaReport {
+    fn from(info: RateLimitInfo) -> Self {
+        QuotaReport {
+            remaining: info.remaining,
+            limit: info.limit,
+            reset: info.reset.map(unix_secs),
+        }
+    }
+}
+
+/// Requests the daemon loop carries out itself, with the channel for its
+/// response.
+#[derive(Debug)]
+pub enum Command {
+    UpdateNow(oneshot::Sender<Response>),
+    ReloadConfig(oneshot::Sender<Response>),
+}
+
+/// The listening end of the control socket. The socket is removed again
+/// when this is dropped.
+#[derive(Debug)]
+pub struct Control {
+    status: Arc<Mutex<Status>>,
+    pause_file: Arc<Mutex<PathBuf>>,
+    commands: Option<mpsc::Receiver<Command>>,
+    #[cfg(unix)]
+    socket: Option<(PathBuf, JoinHandle<()>)>,
+}
+
+impl Control {
+    /// Tracks the status without listening, where the socket is
+    /// unavailable.
+    pub fn disabled(pause_file: &Path) -> Self {
+        Control {
+            status: Arc::new(Mutex::new(Status {
+                started: unix_secs(SystemTime::now()),
+                ..Status::default()
+            })),
+            pause_file: Arc::new(Mutex::new(pause_file.to_path_buf())),
+            commands: None,
+            #[cfg(unix)]
+            socket: None,
+        }
+    }
+
+    /// Listens on `path`, readable by the owner only. A stale socket left
+    /// by a crashed daemon is replaced, while a socket another daemon still
+    /// answers on fails with [`io::ErrorKind::AddrInUse`].
+    #[cfg(unix)]
+    pub fn bind(path: &Path, pause_file: &Path) -> io::Result<Self> {
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("another daemon is listening on {}", path.display()),
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+        let mut control = Control::disabled(pause_file);
+        let (sender, receiver) = mpsc::channel(8);
+        let task = tokio::spawn(serve(
+            listener,
+            control.status.clone(),
+            control.pause_file.clone(),
+            sender,
+        ));
+        control.commands = Some(receiver);
+        control.socket = Some((path.to_path_buf(), task));
+        Ok(control)
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path, _pause_file: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the control socket is only available on Unix",
+        ))
+    }
+
+    /// Whether the pause file exists, which is also reported in the status.
+    pub fn paused(&self) -> bool {
+        let paused = self
+            .pause_file
+            .lock()
+            .map(|path| path.exists())
+            .unwrap_or(false);
+        self.update(|status| status.paused = paused);
+        paused
+    }
+
+    /// Follows a pause file moved by a new configuration.
+    pub fn set_pause_file(&self, path: &Path) {
+        if let Ok(mut pause_file) = self.pause_file.lock() {
+            *pause_file = path.to_path_buf();
+        }
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut Status)) {
+        if let Ok(mut status) = self.status.lock() {
+            f(&mut status);
+        }
+    }
+
+    /// Waits for the next command, forever if there is no socket.
+    pub async fn recv(&mut self) -> Command {
+        let command = match &mut self.commands {
+            Some(commands) => commands.recv().await,
+            None => None,
+        };
+        match command {
+            Some(command) => command,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Control {
+    fn drop(&mut self) {
+        if let Some((path, task)) = &self.socket {
+            task.abort();
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Unable to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Creates or removes the pause file.
+pub fn set_paused(pause_file: &Path, paused: bool) -> io::Result<()> {
+    if paused {
+        if let Some(dir) = pause_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(pause_file, "")
+    } else {
+        match fs::remove_file(pause_file) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn serve(
+    listener: UnixListener,
+    status: Arc<Mutex<Status>>,
+    pause_file: Arc<Mutex<PathBuf>>,
+    commands: mpsc::Sender<Command>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(
+                    stream,
+                    status.clone(),
+                    pause_file.clone(),
+                    commands.clone(),
+                ));
+            }
+            Err(e) => warn!("Unable to accept a control connection: {}", e),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn handle(
+    stream: UnixStream,
+    status: Arc<Mutex<Status>>,
+    pause_file: Arc<Mutex<PathBuf>>,
+    commands: mpsc::Sender<Command>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(path) = pause_file.lock().map(|path| path.clone()) else {
+            return;
+        };
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Status) => match status.lock() {
+                Ok(mut status) => {
+                    status.paused = path.exists();
+                    Response {
+                        ok: true,
+                        message: None,
+                        status: Some(status.clone()),
+                    }
+                }
+                Err(_) => Response::error("the status is unavailable"),
+            },
+            Ok(request @ (Request::Pause | Request::Resume)) => {
+                let paused = request == Request::Pause;
+                match set_paused(&path, paused) {
+                    Ok(()) => {
+                        if let Ok(mut status) = status.lock() {
+                            status.paused = paused;
+                        }
+                        Response::message(if paused { "paused" } else { "resumed" })
+                    }
+                    Err(e) => Response::error(&format!("{}: {}", path.display(), e)),
+                }
+            }
+            Ok(Request::UpdateNow) => forward(&commands, Command::UpdateNow).await,
+            Ok(Request::ReloadConfig) => forward(&commands, Command::ReloadConfig).await,
+            Err(e) => Response::error(&format!("invalid request: {}", e)),
+        };
+
+        let Ok(mut line) = serde_json::to_string(&response) else {
+            return;
+        };
+        line.push('\n');
+        if writer.write_all(line.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Hands a request to the daemon loop, which answers once it is done with
+/// the current pass.
+#[cfg(unix)]
+async fn forward(
+    commands: &mpsc::Sender<Command>,
+    command: fn(oneshot::Sender<Response>) -> Command,
+) -> Response {
+    let (reply, response) = oneshot::channel();
+    if commands.send(command(reply)).await.is_err() {
+        return Response::error("the daemon is shutting down");
+    }
+    response
+        .await
+        .unwrap_or_else(|_| Response::error("the daemon is shutting down"))
+}
+
+fn format_time(secs: u64) -> String {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| secs.to_string())
+}
+
+fn print_status(status: &Status) {
+    println!(
+        "State:     {}",
+        if status.paused { "paused" } else { "running" }
+    );
+    println!("Started:   {}", format_time(status.started));
+    println!("Passes:    {}", status.passes);
+    if let Some(pass) = &status.last_pass {
+        println!(
+            "Last pass: {} ({}, {} records changed)",
+            format_time(pass.finished),
+            pass.outcome,
+            pass.changed
+        );
+        if let Some(timings) = &pass.timings {
+            println!("Timings:   {}", timings);
+        }
+    }
+    if let Some(next) = status.next_pass {
+        println!("Next pass: {}", format_time(next));
+    }
+    if let Some(quota) = &status.api_quota {
+        let limit = quota
+            .limit
+            .map(|limit| format!(" of {}", limit))
+            .unwrap_or_default();
+        let reset = quota
+            .reset
+            .map(|reset| format!(", reset at {}", format_time(reset)))
+            .unwrap_or_default();
+        println!("API quota: {}{} remaining{}", quota.remaining, limit, reset);
+    }
+}
+
+#[cfg(unix)]
+async fn send(path: &Path, request: Request) -> io::Result<Response> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(&request)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+
+    let mut response = String::new();
+    BufReader::new(reader).read_line(&mut response).await?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+#[cfg(not(unix))]
+async fn send(_path: &Path, _request: Request) -> io::Result<Response> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the control socket is only available on Unix",
+    ))
+}
+
+/// Sends the request of the `ctl` subcommand to the daemon listening on
+/// `path`, returning whether it succeeded. Without a daemon, `pause` and
+/// `resume` change the pause file themselves.
+pub async fn run(args: &CtlArgs, path: &Path, pause_file: &Path) -> Result<bool, String> {
+    let response = match send(path, args.request).await {
+        Ok(response) => response,
+        Err(_) if matches!(args.request, Request::Pause | Request::Resume) => {
+            let paused = args.request == Request::Pause;
+            set_paused(pause_file, paused)
+                .map_err(|e| format!("{}: {}", pause_file.display(), e))?;
+            println!("{}", if paused { "paused" } else { "resumed" });
+            return Ok(true);
+        }
+        Err(e) => {
+            return Err(format!(
+                "unable to reach the daemon on {}: {}",
+                path.display(),
+                e
+            ))
+        }
+    };
+    if let Some(status) = &response.status {
+        print_status(status);
+    }
+    if let Some(message) = &response.message {
+        if response.ok {
+            println!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    }
+    Ok(response.ok)
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+struct PendingChange {
+    ip: IpAddr,
+    first_seen: Instant,
+}
+
+#[derive(Debug, Default)]
+struct RecordState {
+    stable: Option<IpAddr>,
+    pending: Option<PendingChange>,
+}
+
+/// Holds back address changes until the new address has been detected for
+/// a whole window, so that a flapping connection does not make the records
+/// flap as well. Only meaningful in daemon mode, where it outlives a pass.
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    window: Option<Duration>,
+    records: HashMap<(String, String), RecordState>,
+    /// Those of the records of the discovered domains.
+    domains: HashMap<String, Debouncer>,
+}
+
+impl Debouncer {
+    pub fn new(window: Option<Duration>) -> Self {
+        Debouncer {
+            window: window.filter(|w| !w.is_zero()),
+            records: HashMap::new(),
+            domains: HashMap::new(),
+        }
+    }
+
+    /// The debouncer of the records of the discovered domain `domain`.
+    pub fn domain(&mut self, domain: &str) -> &mut Debouncer {
+        let window = self.window;
+        self.domains
+            .entry(domain.to_string())
+            .or_insert_with(|| Debouncer::new(window))
+    }
+
+    /// Reports the address detected for a record and returns whether it may
+    /// be pushed. The first address seen for a record is accepted right away.
+    pub fn observe(&mut self, record: &str, dns_type: &str, ip: IpAddr, now: Instant) -> bool {
+        let Some(window) = self.window else {
+            return true;
+        };
+        let state = self
+            .records
+            .entry((record.to_string(), dns_type.to_string()))
+            .or_default();
+
+        match state.stable {
+            None => {
+                state.stable = Some(ip);
+                true
+            }
+            Some(stable) if stable == ip => {
+                // The address reverted before the window elapsed.
+                state.pending = None;
+                true
+            }
+            Some(_) => match state.pending {
+                Some(pending) if pending.ip == ip => {
+                    if now.duration_since(pending.first_seen) < window {
+                        return false;
+                    }
+                    state.stable = Some(ip);
+                    state.pending = None;
+                    true
+                }
+                _ => {
+                    state.pending = Some(PendingChange {
+                        ip,
+                        first_seen: now,
+                    });
+                    false
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! The `diff` subcommand, comparing what the records should hold with the
+//! zone, e.g. as a monitoring check.
+
+use crate::config::DnsConfig;
+use crate::dns_type::DnsType;
+use crate::drift::same_set;
+use crate::error::DdnsError;
+use crate::gandi::{get_zone_records, GandiApi};
+use crate::history::OutputFormat;
+use crate::ip::canonical;
+use crate::records_file::name_matches;
+use crate::template::txt_text;
+use crate::ttl::TtlHistory;
+use crate::{detect_sources, multi_source_values, template_addresses};
+use clap::Args;
+use log::{error, LevelFilter};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+
+pub const EXIT_IN_SYNC: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_DIFFERS: i32 = 2;
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+/// How an rrset compares with the configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    InSync,
+    /// It holds other values than the detected ones.
+    Mismatch,
+    /// It holds the detected values with another TTL than a pass writes.
+    TtlMismatch,
+    /// The record does not exist in the zone.
+    Missing,
+    /// It is listed in `DNS.remove`, so it should not exist.
+    Unmanaged,
+    /// The address it should hold could not be detected.
+    Undetected,
+}
+
+/// One rrset of the report. Every field is always present, `null` when not
+/// known, so that the JSON output has a stable schema.
+#[derive(Debug, Serialize)]
+struct RrsetDiff {
+    record: String,
+    #[serde(rename = "type")]
+    dns_type: DnsType,
+    status: Status,
+    expected: Option<Vec<String>>,
+    actual: Option<Vec<String>>,
+    expected_ttl: Option<u32>,
+    actual_ttl: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    domain: String,
+    in_sync: bool,
+    rrsets: Vec<RrsetDiff>,
+    error: Option<String>,
+}
+
+/// Compares the rrsets of the configured records with the addresses a
+/// pass would write to them, and the templates with their rendered text,
+/// and lists the rrsets of `DNS.remove`.
+async fn compare(config: &DnsConfig, api: &GandiApi) -> Result<Vec<RrsetDiff>, DdnsError> {
+    let detected = detect_sources(config, &mut HashMap::new()).await;
+    let zone = get_zone_records(&config.domain, api).await?;
+    let mut ttls = TtlHistory::load(&config.state_dir, config.adaptive_ttl);
+
+    let mut diffs: Vec<RrsetDiff> = Vec::new();
+    for entry in &config.records {
+        for dns_type in [DnsType::A, DnsType::Aaaa] {
+            let compared = diffs
+                .iter()
+                .any(|diff| diff.record == entry.name && diff.dns_type == dns_type);
+            if compared || !config.versions(entry).allows_type(dns_type) {
+                continue;
+            }
+            let expected = if entry.is_multi_source() {
+                multi_source_values(entry, &detected, dns_type)
+            } else {
+                detected
+                    .iter()
+                    .find(|d| entry.follows(d.source))
+                    .and_then(|d| d.ip(dns_type))
+                    .map(|ip| vec![canonical(ip)])
+            };
+            let rrset = zone
+                .iter()
+                .find(|rrset| rrset.name == entry.name && rrset.record_type == dns_type);
+            let steady_ttl = ttls.steady_ttl(
+                &config.domain,
+                &entry.name,
+                dns_type.as_str(),
+                SystemTime::now(),
+            );
+            let expected_ttl = steady_ttl.unwrap_or_else(|| ttls.change_ttl());
+            let status = match (&expected, rrset) {
+                (None, _) => Status::Undetected,
+                (_, None) => Status::Missing,
+                (Some(expected), Some(rrset)) if !same_set(&rrset.values, &expected.join(",")) => {
+                    Status::Mismatch
+                }
+                (_, Some(rrset)) if rrset.ttl != expected_ttl => Status::TtlMismatch,
+                _ => Status::InSync,
+            };
+            diffs.push(RrsetDiff {
+                record: entry.name.clone(),
+                dns_type,
+                status,
+                expected,
+                actual: rrset.map(|rrset| rrset.values.clone()),
+                expected_ttl: Some(expected_ttl),
+                actual_ttl: rrset.map(|rrset| rrset.ttl),
+            });
+        }
+    }
+
+    // The timestamp of a template alone does not make it differ, so the
+    // TTL is not compared either: the rrset is only written for its text.
+    for template in &config.templates {
+        let addresses = template_addresses(template, &detected);
+        let rrset = zone
+            .iter()
+            .find(|rrset| rrset.name == template.name && rrset.record_type == template.dns_type);
+        let actual: Option<Vec<String>> = rrset.map(|rrset| {
+            rrset
+                .values
+                .iter()
+                .map(|value| txt_text(value))
+                .filter(|text| template.owns(text))
+                .collect()
+        });
+        let expected = template
+            .render(addresses, OffsetDateTime::now_utc())
+            .ok()
+            .map(|text| vec![text]);
+        let status = match (&expected, actual.as_deref()) {
+            (None, _) => Status::Undetected,
+            (_, None | Some([])) => Status::Missing,
+            (_, Some([text])) if template.matches(text, addresses) => Status::InSync,
+            _ => Status::Mismatch,
+        };
+        diffs.push(RrsetDiff {
+            record: template.name.clone(),
+            dns_type: template.dns_type,
+            status,
+            expected,
+            actual,
+            expected_ttl: None,
+            actual_ttl: rrset.map(|rrset| rrset.ttl),
+        });
+    }
+
+    let unmanaged = zone.iter().filter(|rrset| {
+        matches!(rrset.record_type, DnsType::A | DnsType::Aaaa)
+            && config
+                .remove
+                .iter()
+                .any(|pattern| name_matches(pattern, &rrset.name))
+    });
+    for rrset in unmanaged {
+        diffs.push(RrsetDiff {
+            record: rrset.name.clone(),
+            dns_type: rrset.record_type,
+            status: Status::Unmanaged,
+            expected: None,
+            actual: Some(rrset.values.clone()),
+            expected_ttl: None,
+            actual_ttl: Some(rrset.ttl),
+        });
+    }
+    Ok(diffs)
+}
+
+fn values(values: &Option<Vec<String>>) -> String {
+    values
+        .as_ref()
+        .map_or("?".to_string(), |values| values.join(","))
+}
+
+fn ttl(ttl: Option<u32>) -> String {
+    ttl.map_or("?".to_string(), |ttl| ttl.to_string())
+}
+
+/// Prints the report like a unified diff from the zone to the
+/// configuration.
+fn print_text(report: &Report) {
+    println!("--- {} (zone)", report.domain);
+    println!("+++ {} (configuration)", report.domain);
+    for diff in &report.rrsets {
+        let label = format!("{}/{}", diff.record, diff.dns_type);
+        match diff.status {
+            Status::InSync => println!("  {} {}", label, values(&diff.actual)),
+            Status::Mismatch => {
+                println!("- {} {}", label, values(&diff.actual));
+                println!("+ {} {}", label, values(&diff.expected));
+            }
+            Status::TtlMismatch => {
+                println!(
+                    "- {} {} TTL {}",
+                    label,
+                    values(&diff.actual),
+                    ttl(diff.actual_ttl)
+                );
+                println!(
+                    "+ {} {} TTL {}",
+                    label,
+                    values(&diff.expected),
+                    ttl(diff.expected_ttl)
+                );
+            }
+            Status::Missing => println!(
+                "+ {} {}  (missing from the zone)",
+                label,
+                values(&diff.expected)
+            ),
+            Status::Unmanaged => println!(
+                "- {} {}  (listed in DNS.remove)",
+                label,
+                values(&diff.actual)
+            ),
+            Status::Undetected => println!(
+                "? {} {}  (the address was not detected)",
+                label,
+                values(&diff.actual)
+            ),
+        }
+    }
+    let in_sync = report
+        .rrsets
+        .iter()
+        .filter(|diff| diff.status == Status::InSync)
+        .count();
+    println!("{} of {} rrsets in sync", in_sync, report.rrsets.len());
+}
+
+/// Prints the report, returning the exit code: 0 if every rrset is in sync,
+/// 2 if some differ and 1 if the comparison is incomplete.
+pub async fn run(args: &DiffArgs, config: &DnsConfig, api: &GandiApi) -> i32 {
+    // The logs would corrupt the JSON document on stdout.
+    if args.output == OutputFormat::Json {
+        log::set_max_level(LevelFilter::Off);
+    }
+    let (rrsets, failure) = match compare(config, api).await {
+        Ok(rrsets) => (rrsets, None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    let code = if failure.is_some() || rrsets.iter().any(|d| d.status == Status::Undetected) {
+        EXIT_ERROR
+    } else if rrsets.iter().all(|d| d.status == Status::InSync) {
+        EXIT_IN_SYNC
+    } else {
+        EXIT_DIFFERS
+    };
+    let report = Report {
+        domain: config.domain.clone(),
+        in_sync: code == EXIT_IN_SYNC,
+        rrsets,
+        error: failure,
+    };
+
+    match args.output {
+        OutputFormat::Json => match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                error!("{}", e);
+                return EXIT_ERROR;
+            }
+        },
+        OutputFormat::Text => match &report.error {
+            Some(e) => error!("Unable to compare the zone: {}", e),
+            None => print_text(&report),
+        },
+    }
+    code
+}
@@ -0,0 +1,231 @@
+//! The periodic digest of the daemon's activity, so that silence can be
+//! told apart from a dead daemon.
+
+#[cfg(feature = "daemon-mode")]
+use crate::audit::{AuditEntry, AuditLog, Outcome};
+#[cfg(feature = "daemon-mode")]
+use crate::health::unix_secs;
+#[cfg(feature = "daemon-mode")]
+use crate::notify::{DdnsEvent, Notifications};
+#[cfg(feature = "daemon-mode")]
+use log::{info, warn};
+#[cfg(feature = "daemon-mode")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "daemon-mode")]
+use std::fmt::Write;
+#[cfg(feature = "daemon-mode")]
+use std::fs;
+#[cfg(feature = "daemon-mode")]
+use std::io;
+#[cfg(feature = "daemon-mode")]
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+#[cfg(feature = "daemon-mode")]
+use std::time::SystemTime;
+#[cfg(feature = "daemon-mode")]
+use time::format_description::well_known::Rfc3339;
+#[cfg(feature = "daemon-mode")]
+use time::OffsetDateTime;
+
+#[cfg(feature = "daemon-mode")]
+const DIGEST_FILE: &str = "digest.json";
+
+/// What happened since the last digest, kept across restarts.
+#[cfg(feature = "daemon-mode")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DigestState {
+    /// Seconds since the Unix epoch; the period starts at the first run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_sent: Option<u64>,
+    runs: u64,
+    failed_runs: u64,
+}
+
+#[cfg(feature = "daemon-mode")]
+#[derive(Debug)]
+pub struct Digest {
+    path: PathBuf,
+    interval: Duration,
+    state: DigestState,
+}
+
+#[cfg(feature = "daemon-mode")]
+fn format_time(secs: u64) -> String {
+    i64::try_from(secs)
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| secs.to_string())
+}
+
+pub fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    format!(
+        "{}d {}h {}m",
+        minutes / 1440,
+        minutes / 60 % 24,
+        minutes % 60
+    )
+}
+
+#[cfg(feature = "daemon-mode")]
+impl Digest {
+    /// Loads the state of the digest from `state_dir`. A missing or
+    /// unreadable file starts a new period.
+    pub fn load(state_dir: &Path, interval: Duration) -> Self {
+        let path = state_dir.join(DIGEST_FILE);
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Discarding corrupt digest state {}: {}", path.display(), e);
+                DigestState::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => DigestState::default(),
+            Err(e) => {
+                warn!("Unable to read digest state {}: {}", path.display(), e);
+                DigestState::default()
+            }
+        };
+        Digest {
+            path,
+            interval,
+            state,
+        }
+    }
+
+    pub fn record_run(&mut self, success: bool, now: SystemTime) {
+        self.state.last_sent.get_or_insert(unix_secs(now));
+        self.state.runs += 1;
+        if !success {
+            self.state.failed_runs += 1;
+        }
+        self.save_or_warn();
+    }
+
+    /// Sends the digest if the period is over. A digest that could not be
+    /// sent is attempted again after the next run.
+    pub async fn send_if_due(
+        &mut self,
+        notifications: &Notifications,
+        log: &AuditLog,
+        domain: &str,
+        started: SystemTime,
+        now: SystemTime,
+    ) {
+        let now_secs = unix_secs(now);
+        let since = *self.state.last_sent.get_or_insert(now_secs);
+        if now_secs.saturating_sub(since) < self.interval.as_secs() {
+            return;
+        }
+
+        let title = format!(
+            "ddns-gandi digest for {}, {} to {}",
+            domain,
+            format_time(since),
+            format_time(now_secs)
+        );
+        let text = self.compose(log, since, now.duration_since(started).unwrap_or_default());
+        if notifications.send(&DdnsEvent::Digest { title, text }).await {
+            info!("Sent the digest");
+            self.state = DigestState {
+                last_sent: Some(now_secs),
+                runs: 0,
+                failed_runs: 0,
+            };
+            self.save_or_warn();
+        }
+    }
+
+    fn compose(&self, log: &AuditLog, since: u64, uptime: Duration) -> String {
+        let mut changes = Vec::new();
+        let mut failures = Vec::new();
+        let mut drifts = Vec::new();
+        match log.read() {
+            Ok(Some(entries)) => {
+                for entry in entries.flatten().flatten() {
+                    let recent = entry
+                        .time()
+                        .is_some_and(|time| time.unix_timestamp() >= since as i64);
+                    if recent {
+                        match entry.outcome {
+                            Outcome::Changed | Outcome::RolledBack | Outcome::Removed => {
+                                changes.push(entry)
+                            }
+                            Outcome::Failed => failures.push(entry),
+                            Outcome::Drifted => drifts.push(entry),
+                        }
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Unable to read the audit log for the digest: {}", e),
+        }
+
+        let mut text = String::new();
+        let _ = writeln!(text, "Uptime: {}", format_uptime(uptime));
+        let _ = writeln!(
+            text,
+            "Runs: {} ({} failed)",
+            self.state.runs, self.state.failed_runs
+        );
+        let _ = writeln!(text, "Record changes: {}", changes.len());
+        for entry in &changes {
+            let _ = writeln!(text, "  {}", describe(entry));
+        }
+        let _ = writeln!(text, "Failed updates: {}", failures.len());
+        for entry in &failures {
+            let _ = writeln!(
+                text,
+                "  {}: {}",
+                describe(entry),
+                entry.error.as_deref().unwrap_or("unknown error")
+            );
+        }
+        if !drifts.is_empty() {
+            let _ = writeln!(text, "Changed by someone else: {}", drifts.len());
+            for entry in &drifts {
+                let _ = writeln!(
+                    text,
+                    "  {} (last written {})",
+                    describe(entry),
+                    entry.written.as_deref().unwrap_or("?")
+                );
+            }
+        }
+        text
+    }
+
+    fn save_or_warn(&self) {
+        if let Err(e) = self.save() {
+            warn!("Unable to save the digest state: {}", e);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.state)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(feature = "daemon-mode")]
+fn describe(entry: &AuditEntry) -> String {
+    let old = match &entry.old {
+        Some(old) if !old.is_empty() => old.join(","),
+        _ => "?".to_string(),
+    };
+    let provider = match &entry.provider {
+        Some(provider) => format!(" (via {})", provider),
+        None => String::new(),
+    };
+    let new = match entry.outcome {
+        Outcome::Removed => "(deleted)",
+        _ => &entry.new,
+    };
+    format!(
+        "{} {}/{} {} -> {}{}",
+        entry.timestamp, entry.record, entry.dns_type, old, new, provider
+    )
+}
@@ -0,0 +1,99 @@
+//! Updating the same records in every domain of the account that matches
+//! `DNS.domains`, instead of in the single `DNS.domain`.
+//!
+//! The domains are listed again on every pass, so that a domain bought
+//! since is picked up without editing the configuration. Each one keeps
+//! its state in a directory of its own below the state directory.
+
+use crate::config::DnsConfig;
+use crate::error::DdnsError;
+use crate::gandi::{list_domains, GandiApi};
+use crate::records_file::name_matches;
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Directory below the state directory holding those of the domains.
+const DOMAINS_DIR: &str = "domains";
+/// The domains found by the last discovery, to tell the new ones apart.
+const DISCOVERED_FILE: &str = "discovered.json";
+
+/// Which domains of the account are updated, as glob patterns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainDiscovery {
+    /// Patterns the domains have to match one of, any domain if empty.
+    pub include: Vec<String>,
+    /// Patterns of domains left alone even if included.
+    pub exclude: Vec<String>,
+}
+
+impl DomainDiscovery {
+    pub fn matches(&self, domain: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| name_matches(p, domain)))
+            && !self.exclude.iter().any(|p| name_matches(p, domain))
+    }
+}
+
+/// The domains of the account matching `discovery`, sorted, logging those
+/// that appeared or disappeared since the last discovery.
+pub async fn discover(
+    config: &DnsConfig,
+    discovery: &DomainDiscovery,
+    api: &GandiApi,
+) -> Result<Vec<String>, DdnsError> {
+    let mut domains: Vec<String> = list_domains(api)
+        .await?
+        .into_iter()
+        .filter(|domain| discovery.matches(domain))
+        .collect();
+    domains.sort();
+    domains.dedup();
+
+    let previous = load(&config.state_dir);
+    match &previous {
+        None => info!(
+            "Discovered {} domains: {}",
+            domains.len(),
+            domains.join(", ")
+        ),
+        Some(previous) => {
+            for domain in domains.iter().filter(|d| !previous.contains(d)) {
+                info!("Discovered the new domain {}", domain);
+            }
+            for domain in previous.iter().filter(|d| !domains.contains(d)) {
+                warn!(
+                    "{} is no longer on the account or matching DNS.domains, leaving it alone",
+                    domain
+                );
+            }
+        }
+    }
+    if previous.as_ref() != Some(&domains) {
+        if let Err(e) = save(&config.state_dir, &domains) {
+            warn!("Unable to remember the discovered domains: {}", e);
+        }
+    }
+    Ok(domains)
+}
+
+/// The configuration updating the records in `domain`.
+pub fn domain_config(config: &DnsConfig, domain: &str) -> DnsConfig {
+    let mut domain_config = config.clone();
+    domain_config.domain = domain.to_string();
+    domain_config.state_dir = config.state_dir.join(DOMAINS_DIR).join(domain);
+    domain_config
+}
+
+fn load(state_dir: &Path) -> Option<Vec<String>> {
+    let contents = fs::read_to_string(state_dir.join(DISCOVERED_FILE)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn save(state_dir: &Path, domains: &[String]) -> io::Result<()> {
+    fs::create_dir_all(state_dir)?;
+    let path = state_dir.join(DISCOVERED_FILE);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(domains)?)?;
+    fs::rename(&tmp, &path)
+}
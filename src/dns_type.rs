@@ -0,0 +1,132 @@
+//! The types of rrsets Gandi LiveDNS knows, so that a misspelled type is
+//! rejected when it is read rather than by the API.
+
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DnsType {
+    A,
+    Aaaa,
+    Alias,
+    Caa,
+    Cds,
+    Cname,
+    Dname,
+    Ds,
+    Https,
+    Key,
+    Loc,
+    Mx,
+    Naptr,
+    Ns,
+    Openpgpkey,
+    Ptr,
+    Rp,
+    Soa,
+    Spf,
+    Srv,
+    Sshfp,
+    Svcb,
+    Tlsa,
+    Txt,
+    Wks,
+}
+
+const ALL: [DnsType; 25] = [
+    DnsType::A,
+    DnsType::Aaaa,
+    DnsType::Alias,
+    DnsType::Caa,
+    DnsType::Cds,
+    DnsType::Cname,
+    DnsType::Dname,
+    DnsType::Ds,
+    DnsType::Https,
+    DnsType::Key,
+    DnsType::Loc,
+    DnsType::Mx,
+    DnsType::Naptr,
+    DnsType::Ns,
+    DnsType::Openpgpkey,
+    DnsType::Ptr,
+    DnsType::Rp,
+    DnsType::Soa,
+    DnsType::Spf,
+    DnsType::Srv,
+    DnsType::Sshfp,
+    DnsType::Svcb,
+    DnsType::Tlsa,
+    DnsType::Txt,
+    DnsType::Wks,
+];
+
+impl DnsType {
+    /// The name of the type, as Gandi and zone files spell it.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DnsType::A => "A",
+            DnsType::Aaaa => "AAAA",
+            DnsType::Alias => "ALIAS",
+            DnsType::Caa => "CAA",
+            DnsType::Cds => "CDS",
+            DnsType::Cname => "CNAME",
+            DnsType::Dname => "DNAME",
+            DnsType::Ds => "DS",
+            DnsType::Https => "HTTPS",
+            DnsType::Key => "KEY",
+            DnsType::Loc => "LOC",
+            DnsType::Mx => "MX",
+            DnsType::Naptr => "NAPTR",
+            DnsType::Ns => "NS",
+            DnsType::Openpgpkey => "OPENPGPKEY",
+            DnsType::Ptr => "PTR",
+            DnsType::Rp => "RP",
+            DnsType::Soa => "SOA",
+            DnsType::Spf => "SPF",
+            DnsType::Srv => "SRV",
+            DnsType::Sshfp => "SSHFP",
+            DnsType::Svcb => "SVCB",
+            DnsType::Tlsa => "TLSA",
+            DnsType::Txt => "TXT",
+            DnsType::Wks => "WKS",
+        }
+    }
+}
+
+impl fmt::Display for DnsType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown DNS record type '{0}'")]
+pub struct ParseDnsTypeError(String);
+
+impl FromStr for DnsType {
+    type Err = ParseDnsTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALL.into_iter()
+            .find(|dns_type| dns_type.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| ParseDnsTypeError(s.to_string()))
+    }
+}
+
+impl TryFrom<String> for DnsType {
+    type Error = ParseDnsTypeError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Serialize for DnsType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
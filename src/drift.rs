@@ -0,0 +1,165 @@
+//! Noticing records changed by someone else since the client last wrote
+//! them, e.g. a colleague repointing one of them, instead of silently
+//! overwriting them.
+
+use crate::dns_type::DnsType;
+use crate::ip::canonical;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const WRITTEN_FILE: &str = "written.json";
+
+/// What to do with a record that drifted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DriftPolicy {
+    /// Report it and leave it alone.
+    #[default]
+    Warn,
+    /// Report it and write the detected address anyway.
+    Overwrite,
+    /// Report it as a failed update and leave it alone.
+    Fail,
+}
+
+impl DriftPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DriftPolicy::Warn => "warn",
+            DriftPolicy::Overwrite => "overwrite",
+            DriftPolicy::Fail => "fail",
+        }
+    }
+}
+
+impl FromStr for DriftPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "warn" => Ok(DriftPolicy::Warn),
+            "overwrite" => Ok(DriftPolicy::Overwrite),
+            "fail" => Ok(DriftPolicy::Fail),
+            other => Err(format!("unknown drift policy '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WrittenState {
+    #[serde(default)]
+    written: BTreeMap<String, String>,
+    /// The values someone else put in the rrsets, already reported.
+    #[serde(default)]
+    drifted: BTreeMap<String, String>,
+    /// The values `set` wrote, which a normal run does not revert unless
+    /// told so.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    manual: BTreeMap<String, String>,
+}
+
+/// The values the rrsets were last written with or found to hold, by
+/// `record/TYPE`, persisted as JSON in the state directory.
+#[derive(Debug)]
+pub struct WrittenValues {
+    path: PathBuf,
+    state: WrittenState,
+}
+
+fn key(record: &str, dns_type: &str) -> String {
+    format!("{}/{}", record, dns_type)
+}
+
+impl WrittenValues {
+    /// Loads the values from `state_dir`. A missing file yields none, an
+    /// unreadable one is discarded with a warning.
+    pub fn load(state_dir: &Path) -> Self {
+        let path = state_dir.join(WRITTEN_FILE);
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Discarding corrupt {}: {}", path.display(), e);
+                WrittenState::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => WrittenState::default(),
+            Err(e) => {
+                warn!("Unable to read {}: {}", path.display(), e);
+                WrittenState::default()
+            }
+        };
+        WrittenValues { path, state }
+    }
+
+    pub fn get(&self, record: &str, dns_type: &str) -> Option<&str> {
+        self.state
+            .written
+            .get(&key(record, dns_type))
+            .map(String::as_str)
+    }
+
+    /// The value `set` wrote to the rrset, if a normal run has not
+    /// written it since.
+    pub fn manual(&self, record: &str, dns_type: &str) -> Option<&str> {
+        self.state
+            .manual
+            .get(&key(record, dns_type))
+            .map(String::as_str)
+    }
+
+    /// Remembers that someone else put `current` in the rrset, returning
+    /// whether it is new and thus to be reported.
+    pub fn note_drift(&mut self, record: &str, dns_type: &str, current: &[String]) -> bool {
+        let current = current.join(",");
+        let key = key(record, dns_type);
+        if self.state.drifted.get(&key) == Some(&current) {
+            return false;
+        }
+        self.state.drifted.insert(key, current);
+        true
+    }
+
+    /// Remembers the values the rrsets hold after a pass, as
+    /// `(record, type, value)`, `manual` when `set` wrote them.
+    pub fn record(&mut self, confirmed: &[(String, DnsType, String)], manual: bool) {
+        for (record, dns_type, value) in confirmed {
+            let key = key(record, dns_type.as_str());
+            self.state.drifted.remove(&key);
+            if manual {
+                self.state.manual.insert(key.clone(), value.clone());
+            } else {
+                self.state.manual.remove(&key);
+            }
+            self.state.written.insert(key, value.clone());
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.state)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// Whether `values` are the addresses of `expected`, several of them
+/// separated by commas, in any order.
+pub fn same_set<S: AsRef<str>>(values: &[S], expected: &str) -> bool {
+    let mut values: Vec<String> = values.iter().map(|v| canonical(v.as_ref())).collect();
+    let mut expected: Vec<String> = expected.split(',').map(canonical).collect();
+    values.sort();
+    values.dedup();
+    expected.sort();
+    expected.dedup();
+    values == expected
+}
+
+/// Whether an rrset holding `current` was changed by someone else: it
+/// holds neither the values last `written` nor the `detected` ones.
+pub fn drifted<S: AsRef<str>>(current: &[S], written: &str, detected: &str) -> bool {
+    !same_set(current, written) && !same_set(current, detected)
+}
@@ -0,0 +1,402 @@
+//! `echo-server`: a small HTTP server answering every client the address
+//! it comes from, so that a host with a static address can stand in for
+//! the public detection services of the others.
+
+use clap::Args;
+use futures::future::join_all;
+use log::{debug, info, warn};
+use socket2::{Domain, Socket, Type};
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_PORT: u16 = 8053;
+/// Longest request line and headers read, well above what clients send.
+const MAX_HEAD: usize = 8 * 1024;
+/// Bound of a whole exchange, so that idle connections do not pile up.
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Args)]
+pub struct EchoServerArgs {
+    /// Address to listen on, e.g. 0.0.0.0:8053 or [::]:8053; may be given
+    /// several times [default: both of these]
+    #[arg(long, value_name = "ADDR")]
+    listen: Vec<SocketAddr>,
+    /// Reverse proxies in front of the server, whose X-Forwarded-For
+    /// entries are trusted to tell the client address
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    trusted_proxies: usize,
+}
+
+/// Parses an entry of X-Forwarded-For, which some proxies write with the
+/// port of the client.
+fn parse_forwarded(entry: &str) -> Result<IpAddr, String> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpAddr>()
+        .or_else(|_| entry.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| {
+            entry
+                .strip_prefix('[')
+                .and_then(|entry| entry.strip_suffix(']'))
+                .ok_or(())
+                .and_then(|entry| entry.parse::<IpAddr>().map_err(drop))
+        })
+        .map_err(|_| format!("invalid X-Forwarded-For entry '{}'", entry))
+}
+
+/// The address a request comes from: the peer, or the entry of the
+/// `X-Forwarded-For` headers added by the farthest of the `trusted`
+/// proxies. The entries left of it may be made up by the client.
+pub fn client_address(peer: IpAddr, forwarded: &[&str], trusted: usize) -> Result<IpAddr, String> {
+    if trusted == 0 {
+        return Ok(peer.to_canonical());
+    }
+    let mut chain = Vec::new();
+    for header in forwarded {
+        for entry in header.split(',') {
+            chain.push(parse_forwarded(entry)?);
+        }
+    }
+    // Each proxy appends the address it got the request from, the peer
+    // being the nearest.
+    let n_forwarded = chain.len();
+    chain.push(peer);
+    match n_forwarded.checked_sub(trusted) {
+        Some(index) => Ok(chain[index].to_canonical()),
+        None => Err(format!(
+            "X-Forwarded-For holds {} addresses, fewer than the {} trusted proxies",
+            n_forwarded, trusted
+        )),
+    }
+}
+
+/// The parts of a request the answer depends on.
+#[derive(Debug, Default)]
+struct Request {
+    method: String,
+    target: String,
+    forwarded: Vec<String>,
+    accepts_json: bool,
+}
+
+impl Request {
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut words = lines.next()?.split_whitespace();
+        let mut request = Request {
+            method: words.next()?.to_string(),
+            target: words.next()?.to_string(),
+            ..Request::default()
+        };
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("x-forwarded-for") {
+                request.forwarded.push(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("accept") {
+                request.accepts_json |= value.contains("application/json");
+            }
+        }
+        Some(request)
+    }
+
+    /// Whether the address is answered as `{"ip": ...}`, asked for with
+    /// the `/json` path, `?format=json` like ipify, or the `Accept` header.
+    fn wants_json(&self) -> bool {
+        let (path, query) = self
+            .target
+            .split_once('?')
+            .unwrap_or((self.target.as_str(), ""));
+        path.trim_end_matches('/').ends_with("/json")
+            || query.split('&').any(|pair| pair == "format=json")
+            || self.accepts_json
+    }
+}
+
+/// Reads the request line and the headers, `None` if the client sends
+/// more than [`MAX_HEAD`] bytes or hangs up first.
+async fn read_head(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buffer).await.ok()?;
+        if n == 0 || head.len() + n > MAX_HEAD {
+            return None;
+        }
+        head.extend_from_slice(&buffer[..n]);
+    }
+    String::from_utf8(head).ok()
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+    with_body: bool,
+) -> std::io::Result<()> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    if with_body {
+        response.push_str(body);
+    }
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Answers the one request of a connection.
+async fn handle(mut stream: TcpStream, peer: SocketAddr, trusted: usize) -> std::io::Result<()> {
+    let Some(request) = read_head(&mut stream)
+        .await
+        .and_then(|h| Request::parse(&h))
+    else {
+        return respond(
+            &mut stream,
+            "400 Bad Request",
+            "text/plain",
+            "bad request\n",
+            true,
+        )
+        .await;
+    };
+    let with_body = request.method != "HEAD";
+    if request.method != "GET" && request.method != "HEAD" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET and HEAD are allowed\n",
+            true,
+        )
+        .await;
+    }
+    let forwarded: Vec<&str> = request.forwarded.iter().map(String::as_str).collect();
+    let ip = match client_address(peer.ip(), &forwarded, trusted) {
+        Ok(ip) => ip,
+        Err(e) => {
+            debug!("Rejected a request from {}: {}", peer, e);
+            let body = format!("{}\n", e);
+            return respond(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                &body,
+                with_body,
+            )
+            .await;
+        }
+    };
+    if request.wants_json() {
+        let body = serde_json::json!({ "ip": ip.to_string() }).to_string();
+        respond(&mut stream, "200 OK", "application/json", &body, with_body).await
+    } else {
+        let body = format!("{}\n", ip);
+        respond(&mut stream, "200 OK", "text/plain", &body, with_body).await
+    }
+}
+
+/// Listens on `addr`, an IPv6 listener only taking IPv6 connections so that
+/// it can share its port with an IPv4 one.
+fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+async fn serve(listener: TcpListener, trusted: usize) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(async move {
+                    match tokio::time::timeout(EXCHANGE_TIMEOUT, handle(stream, peer, trusted))
+                        .await
+                    {
+                        Ok(Err(e)) => debug!("Unable to answer {}: {}", peer, e),
+                        Err(_) => debug!("{} took too long to send its request", peer),
+                        Ok(Ok(())) => {}
+                    }
+                });
+            }
+            Err(e) => warn!("Unable to accept a connection: {}", e),
+        }
+    }
+}
+
+/// Runs the server until the process is stopped. Without `--listen`, a
+/// host without IPv6 only listens on IPv4.
+pub async fn run(args: &EchoServerArgs) -> Result<(), Box<dyn Error>> {
+    let defaults = args.listen.is_empty();
+    let addrs = match defaults {
+        true => vec![
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DEFAULT_PORT),
+            SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), DEFAULT_PORT),
+        ],
+        false => args.listen.clone(),
+    };
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        match bind(addr) {
+            Ok(listener) => {
+                info!("Answering the source addresses on {}", addr);
+                listeners.push(listener);
+            }
+            Err(e) if defaults => warn!("Unable to listen on {}: {}", addr, e),
+            Err(e) => return Err(format!("unable to listen on {}: {}", addr, e).into()),
+        }
+    }
+    if listeners.is_empty() {
+        return Err("unable to listen on any address".into());
+    }
+    join_all(
+        listeners
+            .into_iter()
+            .map(|listener| serve(listener, args.trusted_proxies)),
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn answers_the_peer_without_trusted_proxies() {
+        assert_eq!(client_address(PEER, &["203.0.113.9"], 0), Ok(PEER));
+        // Mapped addresses of dual-stack sockets are answered as IPv4.
+        assert_eq!(
+            client_address(ip("::ffff:192.0.2.1"), &[], 0),
+            Ok(ip("192.0.2.1"))
+        );
+    }
+
+    #[test]
+    fn trusts_as_many_entries_as_proxies() {
+        assert_eq!(
+            client_address(PEER, &["198.51.100.4"], 1),
+            Ok(ip("198.51.100.4"))
+        );
+        // The client, then the first proxy seen by the second.
+        assert_eq!(
+            client_address(PEER, &["198.51.100.4, 10.0.0.1"], 2),
+            Ok(ip("198.51.100.4"))
+        );
+        // The same split over several headers.
+        assert_eq!(
+            client_address(PEER, &["198.51.100.4", "10.0.0.1"], 2),
+            Ok(ip("198.51.100.4"))
+        );
+    }
+
+    #[test]
+    fn ignores_the_entries_forged_by_the_client() {
+        assert_eq!(
+            client_address(PEER, &["1.1.1.1, 8.8.8.8, 198.51.100.4"], 1),
+            Ok(ip("198.51.100.4"))
+        );
+        assert_eq!(
+            client_address(PEER, &["1.1.1.1", "198.51.100.4, 10.0.0.1"], 2),
+            Ok(ip("198.51.100.4"))
+        );
+    }
+
+    #[test]
+    fn rejects_chains_shorter_than_the_proxies() {
+        assert_eq!(
+            client_address(PEER, &[], 1).unwrap_err(),
+            "X-Forwarded-For holds 0 addresses, fewer than the 1 trusted proxies"
+        );
+        assert!(client_address(PEER, &["198.51.100.4"], 2).is_err());
+        assert_eq!(
+            client_address(PEER, &["unknown"], 1).unwrap_err(),
+            "invalid X-Forwarded-For entry 'unknown'"
+        );
+    }
+
+    #[test]
+    fn parses_entries_with_a_port() {
+        assert_eq!(parse_forwarded(" 198.51.100.4 "), Ok(ip("198.51.100.4")));
+        assert_eq!(parse_forwarded("198.51.100.4:5060"), Ok(ip("198.51.100.4")));
+        assert_eq!(parse_forwarded("2001:db8::1"), Ok(ip("2001:db8::1")));
+        assert_eq!(parse_forwarded("[2001:db8::1]"), Ok(ip("2001:db8::1")));
+        assert_eq!(parse_forwarded("[2001:db8::1]:443"), Ok(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn tells_when_json_is_wanted() {
+        let wants_json = |head: &str| Request::parse(head).unwrap().wants_json();
+        assert!(!wants_json("GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(wants_json("GET /json HTTP/1.1\r\n\r\n"));
+        assert!(wants_json("GET /?format=json HTTP/1.1\r\n\r\n"));
+        assert!(wants_json(
+            "GET / HTTP/1.1\r\nAccept: application/json\r\n\r\n"
+        ));
+        assert!(Request::parse("\r\n\r\n").is_none());
+    }
+
+    /// Sends `head` to `addr`, returning the answer.
+    async fn exchange(addr: SocketAddr, head: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(head.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn answers_on_concurrent_listeners() {
+        let v4 = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+        let v4_addr = v4.local_addr().unwrap();
+        let v6_addr = SocketAddr::from((Ipv6Addr::LOCALHOST, v4_addr.port()));
+        // Sharing the port, unless the host has no IPv6 loopback.
+        let v6 = bind(v6_addr).ok();
+        tokio::spawn(serve(v4, 1));
+        let has_v6 = v6.is_some();
+        if let Some(v6) = v6 {
+            tokio::spawn(serve(v6, 1));
+        }
+
+        let requests = (0..8).map(|i| async move {
+            let client = format!("198.51.100.{}", i);
+            let head = format!(
+                "GET /json HTTP/1.1\r\nX-Forwarded-For: 1.1.1.1, {}\r\n\r\n",
+                client
+            );
+            let addr = if has_v6 && i % 2 == 1 {
+                v6_addr
+            } else {
+                v4_addr
+            };
+            (client, exchange(addr, &head).await)
+        });
+        for (client, response) in join_all(requests).await {
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with(&format!("{{\"ip\":\"{}\"}}", client)));
+        }
+
+        let response = exchange(v4_addr, "GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let response = exchange(v4_addr, "POST / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
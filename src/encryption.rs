@@ -0,0 +1,100 @@
+//! Configuration files encrypted with age, so that the API key is not
+//! stored in plaintext.
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::{Decryptor, Encryptor, Identity, IdentityFile, Recipient};
+use log::info;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const BINARY_HEADER: &[u8] = b"age-encryption.org/v1";
+const ARMOR_HEADER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// Whether `contents` is an age file, binary or armored.
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    let start = contents.trim_ascii_start();
+    start.starts_with(BINARY_HEADER) || start.starts_with(ARMOR_HEADER)
+}
+
+/// `~/.config/ddns-gandi/key.age`, following `XDG_CONFIG_HOME`.
+fn default_key_file() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("ddns-gandi/key.age"));
+    }
+    env::var_os("HOME")
+        .filter(|d| !d.is_empty())
+        .map(|home| PathBuf::from(home).join(".config/ddns-gandi/key.age"))
+}
+
+/// Reads the identities from the file named by `AGE_KEY_FILE`, from
+/// `SOPS_AGE_KEY` as sops does, or from the default key file.
+fn identity_file() -> Result<IdentityFile<age::NoCallbacks>, String> {
+    if let Some(path) = env::var_os("AGE_KEY_FILE").filter(|p| !p.is_empty()) {
+        let path = PathBuf::from(path);
+        return IdentityFile::from_file(path.to_string_lossy().into_owned())
+            .map_err(|e| format!("AGE_KEY_FILE {}: {}", path.display(), e));
+    }
+    if let Ok(keys) = env::var("SOPS_AGE_KEY") {
+        return IdentityFile::from_buffer(keys.as_bytes())
+            .map_err(|e| format!("SOPS_AGE_KEY: {}", e));
+    }
+    match default_key_file() {
+        Some(path) if path.exists() => IdentityFile::from_file(path.to_string_lossy().into_owned())
+            .map_err(|e| format!("{}: {}", path.display(), e)),
+        _ => Err("no age key: set AGE_KEY_FILE or SOPS_AGE_KEY, \
+                  or create ~/.config/ddns-gandi/key.age"
+            .to_string()),
+    }
+}
+
+/// Decrypts an age file with the configured identities.
+pub fn decrypt(contents: &[u8]) -> Result<String, String> {
+    let identities = identity_file()?
+        .into_identities()
+        .map_err(|e| e.to_string())?;
+    let decryptor = Decryptor::new(ArmoredReader::new(contents)).map_err(|e| e.to_string())?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
+        .map_err(|e| e.to_string())?;
+    let mut plaintext = String::new();
+    reader
+        .read_to_string(&mut plaintext)
+        .map_err(|e| e.to_string())?;
+    Ok(plaintext)
+}
+
+/// Encrypts `plaintext` as an armored age file to the recipients of the
+/// configured identities, so that they can decrypt it again.
+pub fn encrypt(plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let recipients = identity_file()?
+        .to_recipients()
+        .map_err(|e| e.to_string())?;
+    let encryptor =
+        Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn Recipient))
+            .map_err(|e| e.to_string())?;
+
+    let write = || -> io::Result<Vec<u8>> {
+        let armored = ArmoredWriter::wrap_output(Vec::new(), Format::AsciiArmor)?;
+        let mut writer = encryptor.wrap_output(armored)?;
+        writer.write_all(plaintext)?;
+        writer.finish()?.finish()
+    };
+    write().map_err(|e| e.to_string())
+}
+
+/// Handles the `encrypt-config` subcommand, writing the encrypted `config`
+/// to `output`.
+pub fn handle(config: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let contents = fs::read(config)?;
+    if is_encrypted(&contents) {
+        return Err(format!("{} is already encrypted", config.display()).into());
+    }
+    // Catch mistakes before they are hidden by the encryption.
+    toml::from_str::<toml::Table>(std::str::from_utf8(&contents)?)?;
+    fs::write(output, encrypt(&contents)?)?;
+    info!("Wrote {}", output.display());
+    Ok(())
+}
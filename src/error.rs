@@ -0,0 +1,103 @@
+use crate::pinning;
+use reqwest::StatusCode;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DdnsError {
+    #[error("request failed: {0}")]
+    Http(reqwest::Error),
+    #[error("the certificate of the Gandi API matches none of HTTP.gandi_spki_pins, the credential was not sent: {0}")]
+    PinMismatch(reqwest::Error),
+    /// The status and the message of the response body.
+    #[error("Gandi responded with status {0}: {1}")]
+    Api(StatusCode, String),
+    #[error("credential rejected — check GANDI.key / PAT expiry: {0}")]
+    Unauthorized(String),
+    #[error("the credential lacks the LiveDNS scope (\"Manage domain name technical configurations\"): {0}")]
+    MissingScope(String),
+    #[error("the domain belongs to another organization — set GANDI.sharing_id to its organization ID: {0}")]
+    WrongOrganization(String),
+    #[error("the domain is not served by Gandi LiveDNS: {0}")]
+    NotOnLiveDns(String),
+    #[error("access denied: {0}")]
+    Forbidden(String),
+    #[error(
+        "the zone changed since it was fetched, so it was not replaced; the next pass updates it"
+    )]
+    ZoneChanged,
+    #[error("unable to fetch the configuration: {0}")]
+    ConfigFetch(String),
+    #[error("invalid configuration: {0}")]
+    Config(String),
+}
+
+impl DdnsError {
+    /// Whether the failure is likely transient (network problems, server
+    /// errors and rate limiting) and thus worth retrying.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DdnsError::Http(_) => true,
+            DdnsError::Api(status, _) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the credential was refused for the domain. Such errors repeat
+    /// for every record, so the run should stop at the first one.
+    pub fn is_auth(&self) -> bool {
+        matches!(
+            self,
+            DdnsError::Unauthorized(_)
+                | DdnsError::PinMismatch(_)
+                | DdnsError::MissingScope(_)
+                | DdnsError::WrongOrganization(_)
+                | DdnsError::NotOnLiveDns(_)
+                | DdnsError::Forbidden(_)
+        )
+    }
+
+    /// The class of the failure, by which the throttle of the notifications
+    /// tells apart e.g. an outage from a revoked credential.
+    pub fn class(&self) -> &'static str {
+        match self {
+            DdnsError::Http(_) => "network",
+            DdnsError::Api(status, _) if *status == StatusCode::TOO_MANY_REQUESTS => "rate_limit",
+            DdnsError::Api(status, _) if status.is_server_error() => "server",
+            DdnsError::Api(..) => "api",
+            DdnsError::PinMismatch(_) => "pin",
+            DdnsError::Unauthorized(_)
+            | DdnsError::MissingScope(_)
+            | DdnsError::WrongOrganization(_)
+            | DdnsError::NotOnLiveDns(_)
+            | DdnsError::Forbidden(_) => "auth",
+            DdnsError::ZoneChanged => "conflict",
+            DdnsError::ConfigFetch(_) | DdnsError::Config(_) => "config",
+        }
+    }
+
+    /// Classifies a 403 response from the message in its body.
+    pub fn forbidden(message: String) -> Self {
+        let lower = message.to_lowercase();
+        if lower.contains("scope") || lower.contains("permission") {
+            DdnsError::MissingScope(message)
+        } else if lower.contains("organization") || lower.contains("sharing") {
+            DdnsError::WrongOrganization(message)
+        } else if lower.contains("livedns") || lower.contains("nameserver") {
+            DdnsError::NotOnLiveDns(message)
+        } else {
+            DdnsError::Forbidden(message)
+        }
+    }
+}
+
+impl From<reqwest::Error> for DdnsError {
+    fn from(error: reqwest::Error) -> Self {
+        if pinning::is_mismatch(&error) {
+            DdnsError::PinMismatch(error)
+        } else {
+            DdnsError::Http(error)
+        }
+    }
+}
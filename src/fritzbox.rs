@@ -0,0 +1,421 @@
+//! Asking a FRITZ!Box for its public addresses over TR-064, instead of an
+//! external service.
+//!
+//! Without credentials the UPnP IGD actions are used, which the router
+//! answers if "Transmit status information over UPnP" is enabled. With
+//! credentials the TR-064 actions are used, authenticated with HTTP digest
+//! authentication.
+
+use crate::ip::{in_prefix, IpVersion};
+use crate::soap::{element, envelope, fault};
+use crate::timeouts::Timeouts;
+use md5::{Digest, Md5};
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use reqwest::StatusCode;
+use std::net::Ipv6Addr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_PORT: u16 = 49000;
+/// Bound of the requests unless `HTTP.ip_detection_timeout` is set, as
+/// the router is on the local network.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const IGD_CONTROL: &str = "/igdupnp/control/WANIPConn1";
+const IGD_SERVICE: &str = "urn:schemas-upnp-org:service:WANIPConnection:1";
+const TR064_CONTROL: &str = "/upnp/control/wanipconnection1";
+const TR064_SERVICE: &str = "urn:dslforum-org:service:WANIPConnection:1";
+
+/// A FRITZ!Box detecting the addresses of a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FritzBox {
+    /// Host name or address of the router, with an optional port, or its
+    /// URL.
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Interface identifier of the host, published in the delegated prefix
+    /// instead of the address of the router itself.
+    pub ipv6_suffix: Option<Ipv6Addr>,
+}
+
+impl FritzBox {
+    fn base_url(&self) -> String {
+        if self.address.contains("://") {
+            self.address.trim_end_matches('/').to_string()
+        } else if self.address.contains(':') {
+            format!("http://{}", self.address)
+        } else {
+            format!("http://{}:{}", self.address, DEFAULT_PORT)
+        }
+    }
+
+    /// The public address of the family `version`, as the router reports
+    /// it, unvalidated.
+    pub async fn external_ip(
+        &self,
+        version: IpVersion,
+        timeouts: Timeouts,
+    ) -> Result<String, String> {
+        match (version, self.ipv6_suffix) {
+            (IpVersion::V4, _) => {
+                let response = self.call("GetExternalIPAddress", timeouts).await?;
+                element(&response, "NewExternalIPAddress")
+            }
+            (IpVersion::V6, None) => {
+                let response = self
+                    .call("X_AVM_DE_GetExternalIPv6Address", timeouts)
+                    .await?;
+                element(&response, "NewExternalIPv6Address")
+            }
+            (IpVersion::V6, Some(suffix)) => {
+                let response = self.call("X_AVM_DE_GetIPv6Prefix", timeouts).await?;
+                let prefix = element(&response, "NewIPv6Prefix")?;
+                let length = element(&response, "NewPrefixLength")?;
+                in_delegated_prefix(&prefix, &length, suffix)
+            }
+        }
+    }
+
+    async fn call(&self, action: &str, timeouts: Timeouts) -> Result<String, String> {
+        let (control, service) = if self.username.is_some() {
+            (TR064_CONTROL, TR064_SERVICE)
+        } else {
+            (IGD_CONTROL, IGD_SERVICE)
+        };
+        let timeout = timeouts.ip_detection.unwrap_or(TIMEOUT);
+        let client = timeouts
+            .client(Some(timeout))
+            .build()
+            .map_err(|e| e.to_string())?;
+        let url = format!("{}{}", self.base_url(), control);
+        let body = envelope(service, action);
+        let request = || {
+            client
+                .post(&url)
+                .header(CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+                .header("SOAPAction", format!("\"{}#{}\"", service, action))
+                .body(body.clone())
+        };
+
+        let mut response = request().send().await.map_err(|e| e.to_string())?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let credentials = self.username.as_deref().zip(self.password.as_deref());
+            let challenge = response
+                .headers()
+                .get(WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let (Some((username, password)), Some(challenge)) = (credentials, challenge) else {
+                return Err("the router asks for a username and a password".to_string());
+            };
+            let authorization = digest_authorization(&challenge, username, password, control)?;
+            response = request()
+                .header(AUTHORIZATION, authorization)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+
+        let status = response.status();
+        let text = response.text().await.map_err(|e| e.to_string())?;
+        if !status.is_success() {
+            return Err(match fault(&text) {
+                Some(fault) => format!("{} failed: {}", action, fault),
+                None => format!("{} failed with status {}", action, status),
+            });
+        }
+        Ok(text)
+    }
+}
+
+/// The address made of the delegated `prefix` of `length` bits and the
+/// interface identifier `suffix`.
+fn in_delegated_prefix(prefix: &str, length: &str, suffix: Ipv6Addr) -> Result<String, String> {
+    let prefix: Ipv6Addr = prefix
+        .parse()
+        .map_err(|_| format!("'{}' is not an IPv6 prefix", prefix))?;
+    let length: u8 = length
+        .parse()
+        .ok()
+        .filter(|length| *length <= 128)
+        .ok_or_else(|| format!("'{}' is not a prefix length", length))?;
+    if prefix.is_unspecified() || length == 0 {
+        return Err("the router has no IPv6 prefix".to_string());
+    }
+    Ok(in_prefix(prefix, length, suffix).to_string())
+}
+
+/// The parameters of a `WWW-Authenticate` challenge, e.g.
+/// `Digest realm="HTTPS Access",nonce="…",qop="auth"`.
+fn challenge_param(challenge: &str, name: &str) -> Option<String> {
+    let params = challenge.trim().strip_prefix("Digest")?;
+    let mut rest = params.trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim().trim_start_matches(',').trim();
+        rest = &rest[eq + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            rest = &quoted[end + 1..];
+            &quoted[..end]
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value.trim()
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(value.to_string());
+        }
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    None
+}
+
+fn md5_hex(input: &str) -> String {
+    format!("{:x}", Md5::digest(input.as_bytes()))
+}
+
+/// Answers the digest authentication `challenge` (RFC 7616, MD5).
+fn digest_authorization(
+    challenge: &str,
+    username: &str,
+    password: &str,
+    uri: &str,
+) -> Result<HeaderValue, String> {
+    let realm = challenge_param(challenge, "realm")
+        .ok_or_else(|| format!("unsupported authentication challenge '{}'", challenge))?;
+    let nonce = challenge_param(challenge, "nonce")
+        .ok_or_else(|| format!("unsupported authentication challenge '{}'", challenge))?;
+    let cnonce = format!(
+        "{:x}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    );
+    let ha1 = md5_hex(&format!("{}:{}:{}", username, realm, password));
+    let ha2 = md5_hex(&format!("POST:{}", uri));
+    let value = match challenge_param(challenge, "qop") {
+        Some(qop) if qop.split(',').any(|q| q.trim() == "auth") => {
+            let response = md5_hex(&format!(
+                "{}:{}:00000001:{}:auth:{}",
+                ha1, nonce, cnonce, ha2
+            ));
+            format!(
+                "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm=MD5, qop=auth, nc=00000001, cnonce=\"{}\", response=\"{}\"",
+                username, realm, nonce, uri, cnonce, response
+            )
+        }
+        _ => {
+            let response = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+            format!(
+                "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+                username, realm, nonce, uri, response
+            )
+        }
+    };
+    HeaderValue::from_str(&value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const IPV6_PREFIX: &str = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:X_AVM_DE_GetIPv6PrefixResponse xmlns:u="urn:dslforum-org:service:WANIPConnection:1">
+<NewIPv6Prefix>2001:db8:1234:5600::</NewIPv6Prefix>
+<NewPrefixLength>56</NewPrefixLength>
+<NewValidLifetime>7200</NewValidLifetime>
+<NewPreferredLifetime>3600</NewPreferredLifetime>
+</u:X_AVM_DE_GetIPv6PrefixResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    const CHALLENGE: &str =
+        r#"Digest realm="HTTPS Access",nonce="E2A1B43D8F3C7C05",algorithm=MD5,qop="auth""#;
+
+    /// Answers the requests in turn with a status, headers and a body, and
+    /// returns its address along with the requests received.
+    async fn router(
+        responses: Vec<(&'static str, &'static str, &'static str)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for (status, headers, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !String::from_utf8_lossy(&request).contains("</s:Envelope>") {
+                    match stream.read(&mut buf).await.unwrap() {
+                        0 => break,
+                        n => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                requests.push(String::from_utf8_lossy(&request).to_string());
+                let response = format!(
+                    "HTTP/1.1 {}\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    headers,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (address, handle)
+    }
+
+    fn fritzbox(address: String, credentials: bool) -> FritzBox {
+        FritzBox {
+            address,
+            username: credentials.then(|| "ddns".to_string()),
+            password: credentials.then(|| "secret".to_string()),
+            ipv6_suffix: None,
+        }
+    }
+
+    #[test]
+    fn completes_the_address_of_the_router() {
+        let url = |address: &str| fritzbox(address.to_string(), false).base_url();
+        assert_eq!(url("fritz.box"), "http://fritz.box:49000");
+        assert_eq!(url("192.168.178.1:49443"), "http://192.168.178.1:49443");
+        assert_eq!(url("https://fritz.box:49443/"), "https://fritz.box:49443");
+    }
+
+    #[test]
+    fn publishes_the_suffix_in_the_delegated_prefix() {
+        let prefix = element(IPV6_PREFIX, "NewIPv6Prefix").unwrap();
+        let length = element(IPV6_PREFIX, "NewPrefixLength").unwrap();
+        let suffix: Ipv6Addr = "::1:2:3:4".parse().unwrap();
+        assert_eq!(
+            in_delegated_prefix(&prefix, &length, suffix).unwrap(),
+            "2001:db8:1234:5600:1:2:3:4"
+        );
+        assert_eq!(
+            in_delegated_prefix("::", "0", suffix).unwrap_err(),
+            "the router has no IPv6 prefix"
+        );
+        assert!(in_delegated_prefix(&prefix, "129", suffix).is_err());
+        assert!(in_delegated_prefix("fritz.box", &length, suffix).is_err());
+    }
+
+    #[test]
+    fn reads_the_parameters_of_a_challenge() {
+        assert_eq!(
+            challenge_param(CHALLENGE, "realm").as_deref(),
+            Some("HTTPS Access")
+        );
+        assert_eq!(
+            challenge_param(CHALLENGE, "nonce").as_deref(),
+            Some("E2A1B43D8F3C7C05")
+        );
+        assert_eq!(
+            challenge_param(CHALLENGE, "ALGORITHM").as_deref(),
+            Some("MD5")
+        );
+        assert_eq!(challenge_param(CHALLENGE, "opaque"), None);
+        assert_eq!(challenge_param("Basic realm=\"x\"", "realm"), None);
+    }
+
+    #[test]
+    fn answers_a_digest_challenge() {
+        // RFC 2617 section 3.5.
+        assert_eq!(
+            md5_hex("Mufasa:testrealm@host.com:Circle Of Life"),
+            "939e7578ed9e3c518a452acee763bce9"
+        );
+
+        let challenge = r#"Digest realm="HTTPS Access", nonce="E2A1B43D8F3C7C05""#;
+        let authorization =
+            digest_authorization(challenge, "ddns", "secret", TR064_CONTROL).unwrap();
+        let ha1 = md5_hex("ddns:HTTPS Access:secret");
+        let ha2 = md5_hex(&format!("POST:{}", TR064_CONTROL));
+        let response = md5_hex(&format!("{}:E2A1B43D8F3C7C05:{}", ha1, ha2));
+        assert_eq!(
+            authorization.to_str().unwrap(),
+            format!(
+                "Digest username=\"ddns\", realm=\"HTTPS Access\", nonce=\"E2A1B43D8F3C7C05\", uri=\"{}\", response=\"{}\"",
+                TR064_CONTROL, response
+            )
+        );
+
+        let authorization =
+            digest_authorization(CHALLENGE, "ddns", "secret", TR064_CONTROL).unwrap();
+        let authorization = authorization.to_str().unwrap();
+        assert!(authorization.contains("qop=auth, nc=00000001, cnonce=\""));
+        assert!(digest_authorization("Digest qop=\"auth\"", "ddns", "secret", "/").is_err());
+    }
+
+    #[tokio::test]
+    async fn asks_for_the_external_ipv4_over_upnp() {
+        let body = r#"<s:Envelope><s:Body><u:GetExternalIPAddressResponse><NewExternalIPAddress>203.0.113.7</NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body></s:Envelope>"#;
+        let (address, requests) = router(vec![("200 OK", "", body)]).await;
+        let ip = fritzbox(address, false)
+            .external_ip(IpVersion::V4, Timeouts::default())
+            .await
+            .unwrap();
+        assert_eq!(ip, "203.0.113.7");
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with(&format!("POST {} HTTP/1.1", IGD_CONTROL)));
+        assert!(requests[0].contains(&format!(
+            "soapaction: \"{}#GetExternalIPAddress\"",
+            IGD_SERVICE
+        )));
+    }
+
+    #[tokio::test]
+    async fn authenticates_to_ask_for_the_delegated_prefix_over_tr064() {
+        let challenge = "www-authenticate: Digest realm=\"HTTPS Access\",nonce=\"E2A1B43D8F3C7C05\",qop=\"auth\"\r\n";
+        let (address, requests) = router(vec![
+            ("401 Unauthorized", challenge, ""),
+            ("200 OK", "", IPV6_PREFIX),
+        ])
+        .await;
+        let fritzbox = FritzBox {
+            ipv6_suffix: Some("::1".parse().unwrap()),
+            ..fritzbox(address, true)
+        };
+        let ip = fritzbox
+            .external_ip(IpVersion::V6, Timeouts::default())
+            .await
+            .unwrap();
+        assert_eq!(ip, "2001:db8:1234:5600::1");
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with(&format!("POST {} HTTP/1.1", TR064_CONTROL)));
+        assert!(!requests[0].contains("authorization:"));
+        assert!(requests[1].contains("authorization: Digest username=\"ddns\""));
+    }
+
+    #[tokio::test]
+    async fn reports_the_faults_of_the_router() {
+        let body = r#"<s:Envelope><s:Body><s:Fault><faultstring>UPnPError</faultstring><detail><UPnPError><errorCode>401</errorCode><errorDescription>Invalid Action</errorDescription></UPnPError></detail></s:Fault></s:Body></s:Envelope>"#;
+        let (address, _) = router(vec![("500 Internal Server Error", "", body)]).await;
+        let error = fritzbox(address, false)
+            .external_ip(IpVersion::V6, Timeouts::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "X_AVM_DE_GetExternalIPv6Address failed: Invalid Action (401)"
+        );
+    }
+
+    #[tokio::test]
+    async fn asks_for_credentials_the_router_wants() {
+        let challenge = "www-authenticate: Digest realm=\"HTTPS Access\",nonce=\"1\"\r\n";
+        let (address, _) = router(vec![("401 Unauthorized", challenge, "")]).await;
+        let error = fritzbox(address, false)
+            .external_ip(IpVersion::V4, Timeouts::default())
+            .await
+            .unwrap_err();
+        assert_eq!(error, "the router asks for a username and a password");
+    }
+}
@@ -0,0 +1,977 @@
+use crate::dns_type::DnsType;
+use crate::drift::drifted;
+use crate::error::DdnsError;
+use crate::ip::{canonical, foreign_values, holds_only};
+use crate::pacing::{ApiRateLimiter, RateLimitInfo};
+use crate::telemetry;
+use crate::timeouts::Timeouts;
+use crate::timing::{self, timed, Operation};
+use crate::tls;
+use log::{debug, info, warn};
+use reqwest::header::{HeaderValue, InvalidHeaderValue, AUTHORIZATION};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub const REST_URL: &str = "https://api.gandi.net/v5/livedns/";
+/// TTL written when adaptive TTLs are disabled.
+pub const RRSET_TTL: u32 = 1800;
+
+/// Permission a Personal Access Token needs to manage LiveDNS records.
+pub const LIVEDNS_PERMISSION: &str = "Manage domain name technical configurations";
+/// Where Personal Access Tokens are created.
+pub const TOKEN_ADMIN_URL: &str = "https://admin.gandi.net/organizations/";
+
+/// A record update to be applied as part of a batch.
+#[derive(Debug, Clone)]
+pub struct RecordUpdate {
+    pub name: String,
+    pub dns_type: DnsType,
+    pub value: String,
+    /// TTL written along with a new value.
+    pub ttl: u32,
+    /// TTL to enforce while the value stays the same, if any.
+    pub steady_ttl: Option<u32>,
+    /// Value last written, if a record that drifted from it is to be left
+    /// alone.
+    pub written: Option<String>,
+    /// Whether to leave the rrset alone if it holds values that do not look
+    /// like its dynamic address.
+    pub protect: bool,
+}
+
+/// What a batch update did to each of the requested records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchItem {
+    /// The rrset was replaced; holds its previous values and TTL.
+    Changed(Vec<String>, Option<u32>),
+    /// Only the TTL of the rrset was adjusted.
+    TtlChanged,
+    Unchanged,
+    Missing,
+    Empty,
+    /// The name is an alias, which cannot have rrsets of other types;
+    /// holds its target.
+    Cname(String),
+    /// The rrset was left alone, since someone else changed it since it
+    /// was last written; holds its values.
+    Drifted(Vec<String>),
+    /// The rrset was left alone, since it holds values that do not look
+    /// like its dynamic address; holds why.
+    Protected(String),
+}
+
+/// An rrset as returned by the record endpoint, named after Gandi's
+/// `rrset_*` fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GandiRrset {
+    #[serde(rename = "rrset_name")]
+    pub name: String,
+    #[serde(rename = "rrset_type")]
+    pub record_type: DnsType,
+    #[serde(rename = "rrset_ttl")]
+    pub ttl: u32,
+    #[serde(rename = "rrset_values", default)]
+    pub values: Vec<String>,
+    #[serde(
+        rename = "rrset_href",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub href: Option<String>,
+}
+
+/// A snapshot of a zone, taken by Gandi before changes made on its website
+/// or on request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub created_at: String,
+    /// Whether Gandi took it by itself.
+    #[serde(default)]
+    pub automatic: bool,
+    /// Only returned when fetching a single snapshot.
+    #[serde(default)]
+    zone_data: Option<Vec<ZoneRrset>>,
+}
+
+impl Snapshot {
+    /// Number of rrsets in the snapshot, if they were fetched.
+    pub fn record_count(&self) -> Option<usize> {
+        self.zone_data.as_ref().map(Vec::len)
+    }
+}
+
+/// Domains asked for per page by [`list_domains`].
+const DOMAINS_PER_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct LiveDnsDomain {
+    fqdn: String,
+}
+
+/// Lists the domains of the account whose zones LiveDNS serves, going
+/// through all pages.
+pub async fn list_domains(api: &GandiApi) -> Result<Vec<String>, DdnsError> {
+    let mut domains = Vec::new();
+    let mut page = 1;
+    loop {
+        let url = format!(
+            "{}domains?page={}&per_page={}",
+            REST_URL, page, DOMAINS_PER_PAGE
+        );
+        let response = api.send(Method::GET, &url, None).await?;
+        if !response.status().is_success() {
+            return Err(error_from_response(response).await);
+        }
+        let listed: Vec<LiveDnsDomain> = response.json().await?;
+        let last = listed.len() < DOMAINS_PER_PAGE;
+        domains.extend(listed.into_iter().map(|d| d.fqdn.to_ascii_lowercase()));
+        if last {
+            return Ok(domains);
+        }
+        page += 1;
+    }
+}
+
+/// Lists the snapshots of the zone of `domain`, without their rrsets.
+pub async fn list_snapshots(domain: &str, api: &GandiApi) -> Result<Vec<Snapshot>, DdnsError> {
+    let url = format!("{}domains/{}/snapshots", REST_URL, domain);
+
+    let response = api.send(Method::GET, &url, None).await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+    Ok(response.json().await?)
+}
+
+/// Fetches a snapshot with its rrsets, or `None` if it does not exist.
+pub async fn get_snapshot(
+    domain: &str,
+    id: &str,
+    api: &GandiApi,
+) -> Result<Option<Snapshot>, DdnsError> {
+    let url = format!("{}domains/{}/snapshots/{}", REST_URL, domain, id);
+
+    let response = api.send(Method::GET, &url, None).await?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    Ok(Some(response.json().await?))
+}
+
+/// Replaces the whole zone of `domain` with the rrsets of `snapshot`, as
+/// fetched by [`get_snapshot`]. LiveDNS has no endpoint restoring a
+/// snapshot, so this is done with the zone records endpoint.
+pub async fn restore_snapshot(
+    domain: &str,
+    snapshot: &Snapshot,
+    api: &GandiApi,
+) -> Result<(), DdnsError> {
+    let url = format!("{}domains/{}/records", REST_URL, domain);
+    let items = snapshot.zone_data.as_deref().unwrap_or_default();
+
+    let payload = serde_json::json!({ "items": items });
+    let response = api.send(Method::PUT, &url, Some(&payload)).await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    Ok(())
+}
+
+/// An rrset as listed by the zone records endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ZoneRrset {
+    rrset_name: String,
+    rrset_type: DnsType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rrset_ttl: Option<u32>,
+    rrset_values: Vec<String>,
+}
+
+/// How the credential is presented in the `Authorization` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// `Bearer <token>`, used by Personal Access Tokens.
+    Bearer,
+    /// `Apikey <key>`, used by legacy API keys.
+    ApiKey,
+    /// The bare credential in an `X-Api-Key` header instead, which some
+    /// proxies in front of the API expect. Only used when configured.
+    XApiKey,
+}
+
+impl AuthScheme {
+    /// Legacy API keys are 24 characters long, tokens are longer.
+    fn guess(key: &str) -> Self {
+        if key.len() == 24 {
+            AuthScheme::ApiKey
+        } else {
+            AuthScheme::Bearer
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            AuthScheme::Bearer => AuthScheme::ApiKey,
+            AuthScheme::ApiKey => AuthScheme::Bearer,
+            AuthScheme::XApiKey => AuthScheme::XApiKey,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthScheme::Bearer => "bearer",
+            AuthScheme::ApiKey => "apikey",
+            AuthScheme::XApiKey => "x-api-key",
+        }
+    }
+}
+
+impl FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bearer" => Ok(AuthScheme::Bearer),
+            "apikey" | "api-key" => Ok(AuthScheme::ApiKey),
+            "x-api-key" => Ok(AuthScheme::XApiKey),
+            other => Err(format!("unknown authorization scheme '{}'", other)),
+        }
+    }
+}
+
+/// Connection details shared by all calls to the LiveDNS API.
+///
+/// Unless the scheme is configured, the one guessed from the credential's
+/// format is tried first and the other one on a 401. The first scheme the
+/// API accepts is then used for the rest of the run.
+///
+/// Every request, including the retry with the other scheme, waits for its
+/// turn on the rate limiter, which may be shared with other clients.
+///
+/// Clones share the accepted scheme and the rate limiter, e.g. to make
+/// requests from other tasks.
+#[derive(Debug, Clone)]
+pub struct GandiApi {
+    client: reqwest::Client,
+    bearer: HeaderValue,
+    apikey: HeaderValue,
+    /// The bare credential if it is sent as `X-Api-Key`.
+    x_api_key: Option<HeaderValue>,
+    sharing_id: Option<String>,
+    use_apikey: Arc<AtomicBool>,
+    settled: Arc<AtomicBool>,
+    limiter: Arc<ApiRateLimiter>,
+}
+
+impl GandiApi {
+    pub fn new(
+        key: &str,
+        sharing_id: Option<String>,
+        auth_method: Option<AuthScheme>,
+        limiter: Arc<ApiRateLimiter>,
+        timeouts: Timeouts,
+    ) -> Result<Self, InvalidHeaderValue> {
+        let scheme = auth_method.unwrap_or_else(|| AuthScheme::guess(key));
+
+        Ok(GandiApi {
+            // Like `Client::new`, only failing if TLS is unavailable.
+            client: tls::configure_gandi(timeouts.client(timeouts.api_call))
+                .build()
+                .unwrap_or_default(),
+            bearer: HeaderValue::from_str(&format!("Bearer {}", key))?,
+            apikey: HeaderValue::from_str(&format!("Apikey {}", key))?,
+            x_api_key: match scheme {
+                AuthScheme::XApiKey => Some(HeaderValue::from_str(key)?),
+                _ => None,
+            },
+            sharing_id,
+            use_apikey: Arc::new(AtomicBool::new(scheme == AuthScheme::ApiKey)),
+            settled: Arc::new(AtomicBool::new(auth_method.is_some())),
+            limiter,
+        })
+    }
+
+    /// The same API reached through `client`, e.g. one resolving it to a
+    /// local server.
+    #[cfg(test)]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn scheme(&self) -> AuthScheme {
+        if self.x_api_key.is_some() {
+            AuthScheme::XApiKey
+        } else if self.use_apikey.load(Ordering::Relaxed) {
+            AuthScheme::ApiKey
+        } else {
+            AuthScheme::Bearer
+        }
+    }
+
+    fn request(&self, method: Method, url: &str, scheme: AuthScheme) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        let request = match (scheme, &self.x_api_key) {
+            (AuthScheme::XApiKey, Some(key)) => request.header("X-Api-Key", key.clone()),
+            (AuthScheme::ApiKey, _) => request.header(AUTHORIZATION, self.apikey.clone()),
+            _ => request.header(AUTHORIZATION, self.bearer.clone()),
+        };
+        match &self.sharing_id {
+            Some(sharing_id) => request.query(&[("sharing_id", sharing_id)]),
+            None => request,
+        }
+    }
+
+    /// Sends a request, retrying once with the other scheme on a 401 until
+    /// a scheme has been accepted.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&Value>,
+    ) -> Result<Response, reqwest::Error> {
+        let operation = match method {
+            Method::PUT => Operation::Put,
+            Method::DELETE => Operation::Delete,
+            _ => Operation::Get,
+        };
+        let label = format!("{} {}", method, url.strip_prefix(REST_URL).unwrap_or(url));
+        let response = timed(operation, &label, self.send_untimed(method, url, body)).await?;
+        if let Some(info) = self.limiter.observe(&response) {
+            timing::record_quota(info.remaining);
+        }
+        Ok(response)
+    }
+
+    /// The quota of requests the API reported last.
+    #[cfg_attr(not(feature = "daemon-mode"), allow(dead_code))]
+    pub fn quota(&self) -> Option<RateLimitInfo> {
+        self.limiter.quota()
+    }
+
+    async fn send_untimed(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<&Value>,
+    ) -> Result<Response, reqwest::Error> {
+        record_request(&method, url);
+        let build = |scheme| {
+            let request = self.request(method.clone(), url, scheme);
+            match body {
+                Some(body) => request.json(body),
+                None => request,
+            }
+        };
+
+        let scheme = self.scheme();
+        let permit = self.limiter.acquire().await;
+        let response = build(scheme).send().await?;
+        drop(permit);
+        telemetry::record("http.response.status_code", response.status().as_u16());
+        if response.status() != StatusCode::UNAUTHORIZED {
+            self.settle(scheme, false);
+            return Ok(response);
+        }
+        if self.settled.load(Ordering::Relaxed) {
+            return Ok(response);
+        }
+
+        let fallback = scheme.other();
+        let permit = self.limiter.acquire().await;
+        let response = build(fallback).send().await?;
+        drop(permit);
+        telemetry::record("http.response.status_code", response.status().as_u16());
+        if response.status() != StatusCode::UNAUTHORIZED {
+            self.settle(fallback, true);
+        }
+        Ok(response)
+    }
+
+    fn settle(&self, scheme: AuthScheme, fell_back: bool) {
+        if self.settled.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        self.use_apikey
+            .store(scheme == AuthScheme::ApiKey, Ordering::Relaxed);
+        if fell_back {
+            info!(
+                "The credential was accepted with the {} scheme after the {} scheme was rejected",
+                scheme.as_str(),
+                scheme.other().as_str()
+            );
+        } else {
+            debug!(
+                "The credential was accepted with the {} scheme",
+                scheme.as_str()
+            );
+        }
+    }
+}
+
+/// Sets the attributes of the span of a request from its URL, e.g. the
+/// record of `domains/<domain>/records/<name>/<type>`, never from its
+/// headers, which hold the credential.
+fn record_request(method: &Method, url: &str) {
+    telemetry::record("http.request.method", method.as_str());
+    let path = url.split('?').next().unwrap_or(url);
+    telemetry::record("url.path", path.strip_prefix(REST_URL).unwrap_or(path));
+    let mut segments = path.strip_prefix(REST_URL).unwrap_or("").split('/');
+    if segments.next() != Some("domains") {
+        return;
+    }
+    if let Some(domain) = segments.next() {
+        telemetry::record("ddns.domain", domain);
+    }
+    if segments.next() == Some("records") {
+        for key in ["ddns.record", "ddns.type"] {
+            if let Some(segment) = segments.next().filter(|s| !s.is_empty()) {
+                telemetry::record(key, segment);
+            }
+        }
+    }
+}
+
+/// Longest excerpt of a body that is not a JSON error kept in the message.
+const MAX_BODY_EXCERPT: usize = 200;
+
+/// Extracts the message of an error body, such as
+/// `{"code": 401001, "message": "Invalid authorization token", ...}`, along
+/// with the descriptions of the failed validations. Other bodies are kept
+/// as they are, shortened.
+fn error_message(body: &str) -> String {
+    if let Ok(json) = serde_json::from_str::<Value>(body) {
+        if let Some(message) = json["message"].as_str() {
+            let details: Vec<&str> = json["errors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|error| error["description"].as_str())
+                .collect();
+            if details.is_empty() {
+                return message.to_string();
+            }
+            return format!("{} ({})", message, details.join("; "));
+        }
+    }
+
+    let body = body.trim();
+    if body.is_empty() {
+        return "empty response body".to_string();
+    }
+    match body.char_indices().nth(MAX_BODY_EXCERPT) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
+
+/// Turns a non-success response into the matching error, reading the
+/// message Gandi puts in the body of 403 responses to tell apart why the
+/// access was denied.
+async fn error_from_response(response: Response) -> DdnsError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    debug!("Gandi responded with status {}: {}", status, body);
+    let message = error_message(&body);
+    match status {
+        StatusCode::UNAUTHORIZED => DdnsError::Unauthorized(message),
+        StatusCode::FORBIDDEN => DdnsError::forbidden(message),
+        status => DdnsError::Api(status, message),
+    }
+}
+
+/// Checks that the credential may access the LiveDNS zone of `domain`.
+///
+/// Gandi does not expose the scopes of a token, so this probes the domain
+/// itself, which requires the same permission as managing its records.
+pub async fn check_livedns_access(domain: &str, api: &GandiApi) -> Result<(), DdnsError> {
+    let url = format!("{}domains/{}", REST_URL, domain);
+
+    let response = api.send(Method::GET, &url, None).await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    Ok(())
+}
+
+/// The URL of the rrset of type `dns_type` of `name`, which is taken
+/// literally, e.g. `*` for the wildcard record.
+fn rrset_url(domain: &str, name: &str, dns_type: DnsType) -> String {
+    format!(
+        "{}domains/{}/records/{}/{}",
+        REST_URL, domain, name, dns_type
+    )
+}
+
+/// Fetches an rrset, or `None` if the record does not exist.
+pub async fn get_gandi_record(
+    domain: &str,
+    name: &str,
+    dns_type: DnsType,
+    api: &GandiApi,
+) -> Result<Option<GandiRrset>, DdnsError> {
+    let url = rrset_url(domain, name, dns_type);
+
+    let response = api.send(Method::GET, &url, None).await?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    Ok(Some(response.json().await?))
+}
+
+/// Fetches every rrset of a name at once, by type. Empty if the name does
+/// not exist.
+pub async fn get_gandi_rrsets(
+    domain: &str,
+    name: &str,
+    api: &GandiApi,
+) -> Result<HashMap<DnsType, GandiRrset>, DdnsError> {
+    let url = format!("{}domains/{}/records/{}", REST_URL, domain, name);
+
+    let response = api.send(Method::GET, &url, None).await?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(HashMap::new());
+    }
+    if !status.is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    let rrsets: Vec<GandiRrset> = response.json().await?;
+    Ok(rrsets
+        .into_iter()
+        .map(|rrset| (rrset.record_type, rrset))
+        .collect())
+}
+
+/// Fetches all rrsets of the zone of `domain`.
+pub async fn get_zone_records(domain: &str, api: &GandiApi) -> Result<Vec<GandiRrset>, DdnsError> {
+    let url = format!("{}domains/{}/records", REST_URL, domain);
+
+    let response = api.send(Method::GET, &url, None).await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+    Ok(response.json().await?)
+}
+
+/// Deletes an rrset, returning whether it existed.
+pub async fn delete_gandi_rrset(
+    domain: &str,
+    name: &str,
+    dns_type: DnsType,
+    api: &GandiApi,
+) -> Result<bool, DdnsError> {
+    let url = rrset_url(domain, name, dns_type);
+
+    let response = api.send(Method::DELETE, &url, None).await?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    if !status.is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    Ok(true)
+}
+
+/// Replaces the values of a record, returning whether Gandi reported the
+/// record as changed. Non-success responses are returned as errors.
+pub async fn update_gandi_record(
+    domain: &str,
+    name: &str,
+    dns_type: DnsType,
+    new_ip: &str,
+    ttl: u32,
+    api: &GandiApi,
+) -> Result<bool, DdnsError> {
+    update_gandi_rrset(domain, name, dns_type, &[new_ip.to_string()], ttl, api).await
+}
+
+/// Replaces the values of an rrset, e.g. with the addresses of several
+/// uplinks.
+pub async fn update_gandi_rrset(
+    domain: &str,
+    name: &str,
+    dns_type: DnsType,
+    values: &[String],
+    ttl: u32,
+    api: &GandiApi,
+) -> Result<bool, DdnsError> {
+    let url = rrset_url(domain, name, dns_type);
+
+    let payload = serde_json::json!({
+        "rrset_ttl": ttl,
+        "rrset_values": values.iter().map(|value| canonical(value)).collect::<Vec<_>>()
+    });
+
+    let response = api.send(Method::PUT, &url, Some(&payload)).await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(error_from_response(response).await);
+    }
+
+    let changed = status.as_u16() == 201;
+    if !changed {
+        warn!("{} -> {}@{}: {}", dns_type, name, domain, status);
+    }
+
+    Ok(changed)
+}
+
+/// Several record updates checked against the current zone, written with
+/// [`apply_batch_update`].
+#[derive(Debug, Clone)]
+pub struct BatchPlan {
+    /// The zone as fetched, to tell whether it changed before the write.
+    fetched: Vec<ZoneRrset>,
+    zone: Vec<ZoneRrset>,
+    /// What the update does to each of the requested records.
+    pub items: Vec<BatchItem>,
+}
+
+impl BatchPlan {
+    /// Whether applying the plan writes anything.
+    pub fn writes(&self) -> bool {
+        self.items
+            .iter()
+            .any(|item| matches!(item, BatchItem::Changed(..) | BatchItem::TtlChanged))
+    }
+
+    /// Whether `current` still holds the rrsets the plan was made from,
+    /// whatever their order.
+    fn planned_from(&self, current: &[ZoneRrset]) -> bool {
+        let key = |r: &&ZoneRrset| (r.rrset_name.clone(), r.rrset_type.as_str());
+        let mut fetched: Vec<&ZoneRrset> = self.fetched.iter().collect();
+        let mut current: Vec<&ZoneRrset> = current.iter().collect();
+        fetched.sort_by_key(key);
+        current.sort_by_key(key);
+        fetched == current
+    }
+}
+
+/// The rrsets of the zone of `domain`.
+async fn get_zone(domain: &str, api: &GandiApi) -> Result<Vec<ZoneRrset>, DdnsError> {
+    let url = format!("{}domains/{}/records", REST_URL, domain);
+    let response = api.send(Method::GET, &url, None).await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+    Ok(response.json().await?)
+}
+
+/// Plans several record updates to a zone, to be applied with a single PUT.
+///
+/// The zone records endpoint replaces the whole zone, so the current zone
+/// is fetched first and only the requested rrsets are substituted. Like the
+/// per-record path, rrsets that do not exist or are empty are left alone.
+pub async fn plan_batch_update(
+    domain: &str,
+    records: &[RecordUpdate],
+    api: &GandiApi,
+) -> Result<BatchPlan, DdnsError> {
+    let fetched = get_zone(domain, api).await?;
+    let mut zone = fetched.clone();
+
+    let items: Vec<BatchItem> = records
+        .iter()
+        .map(|update| {
+            let cname = zone
+                .iter()
+                .find(|r| r.rrset_name == update.name && r.rrset_type == DnsType::Cname)
+                .map(|r| r.rrset_values.join(","));
+            let rrset = zone
+                .iter_mut()
+                .find(|r| r.rrset_name == update.name && r.rrset_type == update.dns_type);
+            match rrset {
+                None => match cname {
+                    Some(target) => BatchItem::Cname(target),
+                    None => BatchItem::Missing,
+                },
+                Some(rrset) if rrset.rrset_values.is_empty() => BatchItem::Empty,
+                Some(rrset) if holds_only(&rrset.rrset_values, &update.value) => {
+                    match update.steady_ttl {
+                        Some(ttl) if rrset.rrset_ttl != Some(ttl) => {
+                            rrset.rrset_ttl = Some(ttl);
+                            BatchItem::TtlChanged
+                        }
+                        _ => BatchItem::Unchanged,
+                    }
+                }
+                Some(rrset)
+                    if update
+                        .written
+                        .as_deref()
+                        .is_some_and(|w| drifted(&rrset.rrset_values, w, &update.value)) =>
+                {
+                    BatchItem::Drifted(rrset.rrset_values.clone())
+                }
+                Some(rrset) => {
+                    if update.protect {
+                        if let Some(reason) = foreign_values(&rrset.rrset_values, update.dns_type) {
+                            return BatchItem::Protected(reason);
+                        }
+                    }
+                    let old =
+                        std::mem::replace(&mut rrset.rrset_values, vec![canonical(&update.value)]);
+                    let old_ttl = rrset.rrset_ttl.replace(update.ttl);
+                    BatchItem::Changed(old, old_ttl)
+                }
+            }
+        })
+        .collect();
+
+    Ok(BatchPlan {
+        fetched,
+        zone,
+        items,
+    })
+}
+
+/// Writes the zone of `plan`, if it changes anything.
+///
+/// The zone is fetched again first and left alone if it changed since the
+/// plan was made, e.g. by an edit in the web interface, which the write
+/// would otherwise undo. Only a change between these last two calls can
+/// still be lost.
+pub async fn apply_batch_update(
+    domain: &str,
+    plan: &BatchPlan,
+    api: &GandiApi,
+) -> Result<(), DdnsError> {
+    if !plan.writes() {
+        return Ok(());
+    }
+    if !plan.planned_from(&get_zone(domain, api).await?) {
+        return Err(DdnsError::ZoneChanged);
+    }
+    let url = format!("{}domains/{}/records", REST_URL, domain);
+    let payload = serde_json::json!({ "items": plan.zone });
+    let response = api.send(Method::PUT, &url, Some(&payload)).await?;
+    if !response.status().is_success() {
+        return Err(error_from_response(response).await);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef01234567";
+    const LEGACY_KEY: &str = "0123456789abcdef01234567";
+
+    /// Serves requests answering 401 unless the `Authorization` header
+    /// starts with `accepted`, and returns its URL along with the headers
+    /// received, in order.
+    async fn server(accepted: Option<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let header = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("authorization: "))
+                    .unwrap_or_default()
+                    .to_string();
+                let status = match accepted {
+                    Some(prefix) if header.starts_with(prefix) => "200 OK",
+                    _ => "401 Unauthorized",
+                };
+                log.lock().unwrap().push(header);
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, seen)
+    }
+
+    fn api(key: &str, auth_method: Option<AuthScheme>) -> GandiApi {
+        let limiter = Arc::new(ApiRateLimiter::new(None, None));
+        GandiApi::new(key, None, auth_method, limiter, Timeouts::default()).unwrap()
+    }
+
+    fn schemes(seen: &Mutex<Vec<String>>) -> Vec<String> {
+        seen.lock()
+            .unwrap()
+            .iter()
+            .map(|header| header.split(' ').next().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn takes_the_wildcard_literally_in_urls() {
+        assert_eq!(
+            rrset_url("example.com", "*", DnsType::A),
+            "https://api.gandi.net/v5/livedns/domains/example.com/records/*/A"
+        );
+        assert_eq!(
+            rrset_url("example.com", "@", DnsType::Aaaa),
+            "https://api.gandi.net/v5/livedns/domains/example.com/records/@/AAAA"
+        );
+    }
+
+    fn rrset(name: &str, dns_type: DnsType, value: &str) -> ZoneRrset {
+        ZoneRrset {
+            rrset_name: name.to_string(),
+            rrset_type: dns_type,
+            rrset_ttl: Some(300),
+            rrset_values: vec![value.to_string()],
+        }
+    }
+
+    #[test]
+    fn tells_whether_the_zone_changed_since_the_plan() {
+        let fetched = vec![
+            rrset("www", DnsType::A, "192.0.2.1"),
+            rrset("@", DnsType::Mx, "10 mail.example.com."),
+        ];
+        let plan = BatchPlan {
+            fetched: fetched.clone(),
+            zone: vec![],
+            items: vec![],
+        };
+        assert!(plan.planned_from(&fetched));
+        // The API may list the rrsets in another order.
+        let reordered: Vec<ZoneRrset> = fetched.iter().rev().cloned().collect();
+        assert!(plan.planned_from(&reordered));
+
+        let mut edited = fetched.clone();
+        edited[1].rrset_values = vec!["20 mail.example.com.".to_string()];
+        assert!(!plan.planned_from(&edited));
+        let mut added = fetched.clone();
+        added.push(rrset("vpn", DnsType::A, "192.0.2.2"));
+        assert!(!plan.planned_from(&added));
+        assert!(!plan.planned_from(&fetched[..1]));
+    }
+
+    #[test]
+    fn guesses_the_scheme_from_the_length_of_the_credential() {
+        assert_eq!(AuthScheme::guess(LEGACY_KEY), AuthScheme::ApiKey);
+        assert_eq!(AuthScheme::guess(TOKEN), AuthScheme::Bearer);
+        assert_eq!(AuthScheme::Bearer.other(), AuthScheme::ApiKey);
+        assert_eq!(AuthScheme::ApiKey.other(), AuthScheme::Bearer);
+        assert_eq!(AuthScheme::XApiKey.other(), AuthScheme::XApiKey);
+    }
+
+    fn headers(api: &GandiApi) -> reqwest::header::HeaderMap {
+        let request = api.request(Method::GET, REST_URL, api.scheme());
+        request.build().unwrap().headers().clone()
+    }
+
+    #[test]
+    fn formats_the_header_of_each_scheme() {
+        let bearer = headers(&api(TOKEN, Some(AuthScheme::Bearer)));
+        assert_eq!(bearer[AUTHORIZATION], format!("Bearer {}", TOKEN));
+        assert!(!bearer.contains_key("X-Api-Key"));
+
+        let apikey = headers(&api(TOKEN, Some(AuthScheme::ApiKey)));
+        assert_eq!(apikey[AUTHORIZATION], format!("Apikey {}", TOKEN));
+
+        let x_api_key = headers(&api(TOKEN, Some(AuthScheme::XApiKey)));
+        assert_eq!(x_api_key["X-Api-Key"], TOKEN);
+        assert!(!x_api_key.contains_key(AUTHORIZATION));
+
+        // Guessed from the credential without a configured scheme.
+        let guessed = headers(&api(LEGACY_KEY, None));
+        assert_eq!(guessed[AUTHORIZATION], format!("Apikey {}", LEGACY_KEY));
+    }
+
+    #[test]
+    fn parses_the_configured_scheme() {
+        assert_eq!("bearer".parse(), Ok(AuthScheme::Bearer));
+        assert_eq!("Api-Key".parse(), Ok(AuthScheme::ApiKey));
+        assert_eq!("apikey".parse(), Ok(AuthScheme::ApiKey));
+        assert_eq!("X-API-KEY".parse(), Ok(AuthScheme::XApiKey));
+        assert!("basic".parse::<AuthScheme>().is_err());
+    }
+
+    #[tokio::test]
+    async fn falls_back_from_bearer_to_apikey() {
+        let (url, seen) = server(Some("Apikey")).await;
+        let api = api(TOKEN, None);
+        let response = api.send(Method::GET, &url, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(schemes(&seen), ["Bearer", "Apikey"]);
+        assert_eq!(api.scheme(), AuthScheme::ApiKey);
+
+        // The accepted scheme is used right away from then on.
+        api.send(Method::GET, &url, None).await.unwrap();
+        assert_eq!(schemes(&seen), ["Bearer", "Apikey", "Apikey"]);
+    }
+
+    #[tokio::test]
+    async fn falls_back_from_apikey_to_bearer() {
+        let (url, seen) = server(Some("Bearer")).await;
+        let api = api(LEGACY_KEY, None);
+        let response = api.send(Method::GET, &url, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(schemes(&seen), ["Apikey", "Bearer"]);
+        assert_eq!(api.scheme(), AuthScheme::Bearer);
+    }
+
+    #[tokio::test]
+    async fn keeps_trying_both_schemes_while_neither_is_accepted() {
+        let (url, seen) = server(None).await;
+        let api = api(TOKEN, None);
+        let response = api.send(Method::GET, &url, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(schemes(&seen), ["Bearer", "Apikey"]);
+        assert_eq!(api.scheme(), AuthScheme::Bearer);
+
+        api.send(Method::GET, &url, None).await.unwrap();
+        assert_eq!(schemes(&seen), ["Bearer", "Apikey", "Bearer", "Apikey"]);
+    }
+
+    #[tokio::test]
+    async fn does_not_fall_back_from_a_configured_scheme() {
+        let (url, seen) = server(Some("Apikey")).await;
+        let api = api(TOKEN, Some(AuthScheme::Bearer));
+        let response = api.send(Method::GET, &url, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(schemes(&seen), ["Bearer"]);
+    }
+}
@@ -0,0 +1,391 @@
+//! Asking the local Internet gateway for the public IPv4 address, over UPnP
+//! IGD, NAT-PMP (RFC 6886) or PCP (RFC 6887), for routers that are not a
+//! FRITZ!Box.
+
+use crate::guard::default_gateway;
+use crate::soap::{element, envelope, fault, find_element};
+use crate::timeouts::Timeouts;
+use log::{debug, warn};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const CACHE_FILE: &str = "gateway.json";
+
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+/// How long to wait for the gateway to answer the discovery.
+const SSDP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Bound of the requests to the gateway unless `HTTP.ip_detection_timeout`
+/// is set, as it is on the local network.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Services with a `GetExternalIPAddress` action, in order of preference.
+const WAN_SERVICES: &[&str] = &[
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+const NAT_PMP_PORT: u16 = 5351;
+/// NAT-PMP and PCP requests are sent again after 250ms, 500ms, ...
+const UDP_ATTEMPTS: u32 = 3;
+const UDP_INITIAL_WAIT: Duration = Duration::from_millis(250);
+
+/// How the gateway is asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayProtocol {
+    Upnp,
+    NatPmp,
+    Pcp,
+    /// UPnP, then NAT-PMP, then PCP.
+    Auto,
+}
+
+impl GatewayProtocol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GatewayProtocol::Upnp => "upnp",
+            GatewayProtocol::NatPmp => "nat-pmp",
+            GatewayProtocol::Pcp => "pcp",
+            GatewayProtocol::Auto => "auto",
+        }
+    }
+}
+
+impl FromStr for GatewayProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "upnp" | "igd" => Ok(GatewayProtocol::Upnp),
+            "nat-pmp" | "natpmp" => Ok(GatewayProtocol::NatPmp),
+            "pcp" => Ok(GatewayProtocol::Pcp),
+            "auto" => Ok(GatewayProtocol::Auto),
+            other => Err(format!(
+                "unknown gateway protocol '{}', expected upnp, nat-pmp, pcp or auto",
+                other
+            )),
+        }
+    }
+}
+
+/// The gateway detecting the IPv4 address of a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gateway {
+    pub protocol: GatewayProtocol,
+    /// Address NAT-PMP and PCP requests are sent to, the gateway of the
+    /// default route if `None`.
+    pub address: Option<Ipv4Addr>,
+}
+
+/// The control URL found through SSDP, kept so that later runs skip the
+/// discovery.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedControl {
+    control_url: String,
+    service: String,
+}
+
+fn cache_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(CACHE_FILE)
+}
+
+fn load_cache(state_dir: &Path) -> Option<CachedControl> {
+    let contents = fs::read_to_string(cache_path(state_dir)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn save_cache(state_dir: &Path, control: &CachedControl) -> io::Result<()> {
+    fs::create_dir_all(state_dir)?;
+    let path = cache_path(state_dir);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(control)?)?;
+    fs::rename(&tmp, &path)
+}
+
+impl Gateway {
+    /// The external IPv4 address the gateway reports, unvalidated.
+    pub async fn external_ipv4(
+        &self,
+        state_dir: &Path,
+        timeouts: Timeouts,
+    ) -> Result<Ipv4Addr, String> {
+        match self.protocol {
+            GatewayProtocol::Upnp => upnp_external_ipv4(state_dir, timeouts).await,
+            GatewayProtocol::NatPmp => nat_pmp_external_ipv4(self.gateway()?).await,
+            GatewayProtocol::Pcp => pcp_external_ipv4(self.gateway()?).await,
+            GatewayProtocol::Auto => {
+                let mut errors = Vec::new();
+                match upnp_external_ipv4(state_dir, timeouts).await {
+                    Ok(ip) => return Ok(ip),
+                    Err(e) => errors.push(format!("UPnP: {}", e)),
+                }
+                match self.gateway() {
+                    Ok(gateway) => {
+                        match nat_pmp_external_ipv4(gateway).await {
+                            Ok(ip) => return Ok(ip),
+                            Err(e) => errors.push(format!("NAT-PMP: {}", e)),
+                        }
+                        match pcp_external_ipv4(gateway).await {
+                            Ok(ip) => return Ok(ip),
+                            Err(e) => errors.push(format!("PCP: {}", e)),
+                        }
+                    }
+                    Err(e) => errors.push(e),
+                }
+                Err(errors.join("; "))
+            }
+        }
+    }
+
+    fn gateway(&self) -> Result<Ipv4Addr, String> {
+        if let Some(address) = self.address {
+            return Ok(address);
+        }
+        default_gateway()?.ok_or_else(|| "no default gateway, set gateway_address".to_string())
+    }
+}
+
+async fn upnp_external_ipv4(state_dir: &Path, timeouts: Timeouts) -> Result<Ipv4Addr, String> {
+    let client = timeouts
+        .client(Some(timeouts.ip_detection.unwrap_or(TIMEOUT)))
+        .build()
+        .map_err(|e| e.to_string())?;
+    if let Some(cached) = load_cache(state_dir) {
+        match get_external_ip(&client, &cached).await {
+            Ok(ip) => return Ok(ip),
+            Err(e) => debug!(
+                "The cached control URL {} failed ({}), discovering the gateway again",
+                cached.control_url, e
+            ),
+        }
+    }
+
+    let control = discover(&client).await?;
+    if let Err(e) = save_cache(state_dir, &control) {
+        warn!("Unable to cache the control URL of the gateway: {}", e);
+    }
+    get_external_ip(&client, &control).await
+}
+
+async fn get_external_ip(
+    client: &reqwest::Client,
+    control: &CachedControl,
+) -> Result<Ipv4Addr, String> {
+    let response = client
+        .post(&control.control_url)
+        .header(CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+        .header(
+            "SOAPAction",
+            format!("\"{}#GetExternalIPAddress\"", control.service),
+        )
+        .body(envelope(&control.service, "GetExternalIPAddress"))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let text = response.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(match fault(&text) {
+            Some(fault) => format!("GetExternalIPAddress failed: {}", fault),
+            None => format!("GetExternalIPAddress failed with status {}", status),
+        });
+    }
+    let ip = element(&text, "NewExternalIPAddress")?;
+    ip.parse()
+        .map_err(|_| format!("the gateway reports '{}', not an IPv4 address", ip))
+}
+
+/// Finds the control URL of the WAN connection of the gateway through
+/// SSDP and its device description.
+async fn discover(client: &reqwest::Client) -> Result<CachedControl, String> {
+    let location = ssdp_location().await?;
+    let description = client
+        .get(&location)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    let base = find_element(&description, "URLBase").unwrap_or_else(|| location.clone());
+    let (service, control) = control_url(&description)
+        .ok_or_else(|| format!("{} describes no WAN connection service", location))?;
+    let control_url = Url::parse(&base)
+        .and_then(|base| base.join(&control))
+        .map_err(|e| format!("invalid control URL '{}': {}", control, e))?;
+    Ok(CachedControl {
+        control_url: control_url.to_string(),
+        service,
+    })
+}
+
+/// The most preferred service of `WAN_SERVICES` in a device description,
+/// with its control URL.
+fn control_url(description: &str) -> Option<(String, String)> {
+    let services: Vec<(String, String)> = description
+        .split("<service>")
+        .skip(1)
+        .filter_map(|service| {
+            let service = service.split("</service>").next()?;
+            Some((
+                find_element(service, "serviceType")?,
+                find_element(service, "controlURL")?,
+            ))
+        })
+        .collect();
+    WAN_SERVICES.iter().find_map(|wanted| {
+        services
+            .iter()
+            .find(|(service, _)| service == wanted)
+            .cloned()
+    })
+}
+
+/// Sends an SSDP search for an Internet gateway and returns the location of
+/// the description of the first one answering.
+async fn ssdp_location() -> Result<String, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| e.to_string())?;
+    socket.set_multicast_ttl_v4(2).map_err(|e| e.to_string())?;
+    let search = concat!(
+        "M-SEARCH * HTTP/1.1\r\n",
+        "HOST: 239.255.255.250:1900\r\n",
+        "MAN: \"ssdp:discover\"\r\n",
+        "MX: 1\r\n",
+        "ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n",
+        "\r\n"
+    );
+    socket
+        .send_to(search.as_bytes(), SSDP_ADDR)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut buf = [0u8; 2048];
+    let answer = tokio::time::timeout(SSDP_TIMEOUT, async {
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            let text = String::from_utf8_lossy(&buf[..len]);
+            if let Some(location) = header_value(&text, "location") {
+                return Ok::<_, io::Error>(location);
+            }
+        }
+    })
+    .await;
+    match answer {
+        Ok(Ok(location)) => Ok(location),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!(
+            "no Internet gateway answered the discovery within {:?}",
+            SSDP_TIMEOUT
+        )),
+    }
+}
+
+fn header_value(response: &str, name: &str) -> Option<String> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Sends `request` to the gateway, again after longer waits, until an
+/// answer comes back.
+async fn exchange(gateway: Ipv4Addr, request: &[u8], buf: &mut [u8]) -> Result<usize, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| e.to_string())?;
+    socket
+        .connect(SocketAddr::new(IpAddr::V4(gateway), NAT_PMP_PORT))
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut wait = UDP_INITIAL_WAIT;
+    for _ in 0..UDP_ATTEMPTS {
+        socket.send(request).await.map_err(|e| e.to_string())?;
+        match tokio::time::timeout(wait, socket.recv(buf)).await {
+            Ok(Ok(len)) => return Ok(len),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => wait *= 2,
+        }
+    }
+    Err(format!("{} did not answer", gateway))
+}
+
+async fn nat_pmp_external_ipv4(gateway: Ipv4Addr) -> Result<Ipv4Addr, String> {
+    // Version 0, opcode 0: the external address.
+    let mut buf = [0u8; 16];
+    let len = exchange(gateway, &[0, 0], &mut buf).await?;
+    if len < 12 || buf[0] != 0 || buf[1] != 128 {
+        return Err(format!("{} sent an invalid NAT-PMP response", gateway));
+    }
+    match u16::from_be_bytes([buf[2], buf[3]]) {
+        0 => Ok(Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11])),
+        code => Err(format!("NAT-PMP result code {}", code)),
+    }
+}
+
+/// PCP has no request for the external address alone, so a mapping of UDP
+/// port 9 (discard) is requested for a few seconds and deleted right away;
+/// its response carries the external address.
+async fn pcp_external_ipv4(gateway: Ipv4Addr) -> Result<Ipv4Addr, String> {
+    let local = {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+        socket
+            .connect(SocketAddr::new(IpAddr::V4(gateway), NAT_PMP_PORT))
+            .map_err(|e| e.to_string())?;
+        match socket.local_addr().map_err(|e| e.to_string())?.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => return Err("no local IPv4 address".to_string()),
+        }
+    };
+    let nonce: [u8; 12] = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_be_bytes()[4..]
+        .try_into()
+        .unwrap_or_default();
+
+    let mut buf = [0u8; 1100];
+    let len = exchange(gateway, &pcp_map(local, nonce, 5), &mut buf).await?;
+    if len < 60 || buf[0] != 2 || buf[1] != 0x81 {
+        return Err(format!("{} sent an invalid PCP response", gateway));
+    }
+    if buf[3] != 0 {
+        return Err(format!("PCP result code {}", buf[3]));
+    }
+    let external: [u8; 16] = buf[44..60].try_into().unwrap_or_default();
+    let external = Ipv6Addr::from(external);
+
+    // Best effort, the mapping expires by itself anyway.
+    let _ = exchange(gateway, &pcp_map(local, nonce, 0), &mut buf).await;
+
+    external
+        .to_ipv4_mapped()
+        .ok_or_else(|| format!("the gateway reports {}, not an IPv4 address", external))
+}
+
+/// A PCP MAP request of UDP port 9 for `lifetime` seconds.
+fn pcp_map(client: Ipv4Addr, nonce: [u8; 12], lifetime: u32) -> Vec<u8> {
+    let mut request = Vec::with_capacity(60);
+    request.extend_from_slice(&[2, 1, 0, 0]);
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request.extend_from_slice(&client.to_ipv6_mapped().octets());
+    request.extend_from_slice(&nonce);
+    request.extend_from_slice(&[17, 0, 0, 0]);
+    request.extend_from_slice(&9u16.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&Ipv6Addr::UNSPECIFIED.octets());
+    request
+}
@@ -0,0 +1,241 @@
+//! Conditions that must hold for this machine to be on the network whose
+//! address the records should follow, so that a roaming laptop does not
+//! publish the address of whatever network it happens to be on.
+
+use ipnet::IpNet;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::process::Command;
+
+#[derive(Debug, Clone, Default)]
+pub struct GuardConfig {
+    /// Expected MAC address of the default IPv4 gateway.
+    pub gateway_mac: Option<String>,
+    /// The machine must hold an address inside one of these prefixes.
+    pub prefixes: Vec<IpNet>,
+    /// The machine must be connected to one of these Wi-Fi networks.
+    pub ssids: Vec<String>,
+}
+
+impl GuardConfig {
+    pub fn is_empty(&self) -> bool {
+        self.gateway_mac.is_none() && self.prefixes.is_empty() && self.ssids.is_empty()
+    }
+}
+
+/// Checks every configured condition, returning why the guard is not
+/// satisfied if any of them fails.
+pub fn evaluate(guard: &GuardConfig) -> Result<(), String> {
+    if let Some(expected) = &guard.gateway_mac {
+        check_gateway_mac(expected, default_gateway_mac()?.as_deref())?;
+    }
+
+    if !guard.prefixes.is_empty() {
+        let addresses: Vec<IpAddr> = if_addrs::get_if_addrs()
+            .map_err(|e| format!("unable to list local addresses: {}", e))?
+            .iter()
+            .map(|iface| iface.ip())
+            .collect();
+        check_prefixes(&guard.prefixes, &addresses)?;
+    }
+
+    if !guard.ssids.is_empty() {
+        let output = Command::new("iw")
+            .arg("dev")
+            .output()
+            .map_err(|e| format!("unable to run iw: {}", e))?;
+        let current = parse_iw_ssids(&String::from_utf8_lossy(&output.stdout));
+        check_ssids(&guard.ssids, &current)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the default gateway, whose MAC address is `mac` if there is
+/// one, is the `expected` one.
+fn check_gateway_mac(expected: &str, mac: Option<&str>) -> Result<(), String> {
+    match mac {
+        Some(mac) if mac.eq_ignore_ascii_case(expected) => Ok(()),
+        Some(mac) => Err(format!("default gateway has MAC address {}", mac)),
+        None => Err("no default gateway found".to_string()),
+    }
+}
+
+/// Checks that one of the local `addresses` is inside one of `prefixes`.
+fn check_prefixes(prefixes: &[IpNet], addresses: &[IpAddr]) -> Result<(), String> {
+    let inside = addresses
+        .iter()
+        .any(|ip| prefixes.iter().any(|net| net.contains(ip)));
+    match inside {
+        true => Ok(()),
+        false => Err("no local address inside the configured prefixes".to_string()),
+    }
+}
+
+/// Checks that one of the `current` Wi-Fi networks is among `ssids`.
+fn check_ssids(ssids: &[String], current: &[String]) -> Result<(), String> {
+    if current.iter().any(|ssid| ssids.contains(ssid)) {
+        return Ok(());
+    }
+    if current.is_empty() {
+        return Err("not connected to any Wi-Fi network".to_string());
+    }
+    Err(format!("connected to {}", current.join(", ")))
+}
+
+/// The gateway of the default IPv4 route (Linux).
+pub fn default_gateway() -> Result<Option<Ipv4Addr>, String> {
+    let route = fs::read_to_string("/proc/net/route")
+        .map_err(|e| format!("unable to read the routing table: {}", e))?;
+    Ok(parse_default_gateway(&route))
+}
+
+fn default_gateway_mac() -> Result<Option<String>, String> {
+    let Some(gateway) = default_gateway()? else {
+        return Ok(None);
+    };
+
+    let arp = fs::read_to_string("/proc/net/arp")
+        .map_err(|e| format!("unable to read the ARP table: {}", e))?;
+    Ok(parse_arp_mac(&arp, gateway))
+}
+
+/// Finds the gateway of the default route in `/proc/net/route`, where
+/// addresses are the raw network-order bytes printed as a native integer.
+fn parse_default_gateway(route: &str) -> Option<Ipv4Addr> {
+    route.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, "00000000", gateway, ..] => u32::from_str_radix(gateway, 16)
+                .ok()
+                .map(|gw| Ipv4Addr::from(gw.to_ne_bytes()))
+                .filter(|gw| !gw.is_unspecified()),
+            _ => None,
+        }
+    })
+}
+
+/// Looks up the hardware address of `ip` in `/proc/net/arp`.
+fn parse_arp_mac(arp: &str, ip: Ipv4Addr) -> Option<String> {
+    arp.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [addr, _, _, mac, ..] if addr.parse() == Ok(ip) => Some(mac.to_string()),
+            _ => None,
+        }
+    })
+}
+
+/// Extracts the SSIDs of the connected interfaces from `iw dev` output.
+fn parse_iw_ssids(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("ssid "))
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+";
+
+    const ARP: &str = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.7      0x1         0x2         11:22:33:44:55:66     *        wlan0
+192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:ff     *        wlan0
+";
+
+    #[test]
+    fn finds_the_default_gateway() {
+        let gateway = Ipv4Addr::from(0x0101a8c0u32.to_ne_bytes());
+        assert_eq!(parse_default_gateway(ROUTE), Some(gateway));
+        assert_eq!(
+            parse_default_gateway(&ROUTE[..ROUTE.find("wlan0\t0000").unwrap()]),
+            None
+        );
+    }
+
+    #[test]
+    fn looks_up_the_gateway_mac() {
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        assert_eq!(
+            parse_arp_mac(ARP, gateway).as_deref(),
+            Some("aa:bb:cc:dd:ee:ff")
+        );
+        assert_eq!(parse_arp_mac(ARP, Ipv4Addr::new(192, 168, 1, 2)), None);
+    }
+
+    #[test]
+    fn gateway_mac_is_compared_case_insensitively() {
+        assert!(check_gateway_mac("AA:BB:CC:DD:EE:FF", Some("aa:bb:cc:dd:ee:ff")).is_ok());
+        assert_eq!(
+            check_gateway_mac("aa:bb:cc:dd:ee:ff", Some("00:11:22:33:44:55")),
+            Err("default gateway has MAC address 00:11:22:33:44:55".to_string())
+        );
+        assert_eq!(
+            check_gateway_mac("aa:bb:cc:dd:ee:ff", None),
+            Err("no default gateway found".to_string())
+        );
+    }
+
+    #[test]
+    fn prefixes_need_a_local_address_inside() {
+        let prefixes: Vec<IpNet> = vec![
+            "192.168.1.0/24".parse().unwrap(),
+            "2001:db8:1::/48".parse().unwrap(),
+        ];
+        let home: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "192.168.1.7".parse().unwrap()];
+        let v6: Vec<IpAddr> = vec!["2001:db8:1:2::7".parse().unwrap()];
+        let hotel: Vec<IpAddr> = vec![
+            "10.0.0.7".parse().unwrap(),
+            "2001:db8:2::7".parse().unwrap(),
+        ];
+        assert!(check_prefixes(&prefixes, &home).is_ok());
+        assert!(check_prefixes(&prefixes, &v6).is_ok());
+        assert!(check_prefixes(&prefixes, &hotel).is_err());
+        assert!(check_prefixes(&prefixes, &[]).is_err());
+    }
+
+    #[test]
+    fn parses_the_ssids_of_iw() {
+        let output = "\
+phy#0
+\tInterface wlan0
+\t\tifindex 3
+\t\tssid Home Network
+\t\ttype managed
+phy#1
+\tInterface wlan1
+\t\ttype managed
+";
+        assert_eq!(parse_iw_ssids(output), vec!["Home Network".to_string()]);
+        assert!(parse_iw_ssids("phy#0\n\tInterface wlan0\n").is_empty());
+    }
+
+    #[test]
+    fn ssids_must_match_exactly() {
+        let ssids = vec!["Home Network".to_string()];
+        assert!(check_ssids(&ssids, &["Home Network".to_string()]).is_ok());
+        assert_eq!(
+            check_ssids(&ssids, &["Hotel".to_string(), "home network".to_string()]),
+            Err("connected to Hotel, home network".to_string())
+        );
+        assert_eq!(
+            check_ssids(&ssids, &[]),
+            Err("not connected to any Wi-Fi network".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_guard_is_satisfied() {
+        let guard = GuardConfig::default();
+        assert!(guard.is_empty());
+        assert!(evaluate(&guard).is_ok());
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(feature = "daemon-mode")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "daemon-mode")]
+use std::fs;
+#[cfg(feature = "daemon-mode")]
+use std::path::Path;
+#[cfg(feature = "daemon-mode")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "daemon-mode")]
+const LAST_RUN_FILE: &str = "last-run.json";
+
+/// How the most recent pass ended, read by `--health-check`.
+#[cfg(feature = "daemon-mode")]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct LastRun {
+    /// Seconds since the Unix epoch.
+    finished: u64,
+    success: bool,
+}
+
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(feature = "daemon-mode")]
+pub fn record_run(state_dir: &Path, success: bool) -> std::io::Result<()> {
+    let last_run = LastRun {
+        finished: unix_secs(SystemTime::now()),
+        success,
+    };
+    fs::create_dir_all(state_dir)?;
+    let path = state_dir.join(LAST_RUN_FILE);
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(&last_run)?)?;
+    fs::rename(&tmp, &path)
+}
+
+/// Checks that the most recent pass succeeded at most `max_age` ago.
+#[cfg(feature = "daemon-mode")]
+pub fn check(state_dir: &Path, max_age: Duration) -> Result<(), String> {
+    let path = state_dir.join(LAST_RUN_FILE);
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    let last_run: LastRun = serde_json::from_str(&contents)
+        .map_err(|e| format!("unable to parse {}: {}", path.display(), e))?;
+
+    let age = unix_secs(SystemTime::now()).saturating_sub(last_run.finished);
+    if !last_run.success {
+        return Err(format!("the last pass failed {}s ago", age));
+    }
+    if age > max_age.as_secs() {
+        return Err(format!("the last pass finished {}s ago", age));
+    }
+    Ok(())
+}
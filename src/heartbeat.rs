@@ -0,0 +1,90 @@
+//! The TXT rrset at `DNS.heartbeat_record`, written after each pass that
+//! changed records, so that the zone itself tells which host last updated
+//! it, when and with which addresses.
+
+use crate::config::DnsConfig;
+use crate::dns_type::DnsType;
+use crate::gandi::{update_gandi_rrset, GandiApi, RRSET_TTL};
+use crate::template::txt_value;
+use log::{info, warn};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Version of the format, the `v` field.
+const FORMAT_VERSION: u32 = 1;
+
+/// The heartbeat, e.g. `v=1; t=2024-05-01T12:00:00Z; host=myserver;
+/// ip4=203.0.113.7; ip6=2001:db8::7`. Several addresses of a family are
+/// separated by commas.
+fn heartbeat(now: OffsetDateTime, host: &str, ipv4: &[&str], ipv6: &[&str]) -> String {
+    let now = now.replace_nanosecond(0).unwrap_or(now);
+    let mut text = format!(
+        "v={}; t={}; host={}",
+        FORMAT_VERSION,
+        now.format(&Rfc3339).unwrap_or_default(),
+        host
+    );
+    for (key, addresses) in [("ip4", ipv4), ("ip6", ipv6)] {
+        if !addresses.is_empty() {
+            text.push_str(&format!("; {}={}", key, addresses.join(",")));
+        }
+    }
+    text
+}
+
+/// The name of this host, reduced to characters that cannot be taken for
+/// separators of the heartbeat.
+fn host_name() -> String {
+    gethostname::gethostname()
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Writes the heartbeat with the addresses the rrsets hold after the pass,
+/// `confirmed` as `(record, type, address)`. Failures are only warned
+/// about, the pass does not fail because of them.
+pub async fn write(
+    config: &DnsConfig,
+    record: &str,
+    confirmed: &[(String, DnsType, String)],
+    api: &GandiApi,
+) {
+    let addresses = |dns_type| {
+        let mut addresses: Vec<&str> = confirmed
+            .iter()
+            .filter(|(_, t, _)| *t == dns_type)
+            .map(|(_, _, address)| address.as_str())
+            .collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses
+    };
+    let text = heartbeat(
+        OffsetDateTime::now_utc(),
+        &host_name(),
+        &addresses(DnsType::A),
+        &addresses(DnsType::Aaaa),
+    );
+    let value = txt_value(&text);
+    match update_gandi_rrset(
+        &config.domain,
+        record,
+        DnsType::Txt,
+        &[value],
+        RRSET_TTL,
+        api,
+    )
+    .await
+    {
+        Ok(_) => info!("\tWrote the heartbeat to {}/TXT", record),
+        Err(e) => warn!("Unable to write the heartbeat to {}/TXT: {}", record, e),
+    }
+}
@@ -0,0 +1,135 @@
+//! The `history` subcommand, printing the audit log.
+
+use crate::audit::{AuditEntry, AuditLog, Outcome};
+use clap::{ArgGroup, Args, ValueEnum};
+use log::warn;
+use std::collections::VecDeque;
+use std::io;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("selection").required(true).args(["record", "all"])))]
+pub struct HistoryArgs {
+    /// Only show the changes of this record
+    #[arg(long)]
+    record: Option<String>,
+    /// Show the changes of all records
+    #[arg(long)]
+    all: bool,
+    /// Only show changes from this day on (YYYY-MM-DD, UTC)
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    since: Option<Date>,
+    /// Show at most this many changes
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+fn parse_date(value: &str) -> Result<Date, String> {
+    Date::parse(value, format_description!("[year]-[month]-[day]"))
+        .map_err(|e| format!("invalid date '{}': {}", value, e))
+}
+
+impl HistoryArgs {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        if let Some(record) = &self.record {
+            if entry.record != *record {
+                return false;
+            }
+        }
+        match self.since {
+            Some(since) => entry
+                .time()
+                .is_some_and(|time| time >= OffsetDateTime::new_utc(since, time::Time::MIDNIGHT)),
+            None => true,
+        }
+    }
+}
+
+/// Prints the matching entries newest first, returning `false` if there is
+/// no audit log yet.
+pub fn show(log: &AuditLog, args: &HistoryArgs) -> io::Result<bool> {
+    let Some(entries) = log.read()? else {
+        return Ok(false);
+    };
+
+    // Only the newest `limit` entries are kept while streaming the file.
+    let mut selected = VecDeque::new();
+    for entry in entries {
+        match entry? {
+            Ok(entry) if args.matches(&entry) => {
+                if args.limit.is_some_and(|limit| selected.len() == limit) {
+                    selected.pop_front();
+                }
+                if args.limit != Some(0) {
+                    selected.push_back(entry);
+                }
+            }
+            Ok(_) => {}
+            Err(corrupt) => warn!(
+                "Skipping corrupt line {} of {}: {}",
+                corrupt.line,
+                log.path().display(),
+                corrupt.error
+            ),
+        }
+    }
+
+    for entry in selected.iter().rev() {
+        match args.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string(entry)?),
+            OutputFormat::Text => println!("{}", format_entry(entry)),
+        }
+    }
+    Ok(true)
+}
+
+fn format_entry(entry: &AuditEntry) -> String {
+    let old = match &entry.old {
+        Some(old) if !old.is_empty() => old.join(","),
+        _ => "?".to_string(),
+    };
+    let outcome = match entry.outcome {
+        Outcome::Changed => "changed".to_string(),
+        Outcome::Failed => match &entry.error {
+            Some(error) => format!("failed: {}", error),
+            None => "failed".to_string(),
+        },
+        Outcome::Drifted => format!("drifted from {}", entry.written.as_deref().unwrap_or("?")),
+        Outcome::RolledBack => "rolled back".to_string(),
+        Outcome::Removed => "removed".to_string(),
+    };
+    let source = match (&entry.source, &entry.provider) {
+        (Some(source), Some(provider)) => format!(" ({}, via {})", source, provider),
+        (Some(source), None) => format!(" ({})", source),
+        (None, Some(provider)) => format!(" (via {})", provider),
+        (None, None) => String::new(),
+    };
+    let comment = match &entry.comment {
+        Some(comment) => format!("  # {}", comment),
+        None => String::new(),
+    };
+    format!(
+        "{}  {}@{}/{}  {} -> {}{}  {}{}",
+        entry.timestamp,
+        entry.record,
+        entry.domain,
+        entry.dns_type,
+        old,
+        match entry.outcome {
+            Outcome::Removed => "(deleted)",
+            _ => &entry.new,
+        },
+        source,
+        outcome,
+        comment
+    )
+}
@@ -0,0 +1,1028 @@
+use crate::cloud::CloudMetadata;
+use crate::dns_type::{DnsType, ParseDnsTypeError};
+use crate::fritzbox::FritzBox;
+use crate::gateway::Gateway;
+use crate::telemetry;
+use crate::timeouts::Timeouts;
+use crate::timing::{timed, Operation};
+use log::{error, info, warn};
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+/// The address families a record is updated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum IpVersionFilter {
+    #[default]
+    Both,
+    Only(IpVersion),
+}
+
+/// Where the detection requests of one address family are sent from, for
+/// hosts whose default route does not go out the link the records follow.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Binding {
+    pub address: Option<IpAddr>,
+    /// Network interface the requests are bound to (`SO_BINDTODEVICE`).
+    pub interface: Option<String>,
+}
+
+/// A detected public address and what told it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpDetectionResult {
+    pub ip: String,
+    /// E.g. `api.ipify.org`, `FRITZ!Box` or `override`.
+    pub provider: String,
+}
+
+impl IpDetectionResult {
+    fn new(ip: impl ToString, provider: &str) -> Self {
+        IpDetectionResult {
+            ip: ip.to_string(),
+            provider: provider.to_string(),
+        }
+    }
+}
+
+/// Names the web service at `url` by its host, for the logs.
+fn provider_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Which mechanisms detect the address of one family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DetectionMethod {
+    /// The metadata service, the FRITZ!Box and the gateway if there are
+    /// any, then the web services.
+    #[default]
+    Auto,
+    Web,
+    Fritzbox,
+    Gateway,
+    Cloud,
+}
+
+/// How the address of one family is detected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FamilyDetection {
+    /// Web services asked in turn, answering the address as text or as the
+    /// `ip` field of a JSON object. ipify if empty.
+    pub providers: Vec<String>,
+    pub method: DetectionMethod,
+    /// Bound of the requests, instead of `HTTP.ip_detection_timeout`.
+    pub timeout: Option<Duration>,
+    pub binding: Binding,
+    /// Sent to the providers, e.g. an API key. The values are marked
+    /// sensitive so they do not show in debug output.
+    pub headers: HeaderMap,
+    /// Length of the prefix delegated to the network the detected IPv6
+    /// address belongs to, so that the records with a `host_part` get an
+    /// address of their own in it.
+    pub prefix_len: Option<u8>,
+    /// Where the delegated prefix is read, the detected address being
+    /// taken to be in it if `None`.
+    pub delegated_prefix: Option<PrefixSource>,
+}
+
+/// Where the prefix delegated to the network is read. On a router, the
+/// detected address is that of the WAN link, which is often numbered from
+/// another prefix than the one delegated with DHCPv6-PD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixSource {
+    /// A DHCPv6 lease file, in which the last prefix written as
+    /// `address/length` is the current one, e.g. the `iaprefix` of dhclient.
+    LeaseFile(PathBuf),
+    /// A LAN interface numbered from the delegated prefix.
+    Interface(String),
+}
+
+impl PrefixSource {
+    /// An address in the delegated prefix.
+    pub fn read(&self) -> Result<Ipv6Addr, String> {
+        match self {
+            PrefixSource::LeaseFile(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+                lease_prefix(&contents)
+                    .ok_or_else(|| format!("no delegated prefix in {}", path.display()))
+            }
+            PrefixSource::Interface(name) => if_addrs::get_if_addrs()
+                .map_err(|e| format!("unable to list the network interfaces: {}", e))?
+                .into_iter()
+                .filter(|i| i.name == *name)
+                .find_map(|i| match i.addr {
+                    if_addrs::IfAddr::V6(addr) if validate_public_ipv6(&addr.ip).is_ok() => {
+                        Some(addr.ip)
+                    }
+                    _ => None,
+                })
+                .ok_or_else(|| format!("no public IPv6 address on the interface '{}'", name)),
+        }
+    }
+}
+
+/// The last public prefix written as `address/length` in a lease file.
+fn lease_prefix(contents: &str) -> Option<Ipv6Addr> {
+    contents
+        .split(|c: char| c.is_whitespace() || matches!(c, ';' | '=' | '"' | '\'' | ','))
+        .filter_map(|token| token.split_once('/'))
+        .filter(|(_, length)| length.parse::<u8>().is_ok_and(|length| length <= 128))
+        .filter_map(|(address, _)| address.parse::<Ipv6Addr>().ok())
+        .rfind(|address| validate_public_ipv6(address).is_ok())
+}
+
+/// How the addresses of one uplink are detected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DetectionSource {
+    pub ipv4: FamilyDetection,
+    pub ipv6: FamilyDetection,
+    /// Router asked for the addresses before the web service.
+    pub fritzbox: Option<FritzBox>,
+    /// Gateway asked for the IPv4 address before the web service.
+    pub gateway: Option<Gateway>,
+    /// Cloud metadata service asked for the addresses before all the
+    /// others.
+    pub cloud: Option<CloudMetadata>,
+}
+
+impl Binding {
+    fn client(&self, version: IpVersion, timeouts: Timeouts) -> Result<reqwest::Client, String> {
+        self.builder(version, timeouts)?
+            .build()
+            .map_err(|e| e.to_string())
+    }
+
+    fn builder(
+        &self,
+        version: IpVersion,
+        timeouts: Timeouts,
+    ) -> Result<reqwest::ClientBuilder, String> {
+        if let Some(address) = self.address {
+            let matches = match version {
+                IpVersion::V4 => address.is_ipv4(),
+                IpVersion::V6 => address.is_ipv6(),
+            };
+            if !matches {
+                return Err(format!(
+                    "bind_address {} is not an IP{} address",
+                    address,
+                    version.as_str()
+                ));
+            }
+        }
+
+        // Binding to the unspecified address of the family, unless told
+        // otherwise, only connects to the addresses of that family the host
+        // of a provider resolves to: over IPv6, a dual-stack service answers
+        // the IPv6 address to the IPv4 query.
+        let address = self.address.unwrap_or(match version {
+            IpVersion::V4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpVersion::V6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+        #[allow(unused_mut)]
+        let mut builder = timeouts
+            .client(timeouts.ip_detection)
+            .local_address(address);
+        if let Some(interface) = &self.interface {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            {
+                let exists = if_addrs::get_if_addrs()
+                    .map_err(|e| format!("unable to list the network interfaces: {}", e))?
+                    .iter()
+                    .any(|i| i.name == *interface);
+                if !exists {
+                    return Err(format!("no network interface named '{}'", interface));
+                }
+                builder = builder.interface(interface);
+            }
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            return Err(format!(
+                "unable to bind to '{}': bind_interface is only supported on Linux",
+                interface
+            ));
+        }
+        Ok(builder)
+    }
+}
+
+impl DetectionMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DetectionMethod::Auto => "auto",
+            DetectionMethod::Web => "web",
+            DetectionMethod::Fritzbox => "fritzbox",
+            DetectionMethod::Gateway => "gateway",
+            DetectionMethod::Cloud => "cloud",
+        }
+    }
+}
+
+impl FromStr for DetectionMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(DetectionMethod::Auto),
+            "web" => Ok(DetectionMethod::Web),
+            "fritzbox" => Ok(DetectionMethod::Fritzbox),
+            "gateway" => Ok(DetectionMethod::Gateway),
+            "cloud" => Ok(DetectionMethod::Cloud),
+            other => Err(format!(
+                "unknown detection method '{}', expected auto, web, fritzbox, gateway or cloud",
+                other
+            )),
+        }
+    }
+}
+
+impl FamilyDetection {
+    /// The web services asked for the address of the family `version`.
+    pub fn providers(&self, version: IpVersion) -> Vec<&str> {
+        if !self.providers.is_empty() {
+            return self.providers.iter().map(String::as_str).collect();
+        }
+        match version {
+            IpVersion::V4 => vec!["https://api.ipify.org?format=json"],
+            IpVersion::V6 => vec!["https://api6.ipify.org?format=json"],
+        }
+    }
+}
+
+impl DetectionSource {
+    pub fn family(&self, version: IpVersion) -> &FamilyDetection {
+        match version {
+            IpVersion::V4 => &self.ipv4,
+            IpVersion::V6 => &self.ipv6,
+        }
+    }
+}
+
+/// The only address family the web service at `url` answers over, if the
+/// URL tells: an address literal, or a host name such as `api6.ipify.org`
+/// or `ipv4.icanhazip.com`.
+pub fn provider_family(url: &str) -> Option<IpVersion> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(IpAddr::V4(_)) => return Some(IpVersion::V4),
+        Ok(IpAddr::V6(_)) => return Some(IpVersion::V6),
+        Err(_) => {}
+    }
+    let label = host.split('.').next()?;
+    match label {
+        "ipv4" | "v4" | "api4" | "4" => Some(IpVersion::V4),
+        "ipv6" | "v6" | "api6" | "6" => Some(IpVersion::V6),
+        _ => None,
+    }
+}
+
+impl IpVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IpVersion::V4 => "v4",
+            IpVersion::V6 => "v6",
+        }
+    }
+
+    pub fn dns_type(self) -> DnsType {
+        match self {
+            IpVersion::V4 => DnsType::A,
+            IpVersion::V6 => DnsType::Aaaa,
+        }
+    }
+
+    /// The family of the addresses rrsets of `dns_type` hold, if any.
+    pub fn of_type(dns_type: DnsType) -> Option<Self> {
+        [IpVersion::V4, IpVersion::V6]
+            .into_iter()
+            .find(|version| version.dns_type() == dns_type)
+    }
+}
+
+/// Parses an address rrset type, `A` or `AAAA`.
+pub fn parse_address_type(value: &str) -> Result<DnsType, String> {
+    let dns_type: DnsType = value
+        .parse()
+        .map_err(|e: ParseDnsTypeError| e.to_string())?;
+    match IpVersion::of_type(dns_type) {
+        Some(_) => Ok(dns_type),
+        None => Err(format!("{} rrsets do not hold addresses", dns_type)),
+    }
+}
+
+impl FromStr for IpVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "v4" | "ipv4" | "4" => Ok(IpVersion::V4),
+            "v6" | "ipv6" | "6" => Ok(IpVersion::V6),
+            other => Err(format!("unknown IP version '{}'", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for IpVersion {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl IpVersionFilter {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IpVersionFilter::Both => "both",
+            IpVersionFilter::Only(version) => version.as_str(),
+        }
+    }
+
+    pub fn allows(self, version: IpVersion) -> bool {
+        match self {
+            IpVersionFilter::Both => true,
+            IpVersionFilter::Only(only) => only == version,
+        }
+    }
+
+    /// Whether records of `dns_type` are updated; types that do not hold
+    /// an address are not filtered.
+    pub fn allows_type(self, dns_type: DnsType) -> bool {
+        IpVersion::of_type(dns_type).is_none_or(|version| self.allows(version))
+    }
+
+    /// The families of the address rrset `types`, e.g. `[A]` for IPv4 only.
+    pub fn of_types(types: &[DnsType]) -> Result<Self, String> {
+        let mut filters = Vec::new();
+        for &dns_type in types {
+            let version = IpVersion::of_type(dns_type)
+                .ok_or_else(|| format!("{} rrsets do not hold addresses", dns_type))?;
+            filters.push(IpVersionFilter::Only(version));
+        }
+        Ok(IpVersionFilter::covering(filters))
+    }
+
+    /// The smallest filter allowing every family one of `filters` allows.
+    pub fn covering(filters: impl IntoIterator<Item = IpVersionFilter>) -> Self {
+        let mut covered: Option<IpVersionFilter> = None;
+        for filter in filters {
+            covered = match covered {
+                None => Some(filter),
+                Some(covered) if covered == filter => Some(covered),
+                Some(_) => Some(IpVersionFilter::Both),
+            };
+        }
+        covered.unwrap_or_default()
+    }
+}
+
+impl FromStr for IpVersionFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "both" => Ok(IpVersionFilter::Both),
+            other => other
+                .parse()
+                .map(IpVersionFilter::Only)
+                .map_err(|_| format!("unknown IP version '{}', expected v4, v6 or both", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for IpVersionFilter {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Formats an error along with its causes, which reqwest keeps out of its
+/// own message.
+pub fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Writes an address in its canonical form, e.g. `2001:db8::1` for
+/// `2001:DB8:0:0:0:0:0:1`. Values that are not addresses are kept as is.
+pub fn canonical(value: &str) -> String {
+    match value.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => value.to_string(),
+    }
+}
+
+/// Whether two values hold the same address, however they are written.
+/// An IPv4-mapped IPv6 address differs from the IPv4 address, as they
+/// belong in different record types.
+pub fn same_address(a: &str, b: &str) -> bool {
+    match (a.parse::<IpAddr>(), b.parse::<IpAddr>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Whether the values of an rrset are exactly the address `ip`.
+pub fn holds_only<S: AsRef<str>>(values: &[S], ip: &str) -> bool {
+    matches!(values, [value] if same_address(value.as_ref(), ip))
+}
+
+/// Why the values of an rrset do not look like a dynamic address of
+/// `dns_type`, e.g. an intentional round-robin or a record repurposed for
+/// something else, or `None` if they do.
+pub fn foreign_values<S: AsRef<str>>(values: &[S], dns_type: DnsType) -> Option<String> {
+    if values.len() > 1 {
+        let values: Vec<&str> = values.iter().map(AsRef::as_ref).collect();
+        return Some(format!(
+            "it holds {} values ({})",
+            values.len(),
+            values.join(",")
+        ));
+    }
+    let value = values.first()?.as_ref();
+    let family = if dns_type == DnsType::Aaaa {
+        "IPv6"
+    } else {
+        "IPv4"
+    };
+    let expected = match value.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => family == "IPv4",
+        Ok(IpAddr::V6(_)) => family == "IPv6",
+        Err(_) => false,
+    };
+    (!expected).then(|| format!("it holds {}, which is not an {} address", value, family))
+}
+
+/// Checks that an IPv4 address is publicly routable, rejecting private,
+/// shared (CGNAT), loopback, link-local and other special-purpose ranges.
+pub fn validate_public_ipv4(ip: &Ipv4Addr) -> Result<(), String> {
+    let octets = ip.octets();
+    let reason = if ip.is_unspecified() {
+        "unspecified"
+    } else if ip.is_loopback() {
+        "loopback"
+    } else if ip.is_private() {
+        "private"
+    } else if octets[0] == 100 && (octets[1] & 0xc0) == 64 {
+        "shared (CGNAT)"
+    } else if ip.is_link_local() {
+        "link-local"
+    } else if ip.is_multicast() {
+        "multicast"
+    } else if ip.is_broadcast() || octets[0] >= 240 {
+        "reserved"
+    } else {
+        return Ok(());
+    };
+    Err(format!("{} is a {} address", ip, reason))
+}
+
+/// Checks that an IPv6 address is publicly routable, rejecting loopback,
+/// link-local, unique local, multicast and IPv4-mapped addresses.
+pub fn validate_public_ipv6(ip: &Ipv6Addr) -> Result<(), String> {
+    let first = ip.segments()[0];
+    let reason = if ip.is_unspecified() {
+        "unspecified"
+    } else if ip.is_loopback() {
+        "loopback"
+    } else if (first & 0xffc0) == 0xfe80 {
+        "link-local"
+    } else if (first & 0xfe00) == 0xfc00 {
+        "unique local"
+    } else if ip.is_multicast() {
+        "multicast"
+    } else if ip.to_ipv4_mapped().is_some() {
+        "IPv4-mapped"
+    } else {
+        return Ok(());
+    };
+    Err(format!("{} is a {} address", ip, reason))
+}
+
+/// Returns the IPv4 address embedded in an address synthesized by NAT64
+/// (RFC 6052), either under the well-known prefix `64:ff9b::/96` or the
+/// local-use prefix `64:ff9b:1::/48` of RFC 8215.
+pub fn nat64_embedded_ipv4(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let o = ip.octets();
+    match segments {
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => Some(Ipv4Addr::new(o[12], o[13], o[14], o[15])),
+        // The IPv4 address skips the reserved bits 64 to 71.
+        [0x64, 0xff9b, 1, _, _, _, _, _] => Some(Ipv4Addr::new(o[6], o[7], o[9], o[10])),
+        _ => None,
+    }
+}
+
+/// The address made of the first `length` bits of `prefix` and the rest of
+/// `suffix`.
+pub fn in_prefix(prefix: Ipv6Addr, length: u8, suffix: Ipv6Addr) -> Ipv6Addr {
+    let mask = prefix_mask(length);
+    Ipv6Addr::from((u128::from(prefix) & mask) | (u128::from(suffix) & !mask))
+}
+
+/// The mask of the first `length` bits of an IPv6 address.
+pub fn prefix_mask(length: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(length.min(128)))
+        .unwrap_or(0)
+}
+
+pub fn parse_public_ipv4(value: &str) -> Result<Ipv4Addr, String> {
+    let ip = value
+        .parse()
+        .map_err(|_| format!("'{}' is not an IPv4 address", value))?;
+    validate_public_ipv4(&ip)?;
+    Ok(ip)
+}
+
+pub fn parse_public_ipv6(value: &str) -> Result<Ipv6Addr, String> {
+    let ip = value
+        .parse()
+        .map_err(|_| format!("'{}' is not an IPv6 address", value))?;
+    validate_public_ipv6(&ip)?;
+    Ok(ip)
+}
+
+/// Parses and validates a public address of the given family.
+pub fn parse_public_ip(version: IpVersion, value: &str) -> Result<IpAddr, String> {
+    match version {
+        IpVersion::V4 => parse_public_ipv4(value).map(IpAddr::V4),
+        IpVersion::V6 => parse_public_ipv6(value).map(IpAddr::V6),
+    }
+}
+
+/// Asks the web services of `family` in turn for the public address of the
+/// family `version`.
+pub async fn get_public_ip(
+    version: IpVersion,
+    family: &FamilyDetection,
+    timeouts: Timeouts,
+) -> Option<IpDetectionResult> {
+    let str_ip_type = version.as_str();
+
+    let client = match family.binding.client(version, timeouts) {
+        Ok(client) => client,
+        Err(e) => {
+            error!(
+                "Critical Error: Unable to detect the public IP{}: {}",
+                str_ip_type, e
+            );
+            return None;
+        }
+    };
+
+    let providers = family.providers(version);
+    for (index, url) in providers.iter().enumerate() {
+        let label = format!("IP{} detection through {}", str_ip_type, url);
+        match timed(
+            Operation::Detection,
+            &label,
+            ask_provider(&client, url, &family.headers, version),
+        )
+        .await
+        {
+            Ok(ip) => {
+                let provider = provider_name(url);
+                info!("Public IP{}: {} (via {})", str_ip_type, ip, provider);
+                return Some(IpDetectionResult::new(ip, &provider));
+            }
+            Err(e) if index + 1 < providers.len() => warn!(
+                "Unable to detect the public IP{} through {}: {}, falling back",
+                str_ip_type, url, e
+            ),
+            Err(e) => error!(
+                "Critical Error: Unable to detect the public IP{} through {}: {}",
+                str_ip_type, url, e
+            ),
+        }
+    }
+    None
+}
+
+/// Asks the web service at `url` for the public address of the family
+/// `version`.
+async fn ask_provider(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    version: IpVersion,
+) -> Result<IpAddr, String> {
+    let response = client
+        .get(url)
+        .headers(headers.clone())
+        .send()
+        .await
+        .map_err(|e| error_chain(&e))?;
+    let status = response.status();
+    telemetry::record("http.response.status_code", status.as_u16());
+    if !status.is_success() {
+        return Err(format!("status code {}", status));
+    }
+    let body = response.text().await.map_err(|e| error_chain(&e))?;
+    let ip = match serde_json::from_str::<Value>(&body) {
+        Ok(json) => json["ip"].as_str().unwrap_or("").to_string(),
+        Err(_) => body.trim().to_string(),
+    };
+    parse_public_ip(version, &ip).map_err(|e| format!("invalid address: {}", e))
+}
+
+/// Detects the public address of the family `version` through `source`,
+/// asking its metadata service, its FRITZ!Box and then its gateway first if
+/// it has them. The metadata service telling that the instance has no
+/// address of the family is the last word.
+async fn detect_public_ip(
+    version: IpVersion,
+    source: &DetectionSource,
+    timeouts: Timeouts,
+    state_dir: &Path,
+) -> Option<IpDetectionResult> {
+    let str_ip_type = version.as_str();
+    let family = source.family(version);
+    let timeouts = Timeouts {
+        ip_detection: family.timeout.or(timeouts.ip_detection),
+        ..timeouts
+    };
+    let method = family.method;
+    let clouds = method == DetectionMethod::Auto || method == DetectionMethod::Cloud;
+    if let (true, Some(cloud)) = (clouds, &source.cloud) {
+        let name = cloud.provider.label();
+        let label = format!("IP{} detection through the {} metadata", str_ip_type, name);
+        let answer = timed(
+            Operation::Detection,
+            &label,
+            cloud.public_ip(version, timeouts),
+        );
+        match answer.await {
+            Ok(Some(ip)) => match parse_public_ip(version, &ip) {
+                Ok(ip) => {
+                    info!("Public IP{}: {} (via {} metadata)", str_ip_type, ip, name);
+                    return Some(IpDetectionResult::new(ip, name));
+                }
+                Err(e) => warn!(
+                    "The {} metadata reports no usable public IP{}: {}, falling back",
+                    name, str_ip_type, e
+                ),
+            },
+            Ok(None) => {
+                info!(
+                    "The {} instance has no public IP{} assigned",
+                    name, str_ip_type
+                );
+                return None;
+            }
+            Err(e) => warn!(
+                "Unable to ask the {} metadata for the public IP{}: {}, falling back",
+                name, str_ip_type, e
+            ),
+        }
+    }
+    let routers = method == DetectionMethod::Auto || method == DetectionMethod::Fritzbox;
+    if let (true, Some(router)) = (routers, &source.fritzbox) {
+        let label = format!("IP{} detection through the FRITZ!Box", str_ip_type);
+        let answer = timed(
+            Operation::Detection,
+            &label,
+            router.external_ip(version, timeouts),
+        );
+        match answer.await {
+            Ok(ip) => match parse_public_ip(version, &ip) {
+                Ok(ip) => {
+                    info!("Public IP{}: {} (via FRITZ!Box)", str_ip_type, ip);
+                    return Some(IpDetectionResult::new(ip, "FRITZ!Box"));
+                }
+                Err(e) => warn!(
+                    "The FRITZ!Box reports no usable public IP{}: {}, falling back",
+                    str_ip_type, e
+                ),
+            },
+            Err(e) => warn!(
+                "Unable to ask the FRITZ!Box at {} for the public IP{}: {}, falling back",
+                router.address, str_ip_type, e
+            ),
+        }
+    }
+    // The gateway protocols only know about IPv4.
+    let gateways = method == DetectionMethod::Auto || method == DetectionMethod::Gateway;
+    if let (IpVersion::V4, true, Some(gateway)) = (version, gateways, &source.gateway) {
+        let answer = timed(
+            Operation::Detection,
+            "IPv4 detection through the gateway",
+            gateway.external_ipv4(state_dir, timeouts),
+        );
+        match answer.await {
+            Ok(ip) => match validate_public_ipv4(&ip) {
+                Ok(()) => {
+                    info!("Public IPv4: {} (via gateway)", ip);
+                    return Some(IpDetectionResult::new(ip, "gateway"));
+                }
+                Err(e) => warn!(
+                    "The gateway reports {}, so it is itself behind NAT (double NAT), falling back",
+                    e
+                ),
+            },
+            Err(e) => warn!(
+                "Unable to ask the gateway for the public IPv4 over {}: {}, falling back",
+                gateway.protocol.as_str(),
+                e
+            ),
+        }
+    }
+    if method != DetectionMethod::Auto && method != DetectionMethod::Web {
+        error!(
+            "Critical Error: Unable to detect the public IP{} through the {}",
+            str_ip_type,
+            method.as_str()
+        );
+        return None;
+    }
+    get_public_ip(version, family, timeouts).await
+}
+
+/// Detects the public addresses through `source`, except for the families
+/// whose address was given explicitly or that are not `wanted`. Both
+/// families are detected at the same time.
+///
+/// Behind NAT64 the IPv6 detection only sees an address synthesized for
+/// the IPv4 detection service. It is not the address of the host, so the
+/// AAAA records are left alone, and the embedded IPv4 address stands in
+/// for the A records if the IPv4 detection failed.
+pub async fn get_public_ips(
+    ipv4_override: Option<Ipv4Addr>,
+    ipv6_override: Option<Ipv6Addr>,
+    source: &DetectionSource,
+    wanted: IpVersionFilter,
+    timeouts: Timeouts,
+    state_dir: &Path,
+) -> (Option<IpDetectionResult>, Option<IpDetectionResult>) {
+    let ipv4 = async {
+        match ipv4_override {
+            _ if !wanted.allows(IpVersion::V4) => None,
+            Some(ip) => {
+                info!("Public IPv4: {} (via override)", ip);
+                Some(IpDetectionResult::new(ip, "override"))
+            }
+            None => detect_public_ip(IpVersion::V4, source, timeouts, state_dir).await,
+        }
+    };
+    let ipv6 = async {
+        match ipv6_override {
+            _ if !wanted.allows(IpVersion::V6) => None,
+            Some(ip) => {
+                info!("Public IPv6: {} (via override)", ip);
+                Some(IpDetectionResult::new(ip, "override"))
+            }
+            None => detect_public_ip(IpVersion::V6, source, timeouts, state_dir).await,
+        }
+    };
+    let (mut ip4, ip6) = tokio::join!(ipv4, ipv6);
+
+    let embedded = match (ipv6_override, &ip6) {
+        (None, Some(detected)) => detected
+            .ip
+            .parse()
+            .ok()
+            .and_then(|ip| nat64_embedded_ipv4(&ip)),
+        _ => None,
+    };
+    let Some(embedded) = embedded else {
+        return (ip4, ip6);
+    };
+    warn!(
+        "The public IPv6 {} is synthesized by NAT64 for {}, there is no native IPv6 connectivity; not updating the AAAA records",
+        ip6.as_ref().map(|detected| detected.ip.as_str()).unwrap_or_default(),
+        embedded
+    );
+    if ip4.is_none() && wanted.allows(IpVersion::V4) && validate_public_ipv4(&embedded).is_ok() {
+        info!("Public IPv4: {} (via NAT64)", embedded);
+        ip4 = Some(IpDetectionResult::new(embedded, "NAT64"));
+    }
+    (ip4, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn embedded(ip: &str) -> Option<Ipv4Addr> {
+        nat64_embedded_ipv4(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn extracts_the_ipv4_of_the_well_known_nat64_prefix() {
+        let ipv4 = Some(Ipv4Addr::new(192, 0, 2, 33));
+        assert_eq!(embedded("64:ff9b::192.0.2.33"), ipv4);
+        assert_eq!(embedded("64:ff9b::c000:221"), ipv4);
+        assert_eq!(embedded("64:FF9B:0:0:0:0:C000:0221"), ipv4);
+    }
+
+    #[test]
+    fn extracts_the_ipv4_of_the_local_use_nat64_prefix() {
+        // RFC 6052 section 2.2, for a /48 prefix.
+        assert_eq!(
+            embedded("64:ff9b:1:c000:2:2100::"),
+            Some(Ipv4Addr::new(192, 0, 2, 33))
+        );
+    }
+
+    #[test]
+    fn leaves_other_ipv6_addresses_alone() {
+        assert_eq!(embedded("2001:db8::c000:221"), None);
+        assert_eq!(embedded("64:ff9b:0:1::c000:221"), None);
+        assert_eq!(embedded("::ffff:192.0.2.33"), None);
+        assert_eq!(embedded("64:ff9a::192.0.2.33"), None);
+    }
+
+    #[test]
+    fn tells_the_family_a_provider_answers_over() {
+        let family = provider_family;
+        assert_eq!(
+            family("https://api6.ipify.org?format=json"),
+            Some(IpVersion::V6)
+        );
+        assert_eq!(family("https://api4.ipify.org"), Some(IpVersion::V4));
+        assert_eq!(family("https://IPv4.icanhazip.com"), Some(IpVersion::V4));
+        assert_eq!(family("https://v6.ident.me/"), Some(IpVersion::V6));
+        assert_eq!(family("http://192.0.2.1:8080/ip"), Some(IpVersion::V4));
+        assert_eq!(family("http://[2001:db8::1]/ip"), Some(IpVersion::V6));
+        assert_eq!(family("https://api.ipify.org"), None);
+        assert_eq!(family("https://ipv4only.example.com"), None);
+        assert_eq!(family("not a url"), None);
+    }
+
+    #[test]
+    fn rejects_a_bind_address_of_the_other_family() {
+        let binding = Binding {
+            address: Some(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+            ..Binding::default()
+        };
+        assert_eq!(
+            binding
+                .client(IpVersion::V4, Timeouts::default())
+                .unwrap_err(),
+            "bind_address ::1 is not an IPv4 address"
+        );
+        assert!(binding.client(IpVersion::V6, Timeouts::default()).is_ok());
+    }
+
+    /// Listens on the same port of both loopback addresses, answering the
+    /// public address `ipv4` over IPv4 and `ipv6` over IPv6, or `None` if
+    /// no such port could be found.
+    async fn dual_stack_provider(ipv4: &'static str, ipv6: &'static str) -> Option<u16> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        async fn serve(listener: TcpListener, ip: &'static str) {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    ip.len(),
+                    ip
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        }
+
+        for _ in 0..10 {
+            let v4 = TcpListener::bind("127.0.0.1:0").await.ok()?;
+            let port = v4.local_addr().ok()?.port();
+            let Ok(v6) = TcpListener::bind(("::1", port)).await else {
+                continue;
+            };
+            tokio::spawn(serve(v4, ipv4));
+            tokio::spawn(serve(v6, ipv6));
+            return Some(port);
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn asks_a_dual_stack_provider_over_the_detected_family() {
+        const IPV4: &str = "1.1.1.1";
+        const IPV6: &str = "2606:4700:4700::1111";
+        let Some(port) = dual_stack_provider(IPV4, IPV6).await else {
+            // No IPv6 loopback to test with.
+            return;
+        };
+        let url = format!("http://dual-stack.test:{}/", port);
+        let resolved = [
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+        ];
+        for (version, expected) in [(IpVersion::V4, IPV4), (IpVersion::V6, IPV6)] {
+            let client = Binding::default()
+                .builder(version, Timeouts::default())
+                .unwrap()
+                .resolve_to_addrs("dual-stack.test", &resolved)
+                .build()
+                .unwrap();
+            let ip = ask_provider(&client, &url, &HeaderMap::new(), version).await;
+            assert_eq!(ip, Ok(expected.parse().unwrap()));
+        }
+
+        // Rather than over the other family.
+        let client = Binding::default()
+            .builder(IpVersion::V6, Timeouts::default())
+            .unwrap()
+            .resolve_to_addrs("dual-stack.test", &resolved[1..])
+            .build()
+            .unwrap();
+        assert!(
+            ask_provider(&client, &url, &HeaderMap::new(), IpVersion::V6)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn compares_addresses_however_they_are_written() {
+        assert!(same_address("2001:DB8:0:0:0:0:0:1", "2001:db8::1"));
+        assert!(same_address("2001:db8::1", "2001:0db8::0001"));
+        assert!(!same_address("2001:db8::1", "2001:db8::2"));
+        assert!(same_address("::ffff:192.0.2.1", "::FFFF:C000:201"));
+        // They belong in different record types.
+        assert!(!same_address("::ffff:192.0.2.1", "192.0.2.1"));
+        assert!(same_address("target.example.com.", "target.example.com."));
+        assert!(!same_address("target.example.com.", "other.example.com."));
+    }
+
+    #[test]
+    fn writes_addresses_in_their_canonical_form() {
+        assert_eq!(canonical("2001:DB8:0:0:0:0:0:1"), "2001:db8::1");
+        assert_eq!(canonical("2001:db8::1"), "2001:db8::1");
+        assert_eq!(canonical("::FFFF:C000:201"), "::ffff:192.0.2.1");
+        assert_eq!(canonical("192.0.2.1"), "192.0.2.1");
+        assert_eq!(canonical("Target.example.com."), "Target.example.com.");
+    }
+
+    #[test]
+    fn holds_only_a_single_matching_address() {
+        assert!(holds_only(&["2001:DB8:0:0:0:0:0:1"], "2001:db8::1"));
+        assert!(!holds_only(&["2001:db8::1", "2001:db8::2"], "2001:db8::1"));
+        assert!(!holds_only::<&str>(&[], "2001:db8::1"));
+    }
+
+    #[test]
+    fn reads_the_last_prefix_of_the_lease_file() {
+        let dhclient = r#"
+default-duid "\000\001\000\001";
+lease6 {
+  interface "wan";
+  ia-pd 1a:2b:3c:4d {
+    starts 1714564800;
+    iaprefix 2001:db8:1200::/56 {
+      starts 1714564800;
+      preferred-life 3600;
+    }
+  }
+}
+lease6 {
+  interface "wan";
+  ia-pd 1a:2b:3c:4d {
+    iaprefix 2001:db8:3400::/56 {
+      preferred-life 3600;
+    }
+  }
+}
+"#;
+        assert_eq!(
+            lease_prefix(dhclient),
+            Some("2001:db8:3400::".parse().unwrap())
+        );
+        // The unique local prefix of the LAN is not the delegated one.
+        assert_eq!(
+            lease_prefix("PREFIXES=2001:db8:5600::/48,7200,3600 ULA=fd00:1::/48"),
+            Some("2001:db8:5600::".parse().unwrap())
+        );
+        assert_eq!(
+            lease_prefix("lease6 { ia-na 1 { iaaddr 2001:db8::5 {} } }"),
+            None
+        );
+    }
+}
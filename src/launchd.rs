@@ -0,0 +1,138 @@
+//! Generating and installing a launchd job for macOS.
+
+use clap::Args;
+use log::info;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const LABEL: &str = "net.gandi.ddns";
+
+const PLIST_TEMPLATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{binary_path}</string>
+        <string>--config</string>
+        <string>{config_path}</string>
+    </array>
+    <key>StartInterval</key>
+    <integer>{interval}</integer>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{stdout_path}</string>
+    <key>StandardErrorPath</key>
+    <string>{stderr_path}</string>
+</dict>
+</plist>
+"#;
+
+#[derive(Debug, Args)]
+pub struct LaunchdArgs {
+    /// Seconds between two runs
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    interval: u32,
+    /// Where to write the plist instead of printing it (defaults to
+    /// ~/Library/LaunchAgents/net.gandi.ddns.plist with --install and
+    /// --uninstall)
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Directory of the log files (defaults to ~/Library/Logs/ddns-gandi)
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+    /// Write the plist and load it with `launchctl load`
+    #[arg(long, conflicts_with = "uninstall")]
+    install: bool,
+    /// Unload the job with `launchctl unload` and delete the plist
+    #[arg(long)]
+    uninstall: bool,
+}
+
+fn home() -> Result<PathBuf, String> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| "HOME is not set".to_string())
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// launchd starts jobs in `/`, so all paths must be absolute.
+fn log_dir(args: &LaunchdArgs) -> Result<PathBuf, Box<dyn Error>> {
+    match &args.log_dir {
+        Some(dir) => Ok(std::path::absolute(dir)?),
+        None => Ok(home()?.join("Library/Logs/ddns-gandi")),
+    }
+}
+
+fn render(args: &LaunchdArgs, config: &Path) -> Result<String, Box<dyn Error>> {
+    let log_dir = log_dir(args)?;
+    let path = |path: &Path| xml_escape(&path.to_string_lossy());
+
+    Ok(PLIST_TEMPLATE
+        .replace("{label}", LABEL)
+        .replace("{binary_path}", &path(&env::current_exe()?))
+        .replace("{config_path}", &path(&std::path::absolute(config)?))
+        .replace("{interval}", &args.interval.to_string())
+        .replace("{stdout_path}", &path(&log_dir.join("ddns-gandi.log")))
+        .replace("{stderr_path}", &path(&log_dir.join("ddns-gandi.err.log"))))
+}
+
+fn plist_path(args: &LaunchdArgs) -> Result<PathBuf, String> {
+    match &args.output {
+        Some(path) => Ok(path.clone()),
+        None => Ok(home()?
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", LABEL))),
+    }
+}
+
+fn launchctl(action: &str, plist: &Path) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("launchctl").arg(action).arg(plist).status()?;
+    if !status.success() {
+        return Err(format!("launchctl {} failed with {}", action, status).into());
+    }
+    Ok(())
+}
+
+pub fn handle(args: &LaunchdArgs, config: &Path) -> Result<(), Box<dyn Error>> {
+    if args.uninstall {
+        let plist = plist_path(args)?;
+        launchctl("unload", &plist)?;
+        fs::remove_file(&plist)?;
+        info!("Unloaded and removed {}", plist.display());
+        return Ok(());
+    }
+
+    let contents = render(args, config)?;
+    if !args.install && args.output.is_none() {
+        print!("{}", contents);
+        return Ok(());
+    }
+
+    let plist = plist_path(args)?;
+    if let Some(dir) = plist.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&plist, contents)?;
+    info!("Wrote {}", plist.display());
+
+    if args.install {
+        fs::create_dir_all(log_dir(args)?)?;
+        launchctl("load", &plist)?;
+        info!("Loaded the {} job", LABEL);
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Following the configuration file in daemon mode, reading it again only
+//! once its modification time changed rather than on every pass.
+
+use crate::config::DnsConfig;
+use log::{error, info};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+
+/// How long to wait after a file system event before reading the file, as
+/// editors tend to write it in several steps.
+#[cfg(feature = "inotify")]
+const SETTLE: Duration = Duration::from_millis(500);
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Starts following the configuration file at `path`, sending what `load`
+/// reads from it each time it changes. The file is checked every
+/// `DAEMON.config_poll_interval` of the configuration last sent and, with
+/// the `inotify` feature, as soon as the file system reports a change.
+pub fn spawn<F, Fut>(path: PathBuf, initial: DnsConfig, load: F) -> watch::Receiver<DnsConfig>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<DnsConfig, String>> + Send + 'static,
+{
+    let (sender, receiver) = watch::channel(initial);
+    tokio::spawn(follow(path, sender, load));
+    receiver
+}
+
+async fn follow<F, Fut>(path: PathBuf, sender: watch::Sender<DnsConfig>, load: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<DnsConfig, String>>,
+{
+    let mut last = modified(&path).await;
+    let mut events = Events::watch(&path);
+    loop {
+        let interval = sender.borrow().config_poll_interval;
+        if interval.is_none() && events.is_none() {
+            return;
+        }
+        tokio::select! {
+            _ = poll(interval) => {}
+            _ = next_event(&mut events) => {}
+            _ = sender.closed() => return,
+        }
+        let current = modified(&path).await;
+        if current.is_none() || current == last {
+            continue;
+        }
+        last = current;
+        info!("{} changed, reloading the configuration", path.display());
+        match load().await {
+            Ok(config) => {
+                sender.send_replace(config);
+            }
+            Err(e) => error!("Unable to reload the configuration: {}", e),
+        }
+    }
+}
+
+async fn poll(interval: Option<Duration>) {
+    match interval {
+        Some(interval) => tokio::time::sleep(interval).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Changes to the directory of the configuration file reported by the
+/// file system.
+#[cfg(feature = "inotify")]
+struct Events {
+    _watcher: ::notify::RecommendedWatcher,
+    receiver: tokio::sync::mpsc::UnboundedReceiver<()>,
+}
+
+#[cfg(feature = "inotify")]
+impl Events {
+    /// Watches the directory rather than the file, which editors replace.
+    fn watch(path: &Path) -> Option<Self> {
+        use ::notify::Watcher;
+        use log::warn;
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let result = ::notify::recommended_watcher(move |_| {
+            let _ = sender.send(());
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(dir, ::notify::RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        match result {
+            Ok(watcher) => Some(Events {
+                _watcher: watcher,
+                receiver,
+            }),
+            Err(e) => {
+                warn!("Unable to watch {}: {}", dir.display(), e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "inotify")]
+async fn next_event(events: &mut Option<Events>) {
+    match events {
+        Some(events) => {
+            if events.receiver.recv().await.is_none() {
+                return std::future::pending().await;
+            }
+            tokio::time::sleep(SETTLE).await;
+            while events.receiver.try_recv().is_ok() {}
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(feature = "inotify"))]
+struct Events;
+
+#[cfg(not(feature = "inotify"))]
+impl Events {
+    fn watch(_path: &Path) -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(not(feature = "inotify"))]
+async fn next_event(_events: &mut Option<Events>) {
+    std::future::pending().await
+}
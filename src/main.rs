@@ -1,190 +1,1448 @@
-use config::{Config, ConfigError, File};
-use log::{error, info, warn};
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
-use serde_json::Value;
+mod assertion;
+mod audit;
+mod cloud;
+mod config;
+#[cfg(feature = "daemon-mode")]
+mod control;
+mod debounce;
+mod diff;
+mod digest;
+mod discovery;
+mod dns_type;
+mod drift;
+mod echo_server;
+mod encryption;
+mod error;
+mod fritzbox;
+mod gandi;
+mod gateway;
+mod guard;
+mod health;
+mod heartbeat;
+mod history;
+mod ip;
+mod launchd;
+#[cfg(feature = "daemon-mode")]
+mod live_config;
+mod manual;
+mod message_templates;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod network;
+mod notify;
+mod pacing;
+mod pinning;
+mod precheck;
+mod prefetch;
+mod records_file;
+mod redacted;
+mod remote_config;
+mod retry;
+mod rollback;
+mod schema;
+mod secret_store;
+#[cfg(all(windows, feature = "daemon-mode"))]
+mod service;
+mod snapshot;
+mod soap;
+mod systemd;
+mod telemetry;
+mod template;
+mod throttle;
+mod timeouts;
+mod timing;
+mod tls;
+mod ttl;
+#[cfg(feature = "daemon-mode")]
+mod wakeup;
+mod watchdog;
+mod zonefile;
+
+use ::config::ConfigError;
+use audit::{AuditEntry, AuditLog, Outcome};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use config::{
+    dump_config, normalize_record_name, read_config, read_config_from_url, DnsConfig, PartialSet,
+    RecordEntry, DEFAULT_CONFIG_PATH,
+};
+#[cfg(feature = "daemon-mode")]
+use control::{Command as ControlCommand, Control, PassReport, QuotaReport, Response};
+use debounce::Debouncer;
+#[cfg(feature = "daemon-mode")]
+use digest::Digest;
+use discovery::DomainDiscovery;
+use dns_type::DnsType;
+use drift::{drifted, DriftPolicy, WrittenValues};
+use error::DdnsError;
+use gandi::{
+    apply_batch_update, check_livedns_access, delete_gandi_rrset, get_gandi_rrsets,
+    get_zone_records, list_domains, plan_batch_update, update_gandi_record, update_gandi_rrset,
+    BatchItem, GandiApi, RecordUpdate, LIVEDNS_PERMISSION, RRSET_TTL, TOKEN_ADMIN_URL,
+};
+use ip::{
+    canonical, foreign_values, get_public_ips, holds_only, in_prefix, parse_address_type,
+    parse_public_ipv4, parse_public_ipv6, same_address, IpVersion, IpVersionFilter,
+};
+use log::{debug, error, info, warn};
+use notify::{DdnsEvent, Notifications, RecordChange, RecordFailure};
+use pacing::ApiRateLimiter;
+use precheck::{DnsPrecheck, Precheck};
+use prefetch::Prefetch;
+#[cfg(feature = "daemon-mode")]
+use remote_config::RemoteConfig;
+use retry::{QueuedUpdate, RetryQueue};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use template::{txt_text, Addresses, TemplateRecord};
+use time::OffsetDateTime;
+use timeouts::{parse_timeout, Timeouts, DEFAULT_REQUEST_TIMEOUT};
+use timing::Timings;
+#[cfg(feature = "daemon-mode")]
+use tokio::sync::watch;
+use ttl::TtlHistory;
+#[cfg(feature = "daemon-mode")]
+use wakeup::{Trigger, Wakeup, Woken};
+use watchdog::Watchdog;
 
-const REST_URL: &str = "https://api.gandi.net/v5/livedns/";
+/// Exit code used when `RUN.max_run_duration` elapses before the pass has
+/// finished, matching the convention of coreutils' `timeout`.
+const EXIT_DEADLINE_EXCEEDED: i32 = 124;
 
-#[derive(Debug)]
-struct DnsConfig {
-    key: String,
-    domain: String,
+/// Exit code used when the network did not come up within the budget of
+/// `--wait-for-network`, `EX_TEMPFAIL` of sysexits.h.
+const EXIT_NO_NETWORK: i32 = 75;
+
+/// Budget of `--wait-for-network` given without a value.
+const DEFAULT_NETWORK_WAIT: &str = "120s";
+
+/// How often the daemon repeats that it is paused.
+#[cfg(feature = "daemon-mode")]
+const PAUSED_LOG_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Pause before the second-chance pass over failed updates.
+const SECOND_PASS_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Dynamic DNS client for Gandi LiveDNS")]
+struct Cli {
+    /// Path of the configuration file, or a `file://`, `http(s)://` or
+    /// `consul://kv/<key>` URL to fetch it from
+    #[arg(short, long, global = true, value_name = "FILE", default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+
+    /// Only warn about unknown keys in the configuration file
+    #[arg(long, global = true)]
+    lax_config: bool,
+
+    /// Bound every HTTP request and connection by this many milliseconds,
+    /// or a duration such as `5s`, instead of the `HTTP.*_timeout` keys
+    #[arg(long, global = true, value_name = "TIMEOUT", value_parser = parse_timeout)]
+    timeout: Option<Duration>,
+
+    /// Before the first pass, wait this long (120s if not given) for the
+    /// Gandi API to be reachable, e.g. when started early at boot
+    #[arg(
+        long,
+        value_name = "DURATION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_NETWORK_WAIT,
+        value_parser = parse_timeout
+    )]
+    wait_for_network: Option<Duration>,
+
+    /// Print the fully-resolved configuration as TOML (with secrets
+    /// redacted) and exit
+    #[arg(long, alias = "print-config")]
+    dump_config: bool,
+
+    /// Keep running and update the records every `DAEMON.interval`
+    #[arg(long)]
+    daemon: bool,
+
+    /// Send a sample notification to the configured channels and exit
+    #[arg(long)]
+    test_notify: bool,
+
+    /// Exit with 0 if the last pass succeeded recently enough, 1 otherwise
+    #[arg(long)]
+    health_check: bool,
+
+    /// Do not check the credential before updating the records, saving an
+    /// API call per run
+    #[arg(long)]
+    no_preflight: bool,
+
+    /// Exit code when records were changed
+    #[arg(long, value_name = "CODE", default_value_t = 0)]
+    exit_code_on_change: i32,
+
+    /// Exit code when updates failed
+    #[arg(long, value_name = "CODE", default_value_t = 1)]
+    exit_code_on_failure: i32,
+
+    /// Only update the A records
+    #[arg(long, conflicts_with = "ipv6_only")]
+    ipv4_only: bool,
+
+    /// Only update the AAAA records
+    #[arg(long)]
+    ipv6_only: bool,
+
+    /// Only update the rrsets of these types, `A` or `AAAA`
+    #[arg(
+        long,
+        value_name = "TYPE",
+        value_delimiter = ',',
+        value_parser = parse_address_type,
+        conflicts_with_all = ["ipv4_only", "ipv6_only"]
+    )]
+    record_type: Vec<DnsType>,
+
+    /// Only update these records, e.g. `www,@`
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
     records: Vec<String>,
+
+    /// Use this IPv4 address instead of detecting it
+    #[arg(long, value_name = "ADDR", value_parser = parse_public_ipv4)]
+    ipv4_override: Option<Ipv4Addr>,
+
+    /// Use this IPv6 address instead of detecting it
+    #[arg(long, value_name = "ADDR", value_parser = parse_public_ipv6)]
+    ipv6_override: Option<Ipv6Addr>,
+
+    /// Write the records even over rrsets holding several values or one
+    /// that is not an address, like `allow_overwrite` on every record
+    #[arg(long)]
+    force_overwrite: bool,
+
+    /// Delete the A and AAAA rrsets of the records in `DNS.remove`
+    #[arg(long)]
+    allow_delete: bool,
+
+    /// Revert the addresses written by `set` instead of leaving them alone,
+    /// and roll back without asking
+    #[arg(long, global = true)]
+    yes: bool,
+
+    /// Log only errors, and the result of a pass only if it changed records
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Exit code when rrsets were left alone since they look like they are
+    /// used for something else
+    #[arg(long, value_name = "CODE", default_value_t = 0)]
+    exit_code_on_protected: i32,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum IpVersion {
-    V4,
-    V6,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print a roff man page to stdout
+    Man,
+    /// Show the record changes from the audit log
+    History(history::HistoryArgs),
+    /// Query or control the running daemon
+    #[cfg(feature = "daemon-mode")]
+    Ctl(control::CtlArgs),
+    /// Check that a record holds the given values, without changing it
+    Assert(assertion::AssertArgs),
+    /// Write the zone as an RFC 1035 zone file, e.g. for backups
+    Axfr(zonefile::AxfrArgs),
+    /// Write the given addresses to the records instead of detecting them;
+    /// later runs leave them alone unless passed --yes
+    Set(manual::SetArgs),
+    /// Put back the values the rrsets held before the last changes in the
+    /// audit log
+    Rollback(rollback::RollbackArgs),
+    /// Compare what the records should hold with the zone, exiting with 2
+    /// if they differ
+    Diff(diff::DiffArgs),
+    /// List the snapshots Gandi keeps of the zone
+    ListSnapshots,
+    /// Replace the whole zone with one of its snapshots
+    RestoreSnapshot(snapshot::RestoreArgs),
+    /// Encrypt the configuration file with age, to the recipients of the
+    /// age key
+    EncryptConfig {
+        /// Where to write the encrypted configuration
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+    },
+    /// Store the API key in the OS secret store, for `key_source =
+    /// "keyring"`
+    SetKey {
+        /// Service of the entry, instead of `GANDI.keyring_service`
+        #[arg(long)]
+        service: Option<String>,
+        /// Account of the entry, instead of `GANDI.keyring_account`
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Generate a launchd property list running the update periodically
+    /// (macOS)
+    LaunchdPlist(launchd::LaunchdArgs),
+    /// Generate systemd units running the update periodically (Linux)
+    SystemdUnit(systemd::SystemdArgs),
+    /// Answer every HTTP client the address it comes from, as text or
+    /// JSON, to serve as a detection provider of the other hosts
+    EchoServer(echo_server::EchoServerArgs),
+    /// Check the keys the Gandi API presents against `HTTP.gandi_spki_pins`,
+    /// without sending the credential
+    Pin(pinning::PinArgs),
+    /// Manage the Windows service
+    #[cfg(all(windows, feature = "daemon-mode"))]
+    Service {
+        #[command(subcommand)]
+        action: service::ServiceAction,
+    },
 }
 
-async fn get_public_ip(version: IpVersion) -> Option<String> {
-    let ip_type = match version {
-        IpVersion::V4 => "",
-        IpVersion::V6 => "6",
-    };
-    let str_ip_type = match version {
-        IpVersion::V4 => "v4",
-        IpVersion::V6 => "v6",
-    };
+/// How an update pass ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PassOutcome {
+    Success {
+        n_changed: usize,
+        /// Rrsets left alone since they look like they are used for
+        /// something else.
+        n_protected: usize,
+    },
+    Failed,
+    DeadlineExceeded,
+}
 
-    let url = format!("https://api{}.ipify.org?format=json", ip_type);
-    let response = reqwest::get(&url).await.ok()?;
+impl PassOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            PassOutcome::Success { .. } => "success",
+            PassOutcome::Failed => "failed",
+            PassOutcome::DeadlineExceeded => "deadline exceeded",
+        }
+    }
 
-    if response.status().is_success() {
-        let json: Value = response.json().await.ok()?;
-        let ip = json["ip"].as_str().unwrap_or("").to_string();
-        info!("Public IP{}: {}", str_ip_type, ip);
-        Some(ip)
-    } else {
-        error!("Critical Error: Unable to get public IP!");
-        error!("Status Code: {}", response.status());
-        None
+    fn exit_code(self, cli: &Cli) -> i32 {
+        match self {
+            PassOutcome::Success { n_protected, .. }
+                if n_protected > 0 && cli.exit_code_on_protected != 0 =>
+            {
+                cli.exit_code_on_protected
+            }
+            PassOutcome::Success { n_changed: 0, .. } => 0,
+            PassOutcome::Success { .. } => cli.exit_code_on_change,
+            PassOutcome::Failed => cli.exit_code_on_failure,
+            PassOutcome::DeadlineExceeded => EXIT_DEADLINE_EXCEEDED,
+        }
     }
 }
 
-async fn get_public_ips() -> (Option<String>, Option<String>) {
-    let ip4 = get_public_ip(IpVersion::V4).await;
-    let ip6 = get_public_ip(IpVersion::V6).await;
-    (ip4, ip6)
+/// Progress of an update pass, kept outside of the pass itself so it
+/// survives the pass being cancelled by the run deadline.
+#[derive(Debug, Default)]
+struct RunSummary {
+    n_changed: usize,
+    /// Rrsets found to be up to date already, so not written.
+    n_skipped: usize,
+    completed: Vec<String>,
+    /// Updates already applied by flushing the retry queue.
+    retried: Vec<QueuedUpdate>,
+    failed: Vec<FailedUpdate>,
+    /// Rrsets that were only updated by the second-chance pass.
+    second_pass: Vec<(String, DnsType)>,
+    /// Changes to append to the audit log once the pass is over.
+    audit: Vec<AuditEntry>,
+    /// Rrsets known to hold the detected address after the pass, as
+    /// `(record, type, address)`.
+    confirmed: Vec<(String, DnsType, String)>,
+    /// Rrsets someone else changed, to be notified about.
+    drifts: Vec<String>,
+    /// Rrsets left alone since they look like they are used for something
+    /// else, as `record/TYPE`.
+    protected: Vec<String>,
+    /// Rrsets deleted as listed in `DNS.remove`, as `record/TYPE`.
+    removed: Vec<String>,
+    /// What told the addresses of each source and type, for the audit log.
+    providers: HashMap<(Option<String>, DnsType), String>,
 }
 
-async fn get_gandi_record(
-    domain: &str,
-    name: &str,
-    dns_type: &str,
-    headers: &HeaderMap,
-) -> Option<Vec<String>> {
-    let client = reqwest::Client::new();
-    let url = format!(
-        "{}domains/{}/records/{}/{}",
-        REST_URL, domain, name, dns_type
-    );
+impl RunSummary {
+    fn changed(&mut self, update: &QueuedUpdate, old: Option<Vec<String>>, old_ttl: Option<u32>) {
+        self.n_changed += 1;
+        self.confirm(&update.record, update.dns_type, &update.target_ip);
+        let mut entry = AuditEntry::new(
+            &update.domain,
+            &update.record,
+            update.dns_type.as_str(),
+            old,
+            &update.target_ip,
+            Outcome::Changed,
+        );
+        entry.source = update.source.clone();
+        entry.old_ttl = old_ttl;
+        self.audit.push(entry);
+    }
 
-    let response = client
-        .get(&url)
-        .headers(headers.clone())
-        .send()
-        .await
-        .ok()?;
+    /// Counts the rrset `record` rendered from a template as changed.
+    /// It holds no address, so it is not confirmed.
+    fn templated(
+        &mut self,
+        domain: &str,
+        record: &str,
+        dns_type: DnsType,
+        old: Option<Vec<String>>,
+        new: &str,
+    ) {
+        self.n_changed += 1;
+        self.audit.push(AuditEntry::new(
+            domain,
+            record,
+            dns_type.as_str(),
+            old,
+            new,
+            Outcome::Changed,
+        ));
+    }
 
-    if response.status().is_success() {
-        let json: Value = response.json().await.ok()?;
-        let values = json["rrset_values"]
-            .as_array()
-            .unwrap_or(&Vec::new())
-            .iter()
-            .map(|v| v.as_str().unwrap_or("").to_string())
-            .collect();
-        Some(values)
-    } else {
-        error!(
-            "Critical Error: Unable to retrieve the {} record for {}@{} from Gandi!",
-            dns_type, name, domain
+    /// Leaves an rrset alone since `reason`.
+    fn protect(&mut self, record: &str, dns_type: DnsType, reason: &str) {
+        warn!(
+            "\tLeaving {}/{} alone since {}; set allow_overwrite = true on the record or pass --force-overwrite to write it anyway",
+            record, dns_type, reason
         );
-        error!("Status Code: {}", response.status());
-        None
+        self.protected.push(format!("{}/{}", record, dns_type));
+    }
+
+    fn confirm(&mut self, record: &str, dns_type: DnsType, ip: &str) {
+        self.confirmed
+            .push((record.to_string(), dns_type, ip.to_string()));
+    }
+
+    /// Whether flushing the retry queue already updated the rrset with an
+    /// address from the same source.
+    fn was_retried(&self, record: &RecordEntry, dns_type: DnsType) -> bool {
+        self.retried.iter().any(|update| {
+            update.record == record.name
+                && update.source == record.source
+                && update.dns_type == dns_type
+        })
     }
 }
 
-async fn update_gandi_record(
-    domain: &str,
-    name: &str,
-    dns_type: &str,
-    new_ip: &str,
-    headers: &HeaderMap,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
-    let url = format!(
-        "{}domains/{}/records/{}/{}",
-        REST_URL, domain, name, dns_type
-    );
+/// The addresses detected through one detection source.
+#[derive(Debug)]
+struct Detected<'a> {
+    /// `None` for the default detection settings.
+    source: Option<&'a str>,
+    /// The records following only this source.
+    records: Vec<&'a RecordEntry>,
+    ipv4: Option<String>,
+    ipv6: Option<String>,
+}
 
-    let payload = serde_json::json!({
-        "rrset_ttl": 1800,
-        "rrset_values": [new_ip]
-    });
+impl Detected<'_> {
+    fn ip_configs(&self) -> [(Option<&String>, DnsType); 2] {
+        [
+            (self.ipv4.as_ref(), DnsType::A),
+            (self.ipv6.as_ref(), DnsType::Aaaa),
+        ]
+    }
 
-    let response = client
-        .put(&url)
-        .headers(headers.clone())
-        .json(&payload)
-        .send()
-        .await?;
+    fn ip(&self, dns_type: DnsType) -> Option<&String> {
+        self.ip_configs()
+            .into_iter()
+            .find(|(_, t)| *t == dns_type)
+            .and_then(|(ip, _)| ip)
+    }
+}
 
-    let changed = response.status().as_u16() == 201;
-    if !changed {
-        warn!("{} -> {}@{}: {}", dns_type, name, domain, response.status());
+/// Describes a record for the logs, naming its source if it has one.
+fn record_label(record: &str, source: Option<&str>) -> String {
+    match source {
+        Some(source) => format!("{} ({})", record, source),
+        None => record.to_string(),
     }
+}
 
-    Ok(changed)
+#[derive(Debug)]
+struct FailedUpdate {
+    update: QueuedUpdate,
+    retryable: bool,
+    error: String,
 }
 
-fn read_config() -> Result<DnsConfig, ConfigError> {
-    let config = Config::builder()
-        .add_source(File::with_name(".gandi.toml"))
-        .build()?;
+/// Retries the queued updates that still target the address currently
+/// detected through their source. Entries for an address family that could
+/// not be detected stay queued, while entries pointing at an outdated
+/// address are dropped since the fresh update supersedes them. The others
+/// are only removed once applied, so that those a deadline cuts off are
+/// still saved.
+async fn flush_retry_queue(
+    detected: &[Detected<'_>],
+    api: &GandiApi,
+    queue: &mut RetryQueue,
+    ttls: &TtlHistory,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    for update in queue.entries().to_vec() {
+        let current = detected
+            .iter()
+            .filter(|d| d.source == update.source.as_deref())
+            .find(|d| d.records.iter().any(|r| r.name == update.record))
+            .or_else(|| {
+                detected
+                    .iter()
+                    .find(|d| d.source == update.source.as_deref())
+            })
+            .and_then(|d| d.ip(update.dns_type));
 
-    let key = config.get_string("GANDI.key")?;
-    let domain = config.get_string("DNS.domain")?;
-    let records_str = config.get_string("DNS.records")?;
-    let records: Vec<String> = records_str.split('\n').map(String::from).collect();
+        match current {
+            None => {}
+            Some(ip) if !same_address(ip, &update.target_ip) => {
+                info!(
+                    "\tDropping the queued update of {}/{} to {}, superseded by {}",
+                    update.record, update.dns_type, update.target_ip, ip
+                );
+                queue.remove(&update);
+            }
+            Some(_) => {
+                match update_gandi_record(
+                    &update.domain,
+                    &update.record,
+                    update.dns_type,
+                    &update.target_ip,
+                    ttls.change_ttl(),
+                    api,
+                )
+                .await
+                {
+                    Ok(changed) => {
+                        info!(
+                            "\tRetried the queued update of {}/{} to {}",
+                            update.record, update.dns_type, update.target_ip
+                        );
+                        if changed {
+                            summary.changed(&update, None, None);
+                        } else {
+                            summary.confirm(&update.record, update.dns_type, &update.target_ip);
+                        }
+                        queue.remove(&update);
+                        summary.retried.push(update);
+                    }
+                    Err(e) if e.is_auth() => return Err(e),
+                    Err(e) => warn!(
+                        "\tThe queued update of {}/{} failed again: {}",
+                        update.record, update.dns_type, e
+                    ),
+                }
+            }
+        }
+    }
 
-    Ok(DnsConfig {
-        key,
-        domain,
-        records,
-    })
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    simple_logger::init_with_level(log::Level::Info)?;
+/// Warns that `record` is an alias, whose A and AAAA records cannot be
+/// managed.
+fn warn_cname(record: &str, target: &str) {
+    warn!(
+        "Warning! {} is an alias of {}, so it cannot have A or AAAA records; update {} instead",
+        record, target, target
+    );
+}
 
-    let config = match read_config() {
-        Ok(cfg) => cfg,
-        Err(e) => {
-            error!("Invalid Configuration File! {}", e);
-            process::exit(1);
-        }
+/// Whether the debounce window lets the detected address be pushed to the
+/// record yet.
+fn debounce_allows(debouncer: &mut Debouncer, record: &str, dns_type: DnsType, ip: &str) -> bool {
+    let Ok(addr) = ip.parse::<IpAddr>() else {
+        return true;
     };
+    if debouncer.observe(record, dns_type.as_str(), addr, Instant::now()) {
+        return true;
+    }
+    info!(
+        "\tHolding back {}/{} -> {} until the address has been stable",
+        record, dns_type, ip
+    );
+    false
+}
 
-    info!("Updating the records of {} ...", config.domain);
-
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        AUTHORIZATION,
-        HeaderValue::from_str(&format!("Bearer {}", config.key))?,
+/// Records a failed update, to be retried if `queueable` and the error is
+/// transient. Only a write that failed after the rrset passed the checks
+/// is queueable, as the retries write it without fetching it again.
+/// Authorization failures are returned instead, since the same credential
+/// would be rejected for every other record.
+fn record_failure(
+    summary: &mut RunSummary,
+    update: QueuedUpdate,
+    e: DdnsError,
+    queueable: bool,
+) -> Result<(), DdnsError> {
+    if e.is_auth() {
+        return Err(e);
+    }
+    error!(
+        "Unable to update the record {}/{}: {}",
+        update.record, update.dns_type, e
     );
+    summary.failed.push(FailedUpdate {
+        update,
+        retryable: queueable && e.is_retryable(),
+        error: e.to_string(),
+    });
+    Ok(())
+}
 
-    let (ipv4, ipv6) = get_public_ips().await;
+/// Handles an rrset someone else changed to `current` since it was last
+/// written with `last`, returning whether to overwrite it anyway. Each
+/// foreign value is only reported once, however long it stays.
+fn handle_drift(
+    config: &DnsConfig,
+    written: &mut WrittenValues,
+    summary: &mut RunSummary,
+    update: &QueuedUpdate,
+    current: &[String],
+    last: &str,
+) -> bool {
+    let action = match config.on_drift {
+        DriftPolicy::Warn => "leaving it alone",
+        DriftPolicy::Overwrite => "overwriting it",
+        DriftPolicy::Fail => "failing",
+    };
+    warn!(
+        "\t{}/{} was changed by someone else to {} (last written {}, detected {}), {}",
+        update.record,
+        update.dns_type,
+        current.join(","),
+        last,
+        update.target_ip,
+        action
+    );
+    if written.note_drift(&update.record, update.dns_type.as_str(), current) {
+        let mut entry = AuditEntry::new(
+            &update.domain,
+            &update.record,
+            update.dns_type.as_str(),
+            Some(current.to_vec()),
+            &update.target_ip,
+            Outcome::Drifted,
+        );
+        entry.source = update.source.clone();
+        entry.written = Some(last.to_string());
+        summary.audit.push(entry);
+        summary.drifts.push(format!(
+            "{}/{} holds {} instead of {} last written (detected {})",
+            update.record,
+            update.dns_type,
+            current.join(","),
+            last,
+            update.target_ip
+        ));
+    }
+    match config.on_drift {
+        DriftPolicy::Warn => false,
+        DriftPolicy::Overwrite => true,
+        DriftPolicy::Fail => {
+            summary.failed.push(FailedUpdate {
+                update: update.clone(),
+                retryable: false,
+                error: format!("changed by someone else to {}", current.join(",")),
+            });
+            false
+        }
+    }
+}
 
-    let ip_configs = [
-        (ipv4.as_ref(), "A"),
-        (ipv6.as_ref(), "AAAA"),
-    ];
+/// Whether the published answer already matches `ip` and, if given, `ttl`,
+/// so that the API does not need to be asked.
+async fn published(
+    precheck: Option<&DnsPrecheck>,
+    domain: &str,
+    record: &str,
+    dns_type: DnsType,
+    ip: &str,
+    ttl: Option<u32>,
+) -> bool {
+    let (Some(precheck), Ok(ip)) = (precheck, ip.parse::<IpAddr>()) else {
+        return false;
+    };
+    match precheck.lookup(domain, record, dns_type).await {
+        Ok(Some(answer)) => answer.values == [ip] && ttl.is_none_or(|ttl| answer.ttl == ttl),
+        // Missing and empty records are left to the API path, which warns
+        // about them.
+        Ok(None) => false,
+        Err(e) => {
+            warn!(
+                "\tUnable to resolve {}/{}, asking the API instead: {}",
+                record, dns_type, e
+            );
+            false
+        }
+    }
+}
 
-    let mut n_changed = 0;
-    for record in &config.records {
-        info!("\tUpdating the entries of {}@{} ...", record, config.domain);
+/// Updates the records one rrset at a time.
+#[allow(clippy::too_many_arguments)]
+async fn update_each(
+    config: &DnsConfig,
+    records: &[&RecordEntry],
+    ip_configs: &[(Option<&String>, DnsType)],
+    api: &GandiApi,
+    precheck: Option<&DnsPrecheck>,
+    prefetch: &mut Prefetch,
+    debouncer: &mut Debouncer,
+    ttls: &mut TtlHistory,
+    written: &mut WrittenValues,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    for entry in records {
+        let record = &entry.name;
+        info!(
+            "\tUpdating the entries of {}@{} ...",
+            record_label(record, entry.source.as_deref()),
+            config.domain
+        );
+        if let Some(comment) = &entry.comment {
+            info!("\t# {}", comment);
+        }
 
-        for (ip, dns_type) in ip_configs.iter() {
+        // Fetched with the first type that is not known to be up to date.
+        let mut rrsets = None;
+        for &(ip, dns_type) in ip_configs {
             if let Some(ip) = ip {
-                let gandi_record = get_gandi_record(&config.domain, record, dns_type, &headers).await;
+                if summary.was_retried(entry, dns_type)
+                    || !config.versions(entry).allows_type(dns_type)
+                    || keeps_manual(config, written, record, dns_type, ip)
+                {
+                    continue;
+                }
+                if !debounce_allows(debouncer, record, dns_type, ip) {
+                    continue;
+                }
+
+                let steady_ttl =
+                    ttls.steady_ttl(&config.domain, record, dns_type.as_str(), SystemTime::now());
+                if published(precheck, &config.domain, record, dns_type, ip, steady_ttl).await {
+                    info!("\t{}/{} is already up to date", record, dns_type);
+                    summary.n_skipped += 1;
+                    summary.confirm(record, dns_type, ip);
+                    continue;
+                }
 
-                if let Some(gandi_record) = gandi_record {
-                    if gandi_record.is_empty() {
+                let update = QueuedUpdate {
+                    domain: config.domain.clone(),
+                    record: record.clone(),
+                    dns_type,
+                    target_ip: ip.to_string(),
+                    source: entry.source.clone(),
+                };
+
+                let fetched = match rrsets.take() {
+                    Some(fetched) => fetched,
+                    None => match prefetch.take(&config.domain, record, api).await {
+                        Ok(fetched) => fetched,
+                        // Nothing was checked, so the update is not queued.
+                        Err(e) => {
+                            record_failure(summary, update, e, false)?;
+                            continue;
+                        }
+                    },
+                };
+                let fetched = rrsets.insert(fetched);
+                if let Some(cname) = fetched.get(&DnsType::Cname) {
+                    warn_cname(record, &cname.values.join(","));
+                    break;
+                }
+                match fetched.get(&dns_type).cloned() {
+                    Some(rrset) if rrset.values.is_empty() => {
                         warn!(
                             "Warning! The record {}/{} is empty, and thus cannot be updated!",
                             record, dns_type
                         );
+                    }
+                    Some(rrset) => {
+                        let last = written.get(record, dns_type.as_str()).map(str::to_string);
+                        if let Some(last) = last.filter(|last| drifted(&rrset.values, last, ip)) {
+                            if !handle_drift(
+                                config,
+                                written,
+                                summary,
+                                &update,
+                                &rrset.values,
+                                &last,
+                            ) {
+                                continue;
+                            }
+                        }
+                        if !config.may_overwrite(entry) {
+                            if let Some(reason) = foreign_values(&rrset.values, dns_type) {
+                                summary.protect(record, dns_type, &reason);
+                                continue;
+                            }
+                        }
+                        let unchanged = holds_only(&rrset.values, ip);
+                        let ttl = steady_ttl
+                            .filter(|_| unchanged)
+                            .unwrap_or_else(|| ttls.change_ttl());
+                        if unchanged && rrset.ttl == ttl {
+                            debug!(
+                                "Record {}/{} already up to date ({}), skipping PUT",
+                                record, dns_type, ip
+                            );
+                            summary.n_skipped += 1;
+                            summary.confirm(record, dns_type, ip);
+                            continue;
+                        }
+                        let old = rrset.values;
+                        match update_gandi_record(&config.domain, record, dns_type, ip, ttl, api)
+                            .await
+                        {
+                            Ok(true) if unchanged => {
+                                info!("\tSet the TTL of {}/{} to {}s", record, dns_type, ttl);
+                                summary.confirm(record, dns_type, ip);
+                            }
+                            Ok(true) => summary.changed(&update, Some(old), Some(rrset.ttl)),
+                            Ok(false) => summary.confirm(record, dns_type, ip),
+                            Err(e) => record_failure(summary, update, e, true)?,
+                        }
+                    }
+                    None => {
+                        warn!(
+                            "Warning! The record {}/{} does not exist, and thus cannot be updated!",
+                            record, dns_type
+                        );
+                    }
+                }
+            }
+        }
+
+        summary.completed.push(record.clone());
+    }
+
+    Ok(())
+}
+
+/// Applies the updates of the records with a single zone update.
+#[allow(clippy::too_many_arguments)]
+async fn update_batch(
+    config: &DnsConfig,
+    records: &[&RecordEntry],
+    ip_configs: &[(Option<&String>, DnsType)],
+    api: &GandiApi,
+    precheck: Option<&DnsPrecheck>,
+    debouncer: &mut Debouncer,
+    ttls: &mut TtlHistory,
+    written: &mut WrittenValues,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    let source = records.first().and_then(|entry| entry.source.clone());
+    let mut updates = Vec::new();
+    for entry in records {
+        let record = &entry.name;
+        for &(ip, dns_type) in ip_configs {
+            let skipped = summary.was_retried(entry, dns_type)
+                || !config.versions(entry).allows_type(dns_type)
+                || ip.is_some_and(|ip| keeps_manual(config, written, record, dns_type, ip));
+            if let (Some(ip), false) = (ip, skipped) {
+                if !debounce_allows(debouncer, record, dns_type, ip) {
+                    continue;
+                }
+                let steady_ttl =
+                    ttls.steady_ttl(&config.domain, record, dns_type.as_str(), SystemTime::now());
+                if published(precheck, &config.domain, record, dns_type, ip, steady_ttl).await {
+                    info!("\t{}/{} is already up to date", record, dns_type);
+                    summary.n_skipped += 1;
+                    summary.confirm(record, dns_type, ip);
+                    continue;
+                }
+                updates.push(RecordUpdate {
+                    name: record.clone(),
+                    dns_type,
+                    value: ip.to_string(),
+                    ttl: ttls.change_ttl(),
+                    steady_ttl,
+                    written: written
+                        .get(record, dns_type.as_str())
+                        .filter(|_| config.on_drift != DriftPolicy::Overwrite)
+                        .map(str::to_string),
+                    protect: !config.may_overwrite(entry),
+                });
+            }
+        }
+    }
+
+    let names = records.iter().map(|entry| entry.name.clone());
+    if updates.is_empty() {
+        summary.completed.extend(names);
+        return Ok(());
+    }
+
+    info!(
+        "\tUpdating {} entries of {} in one batch ...",
+        updates.len(),
+        record_label(&config.domain, source.as_deref())
+    );
+
+    let failed = |update: &RecordUpdate, e: &DdnsError, retryable: bool| FailedUpdate {
+        update: QueuedUpdate {
+            domain: config.domain.clone(),
+            record: update.name.clone(),
+            dns_type: update.dns_type,
+            target_ip: update.value.clone(),
+            source: source.clone(),
+        },
+        retryable,
+        error: e.to_string(),
+    };
+    // Without the zone, nothing was checked, so the updates are not queued:
+    // a retry writes the rrsets without looking at them first.
+    let plan = match plan_batch_update(&config.domain, &updates, api).await {
+        Ok(plan) => plan,
+        Err(e) if e.is_auth() => return Err(e),
+        Err(e) => {
+            error!("Unable to get the records of {}: {}", config.domain, e);
+            for update in &updates {
+                summary.failed.push(failed(update, &e, false));
+            }
+            summary.completed.extend(names);
+            return Ok(());
+        }
+    };
+    // Only the rrsets the plan writes passed the checks and may be retried.
+    let applied = match apply_batch_update(&config.domain, &plan, api).await {
+        Ok(()) => true,
+        Err(e) if e.is_auth() => return Err(e),
+        Err(e) => {
+            error!("Unable to update the records of {}: {}", config.domain, e);
+            for (update, item) in updates.iter().zip(&plan.items) {
+                if matches!(item, BatchItem::Changed(..) | BatchItem::TtlChanged) {
+                    summary.failed.push(failed(update, &e, e.is_retryable()));
+                }
+            }
+            false
+        }
+    };
+
+    for (update, item) in updates.iter().zip(plan.items) {
+        let queued = QueuedUpdate {
+            domain: config.domain.clone(),
+            record: update.name.clone(),
+            dns_type: update.dns_type,
+            target_ip: update.value.clone(),
+            source: source.clone(),
+        };
+        match item {
+            BatchItem::Changed(..) | BatchItem::TtlChanged if !applied => {}
+            BatchItem::Changed(old, old_ttl) => {
+                // Drifted rrsets are only overwritten by policy.
+                let last = written.get(&update.name, update.dns_type.as_str());
+                if let Some(last) = last.map(str::to_string) {
+                    if drifted(&old, &last, &update.value) {
+                        handle_drift(config, written, summary, &queued, &old, &last);
+                    }
+                }
+                summary.changed(&queued, Some(old), old_ttl);
+            }
+            BatchItem::Drifted(current) => {
+                let last = update.written.as_deref().unwrap_or_default();
+                handle_drift(config, written, summary, &queued, &current, last);
+            }
+            BatchItem::Protected(reason) => {
+                summary.protect(&update.name, update.dns_type, &reason);
+            }
+            BatchItem::TtlChanged => {
+                info!(
+                    "\tSet the TTL of {}/{} to {}s",
+                    update.name,
+                    update.dns_type,
+                    update.steady_ttl.unwrap_or(update.ttl)
+                );
+                summary.confirm(&update.name, update.dns_type, &update.value);
+            }
+            BatchItem::Unchanged => {
+                summary.n_skipped += 1;
+                summary.confirm(&update.name, update.dns_type, &update.value);
+            }
+            BatchItem::Missing => warn!(
+                "Warning! The record {}/{} does not exist, and thus cannot be updated!",
+                update.name, update.dns_type
+            ),
+            BatchItem::Empty => warn!(
+                "Warning! The record {}/{} is empty, and thus cannot be updated!",
+                update.name, update.dns_type
+            ),
+            BatchItem::Cname(target) => warn_cname(&update.name, &target),
+        }
+    }
+
+    summary.completed.extend(names);
+    Ok(())
+}
+
+/// Detects the addresses of every source the records follow, noting what
+/// told them in `providers`.
+async fn detect_sources<'a>(
+    config: &'a DnsConfig,
+    providers: &mut HashMap<(Option<String>, DnsType), String>,
+) -> Vec<Detected<'a>> {
+    // Each source is only asked once, however many records follow it.
+    let mut detected = Vec::new();
+    for source in config.used_sources() {
+        if let Some(name) = source {
+            info!("Detecting the addresses of {} ...", name);
+        }
+        let following = config
+            .records
+            .iter()
+            .filter(|record| record.follows(source));
+        let templates = config
+            .templates
+            .iter()
+            .filter(|template| template.source.as_deref() == source)
+            .map(|template| match config.ip_version_override {
+                Some(version) => IpVersionFilter::Only(version),
+                None => template.versions(),
+            });
+        let wanted = IpVersionFilter::covering(
+            following
+                .clone()
+                .map(|r| config.versions(r))
+                .chain(templates),
+        );
+        let records: Vec<&RecordEntry> = following.filter(|r| !r.is_multi_source()).collect();
+        let (ipv4, ipv6) = get_public_ips(
+            config.ipv4_override,
+            config.ipv6_override,
+            config.detection(source),
+            wanted,
+            config.timeouts,
+            &config.state_dir,
+        )
+        .await;
+        for (result, dns_type) in [(&ipv4, DnsType::A), (&ipv6, DnsType::Aaaa)] {
+            if let Some(result) = result {
+                providers.insert(
+                    (source.map(str::to_string), dns_type),
+                    result.provider.clone(),
+                );
+            }
+        }
+        let ipv4 = ipv4.map(|result| result.ip);
+        let ipv6 = ipv6.map(|result| result.ip);
+
+        // The records with a host part get their own address in the
+        // delegated prefix, as if another source had detected it.
+        let (hosts, records): (Vec<&RecordEntry>, _) =
+            records.into_iter().partition(|r| r.host_part.is_some());
+        let mut host_parts: Vec<Ipv6Addr> = Vec::new();
+        for host_part in hosts.iter().filter_map(|r| r.host_part) {
+            if !host_parts.contains(&host_part) {
+                host_parts.push(host_part);
+            }
+        }
+        detected.push(Detected {
+            source,
+            records,
+            ipv4: ipv4.clone(),
+            ipv6: ipv6.clone(),
+        });
+        let family = &config.detection(source).ipv6;
+        let prefix = match &family.delegated_prefix {
+            Some(_) if host_parts.is_empty() => None,
+            Some(delegated) => match delegated.read() {
+                Ok(prefix) => Some(prefix),
+                Err(e) => {
+                    warn!("Unable to read the delegated prefix: {}", e);
+                    None
+                }
+            },
+            None => ipv6.as_deref().and_then(|ip| ip.parse().ok()),
+        };
+        for host_part in host_parts {
+            let records: Vec<&RecordEntry> = hosts
+                .iter()
+                .copied()
+                .filter(|r| r.host_part == Some(host_part))
+                .collect();
+            let ip = prefix
+                .zip(family.prefix_len)
+                .map(|(prefix, length)| in_prefix(prefix, length, host_part).to_string());
+            if let Some(ip) = &ip {
+                let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+                info!(
+                    "IPv6 of {} in the delegated prefix: {}",
+                    names.join(", "),
+                    ip
+                );
+            }
+            detected.push(Detected {
+                source,
+                records,
+                ipv4: ipv4.clone(),
+                ipv6: ip,
+            });
+        }
+    }
+
+    detected
+}
+
+async fn update_records(
+    config: &DnsConfig,
+    api: &GandiApi,
+    queue: &mut RetryQueue,
+    debouncer: &mut Debouncer,
+    ttls: &mut TtlHistory,
+    written: &mut WrittenValues,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    // The rrsets are needed unless the batch or the DNS precheck avoid
+    // asking for them one by one, so their fetches overlap the detection.
+    let mut prefetch = if config.use_batch || config.precheck == Precheck::Dns {
+        Prefetch::default()
+    } else {
+        let names = config
+            .records
+            .iter()
+            .filter(|record| !record.is_multi_source())
+            .map(|record| record.name.as_str());
+        Prefetch::start(&config.domain, names, api)
+    };
+    let detected = detect_sources(config, &mut summary.providers).await;
+
+    let any_detected = detected
+        .iter()
+        .any(|d| d.ipv4.is_some() || d.ipv6.is_some());
+    if !queue.is_empty() && any_detected {
+        flush_retry_queue(&detected, api, queue, ttls, summary).await?;
+    }
+
+    let precheck = match config.precheck {
+        Precheck::Dns => match DnsPrecheck::new(&config.domain, config.precheck_resolver).await {
+            Ok(precheck) => Some(precheck),
+            Err(e) => {
+                warn!(
+                    "Unable to set up the DNS precheck, asking the API instead: {}",
+                    e
+                );
+                None
+            }
+        },
+        Precheck::Api => None,
+    };
+
+    for detected in &detected {
+        let ip_configs = detected.ip_configs();
+        if config.use_batch {
+            update_batch(
+                config,
+                &detected.records,
+                &ip_configs,
+                api,
+                precheck.as_ref(),
+                debouncer,
+                ttls,
+                written,
+                summary,
+            )
+            .await?;
+        } else {
+            update_each(
+                config,
+                &detected.records,
+                &ip_configs,
+                api,
+                precheck.as_ref(),
+                &mut prefetch,
+                debouncer,
+                ttls,
+                written,
+                summary,
+            )
+            .await?;
+        }
+    }
+    drop(prefetch);
+    update_multi_source(config, &detected, api, ttls, written, summary).await?;
+    update_templates(config, &detected, api, summary).await?;
+    remove_records(config, api, summary).await?;
+
+    if summary.failed.iter().any(|f| f.retryable) {
+        second_chance_pass(api, ttls, summary).await?;
+    }
+
+    Ok(())
+}
+
+/// The addresses detected through the source of `template`.
+fn template_addresses<'a>(
+    template: &TemplateRecord,
+    detected: &'a [Detected<'_>],
+) -> Addresses<'a> {
+    let found = detected
+        .iter()
+        .find(|d| d.source == template.source.as_deref());
+    Addresses {
+        ipv4: found.and_then(|d| d.ipv4.as_deref()),
+        ipv6: found.and_then(|d| d.ipv6.as_deref()),
+    }
+}
+
+/// Writes the rrsets rendered from a template unless they already hold
+/// what it renders, the timestamp aside. The other values of the rrsets are
+/// kept. Failures are not queued for retry, the template being rendered
+/// again on the next pass.
+async fn update_templates(
+    config: &DnsConfig,
+    detected: &[Detected<'_>],
+    api: &GandiApi,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    for template in &config.templates {
+        let record = &template.name;
+        info!(
+            "\tUpdating the template of {}/{}@{} ...",
+            record_label(record, template.source.as_deref()),
+            template.dns_type,
+            config.domain
+        );
+        if let Some(comment) = &template.comment {
+            info!("\t# {}", comment);
+        }
+        let addresses = template_addresses(template, detected);
+        let text = match template.render(addresses, OffsetDateTime::now_utc()) {
+            Ok(text) => text,
+            Err(version) => {
+                warn!(
+                    "\tLeaving {}/{} alone, no IP{} address was detected",
+                    record,
+                    template.dns_type,
+                    version.as_str()
+                );
+                continue;
+            }
+        };
+        let update = QueuedUpdate {
+            domain: config.domain.clone(),
+            record: record.clone(),
+            dns_type: template.dns_type,
+            target_ip: text.clone(),
+            source: template.source.clone(),
+        };
+        let rrset = match get_gandi_rrsets(&config.domain, record, api).await {
+            Ok(mut rrsets) => rrsets.remove(&template.dns_type),
+            Err(e) => {
+                record_failure(summary, update, e, false)?;
+                continue;
+            }
+        };
+        let current = rrset.as_ref().map_or(&[][..], |rrset| &rrset.values[..]);
+        let (ours, mut values): (Vec<&String>, Vec<&String>) = current
+            .iter()
+            .partition(|value| template.owns(&txt_text(value)));
+        if let [value] = ours[..] {
+            if template.matches(&txt_text(value), addresses) {
+                debug!(
+                    "Record {}/{} already up to date, skipping PUT",
+                    record, template.dns_type
+                );
+                summary.n_skipped += 1;
+                summary.completed.push(record.clone());
+                continue;
+            }
+        }
+        let value = template.value(&text);
+        values.push(&value);
+        let values: Vec<String> = values.into_iter().cloned().collect();
+        let ttl = rrset.as_ref().map_or(RRSET_TTL, |rrset| rrset.ttl);
+        match update_gandi_rrset(&config.domain, record, template.dns_type, &values, ttl, api).await
+        {
+            Ok(true) => {
+                let old: Vec<String> = ours.iter().map(|value| txt_text(value)).collect();
+                info!(
+                    "\tUpdated {}/{}: {} -> {}",
+                    record,
+                    template.dns_type,
+                    if old.is_empty() {
+                        "(none)".to_string()
                     } else {
-                        if update_gandi_record(&config.domain, record, dns_type, ip, &headers).await? {
-                            n_changed += 1;
+                        old.join(", ")
+                    },
+                    text
+                );
+                summary.templated(&config.domain, record, template.dns_type, Some(old), &text);
+            }
+            Ok(false) => {}
+            Err(e) => record_failure(summary, update, e, false)?,
+        }
+        summary.completed.push(record.clone());
+    }
+    Ok(())
+}
+
+/// The addresses of the family `dns_type` to publish in a record following
+/// several sources, sorted, or `None` to leave the rrset alone.
+fn multi_source_values(
+    entry: &RecordEntry,
+    detected: &[Detected<'_>],
+    dns_type: DnsType,
+) -> Option<Vec<String>> {
+    let mut values = Vec::new();
+    let mut missing = Vec::new();
+    for source in &entry.sources {
+        match detected
+            .iter()
+            .find(|d| d.source == Some(source.as_str()))
+            .and_then(|d| d.ip(dns_type))
+        {
+            Some(ip) => values.push(canonical(ip)),
+            None => missing.push(source.as_str()),
+        }
+    }
+    if values.is_empty() {
+        return None;
+    }
+    if !missing.is_empty() {
+        match entry.on_source_failure {
+            PartialSet::Keep => {
+                warn!(
+                    "\tKeeping {}/{} as it is, no address was detected through {}",
+                    entry.name,
+                    dns_type,
+                    missing.join(", ")
+                );
+                return None;
+            }
+            PartialSet::Publish => warn!(
+                "\tPublishing {}/{} without {}, which detected no address",
+                entry.name,
+                dns_type,
+                missing.join(", ")
+            ),
+        }
+    }
+    values.sort();
+    values.dedup();
+    Some(values)
+}
+
+/// Updates the records holding the addresses of several sources. The
+/// values are compared as sets, so that their order does not cause
+/// updates.
+async fn update_multi_source(
+    config: &DnsConfig,
+    detected: &[Detected<'_>],
+    api: &GandiApi,
+    ttls: &mut TtlHistory,
+    written: &mut WrittenValues,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    for entry in config.records.iter().filter(|r| r.is_multi_source()) {
+        let record = &entry.name;
+        let label = entry.source_label();
+        info!(
+            "\tUpdating the entries of {}@{} ...",
+            record_label(record, label.as_deref()),
+            config.domain
+        );
+        if let Some(comment) = &entry.comment {
+            info!("\t# {}", comment);
+        }
+
+        let mut rrsets = None;
+        for dns_type in [DnsType::A, DnsType::Aaaa] {
+            if !config.versions(entry).allows_type(dns_type) {
+                continue;
+            }
+            let Some(values) = multi_source_values(entry, detected, dns_type) else {
+                continue;
+            };
+            let joined = values.join(",");
+            if keeps_manual(config, written, record, dns_type, &joined) {
+                continue;
+            }
+            let update = QueuedUpdate {
+                domain: config.domain.clone(),
+                record: record.clone(),
+                dns_type,
+                target_ip: joined.clone(),
+                source: label.clone(),
+            };
+            let steady_ttl =
+                ttls.steady_ttl(&config.domain, record, dns_type.as_str(), SystemTime::now());
+
+            // The retry queue only holds updates to the address of a
+            // single source, so failures are not queued.
+            let fetched = match rrsets.take() {
+                Some(fetched) => fetched,
+                None => match get_gandi_rrsets(&config.domain, record, api).await {
+                    Ok(fetched) => fetched,
+                    Err(e) => {
+                        record_failure(summary, update, e, false)?;
+                        continue;
+                    }
+                },
+            };
+            let fetched = rrsets.insert(fetched);
+            if let Some(cname) = fetched.get(&DnsType::Cname) {
+                warn_cname(record, &cname.values.join(","));
+                break;
+            }
+            match fetched.get(&dns_type).cloned() {
+                Some(rrset) if rrset.values.is_empty() => {
+                    warn!(
+                        "Warning! The record {}/{} is empty, and thus cannot be updated!",
+                        record, dns_type
+                    );
+                }
+                Some(rrset) => {
+                    let last = written.get(record, dns_type.as_str()).map(str::to_string);
+                    if let Some(last) = last.filter(|last| drifted(&rrset.values, last, &joined)) {
+                        if !handle_drift(config, written, summary, &update, &rrset.values, &last) {
+                            continue;
                         }
                     }
-                } else {
+                    let mut current: Vec<String> =
+                        rrset.values.iter().map(|v| canonical(v)).collect();
+                    current.sort();
+                    current.dedup();
+                    let unchanged = current == values;
+                    let ttl = steady_ttl
+                        .filter(|_| unchanged)
+                        .unwrap_or_else(|| ttls.change_ttl());
+                    if unchanged && rrset.ttl == ttl {
+                        debug!(
+                            "Record {}/{} already up to date ({}), skipping PUT",
+                            record, dns_type, joined
+                        );
+                        summary.n_skipped += 1;
+                        summary.confirm(record, dns_type, &joined);
+                        continue;
+                    }
+                    match update_gandi_rrset(&config.domain, record, dns_type, &values, ttl, api)
+                        .await
+                    {
+                        Ok(true) if unchanged => {
+                            info!("\tSet the TTL of {}/{} to {}s", record, dns_type, ttl);
+                            summary.confirm(record, dns_type, &joined);
+                        }
+                        Ok(true) => summary.changed(&update, Some(rrset.values), Some(rrset.ttl)),
+                        Ok(false) => summary.confirm(record, dns_type, &joined),
+                        Err(e) => record_failure(summary, update, e, false)?,
+                    }
+                }
+                None => {
                     warn!(
                         "Warning! The record {}/{} does not exist, and thus cannot be updated!",
                         record, dns_type
@@ -192,8 +1450,1534 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
+
+        summary.completed.push(record.clone());
+    }
+
+    Ok(())
+}
+
+/// Gives the updates that failed with a retryable error one more attempt,
+/// so a transient hiccup heals within the same run.
+async fn second_chance_pass(
+    api: &GandiApi,
+    ttls: &TtlHistory,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    info!("Retrying the failed updates in {:?} ...", SECOND_PASS_DELAY);
+    tokio::time::sleep(SECOND_PASS_DELAY).await;
+
+    for failure in std::mem::take(&mut summary.failed) {
+        if !failure.retryable {
+            summary.failed.push(failure);
+            continue;
+        }
+
+        let update = &failure.update;
+        match update_gandi_record(
+            &update.domain,
+            &update.record,
+            update.dns_type,
+            &update.target_ip,
+            ttls.change_ttl(),
+            api,
+        )
+        .await
+        {
+            Ok(changed) => {
+                if changed {
+                    summary.changed(update, None, None);
+                } else {
+                    summary.confirm(&update.record, update.dns_type, &update.target_ip);
+                }
+                summary
+                    .second_pass
+                    .push((update.record.clone(), update.dns_type));
+            }
+            Err(e) if e.is_auth() => return Err(e),
+            Err(e) => {
+                error!(
+                    "The record {}/{} failed again: {}",
+                    update.record, update.dns_type, e
+                );
+                summary.failed.push(FailedUpdate {
+                    retryable: e.is_retryable(),
+                    error: e.to_string(),
+                    ..failure
+                });
+            }
+        }
     }
 
-    info!("Success! {} DNS records were changed.", n_changed);
     Ok(())
 }
+
+/// Appends the changes and failures of a pass to the audit log.
+fn write_audit(config: &DnsConfig, summary: &mut RunSummary) {
+    for failure in &summary.failed {
+        let update = &failure.update;
+        let mut entry = AuditEntry::new(
+            &update.domain,
+            &update.record,
+            update.dns_type.as_str(),
+            None,
+            &update.target_ip,
+            Outcome::Failed,
+        );
+        entry.source = update.source.clone();
+        entry.error = Some(failure.error.clone());
+        summary.audit.push(entry);
+    }
+
+    for entry in &mut summary.audit {
+        entry.comment = config.comment(&entry.record).map(str::to_string);
+        if !matches!(entry.outcome, Outcome::Drifted | Outcome::Removed) {
+            entry.provider = entry.dns_type.parse().ok().and_then(|dns_type| {
+                summary
+                    .providers
+                    .get(&(entry.source.clone(), dns_type))
+                    .cloned()
+            });
+        }
+    }
+
+    let log = AuditLog::new(&config.state_dir);
+    if let Err(e) = log.append(&summary.audit) {
+        warn!(
+            "Unable to write the audit log {}: {}",
+            log.path().display(),
+            e
+        );
+    }
+}
+
+/// Deletes the A and AAAA rrsets of the records matching `DNS.remove`,
+/// or only lists them unless deleting was allowed.
+async fn remove_records(
+    config: &DnsConfig,
+    api: &GandiApi,
+    summary: &mut RunSummary,
+) -> Result<(), DdnsError> {
+    if config.remove.is_empty() {
+        return Ok(());
+    }
+    let allowed = config.allow_delete || !config.confirm_deletes;
+    let stale = get_zone_records(&config.domain, api)
+        .await?
+        .into_iter()
+        .filter(|rrset| matches!(rrset.record_type, DnsType::A | DnsType::Aaaa))
+        .filter(|rrset| {
+            config
+                .remove
+                .iter()
+                .any(|pattern| records_file::name_matches(pattern, &rrset.name))
+        });
+    for rrset in stale {
+        let values = rrset.values.join(",");
+        if !allowed {
+            warn!(
+                "\tWould remove {}/{} ({}), pass --allow-delete or set DNS.confirm_deletes = false",
+                rrset.name, rrset.record_type, values
+            );
+            continue;
+        }
+        match delete_gandi_rrset(&config.domain, &rrset.name, rrset.record_type, api).await {
+            Ok(true) => {
+                warn!(
+                    "\tRemoved {}/{} ({})",
+                    rrset.name, rrset.record_type, values
+                );
+                summary
+                    .removed
+                    .push(format!("{}/{}", rrset.name, rrset.record_type));
+                summary.audit.push(AuditEntry::new(
+                    &config.domain,
+                    &rrset.name,
+                    rrset.record_type.as_str(),
+                    Some(rrset.values),
+                    "",
+                    Outcome::Removed,
+                ));
+            }
+            Ok(false) => debug!("{}/{} is already gone", rrset.name, rrset.record_type),
+            Err(e) if e.is_auth() => return Err(e),
+            Err(e) => {
+                error!(
+                    "Unable to remove {}/{}: {}",
+                    rrset.name, rrset.record_type, e
+                );
+                summary.failed.push(FailedUpdate {
+                    update: QueuedUpdate {
+                        domain: config.domain.clone(),
+                        record: rrset.name,
+                        dns_type: rrset.record_type,
+                        target_ip: String::new(),
+                        source: None,
+                    },
+                    retryable: false,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether to leave an rrset alone since `set` wrote another value to it,
+/// which only `--yes` reverts.
+fn keeps_manual(
+    config: &DnsConfig,
+    written: &WrittenValues,
+    record: &str,
+    dns_type: DnsType,
+    value: &str,
+) -> bool {
+    if config.manual_set || config.revert_manual {
+        return false;
+    }
+    match written.manual(record, dns_type.as_str()) {
+        Some(manual) if manual != value => {
+            warn!(
+                "\tLeaving {}/{} at {}, written by set, instead of {}; pass --yes to revert it",
+                record, dns_type, manual, value
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Checks the guard conditions, logging why they are not satisfied.
+fn guard_satisfied(config: &DnsConfig) -> bool {
+    if config.guard.is_empty() {
+        return true;
+    }
+    match guard::evaluate(&config.guard) {
+        Ok(()) => true,
+        Err(reason) => {
+            info!("Guard not satisfied ({}), skipping.", reason);
+            false
+        }
+    }
+}
+
+/// Checks that the credential may manage the domain unless
+/// `--no-preflight` is given, returning `false` if it was refused.
+async fn verify_credential(cli: &Cli, config: &DnsConfig, api: &GandiApi) -> bool {
+    warn_expiry(config);
+    if cli.no_preflight {
+        return true;
+    }
+    let checked = match config.discovery {
+        Some(_) => list_domains(api).await.map(drop),
+        None => check_livedns_access(&config.domain, api).await,
+    };
+    match checked {
+        Ok(()) => true,
+        Err(e @ DdnsError::PinMismatch(_)) => {
+            error!("Critical Error: {}", e);
+            false
+        }
+        Err(e) if e.is_auth() => {
+            error!("Critical Error: {}", e);
+            let domains = match config.discovery {
+                Some(_) => "the discovered domains",
+                None => &config.domain,
+            };
+            error!(
+                "The credential needs the \"{}\" permission for {}.",
+                LIVEDNS_PERMISSION, domains
+            );
+            error!(
+                "Create a properly scoped Personal Access Token at {}",
+                TOKEN_ADMIN_URL
+            );
+            false
+        }
+        Err(e) => {
+            warn!("Unable to verify the credential: {}", e);
+            true
+        }
+    }
+}
+
+/// Warns ahead of `GANDI.key_expires`, so that the credential can be
+/// rotated before the updates start failing.
+fn warn_expiry(config: &DnsConfig) {
+    let Some(expires) = config.key_expires else {
+        return;
+    };
+    let days = (expires - OffsetDateTime::now_utc().date()).whole_days();
+    if days < 0 {
+        warn!("The credential expired on {}", expires);
+    } else if days <= i64::from(config.expiry_warning_days) {
+        warn!(
+            "The credential expires on {}, in {} days. Create a new one at {}",
+            expires, days, TOKEN_ADMIN_URL
+        );
+    }
+}
+
+/// Sends the rrsets someone else changed to the notification channels.
+async fn notify_drifts(config: &DnsConfig, drifts: &[String]) {
+    if drifts.is_empty() || !config.notify.has_channels() {
+        return;
+    }
+    match Notifications::new(&config.notify, config.timeouts, &config.state_dir) {
+        Ok(notifications) => {
+            let event = DdnsEvent::Drift {
+                domain: config.domain.clone(),
+                drifts: drifts.to_vec(),
+            };
+            notifications.send(&event).await;
+        }
+        Err(e) => warn!("Unable to set up the notifications: {}", e),
+    }
+}
+
+/// Sends the changes of a pass and its failure, `result` being `None` if
+/// it exceeded its deadline, to the channels taking them.
+async fn notify_pass(
+    config: &DnsConfig,
+    summary: &RunSummary,
+    result: Option<&Result<(), DdnsError>>,
+) {
+    let changes: Vec<RecordChange> = summary
+        .audit
+        .iter()
+        .filter(|entry| entry.outcome == Outcome::Changed)
+        .filter_map(|entry| {
+            Some(RecordChange {
+                record: entry.record.clone(),
+                dns_type: entry.dns_type.parse().ok()?,
+                old: entry.old.clone(),
+                new: entry.new.clone(),
+                comment: config.comment(&entry.record).map(str::to_string),
+            })
+        })
+        .collect();
+    let failure = match result {
+        None => Some(("the run exceeded its deadline".to_string(), "deadline")),
+        Some(Err(e)) => Some((e.to_string(), e.class())),
+        Some(Ok(())) if !summary.failed.is_empty() => Some((
+            format!("{} updates failed", summary.failed.len()),
+            "records",
+        )),
+        Some(Ok(())) => None,
+    };
+    // With the throttle, the channels told about the failures are told when
+    // a pass succeeds again.
+    let recovered = failure.is_none() && config.notify.throttle_interval.is_some();
+    if (changes.is_empty() && failure.is_none() && !recovered) || !config.notify.has_channels() {
+        return;
+    }
+    let notifications = match Notifications::new(&config.notify, config.timeouts, &config.state_dir)
+    {
+        Ok(notifications) => notifications,
+        Err(e) => {
+            warn!("Unable to set up the notifications: {}", e);
+            return;
+        }
+    };
+    if recovered {
+        notifications.recover(&config.domain).await;
+    }
+    if !changes.is_empty() {
+        let event = DdnsEvent::RecordsChanged {
+            domain: config.domain.clone(),
+            changes,
+        };
+        notifications.send(&event).await;
+    }
+    if let Some((reason, class)) = failure {
+        let event = DdnsEvent::UpdateFailed {
+            domain: config.domain.clone(),
+            reason,
+            class,
+            failures: summary
+                .failed
+                .iter()
+                .map(|failure| RecordFailure {
+                    record: failure.update.record.clone(),
+                    dns_type: failure.update.dns_type,
+                    error: failure.error.to_string(),
+                    comment: config.comment(&failure.update.record).map(str::to_string),
+                })
+                .collect(),
+        };
+        notifications.send(&event).await;
+    }
+}
+
+/// Runs one update pass and reports how long its steps took.
+async fn run_pass(
+    config: &DnsConfig,
+    api: &GandiApi,
+    debouncer: &mut Debouncer,
+    watchdog: Option<&mut Watchdog>,
+) -> (PassOutcome, Timings) {
+    let started = timing::start();
+    let domain = match config.discovery {
+        Some(_) => "(discovered)".to_string(),
+        None => config.domain.clone(),
+    };
+    let pass = async {
+        let outcome = match &config.discovery {
+            Some(discovery) => discovered_pass(config, discovery, api, debouncer).await,
+            None => update_pass(config, api, debouncer, watchdog).await,
+        };
+        telemetry::record("ddns.outcome", outcome.as_str());
+        match outcome {
+            PassOutcome::Success { n_changed, .. } => telemetry::record("ddns.changed", n_changed),
+            _ => telemetry::fail(outcome.as_str()),
+        }
+        outcome
+    };
+    let outcome = telemetry::in_span("ddns.run", vec![("ddns.domain", domain.into())], pass).await;
+    let timings = timing::finish(started);
+    info!("Timings: {}", timings);
+    (outcome, timings)
+}
+
+/// Runs an update pass in every discovered domain, one after the other,
+/// failing if any of them does.
+async fn discovered_pass(
+    config: &DnsConfig,
+    discovery: &DomainDiscovery,
+    api: &GandiApi,
+    debouncer: &mut Debouncer,
+) -> PassOutcome {
+    let domains = match discovery::discover(config, discovery, api).await {
+        Ok(domains) => domains,
+        Err(e) => {
+            error!("Unable to list the domains of the account: {}", e);
+            return PassOutcome::Failed;
+        }
+    };
+    if domains.is_empty() {
+        warn!("No domain of the account matches DNS.domains.");
+    }
+
+    let (mut n_changed, mut n_protected) = (0, 0);
+    let mut failure = None;
+    for domain in &domains {
+        let domain_config = discovery::domain_config(config, domain);
+        match update_pass(&domain_config, api, debouncer.domain(domain), None).await {
+            PassOutcome::Success {
+                n_changed: changed,
+                n_protected: protected,
+            } => {
+                n_changed += changed;
+                n_protected += protected;
+            }
+            PassOutcome::DeadlineExceeded => failure = Some(PassOutcome::DeadlineExceeded),
+            PassOutcome::Failed => {
+                failure = failure.or(Some(PassOutcome::Failed));
+            }
+        }
+    }
+    failure.unwrap_or(PassOutcome::Success {
+        n_changed,
+        n_protected,
+    })
+}
+
+/// Runs one update pass within the configured deadline and reports it.
+/// The watchdog learns the addresses the records hold afterwards.
+async fn update_pass(
+    config: &DnsConfig,
+    api: &GandiApi,
+    debouncer: &mut Debouncer,
+    watchdog: Option<&mut Watchdog>,
+) -> PassOutcome {
+    info!("Updating the records of {} ...", config.domain);
+
+    let mut queue = RetryQueue::load(&config.state_dir, config.retry_queue_depth);
+    let mut ttls = TtlHistory::load(&config.state_dir, config.adaptive_ttl);
+    let mut summary = RunSummary::default();
+    let mut written = WrittenValues::load(&config.state_dir);
+    let pass = update_records(
+        config,
+        api,
+        &mut queue,
+        debouncer,
+        &mut ttls,
+        &mut written,
+        &mut summary,
+    );
+
+    // Dropping the pass on expiry cancels any request still in flight.
+    let result = match config.max_run_duration {
+        Some(limit) => tokio::time::timeout(limit, pass).await.ok(),
+        None => Some(pass.await),
+    };
+
+    // Only transient failures have a chance to heal on a later run.
+    for failure in &summary.failed {
+        if failure.retryable {
+            queue.push(failure.update.clone());
+        }
+    }
+    if let Err(e) = queue.save() {
+        warn!("Unable to save the retry queue: {}", e);
+    }
+    let now = SystemTime::now();
+    for entry in summary
+        .audit
+        .iter()
+        .filter(|e| e.outcome == Outcome::Changed)
+    {
+        ttls.record_change(&entry.domain, &entry.record, &entry.dns_type, now);
+    }
+    if let Err(e) = ttls.save() {
+        warn!("Unable to save the TTL history: {}", e);
+    }
+    write_audit(config, &mut summary);
+    written.record(&summary.confirmed, config.manual_set);
+    if let Err(e) = written.save() {
+        warn!("Unable to save the values written: {}", e);
+    }
+    if let Some(watchdog) = watchdog {
+        watchdog.expect(&summary.confirmed);
+    }
+    notify_drifts(config, &summary.drifts).await;
+    notify_pass(config, &summary, result.as_ref()).await;
+    if let (Some(record), Some(_), true) =
+        (&config.heartbeat_record, &result, summary.n_changed > 0)
+    {
+        heartbeat::write(config, record, &summary.confirmed, api).await;
+    }
+
+    let Some(result) = result else {
+        error!(
+            "Critical Error: The run exceeded its deadline of {:?}!",
+            config.max_run_duration.unwrap_or_default()
+        );
+        for record in &config.records {
+            if !summary.completed.contains(&record.name) {
+                warn!(
+                    "\t{}@{}: deadline exceeded",
+                    record_label(&record.name, record.source_label().as_deref()),
+                    config.domain
+                );
+            }
+        }
+        info!(
+            "{} DNS records were changed before the deadline.",
+            summary.n_changed
+        );
+        return PassOutcome::DeadlineExceeded;
+    };
+
+    if let Err(e) = result {
+        error!("Critical Error: {}", e);
+        error!("Stopping the run, the remaining records would be rejected as well.");
+        return PassOutcome::Failed;
+    }
+
+    for (record, dns_type) in &summary.second_pass {
+        warn!(
+            "\t{}/{} only succeeded on the second pass",
+            record, dns_type
+        );
+    }
+
+    if !summary.failed.is_empty() {
+        for failure in &summary.failed {
+            error!(
+                "\t{}/{} failed{}",
+                record_label(&failure.update.record, failure.update.source.as_deref()),
+                failure.update.dns_type,
+                if failure.retryable {
+                    " (queued for retry)"
+                } else {
+                    ""
+                }
+            );
+        }
+        error!(
+            "{} DNS records were changed, {} updates failed.",
+            summary.n_changed,
+            summary.failed.len()
+        );
+        return PassOutcome::Failed;
+    }
+
+    if !summary.removed.is_empty() {
+        warn!(
+            "Removed {} rrsets: {}.",
+            summary.removed.len(),
+            summary.removed.join(", ")
+        );
+    }
+    let message = if summary.protected.is_empty() {
+        format!(
+            "Success! {} DNS records were changed, {} already up to date.",
+            summary.n_changed, summary.n_skipped
+        )
+    } else {
+        format!(
+            "Success! {} DNS records were changed, {} already up to date, {} skipped (protected): {}.",
+            summary.n_changed,
+            summary.n_skipped,
+            summary.protected.len(),
+            summary.protected.join(", ")
+        )
+    };
+    // Quiet runs stay silent unless something changed, e.g. for cron
+    if !config.quiet {
+        info!("{}", message);
+    } else if summary.n_changed > 0 {
+        println!("{}", message);
+    }
+    PassOutcome::Success {
+        n_changed: summary.n_changed,
+        n_protected: summary.protected.len(),
+    }
+}
+
+/// Listens on the control socket, exiting if another daemon already does.
+/// The daemon runs without the socket if it cannot be created otherwise.
+#[cfg(feature = "daemon-mode")]
+fn bind_control(config: &DnsConfig) -> Control {
+    match Control::bind(&config.control_socket, &config.pause_file) {
+        Ok(control) => control,
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            error!("Critical Error: {}", e);
+            process::exit(1);
+        }
+        Err(e) => {
+            warn!(
+                "Unable to listen on {}: {}",
+                config.control_socket.display(),
+                e
+            );
+            Control::disabled(&config.pause_file)
+        }
+    }
+}
+
+/// Sets up the digest if `NOTIFY.digest_interval` is set.
+#[cfg(feature = "daemon-mode")]
+fn start_digest(config: &DnsConfig) -> Option<(Notifications, Digest)> {
+    let interval = config.notify.digest_interval?;
+    if !config.notify.has_channels() {
+        warn!("NOTIFY.digest_interval is set, but no notification channel is configured.");
+        return None;
+    }
+    match Notifications::new(&config.notify, config.timeouts, &config.state_dir) {
+        Ok(notifications) => Some((notifications, Digest::load(&config.state_dir, interval))),
+        Err(e) => {
+            warn!("Unable to set up the notifications: {}", e);
+            None
+        }
+    }
+}
+
+/// Sets up the watchdog if `WATCHDOG.interval` is set, with the
+/// notification channels it reports to.
+#[cfg(feature = "daemon-mode")]
+fn start_watchdog(config: &DnsConfig) -> Option<(Watchdog, Option<Notifications>)> {
+    let watchdog = Watchdog::load(&config.state_dir, config.watchdog?);
+    if !config.notify.has_channels() {
+        return Some((watchdog, None));
+    }
+    match Notifications::new(&config.notify, config.timeouts, &config.state_dir) {
+        Ok(notifications) => Some((watchdog, Some(notifications))),
+        Err(e) => {
+            warn!("Unable to set up the notifications: {}", e);
+            Some((watchdog, None))
+        }
+    }
+}
+
+/// Sleeps until `due`, or forever without a watchdog.
+#[cfg(feature = "daemon-mode")]
+async fn watchdog_due(due: Option<Instant>) {
+    match due {
+        Some(due) => tokio::time::sleep(due.saturating_duration_since(Instant::now())).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Follows the configuration file for changes, `None` if there is no such
+/// file or the configuration is read from a URL, which is checked for
+/// changes on the passes instead.
+#[cfg(feature = "daemon-mode")]
+fn follow_config(cli: &Cli, config: &DnsConfig) -> Option<watch::Receiver<DnsConfig>> {
+    if config_url(cli).is_some() || !cli.config.is_file() {
+        return None;
+    }
+    let origin = ConfigOrigin::new(cli);
+    Some(live_config::spawn(
+        cli.config.clone(),
+        config.clone(),
+        move || {
+            let origin = origin.clone();
+            async move { origin.load().await.map_err(|e| e.to_string()) }
+        },
+    ))
+}
+
+/// Waits for the followed configuration file to change.
+#[cfg(feature = "daemon-mode")]
+async fn config_changed(live: &mut Option<watch::Receiver<DnsConfig>>) {
+    let Some(live) = live else {
+        return std::future::pending().await;
+    };
+    if live.changed().await.is_err() {
+        std::future::pending().await
+    }
+}
+
+/// Runs an update pass every `DAEMON.interval`. Failed passes, including
+/// ones that exceed the deadline, are only logged.
+#[cfg(feature = "daemon-mode")]
+async fn run_daemon(cli: &Cli, mut config: DnsConfig, mut api: GandiApi) {
+    let mut debouncer = Debouncer::new(config.change_debounce);
+    let mut wakeup = Wakeup::new();
+    let mut control = bind_control(&config);
+    let mut digest = start_digest(&config);
+    let mut watchdog = start_watchdog(&config);
+    let started = SystemTime::now();
+    let mut verified = false;
+    let mut trigger = Trigger::Interval;
+    let mut paused_logged: Option<Instant> = None;
+    let mut remote = config_url(cli).and_then(|url| {
+        match RemoteConfig::new(url, None, config.timeouts.request) {
+            Ok(remote) => Some(remote),
+            Err(e) => {
+                warn!("Unable to watch {} for changes: {}", url, e);
+                None
+            }
+        }
+    });
+    if let Some(remote) = &mut remote {
+        // Only later changes cause a reload.
+        if let Err(e) = remote.fetch().await {
+            warn!("{}", e);
+        }
+    }
+    let mut live = follow_config(cli, &config);
+
+    loop {
+        if let Some(live) = &mut live {
+            if live.has_changed().unwrap_or(false) {
+                let new_config = live.borrow_and_update().clone();
+                let _ = apply_config(
+                    with_api(new_config),
+                    &mut config,
+                    &mut api,
+                    &mut digest,
+                    &mut watchdog,
+                    &mut verified,
+                    &control,
+                );
+            }
+        }
+        if let (Trigger::Interval, Some(remote)) = (trigger, &mut remote) {
+            match remote.fetch().await {
+                Ok(Some(_)) => {
+                    info!("{} changed, reloading the configuration", remote.url());
+                    let _ = reload_daemon(
+                        cli,
+                        &mut config,
+                        &mut api,
+                        &mut digest,
+                        &mut watchdog,
+                        &mut verified,
+                        &control,
+                    )
+                    .await;
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Unable to check the configuration for changes: {}", e),
+            }
+        }
+
+        match trigger {
+            Trigger::Signal => info!("Received SIGUSR1, starting a {} pass", trigger),
+            Trigger::Request | Trigger::Watchdog => info!("Starting a {} pass", trigger),
+            Trigger::Interval => {}
+        }
+        let paused = control.paused();
+        if !paused && paused_logged.take().is_some() {
+            info!("Resumed");
+        }
+        if matches!(trigger, Trigger::Interval | Trigger::Watchdog) && paused {
+            if paused_logged.is_none_or(|logged| logged.elapsed() >= PAUSED_LOG_INTERVAL) {
+                info!(
+                    "Paused by {}, skipping the scheduled passes",
+                    config.pause_file.display()
+                );
+                paused_logged = Some(Instant::now());
+            }
+        } else if guard_satisfied(&config) {
+            if !verified {
+                verified = verify_credential(cli, &config, &api).await;
+            }
+            if verified {
+                let (outcome, timings) = run_pass(
+                    &config,
+                    &api,
+                    &mut debouncer,
+                    watchdog.as_mut().map(|(watchdog, _)| watchdog),
+                )
+                .await;
+                record_health(&config, outcome);
+                record_metrics(&config, outcome, &timings);
+                control.update(|status| {
+                    status.passes += 1;
+                    status.last_pass = Some(PassReport {
+                        finished: health::unix_secs(SystemTime::now()),
+                        outcome: outcome.as_str().to_string(),
+                        changed: match outcome {
+                            PassOutcome::Success { n_changed, .. } => n_changed,
+                            _ => 0,
+                        },
+                        timings: Some(timings),
+                    });
+                    status.api_quota = api.quota().map(QuotaReport::from);
+                });
+                if let Some((_, digest)) = &mut digest {
+                    let success = matches!(outcome, PassOutcome::Success { .. });
+                    digest.record_run(success, SystemTime::now());
+                }
+            }
+        }
+        if let Some((notifications, digest)) = &mut digest {
+            let log = AuditLog::new(&config.state_dir);
+            digest
+                .send_if_due(
+                    notifications,
+                    &log,
+                    &config.domain,
+                    started,
+                    SystemTime::now(),
+                )
+                .await;
+        }
+
+        // Requests that do not start a pass leave the schedule as it is.
+        let next = Instant::now() + config.daemon_interval;
+        control.update(|status| {
+            status.next_pass = Some(health::unix_secs(
+                SystemTime::now() + config.daemon_interval,
+            ))
+        });
+        trigger = loop {
+            let due = watchdog.as_ref().map(|(watchdog, _)| watchdog.next_check());
+            tokio::select! {
+                woken = wakeup.sleep(next.saturating_duration_since(Instant::now())) => match woken {
+                    Woken::Pass(woken) => break woken,
+                    Woken::Reload => {
+                        info!("Received SIGHUP, reloading the configuration");
+                        let _ = reload_daemon(cli, &mut config, &mut api, &mut digest, &mut watchdog, &mut verified, &control).await;
+                    }
+                    Woken::Stop => {
+                        info!("Received a termination signal, shutting down");
+                        return;
+                    }
+                },
+                command = control.recv() => match command {
+                    ControlCommand::UpdateNow(reply) => {
+                        let _ = reply.send(Response::message("starting a pass"));
+                        break Trigger::Request;
+                    }
+                    ControlCommand::ReloadConfig(reply) => {
+                        let response =
+                            match reload_daemon(cli, &mut config, &mut api, &mut digest, &mut watchdog, &mut verified, &control).await {
+                                Ok(()) => Response::message("configuration reloaded"),
+                                Err(e) => Response::error(&e),
+                            };
+                        let _ = reply.send(response);
+                    }
+                },
+                _ = config_changed(&mut live) => {
+                    if let Some(live) = &mut live {
+                        let new_config = live.borrow_and_update().clone();
+                        let _ = apply_config(with_api(new_config), &mut config, &mut api, &mut digest, &mut watchdog, &mut verified, &control);
+                    }
+                }
+                _ = watchdog_due(due) => {
+                    if let Some((watchdog, notifications)) = &mut watchdog {
+                        if watchdog.check(&config.domain, notifications.as_ref()).await {
+                            break Trigger::Watchdog;
+                        }
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// Replaces the configuration of the running daemon, which keeps the
+/// current one if the new one is invalid.
+#[cfg(feature = "daemon-mode")]
+async fn reload_daemon(
+    cli: &Cli,
+    config: &mut DnsConfig,
+    api: &mut GandiApi,
+    digest: &mut Option<(Notifications, Digest)>,
+    watchdog: &mut Option<(Watchdog, Option<Notifications>)>,
+    verified: &mut bool,
+    control: &Control,
+) -> Result<(), String> {
+    let reloaded = reload_config(cli).await;
+    apply_config(reloaded, config, api, digest, watchdog, verified, control)
+}
+
+/// Switches the running daemon to the configuration `reloaded`, keeping
+/// the current one on errors.
+#[cfg(feature = "daemon-mode")]
+fn apply_config(
+    reloaded: Result<(DnsConfig, GandiApi), String>,
+    config: &mut DnsConfig,
+    api: &mut GandiApi,
+    digest: &mut Option<(Notifications, Digest)>,
+    watchdog: &mut Option<(Watchdog, Option<Notifications>)>,
+    verified: &mut bool,
+    control: &Control,
+) -> Result<(), String> {
+    match reloaded {
+        Ok((new_config, new_api)) => {
+            info!("Reloaded the configuration");
+            *config = new_config;
+            *api = new_api;
+            *digest = start_digest(config);
+            *watchdog = start_watchdog(config);
+            *verified = false;
+            control.set_pause_file(&config.pause_file);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Unable to reload the configuration: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Reads the configuration again for the running daemon.
+#[cfg(feature = "daemon-mode")]
+async fn reload_config(cli: &Cli) -> Result<(DnsConfig, GandiApi), String> {
+    let config = try_load_config(cli).await.map_err(|e| e.to_string())?;
+    with_api(config)
+}
+
+#[cfg(feature = "daemon-mode")]
+fn with_api(config: DnsConfig) -> Result<(DnsConfig, GandiApi), String> {
+    let api = create_api(&config).map_err(|e| format!("GANDI.key: {}", e))?;
+    Ok((config, api))
+}
+
+fn create_api(config: &DnsConfig) -> Result<GandiApi, reqwest::header::InvalidHeaderValue> {
+    GandiApi::new(
+        &config.key,
+        config.sharing_id.clone(),
+        config.auth_method,
+        Arc::new(
+            ApiRateLimiter::new(
+                config.max_concurrent_requests,
+                config.gandi_request_interval,
+            )
+            .warn_below(config.quota_warning_threshold)
+            .header_prefix(config.rate_limit_header_prefix.clone()),
+        ),
+        config.timeouts,
+    )
+}
+
+/// Remembers how the pass ended for `--health-check`.
+#[cfg(feature = "daemon-mode")]
+fn record_health(config: &DnsConfig, outcome: PassOutcome) {
+    if let Err(e) = health::record_run(
+        &config.state_dir,
+        matches!(outcome, PassOutcome::Success { .. }),
+    ) {
+        warn!("Unable to record the outcome of the pass: {}", e);
+    }
+}
+
+/// Writes the metrics of the pass to `STATE.metrics_file`, if set.
+fn record_metrics(config: &DnsConfig, outcome: PassOutcome, timings: &Timings) {
+    let Some(path) = &config.metrics_file else {
+        return;
+    };
+    #[cfg(feature = "metrics")]
+    {
+        let pass = metrics::PassMetrics {
+            finished: health::unix_secs(SystemTime::now()),
+            success: matches!(outcome, PassOutcome::Success { .. }),
+            changed: match outcome {
+                PassOutcome::Success { n_changed, .. } => n_changed,
+                _ => 0,
+            },
+            timings,
+        };
+        if let Err(e) = metrics::write(path, &pass) {
+            warn!("Unable to write the metrics to {}: {}", path.display(), e);
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = (path, outcome, timings);
+        warn!("STATE.metrics_file is set, but ddns-gandi was built without the metrics feature");
+    }
+}
+
+/// Reads the configuration named on the command line and merges the
+/// command line overrides into it, exiting on errors.
+async fn load_config(cli: &Cli) -> DnsConfig {
+    match try_load_config(cli).await {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid Configuration File! {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The URL given instead of a path with `--config`.
+#[cfg(feature = "daemon-mode")]
+fn config_url(cli: &Cli) -> Option<&str> {
+    cli.config.to_str().filter(|url| remote_config::is_url(url))
+}
+
+async fn try_load_config(cli: &Cli) -> Result<DnsConfig, ConfigError> {
+    ConfigOrigin::new(cli).load().await
+}
+
+/// Where the configuration is read from and the command line overrides
+/// merged into it, kept to read it again away from the [`Cli`].
+#[derive(Debug, Clone)]
+struct ConfigOrigin {
+    location: PathBuf,
+    lax: bool,
+    ipv4_override: Option<Ipv4Addr>,
+    ipv6_override: Option<Ipv6Addr>,
+    ip_version_override: Option<IpVersion>,
+    /// Names of the records to update, all if empty.
+    records: Vec<String>,
+    force_overwrite: bool,
+    allow_delete: bool,
+    revert_manual: bool,
+    quiet: bool,
+    timeout: Option<Duration>,
+}
+
+impl ConfigOrigin {
+    fn new(cli: &Cli) -> Self {
+        ConfigOrigin {
+            location: cli.config.clone(),
+            lax: cli.lax_config,
+            ipv4_override: cli.ipv4_override,
+            ipv6_override: cli.ipv6_override,
+            ip_version_override: match (cli.ipv4_only, cli.ipv6_only) {
+                (true, _) => Some(IpVersion::V4),
+                (_, true) => Some(IpVersion::V6),
+                _ => match IpVersionFilter::of_types(&cli.record_type) {
+                    Ok(IpVersionFilter::Only(version)) => Some(version),
+                    _ => None,
+                },
+            },
+            records: cli.records.clone(),
+            force_overwrite: cli.force_overwrite,
+            allow_delete: cli.allow_delete,
+            revert_manual: cli.yes,
+            quiet: cli.quiet,
+            timeout: cli.timeout,
+        }
+    }
+
+    async fn load(&self) -> Result<DnsConfig, ConfigError> {
+        let url = self
+            .location
+            .to_str()
+            .filter(|url| remote_config::is_url(url));
+        let mut config = match url {
+            Some(url) => read_config_from_url(
+                url,
+                None,
+                self.lax,
+                self.timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+            )
+            .await
+            .map_err(|e| ConfigError::Message(e.to_string()))?,
+            None => read_config(&self.location, self.lax)?,
+        };
+
+        config.ipv4_override = self.ipv4_override;
+        config.ipv6_override = self.ipv6_override;
+        config.ip_version_override = self.ip_version_override;
+        if !self.records.is_empty() {
+            let names: Vec<String> = self
+                .records
+                .iter()
+                .map(|name| normalize_record_name(name, &config.domain))
+                .collect();
+            if let Some(name) = names
+                .iter()
+                .find(|name| !config.records.iter().any(|record| &record.name == *name))
+            {
+                return Err(ConfigError::Message(format!(
+                    "--records: {} is not a configured record",
+                    name
+                )));
+            }
+            config.records.retain(|record| names.contains(&record.name));
+        }
+        config.force_overwrite = self.force_overwrite;
+        config.allow_delete = self.allow_delete;
+        config.revert_manual = self.revert_manual;
+        config.quiet = self.quiet;
+        tls::install(&config.tls);
+        if let Some(timeout) = self.timeout {
+            config.timeouts = Timeouts::all(timeout);
+            let sources = std::iter::once(&mut config.detection).chain(config.sources.values_mut());
+            for source in sources {
+                source.ipv4.timeout = None;
+                source.ipv6.timeout = None;
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    // These only document the CLI and do not need a configuration.
+    match &cli.command {
+        Some(Command::Completions { shell }) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+            return Ok(());
+        }
+        Some(Command::Man) => {
+            clap_mangen::Man::new(Cli::command()).render(&mut io::stdout())?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    // The service logs to a file of its own.
+    #[cfg(all(windows, feature = "daemon-mode"))]
+    if let Some(Command::Service { action }) = &cli.command {
+        return service::handle(action, &cli.config);
+    }
+
+    simple_logger::init_with_level(log::Level::Info)?;
+    if cli.quiet {
+        log::set_max_level(log::LevelFilter::Error);
+    }
+    #[cfg(not(feature = "daemon-mode"))]
+    if cli.daemon || cli.health_check {
+        return Err("--daemon and --health-check need the daemon-mode feature".into());
+    }
+
+    if let Some(Command::EchoServer(args)) = &cli.command {
+        return echo_server::run(args).await;
+    }
+
+    if let Some(Command::LaunchdPlist(args)) = &cli.command {
+        return launchd::handle(args, &cli.config);
+    }
+
+    if let Some(Command::SetKey { service, account }) = &cli.command {
+        let mut entry = config::read_keyring_entry(&cli.config, cli.lax_config)?;
+        if let Some(service) = service {
+            entry.service = service.clone();
+        }
+        if let Some(account) = account {
+            entry.account = account.clone();
+        }
+        return secret_store::handle(&entry);
+    }
+
+    if let Some(Command::EncryptConfig { output }) = &cli.command {
+        return encryption::handle(&cli.config, output);
+    }
+
+    let mut config = load_config(&cli).await;
+
+    if cli.dump_config {
+        print!("{}", dump_config(&config)?);
+        return Ok(());
+    }
+
+    if cli.test_notify {
+        if !config.notify.has_channels() {
+            error!("No notification channel is configured in NOTIFY.");
+            process::exit(1);
+        }
+        let notifications = Notifications::new(&config.notify, config.timeouts, &config.state_dir)?;
+        if notifications.is_empty() {
+            error!("None of the notification channels in NOTIFY is built in.");
+            process::exit(1);
+        }
+        let event = DdnsEvent::Test {
+            domain: config.domain.clone(),
+        };
+        if !notifications.send(&event).await {
+            process::exit(1);
+        }
+        info!("Sent a test notification");
+        return Ok(());
+    }
+
+    #[cfg(feature = "daemon-mode")]
+    if cli.health_check {
+        if config.pause_file.exists() {
+            if config.healthy_while_paused {
+                info!("Paused by {}", config.pause_file.display());
+                return Ok(());
+            }
+            error!("Unhealthy: paused by {}", config.pause_file.display());
+            process::exit(1);
+        }
+        // A pass is due every interval and may take up to the deadline.
+        let max_age = config.daemon_interval * 2 + config.max_run_duration.unwrap_or_default();
+        match health::check(&config.state_dir, max_age) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                error!("Unhealthy: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(Command::SystemdUnit(args)) = &cli.command {
+        return systemd::handle(args, &cli.config, &config.state_dir);
+    }
+
+    if let Some(Command::History(args)) = &cli.command {
+        let log = AuditLog::new(&config.state_dir);
+        if !history::show(&log, args)? {
+            info!("No audit log exists yet at {}", log.path().display());
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "daemon-mode")]
+    if let Some(Command::Ctl(args)) = &cli.command {
+        return match control::run(args, &config.control_socket, &config.pause_file).await {
+            Ok(true) => Ok(()),
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                error!("{}", e);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(Command::Pin(args)) = &cli.command {
+        return match pinning::run(args, &config.tls, config.timeouts).await {
+            Ok(true) => Ok(()),
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                error!("{}", e);
+                process::exit(1);
+            }
+        };
+    }
+
+    let api = create_api(&config)?;
+
+    // The subcommands below work on one zone.
+    let single_zone = matches!(
+        cli.command,
+        Some(
+            Command::Assert(_)
+                | Command::Axfr(_)
+                | Command::Diff(_)
+                | Command::ListSnapshots
+                | Command::RestoreSnapshot(_)
+                | Command::Rollback(_)
+                | Command::Set(_)
+        )
+    );
+    if single_zone && config.discovery.is_some() {
+        return Err("this subcommand needs DNS.domain instead of DNS.domains".into());
+    }
+
+    if let Some(Command::Assert(args)) = &cli.command {
+        process::exit(assertion::run(args, &config.domain, &api).await);
+    }
+
+    if let Some(Command::Axfr(args)) = &cli.command {
+        return zonefile::run(args, &config.domain, &api).await;
+    }
+
+    if let Some(Command::Diff(args)) = &cli.command {
+        process::exit(diff::run(args, &config, &api).await);
+    }
+
+    if let Some(Command::ListSnapshots) = &cli.command {
+        return snapshot::list(&config.domain, &api).await;
+    }
+
+    if let Some(Command::RestoreSnapshot(args)) = &cli.command {
+        return snapshot::restore(args, &config.domain, &api, cli.yes).await;
+    }
+
+    if let Some(Command::Rollback(args)) = &cli.command {
+        return rollback::run(args, &config, &api, cli.yes).await;
+    }
+
+    if let Some(Command::Set(args)) = &cli.command {
+        if cli.daemon {
+            return Err("set cannot run as a daemon".into());
+        }
+        manual::apply(args, &mut config)?;
+    }
+
+    if let Some(budget) = cli.wait_for_network {
+        if !network::wait(budget).await {
+            error!(
+                "The network did not come up within {}s, giving up",
+                budget.as_secs()
+            );
+            process::exit(EXIT_NO_NETWORK);
+        }
+    }
+
+    #[cfg(feature = "daemon-mode")]
+    if cli.daemon {
+        telemetry::init(config.telemetry.as_ref());
+        run_daemon(&cli, config, api).await;
+        telemetry::shutdown().await;
+        return Ok(());
+    }
+
+    if config.change_debounce.is_some() {
+        warn!("UPDATE.change_debounce_s only applies in daemon mode.");
+    }
+    if config.pause_file.exists() {
+        info!(
+            "Paused by {}, skipping the update",
+            config.pause_file.display()
+        );
+        return Ok(());
+    }
+    if !guard_satisfied(&config) {
+        return Ok(());
+    }
+    telemetry::init(config.telemetry.as_ref());
+    if !verify_credential(&cli, &config, &api).await {
+        telemetry::shutdown().await;
+        process::exit(cli.exit_code_on_failure);
+    }
+
+    let mut debouncer = Debouncer::new(None);
+    let (outcome, timings) = run_pass(&config, &api, &mut debouncer, None).await;
+    #[cfg(feature = "daemon-mode")]
+    record_health(&config, outcome);
+    record_metrics(&config, outcome, &timings);
+    telemetry::shutdown().await;
+    match outcome.exit_code(&cli) {
+        0 => Ok(()),
+        code => process::exit(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DnsConfigBuilder;
+    use crate::ip::{DetectionSource, PrefixSource};
+    use clap::ValueEnum;
+    use std::path::Path;
+    use tokio::net::TcpListener;
+
+    /// An API taking `delay` to drop each connection, too slow for the
+    /// deadlines of the tests.
+    async fn slow_api(delay: Duration) -> GandiApi {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    drop(stream);
+                });
+            }
+        });
+        let client = reqwest::Client::builder()
+            .resolve("api.gandi.net", addr)
+            .build()
+            .unwrap();
+        let limiter = Arc::new(ApiRateLimiter::new(None, None));
+        GandiApi::new(
+            "0123456789abcdef01234567",
+            None,
+            None,
+            limiter,
+            Timeouts::default(),
+        )
+        .unwrap()
+        .with_client(client)
+    }
+
+    /// The A records `names` of example.com, updated to 192.0.2.1 within
+    /// `deadline`, with the state in `state_dir`.
+    fn deadline_config(names: &[&str], deadline: Duration, state_dir: &Path) -> DnsConfig {
+        let mut builder = DnsConfigBuilder::new(
+            "0123456789abcdef01234567".to_string(),
+            "example.com".to_string(),
+        )
+        .ip_version(IpVersionFilter::Only(IpVersion::V4))
+        .max_run_duration(Some(deadline))
+        .state_dir(state_dir.to_path_buf());
+        for name in names {
+            builder = builder.add_record(RecordEntry::named(name.to_string()));
+        }
+        let mut config = builder.build().unwrap();
+        config.ipv4_override = Some(Ipv4Addr::new(192, 0, 2, 1));
+        config
+    }
+
+    fn queued(record: &str) -> QueuedUpdate {
+        QueuedUpdate {
+            domain: "example.com".to_string(),
+            record: record.to_string(),
+            dns_type: DnsType::A,
+            target_ip: "192.0.2.1".to_string(),
+            source: None,
+        }
+    }
+
+    fn state_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ddns-gandi-{}-{}", test, process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn keeps_the_queued_updates_the_deadline_cuts_off() {
+        let dir = state_dir("flush-deadline");
+        let config = deadline_config(&["www", "mail"], Duration::from_millis(300), &dir);
+        let mut queue = RetryQueue::load(&dir, 100);
+        queue.push(queued("www"));
+        queue.push(queued("mail"));
+        queue.save().unwrap();
+
+        let api = slow_api(Duration::from_secs(5)).await;
+        let mut debouncer = Debouncer::new(None);
+        let outcome = update_pass(&config, &api, &mut debouncer, None).await;
+        assert_eq!(outcome, PassOutcome::DeadlineExceeded);
+
+        let queue = RetryQueue::load(&dir, 100);
+        assert_eq!(queue.entries(), [queued("www"), queued("mail")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_slow_api_at_the_deadline() {
+        let dir = state_dir("slow-api");
+        let deadline = Duration::from_millis(300);
+        let config = deadline_config(&["www"], deadline, &dir);
+        // Of a record no longer configured, so it is not retried either.
+        let mut queue = RetryQueue::load(&dir, 100);
+        queue.push(queued("old"));
+        queue.save().unwrap();
+
+        let api = slow_api(Duration::from_secs(5)).await;
+        let mut debouncer = Debouncer::new(None);
+        let started = Instant::now();
+        let outcome = update_pass(&config, &api, &mut debouncer, None).await;
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(outcome, PassOutcome::DeadlineExceeded);
+        let cli = Cli::parse_from(["ddns-gandi"]);
+        assert_eq!(outcome.exit_code(&cli), EXIT_DEADLINE_EXCEEDED);
+
+        let queue = RetryQueue::load(&dir, 100);
+        assert_eq!(queue.entries(), [queued("old")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn generates_the_completions_of_every_shell() {
+        Cli::command().debug_assert();
+        for shell in Shell::value_variants() {
+            let mut command = Cli::command();
+            let mut script = Vec::new();
+            clap_complete::generate(*shell, &mut command, "ddns-gandi", &mut script);
+            assert!(!script.is_empty(), "no completions for {}", shell);
+        }
+    }
+
+    #[test]
+    fn the_man_page_lists_every_subcommand() {
+        let mut page = Vec::new();
+        clap_mangen::Man::new(Cli::command())
+            .render(&mut page)
+            .unwrap();
+        let page = String::from_utf8(page).unwrap();
+        for subcommand in Cli::command().get_subcommands() {
+            if subcommand.is_hide_set() {
+                continue;
+            }
+            // Roff escapes the hyphens.
+            let name = subcommand.get_name().replace('-', "\\-");
+            assert!(
+                page.contains(&name),
+                "{} is missing from the man page",
+                subcommand.get_name()
+            );
+        }
+    }
+
+    /// The address `detected` gives the record `name`.
+    fn ipv6_of(detected: &[Detected<'_>], name: &str) -> Option<String> {
+        detected
+            .iter()
+            .find(|d| d.records.iter().any(|r| r.name == name))
+            .and_then(|d| d.ipv6.clone())
+    }
+
+    #[tokio::test]
+    async fn publishes_the_hosts_in_the_delegated_prefix_rather_than_the_wan_one() {
+        let dir = state_dir("delegated-prefix");
+        std::fs::create_dir_all(&dir).unwrap();
+        let lease_file = dir.join("dhclient6.leases");
+        std::fs::write(
+            &lease_file,
+            "lease6 {\n  ia-pd 1 {\n    iaprefix 2001:db8:bbbb::/48 {\n      max-life 7200;\n    }\n  }\n}\n",
+        )
+        .unwrap();
+        let mut detection = DetectionSource::default();
+        detection.ipv6.prefix_len = Some(48);
+        detection.ipv6.delegated_prefix = Some(PrefixSource::LeaseFile(lease_file.clone()));
+        let mut nas = RecordEntry::named("nas".to_string());
+        nas.host_part = Some("::1:2:3:4".parse().unwrap());
+        let mut config = DnsConfigBuilder::new(
+            "0123456789abcdef01234567".to_string(),
+            "example.com".to_string(),
+        )
+        .ip_version(IpVersionFilter::Only(IpVersion::V6))
+        .detection(detection)
+        .add_record(RecordEntry::named("router".to_string()))
+        .add_record(nas)
+        .build()
+        .unwrap();
+        // The address of the WAN link, outside of the delegated prefix.
+        config.ipv6_override = Some("2001:db8:aaaa::5".parse().unwrap());
+
+        let detected = detect_sources(&config, &mut HashMap::new()).await;
+        assert_eq!(
+            ipv6_of(&detected, "router").as_deref(),
+            Some("2001:db8:aaaa::5")
+        );
+        assert_eq!(
+            ipv6_of(&detected, "nas").as_deref(),
+            Some("2001:db8:bbbb:0:1:2:3:4")
+        );
+
+        // Without a prefix to read, the host is left alone rather than
+        // put in the prefix of the WAN link.
+        std::fs::remove_file(&lease_file).unwrap();
+        let detected = detect_sources(&config, &mut HashMap::new()).await;
+        assert_eq!(ipv6_of(&detected, "nas"), None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
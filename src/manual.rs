@@ -0,0 +1,79 @@
+//! The `set` subcommand, writing given addresses to the records instead of
+//! detected ones, e.g. while the detection is broken.
+
+use crate::config::{normalize_record_name, DnsConfig};
+use crate::ip::{parse_public_ipv4, parse_public_ipv6, IpVersion, IpVersionFilter};
+use clap::{ArgGroup, Args};
+use log::warn;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("address").required(true).multiple(true)))]
+pub struct SetArgs {
+    /// IPv4 address written to the A records
+    #[arg(long, value_name = "ADDR", value_parser = parse_public_ipv4, group = "address")]
+    ipv4: Option<Ipv4Addr>,
+    /// IPv6 address written to the AAAA records
+    #[arg(long, value_name = "ADDR", value_parser = parse_public_ipv6, group = "address")]
+    ipv6: Option<Ipv6Addr>,
+    /// Name of a configured record to write, instead of all of them;
+    /// repeat it for several records
+    #[arg(long)]
+    record: Vec<String>,
+}
+
+/// Makes the pass of `config` write the addresses of `args`, to the
+/// selected records and only for the families they are updated for.
+pub fn apply(args: &SetArgs, config: &mut DnsConfig) -> Result<(), String> {
+    let names: Vec<String> = args
+        .record
+        .iter()
+        .map(|name| normalize_record_name(name, &config.domain))
+        .collect();
+    if let Some(name) = names
+        .iter()
+        .find(|name| !config.records.iter().any(|record| &record.name == *name))
+    {
+        return Err(format!("{} is not a configured record", name));
+    }
+
+    let given = [
+        (IpVersion::V4, args.ipv4.is_some()),
+        (IpVersion::V6, args.ipv6.is_some()),
+    ];
+    let mut records = Vec::new();
+    for mut record in std::mem::take(&mut config.records) {
+        if !names.is_empty() && !names.contains(&record.name) {
+            continue;
+        }
+        let versions = config.versions(&record);
+        let families: Vec<IpVersion> = given
+            .iter()
+            .filter(|(version, set)| *set && versions.allows(*version))
+            .map(|(version, _)| *version)
+            .collect();
+        record.ip_version = Some(match families[..] {
+            [] => {
+                warn!(
+                    "Skipping {}, which is only updated for {}",
+                    record.name,
+                    versions.as_str()
+                );
+                continue;
+            }
+            [version] => IpVersionFilter::Only(version),
+            _ => IpVersionFilter::Both,
+        });
+        records.push(record);
+    }
+    if records.is_empty() {
+        return Err("none of the records is updated for the given addresses".to_string());
+    }
+
+    config.records = records;
+    config.ipv4_override = args.ipv4;
+    config.ipv6_override = args.ipv6;
+    config.ip_version_override = None;
+    config.manual_set = true;
+    Ok(())
+}
@@ -0,0 +1,278 @@
+//! `NOTIFY.templates`: the title and text of the notifications of a kind of
+//! event rendered from Tera templates, e.g. to translate them or to show the
+//! comments of the records. The kinds without a template keep the built-in
+//! messages, and so do all of them without the `notifications` feature.
+
+#[cfg(feature = "notifications")]
+use crate::digest::format_uptime;
+#[cfg(feature = "notifications")]
+use crate::notify::HtmlMessage;
+use crate::notify::{DdnsEvent, Message};
+#[cfg(feature = "notifications")]
+use log::warn;
+use std::collections::BTreeMap;
+#[cfg(feature = "notifications")]
+use std::error::Error;
+#[cfg(feature = "notifications")]
+use tera::{Context, Tera};
+
+/// The kinds of events, as [`DdnsEvent::kind`] names them.
+pub const KINDS: &[&str] = &[
+    "change", "failure", "recovery", "drift", "watchdog", "digest", "test",
+];
+/// The parts of a message a template may give.
+pub const PARTS: &[&str] = &["title", "text"];
+
+/// The compiled templates, named `<kind>.<part>`, and again as
+/// `<kind>.<part>.html` for Tera to escape the values they substitute.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    #[cfg(feature = "notifications")]
+    tera: Tera,
+    sources: BTreeMap<String, String>,
+}
+
+/// The message of a Tera error, with the line and column of a syntax error
+/// that only its sources tell.
+#[cfg(feature = "notifications")]
+fn describe(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = format!("{}: {}", message, error);
+        source = error.source();
+    }
+    message
+}
+
+impl Templates {
+    /// Compiles `sources`, named `<kind>.<part>`, failing with the name of
+    /// the first that does not compile.
+    #[cfg(feature = "notifications")]
+    pub fn new(sources: BTreeMap<String, String>) -> Result<Self, (String, String)> {
+        let mut tera = Tera::default();
+        for (name, source) in &sources {
+            tera.add_raw_template(name, source)
+                .map_err(|e| (name.clone(), describe(&e)))?;
+            tera.add_raw_template(&format!("{}.html", name), source)
+                .map_err(|e| (name.clone(), describe(&e)))?;
+        }
+        Ok(Templates { tera, sources })
+    }
+
+    /// Keeps `sources` to be shown, without compiling them.
+    #[cfg(not(feature = "notifications"))]
+    pub fn new(sources: BTreeMap<String, String>) -> Result<Self, (String, String)> {
+        Ok(Templates { sources })
+    }
+
+    /// The sources, by name.
+    pub fn sources(&self) -> &BTreeMap<String, String> {
+        &self.sources
+    }
+
+    /// The message of `event`, rendered from its templates if it has any.
+    /// A template failing to render, e.g. on a missing variable, leaves
+    /// the built-in part.
+    #[cfg(feature = "notifications")]
+    pub fn message(&self, event: &DdnsEvent) -> Message {
+        let mut message = Message::of(event);
+        let kind = event.kind();
+        let names: Vec<String> = PARTS
+            .iter()
+            .map(|part| format!("{}.{}", kind, part))
+            .filter(|name| self.sources.contains_key(name))
+            .collect();
+        if names.is_empty() {
+            return message;
+        }
+        let context = context(event, &message);
+        // The built-in parts are text, so they are escaped as a whole.
+        let mut html = HtmlMessage {
+            title: tera::escape_html(&message.title),
+            text: tera::escape_html(&message.text),
+        };
+        for name in names {
+            let rendered = match self.tera.render(&name, &context) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    warn!(
+                        "Unable to render NOTIFY.templates.{}: {}",
+                        name,
+                        describe(&e)
+                    );
+                    continue;
+                }
+            };
+            let escaped = self
+                .tera
+                .render(&format!("{}.html", name), &context)
+                .unwrap_or_else(|_| tera::escape_html(&rendered));
+            if name.ends_with(".title") {
+                message.title = rendered.trim().to_string();
+                html.title = escaped.trim().to_string();
+            } else {
+                message.text = rendered.trim_end().to_string();
+                html.text = escaped.trim_end().to_string();
+            }
+            message.templated = true;
+        }
+        if message.templated {
+            message.html = Some(html);
+        }
+        message
+    }
+
+    #[cfg(not(feature = "notifications"))]
+    pub fn message(&self, event: &DdnsEvent) -> Message {
+        Message::of(event)
+    }
+}
+
+/// The variables of the templates: the fields of `event`, its `kind`, and
+/// the built-in `title` and `text`.
+#[cfg(feature = "notifications")]
+fn context(event: &DdnsEvent, message: &Message) -> Context {
+    let mut value = match event {
+        DdnsEvent::RecordsChanged { domain, changes } => serde_json::json!({
+            "domain": domain,
+            "changes": changes
+                .iter()
+                .map(|change| serde_json::json!({
+                    "record": change.record,
+                    "dns_type": change.dns_type.to_string(),
+                    "old": change.old,
+                    "new": change.new,
+                    "comment": change.comment,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        DdnsEvent::UpdateFailed {
+            domain,
+            reason,
+            class,
+            failures,
+        } => serde_json::json!({
+            "domain": domain,
+            "reason": reason,
+            "class": class,
+            "failures": failures
+                .iter()
+                .map(|failure| serde_json::json!({
+                    "record": failure.record,
+                    "dns_type": failure.dns_type.to_string(),
+                    "error": failure.error,
+                    "comment": failure.comment,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        DdnsEvent::Recovered {
+            domain,
+            lasted,
+            suppressed,
+        } => serde_json::json!({
+            "domain": domain,
+            "lasted": format_uptime(*lasted),
+            "lasted_secs": lasted.as_secs(),
+            "suppressed": suppressed,
+        }),
+        DdnsEvent::Drift { domain, drifts } => serde_json::json!({
+            "domain": domain,
+            "drifts": drifts,
+        }),
+        DdnsEvent::Watchdog { domain, reports } => serde_json::json!({
+            "domain": domain,
+            "reports": reports,
+        }),
+        DdnsEvent::Digest { .. } => serde_json::json!({}),
+        DdnsEvent::Test { domain } => serde_json::json!({ "domain": domain }),
+    };
+    value["kind"] = event.kind().into();
+    value["title"] = message.title.clone().into();
+    value["text"] = message.text.clone().into();
+    Context::from_value(value).unwrap_or_default()
+}
+
+#[cfg(all(test, feature = "notifications"))]
+mod tests {
+    use super::*;
+    use crate::dns_type::DnsType;
+    use crate::notify::RecordChange;
+
+    fn templates(sources: &[(&str, &str)]) -> Templates {
+        let sources = sources
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect();
+        Templates::new(sources).unwrap()
+    }
+
+    fn change(comment: &str) -> DdnsEvent {
+        DdnsEvent::RecordsChanged {
+            domain: "example.com".to_string(),
+            changes: vec![RecordChange {
+                record: "www".to_string(),
+                dns_type: DnsType::A,
+                old: Some(vec!["192.0.2.1".to_string()]),
+                new: "192.0.2.2".to_string(),
+                comment: Some(comment.to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn renders_the_templates_of_the_kind_of_the_event() {
+        let templates = templates(&[(
+            "change.text",
+            "{% for c in changes %}{{ c.record }} ({{ c.comment }}): {{ c.new }}{% endfor %}",
+        )]);
+
+        let message = templates.message(&change("web server"));
+        assert!(message.templated);
+        assert_eq!(message.text, "www (web server): 192.0.2.2");
+        // The title has no template, so it is the built-in one.
+        assert_eq!(message.title, Message::of(&change("web server")).title);
+
+        let test = DdnsEvent::Test {
+            domain: "example.com".to_string(),
+        };
+        assert_eq!(templates.message(&test), Message::of(&test));
+    }
+
+    #[test]
+    fn escapes_the_values_substituted_into_the_html() {
+        let templates = templates(&[
+            ("change.title", "<b>{{ domain }}</b> & co"),
+            ("change.text", "<i>{{ changes[0].comment }}</i>"),
+        ]);
+
+        let message = templates.message(&change("<script>&"));
+        assert_eq!(message.text, "<i><script>&</i>");
+        let html = message.html.unwrap();
+        // The markup of the templates is kept, only the values are escaped.
+        assert_eq!(html.title, "<b>example.com</b> & co");
+        assert_eq!(html.text, "<i>&lt;script&gt;&amp;</i>");
+    }
+
+    #[test]
+    fn escapes_the_built_in_parts_of_templated_messages() {
+        let templates = templates(&[("failure.title", "Failed")]);
+        let event = DdnsEvent::UpdateFailed {
+            domain: "example.com".to_string(),
+            reason: "status code 400 <Bad Request>".to_string(),
+            class: "validation",
+            failures: Vec::new(),
+        };
+
+        let html = templates.message(&event).html.unwrap();
+        assert_eq!(html.title, "Failed");
+        assert!(html.text.contains("&lt;Bad Request&gt;"), "{}", html.text);
+    }
+
+    #[test]
+    fn keeps_the_built_in_part_when_a_template_fails_to_render() {
+        let templates = templates(&[("change.title", "{{ missing }}")]);
+        let message = templates.message(&change("web server"));
+        assert_eq!(message, Message::of(&change("web server")));
+    }
+}
@@ -0,0 +1,142 @@
+//! `STATE.metrics_file`: the outcome of the last pass in the Prometheus
+//! text format, for the textfile collector of the node exporter, if built
+//! with the `metrics` feature.
+
+use crate::timing::Timings;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What the metrics of a pass are made of.
+#[derive(Debug)]
+pub struct PassMetrics<'a> {
+    /// Seconds since the Unix epoch.
+    pub finished: u64,
+    pub success: bool,
+    pub changed: usize,
+    pub timings: &'a Timings,
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// The metrics of `pass` in the Prometheus text format.
+pub fn render(pass: &PassMetrics) -> String {
+    let mut out = String::new();
+    gauge(
+        &mut out,
+        "ddns_gandi_last_pass_timestamp_seconds",
+        "When the last pass finished.",
+        pass.finished,
+    );
+    gauge(
+        &mut out,
+        "ddns_gandi_last_pass_success",
+        "Whether the last pass succeeded.",
+        u8::from(pass.success),
+    );
+    gauge(
+        &mut out,
+        "ddns_gandi_last_pass_changed_records",
+        "Records the last pass changed.",
+        pass.changed,
+    );
+    gauge(
+        &mut out,
+        "ddns_gandi_last_pass_duration_seconds",
+        "How long the last pass took.",
+        pass.timings.total_ms as f64 / 1000.0,
+    );
+    if !pass.timings.operations.is_empty() {
+        let name = "ddns_gandi_last_pass_operations";
+        let _ = writeln!(out, "# HELP {} Operations of the last pass.", name);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (operation, stats) in &pass.timings.operations {
+            let _ = writeln!(
+                out,
+                "{}{{operation=\"{}\"}} {}",
+                name, operation, stats.count
+            );
+        }
+    }
+    if let Some(remaining) = pass.timings.api_quota_remaining {
+        gauge(
+            &mut out,
+            "ddns_gandi_api_quota_remaining",
+            "Lowest quota of API requests the API reported during the last pass.",
+            remaining,
+        );
+    }
+    out
+}
+
+/// Replaces `path` with the metrics of `pass`, through a temporary file
+/// so that the collector never reads half of it.
+pub fn write(path: &Path, pass: &PassMetrics) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("prom.tmp");
+    fs::write(&tmp, render(pass))?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::Stats;
+    use std::process;
+
+    #[test]
+    fn renders_the_last_pass() {
+        let mut timings = Timings {
+            total_ms: 1500,
+            api_quota_remaining: Some(42),
+            ..Timings::default()
+        };
+        let mut stats = Stats::default();
+        stats.count = 2;
+        timings.operations.insert("gandi_put".to_string(), stats);
+        let pass = PassMetrics {
+            finished: 1_700_000_000,
+            success: true,
+            changed: 2,
+            timings: &timings,
+        };
+
+        let metrics = render(&pass);
+
+        assert!(metrics.contains("ddns_gandi_last_pass_timestamp_seconds 1700000000\n"));
+        assert!(metrics.contains("ddns_gandi_last_pass_success 1\n"));
+        assert!(metrics.contains("ddns_gandi_last_pass_changed_records 2\n"));
+        assert!(metrics.contains("ddns_gandi_last_pass_duration_seconds 1.5\n"));
+        assert!(metrics.contains("ddns_gandi_last_pass_operations{operation=\"gandi_put\"} 2\n"));
+        assert!(metrics.contains("ddns_gandi_api_quota_remaining 42\n"));
+    }
+
+    #[test]
+    fn replaces_the_metrics_file() {
+        let dir = std::env::temp_dir().join(format!("ddns-gandi-metrics-{}", process::id()));
+        let path = dir.join("ddns-gandi.prom");
+        let timings = Timings::default();
+        for success in [true, false] {
+            let pass = PassMetrics {
+                finished: 0,
+                success,
+                changed: 0,
+                timings: &timings,
+            };
+            write(&path, &pass).unwrap();
+        }
+
+        let metrics = fs::read_to_string(&path).unwrap();
+        assert!(metrics.contains("ddns_gandi_last_pass_success 0\n"));
+        assert!(!metrics.contains("operations"));
+        assert!(!metrics.contains("quota"));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
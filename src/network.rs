@@ -0,0 +1,50 @@
+//! Waiting for the network at boot, when a run may start before the
+//! interfaces are up or before name resolution works.
+
+use log::{debug, info};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// Reached to tell whether the network is up, since every pass needs it.
+const PROBE_ADDRESS: &str = "api.gandi.net:443";
+/// Bound of one attempt to reach [`PROBE_ADDRESS`].
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Pause between two attempts.
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Waits at most `budget` for the Gandi API to accept a connection, which
+/// needs both name resolution and a route. Returns whether it did.
+pub async fn wait(budget: Duration) -> bool {
+    let started = Instant::now();
+    let deadline = started + budget;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let error =
+            match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(PROBE_ADDRESS)).await {
+                Ok(Ok(_)) => {
+                    if attempt > 1 {
+                        info!("The network is up after {}s", started.elapsed().as_secs());
+                    }
+                    return true;
+                }
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => format!("no answer within {:?}", PROBE_TIMEOUT),
+            };
+        if attempt == 1 {
+            info!(
+                "Waiting up to {}s for the network: {} is unreachable: {}",
+                budget.as_secs(),
+                PROBE_ADDRESS,
+                error
+            );
+        } else {
+            debug!("{} is still unreachable: {}", PROBE_ADDRESS, error);
+        }
+        if Instant::now() + PROBE_INTERVAL >= deadline {
+            return false;
+        }
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+}
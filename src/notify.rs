@@ -0,0 +1,616 @@
+//! Sending notifications to the configured channels.
+//!
+//! Every channel is a [`Notifier`], which renders the typed [`DdnsEvent`]s
+//! it takes. [`Notifications`] sends each event to all of them at once.
+
+use crate::digest::format_uptime;
+use crate::dns_type::DnsType;
+use crate::message_templates::Templates;
+use crate::redacted::Redacted;
+use crate::throttle::{self, Decision, Throttle};
+use crate::timeouts::Timeouts;
+use async_trait::async_trait;
+use futures::future::join_all;
+use log::{debug, warn};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+#[cfg(feature = "notifications")]
+mod channels;
+
+pub const DEFAULT_NTFY_SERVER: &str = "https://ntfy.sh";
+
+/// Priority of an ntfy message, 1 (`min`) to 5 (`max`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NtfyPriority(u8);
+
+impl NtfyPriority {
+    pub const DEFAULT: NtfyPriority = NtfyPriority(3);
+    #[cfg_attr(not(feature = "notifications"), allow(dead_code))]
+    const HIGH: NtfyPriority = NtfyPriority(4);
+
+    pub fn as_str(self) -> &'static str {
+        ["min", "low", "default", "high", "max"][usize::from(self.0 - 1)]
+    }
+}
+
+impl FromStr for NtfyPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let priority = match s.trim().to_ascii_lowercase().as_str() {
+            "min" | "1" => 1,
+            "low" | "2" => 2,
+            "default" | "3" => 3,
+            "high" | "4" => 4,
+            "max" | "urgent" | "5" => 5,
+            other => return Err(format!("unknown ntfy priority '{}'", other)),
+        };
+        Ok(NtfyPriority(priority))
+    }
+}
+
+/// Which of the events of every pass a channel is sent, read from the
+/// `on_change` and `on_failure` (or `on_error`) keys of its table. The
+/// other events are always sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct EventFilter {
+    /// Whether the `change` events are sent.
+    pub on_change: bool,
+    /// Whether the `failure` events are sent.
+    #[serde(alias = "on_error")]
+    pub on_failure: bool,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        EventFilter {
+            on_change: true,
+            on_failure: true,
+        }
+    }
+}
+
+impl EventFilter {
+    #[cfg_attr(not(feature = "notifications"), allow(dead_code))]
+    pub fn takes(&self, event: &DdnsEvent) -> bool {
+        match event {
+            DdnsEvent::RecordsChanged { .. } => self.on_change,
+            DdnsEvent::UpdateFailed { .. } => self.on_failure,
+            _ => true,
+        }
+    }
+}
+
+/// A webhook receiving a JSON `POST` with the `event`, a `title` and the
+/// `text`, which Slack and Mattermost style webhooks display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookConfig {
+    /// May hold a token.
+    pub url: Redacted<String>,
+    pub events: EventFilter,
+}
+
+/// A topic of an ntfy server, published to with its HTTP API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtfyConfig {
+    pub server: String,
+    pub topic: String,
+    /// Access token of a protected topic.
+    pub token: Option<Redacted<String>>,
+    /// Priority of the messages, raised to `high` for failures.
+    pub priority: NtfyPriority,
+    pub events: EventFilter,
+}
+
+/// Default priority of the Gotify messages, which Android shows as a
+/// notification from 4 on.
+pub const DEFAULT_GOTIFY_PRIORITY: u8 = 5;
+
+/// A Gotify application the messages are pushed as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GotifyConfig {
+    /// E.g. `https://gotify.example.net`.
+    pub server: String,
+    /// Token of the application.
+    pub token: Redacted<String>,
+    /// Priority of the messages, 0 to 10, raised to 8 for failures.
+    pub priority: u8,
+    pub events: EventFilter,
+}
+
+/// A Telegram bot sending the notifications to chats with the Bot API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelegramConfig {
+    pub bot_token: Redacted<String>,
+    /// Chats receiving the messages, by ID or `@channel` name.
+    pub chat_ids: Vec<String>,
+    pub events: EventFilter,
+}
+
+/// A Matrix room the notifications are posted to with the Client-Server
+/// API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixConfig {
+    /// E.g. `https://matrix.example.org`.
+    pub homeserver_url: String,
+    pub access_token: Redacted<String>,
+    /// E.g. `!abcdefg:example.org`.
+    pub room_id: String,
+    pub events: EventFilter,
+}
+
+/// A Discord webhook receiving the notifications as embeds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscordConfig {
+    /// Holds the token of the webhook.
+    pub webhook_url: Redacted<String>,
+    /// Overrides the name the webhook posts with.
+    pub username: Option<String>,
+    /// Overrides the avatar the webhook posts with.
+    pub avatar_url: Option<String>,
+    pub events: EventFilter,
+}
+
+/// Apprise URLs, e.g. `slack://tokenA/tokenB/`, notified with the
+/// `apprise` command or an Apprise API server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppriseConfig {
+    /// Hold the credentials of the services, so they are also kept off the
+    /// command line.
+    pub urls: Redacted<Vec<String>>,
+    /// E.g. `http://apprise:8000`, instead of running `apprise`.
+    pub apprise_url: Option<String>,
+    pub events: EventFilter,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NotifyConfig {
+    pub webhook: Option<WebhookConfig>,
+    pub ntfy: Option<NtfyConfig>,
+    pub gotify: Option<GotifyConfig>,
+    pub telegram: Option<TelegramConfig>,
+    pub discord: Option<DiscordConfig>,
+    pub matrix: Option<MatrixConfig>,
+    pub apprise: Option<AppriseConfig>,
+    /// Whether the changes and failures are shown as desktop notifications
+    /// on this machine, if built with the `desktop-notifications` feature.
+    pub desktop: bool,
+    /// How often the daemon sends a digest of its activity.
+    pub digest_interval: Option<Duration>,
+    /// Shortest time between two notifications of the same failure, drift
+    /// or watchdog report.
+    pub throttle_interval: Option<Duration>,
+    /// Replace the built-in messages of the kinds of events they are for.
+    pub templates: Templates,
+}
+
+impl NotifyConfig {
+    pub fn has_channels(&self) -> bool {
+        self.has_http_channels() || self.desktop
+    }
+
+    /// Whether a channel other than the desktop is set, which needs the
+    /// `notifications` feature.
+    pub fn has_http_channels(&self) -> bool {
+        self.webhook.is_some()
+            || self.ntfy.is_some()
+            || self.gotify.is_some()
+            || self.telegram.is_some()
+            || self.discord.is_some()
+            || self.matrix.is_some()
+            || self.apprise.is_some()
+    }
+}
+
+/// A record a pass changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordChange {
+    pub record: String,
+    pub dns_type: DnsType,
+    /// The values before, `None` if they are unknown.
+    pub old: Option<Vec<String>>,
+    pub new: String,
+    /// The comment of the record in `DNS.records`, for the templates.
+    pub comment: Option<String>,
+}
+
+/// A record a pass failed to update.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordFailure {
+    pub record: String,
+    pub dns_type: DnsType,
+    pub error: String,
+    /// The comment of the record in `DNS.records`, for the templates.
+    pub comment: Option<String>,
+}
+
+/// What a notification is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DdnsEvent {
+    /// Records of `domain` a pass changed.
+    RecordsChanged {
+        domain: String,
+        changes: Vec<RecordChange>,
+    },
+    /// A pass of `domain` failed for `reason`, with the records it failed
+    /// to update.
+    UpdateFailed {
+        domain: String,
+        reason: String,
+        /// E.g. `network` or `auth`, see [`DdnsError::class`].
+        ///
+        /// [`DdnsError::class`]: crate::error::DdnsError::class
+        class: &'static str,
+        failures: Vec<RecordFailure>,
+    },
+    /// The passes of `domain` succeed again after failures lasting
+    /// `lasted`, `suppressed` of which were held back by the throttle.
+    Recovered {
+        domain: String,
+        lasted: Duration,
+        suppressed: u32,
+    },
+    /// Rrsets of `domain` someone else changed.
+    Drift { domain: String, drifts: Vec<String> },
+    /// What the watchdog found wrong in the public DNS.
+    #[cfg_attr(not(feature = "daemon-mode"), allow(dead_code))]
+    Watchdog {
+        domain: String,
+        reports: Vec<String>,
+    },
+    /// The periodic digest of the daemon.
+    #[cfg_attr(not(feature = "daemon-mode"), allow(dead_code))]
+    Digest { title: String, text: String },
+    /// Sent by `--test-notify`.
+    Test { domain: String },
+}
+
+impl DdnsEvent {
+    /// The name of the kind of event, e.g. the `event` of the webhook
+    /// payloads.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DdnsEvent::RecordsChanged { .. } => "change",
+            DdnsEvent::UpdateFailed { .. } => "failure",
+            DdnsEvent::Recovered { .. } => "recovery",
+            DdnsEvent::Drift { .. } => "drift",
+            DdnsEvent::Watchdog { .. } => "watchdog",
+            DdnsEvent::Digest { .. } => "digest",
+            DdnsEvent::Test { .. } => "test",
+        }
+    }
+
+    /// Whether the event is sent after every pass rather than now and then.
+    fn is_pass_event(&self) -> bool {
+        matches!(
+            self,
+            DdnsEvent::RecordsChanged { .. } | DdnsEvent::UpdateFailed { .. }
+        )
+    }
+
+    /// Whether the event asks for attention.
+    #[cfg_attr(not(feature = "notifications"), allow(dead_code))]
+    fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            DdnsEvent::UpdateFailed { .. } | DdnsEvent::Watchdog { .. }
+        )
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            DdnsEvent::RecordsChanged { domain, changes } => format!(
+                "ddns-gandi: {} records of {} changed",
+                changes.len(),
+                domain
+            ),
+            DdnsEvent::UpdateFailed { domain, .. } => {
+                format!("ddns-gandi: updating {} failed", domain)
+            }
+            DdnsEvent::Recovered { domain, .. } => {
+                format!("ddns-gandi: updating {} works again", domain)
+            }
+            DdnsEvent::Drift { domain, .. } => {
+                format!("ddns-gandi: records of {} changed by someone else", domain)
+            }
+            DdnsEvent::Watchdog { domain, .. } => format!("ddns-gandi watchdog for {}", domain),
+            DdnsEvent::Digest { title, .. } => title.clone(),
+            DdnsEvent::Test { domain } => {
+                format!("ddns-gandi: test notification for {}", domain)
+            }
+        }
+    }
+
+    /// The body of the notification, one line per record.
+    pub fn text(&self) -> String {
+        match self {
+            DdnsEvent::RecordsChanged { changes, .. } => changes
+                .iter()
+                .map(|change| {
+                    let old = change
+                        .old
+                        .as_ref()
+                        .map_or("?".to_string(), |old| old.join(","));
+                    format!(
+                        "{}/{}: {} -> {}",
+                        change.record, change.dns_type, old, change.new
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            DdnsEvent::UpdateFailed {
+                reason, failures, ..
+            } => {
+                let mut lines = vec![reason.clone()];
+                lines.extend(failures.iter().map(|failure| {
+                    format!("{}/{}: {}", failure.record, failure.dns_type, failure.error)
+                }));
+                lines.join("\n")
+            }
+            DdnsEvent::Recovered {
+                lasted, suppressed, ..
+            } => format!(
+                "The failures lasted {}, {} notifications of them were held back.",
+                format_uptime(*lasted),
+                suppressed
+            ),
+            DdnsEvent::Drift { drifts: lines, .. } | DdnsEvent::Watchdog { reports: lines, .. } => {
+                lines.join("\n")
+            }
+            DdnsEvent::Digest { text, .. } => text.clone(),
+            DdnsEvent::Test { .. } => {
+                "The notifications of ddns-gandi reach this channel.".to_string()
+            }
+        }
+    }
+}
+
+/// The title and text a notification is sent with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub title: String,
+    pub text: String,
+    /// Whether `NOTIFY.templates` gave them rather than the event.
+    pub templated: bool,
+    /// The same message with the values the templates substitute escaped,
+    /// for the channels taking the templates as HTML. Set along with
+    /// `templated`.
+    #[cfg_attr(not(feature = "notifications"), allow(dead_code))]
+    pub html: Option<HtmlMessage>,
+}
+
+/// A templated message whose parts are HTML.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlMessage {
+    pub title: String,
+    pub text: String,
+}
+
+impl Message {
+    /// The built-in message of `event`.
+    pub fn of(event: &DdnsEvent) -> Self {
+        Message {
+            title: event.title(),
+            text: event.text(),
+            templated: false,
+            html: None,
+        }
+    }
+}
+
+/// Why a channel did not accept a notification.
+#[derive(Debug, Error)]
+pub enum NotificationError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("{0}")]
+    #[cfg_attr(not(feature = "notifications"), allow(dead_code))]
+    Rejected(String),
+}
+
+/// A channel the notifications are sent to.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Names the channel in the logs.
+    fn name(&self) -> String;
+
+    /// Whether the channel is sent `event` at all.
+    fn takes(&self, _event: &DdnsEvent) -> bool {
+        true
+    }
+
+    /// Sends `event` as `message`, which the channels formatting the events
+    /// themselves only use if a template gave it.
+    async fn notify(&self, event: &DdnsEvent, message: &Message) -> Result<(), NotificationError>;
+}
+
+/// The notification daemon of the desktop session.
+#[derive(Debug)]
+pub struct DesktopNotifier;
+
+#[async_trait]
+impl Notifier for DesktopNotifier {
+    fn name(&self) -> String {
+        "the desktop".to_string()
+    }
+
+    fn takes(&self, event: &DdnsEvent) -> bool {
+        event.is_pass_event()
+            || matches!(event, DdnsEvent::Recovered { .. } | DdnsEvent::Test { .. })
+    }
+
+    async fn notify(&self, event: &DdnsEvent, message: &Message) -> Result<(), NotificationError> {
+        let critical = matches!(event, DdnsEvent::UpdateFailed { .. });
+        // Headless machines have no notification daemon, which is fine.
+        if let Err(e) = show_desktop(&message.title, &message.text, critical).await {
+            debug!(
+                "Unable to show the {} desktop notification: {}",
+                event.kind(),
+                e
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The configured channels.
+pub struct Notifications {
+    notifiers: Vec<Box<dyn Notifier>>,
+    throttle: Option<Throttle>,
+    templates: Templates,
+}
+
+impl fmt::Debug for Notifications {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.notifiers.iter().map(|n| n.name()).collect();
+        f.debug_struct("Notifications")
+            .field("notifiers", &names)
+            .finish()
+    }
+}
+
+impl Notifications {
+    /// The channels of `config`, throttled with the windows kept in
+    /// `state_dir`.
+    pub fn new(
+        config: &NotifyConfig,
+        timeouts: Timeouts,
+        state_dir: &Path,
+    ) -> Result<Self, reqwest::Error> {
+        #[cfg(feature = "notifications")]
+        let mut notifiers = channels::notifiers(config, timeouts)?;
+        #[cfg(not(feature = "notifications"))]
+        let mut notifiers: Vec<Box<dyn Notifier>> = {
+            let _ = timeouts;
+            if config.has_http_channels() {
+                warn!("NOTIFY has channels set, but ddns-gandi was built without the notifications feature");
+            }
+            if !config.templates.sources().is_empty() {
+                warn!("NOTIFY.templates is set, but ddns-gandi was built without the notifications feature");
+            }
+            Vec::new()
+        };
+        if config.desktop {
+            notifiers.push(Box::new(DesktopNotifier));
+        }
+        Ok(Notifications {
+            notifiers,
+            throttle: config
+                .throttle_interval
+                .map(|interval| Throttle::new(state_dir, interval)),
+            templates: config.templates.clone(),
+        })
+    }
+
+    /// Whether no channel is left, e.g. all of them needing a feature the
+    /// binary was built without.
+    pub fn is_empty(&self) -> bool {
+        self.notifiers.is_empty()
+    }
+
+    /// Sends `event` to every channel taking it, all at once, returning
+    /// whether all of them accepted it. Failures are logged as warnings.
+    /// An event held back by `NOTIFY.throttle_interval` counts as sent.
+    pub async fn send(&self, event: &DdnsEvent) -> bool {
+        let takers: Vec<&dyn Notifier> = self
+            .notifiers
+            .iter()
+            .map(|notifier| notifier.as_ref())
+            .filter(|notifier| notifier.takes(event))
+            .collect();
+        let decisions = match &self.throttle {
+            Some(throttle) => {
+                let channels: Vec<String> = takers.iter().map(|n| n.name()).collect();
+                throttle.admit(&channels, event)
+            }
+            None => vec![Decision::Send { repeats: 0 }; takers.len()],
+        };
+        let sent = takers
+            .into_iter()
+            .zip(decisions)
+            .filter_map(|(notifier, decision)| match decision {
+                Decision::Send { repeats: 0 } => Some((notifier, Cow::Borrowed(event))),
+                Decision::Send { repeats } => {
+                    Some((notifier, Cow::Owned(throttle::with_repeats(event, repeats))))
+                }
+                Decision::Suppress => {
+                    debug!(
+                        "Holding back the {} notification from {}",
+                        event.kind(),
+                        notifier.name()
+                    );
+                    None
+                }
+            })
+            .map(
+                |(notifier, event)| async move { deliver(notifier, &event, &self.templates).await },
+            );
+        join_all(sent).await.into_iter().all(|delivered| delivered)
+    }
+
+    /// Tells the channels that were sent the failures of `domain` that a
+    /// pass succeeded again, with how long the failures lasted.
+    pub async fn recover(&self, domain: &str) {
+        let Some(throttle) = &self.throttle else {
+            return;
+        };
+        let recoveries = throttle.resolve(domain, "failure");
+        let sent = recoveries.into_iter().filter_map(|(channel, recovery)| {
+            let notifier = self.notifiers.iter().find(|n| n.name() == channel)?;
+            let event = DdnsEvent::Recovered {
+                domain: domain.to_string(),
+                lasted: recovery.lasted,
+                suppressed: recovery.suppressed,
+            };
+            notifier
+                .takes(&event)
+                .then_some(async move { deliver(notifier.as_ref(), &event, &self.templates).await })
+        });
+        join_all(sent).await;
+    }
+}
+
+/// Sends `event` to `notifier` with its message rendered from `templates`,
+/// returning whether it accepted it. A failure is logged as a warning.
+async fn deliver(notifier: &dyn Notifier, event: &DdnsEvent, templates: &Templates) -> bool {
+    let result = notifier.notify(event, &templates.message(event)).await;
+    if let Err(e) = &result {
+        warn!(
+            "Unable to send the {} notification to {}: {}",
+            event.kind(),
+            notifier.name(),
+            e
+        );
+    }
+    result.is_ok()
+}
+
+/// Shows a notification on the desktop session, urgent if `critical`.
+#[cfg(feature = "desktop-notifications")]
+async fn show_desktop(title: &str, text: &str, critical: bool) -> Result<(), String> {
+    let mut notification = notify_rust::Notification::new();
+    notification.appname("ddns-gandi").summary(title).body(text);
+    #[cfg(not(target_os = "macos"))]
+    if critical {
+        notification.urgency(notify_rust::Urgency::Critical);
+    }
+    #[cfg(target_os = "macos")]
+    let _ = critical;
+    // Talking to the notification daemon blocks.
+    tokio::task::spawn_blocking(move || notification.show().map(|_| ()))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+async fn show_desktop(_title: &str, _text: &str, _critical: bool) -> Result<(), String> {
+    Err("built without the desktop-notifications feature".to_string())
+}
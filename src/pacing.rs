@@ -0,0 +1,279 @@
+//! Limiting the requests to the Gandi API: how many are in flight, how
+//! often they start, and waiting for an exhausted quota to be reset.
+
+use log::{debug, warn};
+use reqwest::Response;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+/// Longest wait for an exhausted quota to be reset, should the reset time
+/// the API tells be far off.
+const MAX_QUOTA_WAIT: Duration = Duration::from_secs(15 * 60);
+/// `X-RateLimit-Reset` values above this are Unix timestamps, those below
+/// seconds from now.
+const MIN_RESET_TIMESTAMP: u64 = 1_000_000_000;
+
+/// The quota of requests the API reported with a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    pub limit: Option<u64>,
+    pub remaining: u64,
+    /// When the quota is reset.
+    pub reset: Option<SystemTime>,
+}
+
+/// Prefixes of the rate limit headers tried in turn, as Gandi spelled
+/// them over time and as the IETF draft spells them.
+const HEADER_PREFIXES: &[&str] = &["x-ratelimit-", "ratelimit-", "x-rate-limit-"];
+
+/// Reads the `<prefix>limit`, `<prefix>remaining` and `<prefix>reset`
+/// headers of `response`, trying `prefix` before the usual spellings.
+/// `None` without a remaining quota, or if it is not a number.
+fn parse_rate_limit_headers(response: &Response, prefix: Option<&str>) -> Option<RateLimitInfo> {
+    let header = |prefix: &str, name: &str| -> Option<u64> {
+        response
+            .headers()
+            .get(format!("{}{}", prefix, name))?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let (prefix, remaining) = prefix
+        .into_iter()
+        .chain(HEADER_PREFIXES.iter().copied())
+        .find_map(|prefix| Some((prefix, header(prefix, "remaining")?)))?;
+    let reset = header(prefix, "reset").map(|reset| {
+        if reset >= MIN_RESET_TIMESTAMP {
+            UNIX_EPOCH + Duration::from_secs(reset)
+        } else {
+            SystemTime::now() + Duration::from_secs(reset)
+        }
+    });
+    Some(RateLimitInfo {
+        limit: header(prefix, "limit"),
+        remaining,
+        reset,
+    })
+}
+
+/// Spaces out requests so that at most one starts per interval, shared by
+/// all tasks using the same API client.
+#[derive(Debug)]
+pub struct Pacer {
+    interval: Duration,
+    /// Earliest time the next request may start.
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    pub fn new(interval: Duration) -> Self {
+        Pacer {
+            interval,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Waits for the next free slot. Slots are handed out in the order the
+    /// callers arrive, and the lock is released before sleeping so that
+    /// later callers can reserve the following slots meanwhile.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next = self.next.lock().await;
+            let now = Instant::now();
+            let slot = match *next {
+                Some(next) if next > now => next,
+                _ => now,
+            };
+            *next = Some(slot + self.interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Limits the requests to the Gandi API, shared through an `Arc` by all
+/// tasks updating records: at most a number of requests in flight, and
+/// optionally a minimum interval between their starts.
+///
+/// Once the API reports the quota to be exhausted, requests wait for it to
+/// be reset instead of being rejected.
+#[derive(Debug, Default)]
+pub struct ApiRateLimiter {
+    in_flight: Option<Semaphore>,
+    pacer: Option<Pacer>,
+    /// The quota reported with the last response.
+    quota: std::sync::Mutex<Option<RateLimitInfo>>,
+    /// Remaining quota below which a warning is logged.
+    warn_below: Option<u64>,
+    /// Prefix of the rate limit headers, before the usual ones.
+    header_prefix: Option<String>,
+}
+
+impl ApiRateLimiter {
+    pub fn new(max_concurrent: Option<usize>, interval: Option<Duration>) -> Self {
+        ApiRateLimiter {
+            in_flight: max_concurrent.map(Semaphore::new),
+            pacer: interval.map(Pacer::new),
+            quota: std::sync::Mutex::new(None),
+            warn_below: None,
+            header_prefix: None,
+        }
+    }
+
+    /// Warns once the remaining quota drops below `threshold`.
+    pub fn warn_below(mut self, threshold: Option<u64>) -> Self {
+        self.warn_below = threshold;
+        self
+    }
+
+    /// Reads the rate limit headers starting with `prefix` first, e.g.
+    /// should Gandi rename them again.
+    pub fn header_prefix(mut self, prefix: Option<String>) -> Self {
+        self.header_prefix = prefix.map(|prefix| prefix.to_ascii_lowercase());
+        self
+    }
+
+    /// Notes the quota reported with `response`, if it has one.
+    pub fn observe(&self, response: &Response) -> Option<RateLimitInfo> {
+        let info = parse_rate_limit_headers(response, self.header_prefix.as_deref())?;
+        match info.limit {
+            Some(limit) => debug!("API quota: {} of {} remaining", info.remaining, limit),
+            None => debug!("API quota: {} remaining", info.remaining),
+        }
+        let Ok(mut quota) = self.quota.lock() else {
+            return Some(info);
+        };
+        let previous = quota.replace(info);
+        // Only the drop below the threshold is warned about, not every
+        // request after it.
+        if let Some(threshold) = self.warn_below {
+            let was_above = previous.is_none_or(|previous| previous.remaining >= threshold);
+            if info.remaining < threshold && was_above {
+                warn!(
+                    "The API quota is running low: {} requests remaining{}",
+                    info.remaining,
+                    info.limit
+                        .map(|limit| format!(" of {}", limit))
+                        .unwrap_or_default()
+                );
+            }
+        }
+        Some(info)
+    }
+
+    /// The quota reported with the last response.
+    #[cfg_attr(not(feature = "daemon-mode"), allow(dead_code))]
+    pub fn quota(&self) -> Option<RateLimitInfo> {
+        *self.quota.lock().ok()?
+    }
+
+    /// How long to wait for the exhausted quota to be reset.
+    fn quota_wait(&self) -> Option<Duration> {
+        let info = self.quota.lock().ok()?.filter(|info| info.remaining == 0)?;
+        let wait = info.reset?.duration_since(SystemTime::now()).ok()?;
+        Some(wait.min(MAX_QUOTA_WAIT))
+    }
+
+    /// Waits until a request may start. The request counts as in flight
+    /// until the returned permit is dropped.
+    pub async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        if let Some(wait) = self.quota_wait() {
+            warn!(
+                "The API quota is exhausted, waiting {}s for it to be reset",
+                wait.as_secs()
+            );
+            tokio::time::sleep(wait).await;
+        }
+        let permit = match &self.in_flight {
+            // The semaphore is never closed.
+            Some(in_flight) => in_flight.acquire().await.ok(),
+            None => None,
+        };
+        if let Some(pacer) = &self.pacer {
+            pacer.acquire().await;
+        }
+        permit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Acquires from `limiter` in `tasks` concurrent tasks, holding the
+    /// permit for `hold`, and returns when each one was let through.
+    async fn starts(limiter: ApiRateLimiter, tasks: usize, hold: Duration) -> Vec<Duration> {
+        let limiter = Arc::new(limiter);
+        let start = Instant::now();
+        let handles: Vec<_> = (0..tasks)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await;
+                    let started = start.elapsed();
+                    tokio::time::sleep(hold).await;
+                    started
+                })
+            })
+            .collect();
+        let mut starts = Vec::new();
+        for handle in handles {
+            starts.push(handle.await.unwrap());
+        }
+        starts.sort();
+        starts
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn spaces_out_concurrent_requests() {
+        let limiter = ApiRateLimiter::new(None, Some(INTERVAL));
+        let starts = starts(limiter, 5, Duration::ZERO).await;
+        let expected: Vec<_> = (0..5).map(|i| INTERVAL * i).collect();
+        assert_eq!(starts, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_delay_requests_after_a_pause() {
+        let pacer = Pacer::new(INTERVAL);
+        pacer.acquire().await;
+        tokio::time::sleep(INTERVAL * 4).await;
+        let before = Instant::now();
+        pacer.acquire().await;
+        assert_eq!(before.elapsed(), Duration::ZERO);
+        pacer.acquire().await;
+        assert_eq!(before.elapsed(), INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_the_requests_in_flight() {
+        let limiter = ApiRateLimiter::new(Some(2), None);
+        let hold = Duration::from_secs(1);
+        let starts = starts(limiter, 5, hold).await;
+        assert_eq!(
+            starts,
+            [Duration::ZERO, Duration::ZERO, hold, hold, hold * 2]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_within_the_limit_of_requests_in_flight() {
+        let limiter = ApiRateLimiter::new(Some(1), Some(INTERVAL));
+        let hold = Duration::from_secs(1);
+        let starts = starts(limiter, 3, hold).await;
+        // The slot is free by the time the previous request is done.
+        assert_eq!(starts, [Duration::ZERO, hold, hold * 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_limit_without_settings() {
+        let limiter = ApiRateLimiter::new(None, None);
+        let starts = starts(limiter, 3, Duration::from_secs(1)).await;
+        assert_eq!(starts, [Duration::ZERO; 3]);
+    }
+}
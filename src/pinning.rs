@@ -0,0 +1,288 @@
+//! Pinning the public keys of the Gandi API, from `HTTP.gandi_spki_pins`:
+//! on top of the usual verification of the chain, one of its certificates
+//! must hold one of the pinned keys, so that a rogue certificate from a
+//! trusted CA or an intercepting proxy does not get the API key.
+
+use crate::gandi::REST_URL;
+use crate::timeouts::Timeouts;
+use crate::tls::TlsConfig;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::Args;
+use log::error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::error::Error as _;
+use std::sync::{Arc, Mutex};
+
+/// The message of the TLS error failing the handshake, by which the
+/// request error is told apart from the other TLS failures.
+const MISMATCH: &str = "the certificate matches none of HTTP.gandi_spki_pins";
+
+#[derive(Debug, Args)]
+pub struct PinArgs {
+    /// Print the hashes of the keys of the chain the API presents, e.g. to
+    /// fill in `HTTP.gandi_spki_pins`, instead of checking them against it
+    #[arg(long)]
+    pub show: bool,
+}
+
+/// The SHA-256 hash of a SubjectPublicKeyInfo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// Parses a `sha256/<base64>` pin, as HPKP and `openssl` print them.
+    pub fn parse(pin: &str) -> Result<Self, String> {
+        let hash = pin
+            .trim()
+            .strip_prefix("sha256/")
+            .ok_or_else(|| format!("'{}' does not start with sha256/", pin))?;
+        STANDARD
+            .decode(hash)
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .map(SpkiPin)
+            .ok_or_else(|| format!("'{}' is not the base64 of a SHA-256 hash", pin))
+    }
+
+    /// The pin of the key of `cert`, `None` if it cannot be parsed.
+    fn of(cert: &[u8]) -> Option<Self> {
+        Some(SpkiPin(Sha256::digest(spki(cert)?).into()))
+    }
+}
+
+impl std::fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256/{}", STANDARD.encode(self.0))
+    }
+}
+
+/// The tag of the DER element at the start of `der`, and the offsets of
+/// its contents and of its end.
+fn element(der: &[u8]) -> Option<(u8, usize, usize)> {
+    let tag = *der.first()?;
+    let first = usize::from(*der.get(1)?);
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let mut len = 0;
+        for byte in der.get(2..2 + n)? {
+            len = len << 8 | usize::from(*byte);
+        }
+        (len, 2 + n)
+    };
+    let end = header.checked_add(len).filter(|end| *end <= der.len())?;
+    Some((tag, header, end))
+}
+
+/// The DER of the SubjectPublicKeyInfo of a certificate.
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, start, end) = element(cert)?;
+    let tbs = &cert[start..end];
+    let (_, start, end) = element(tbs)?;
+    let mut rest = &tbs[start..end];
+    // The version is tagged [0] and left out for v1 certificates.
+    if let Some((0xa0, _, end)) = element(rest) {
+        rest = &rest[end..];
+    }
+    // The serial number, signature algorithm, issuer, validity and subject
+    // come first.
+    for _ in 0..5 {
+        let (_, _, end) = element(rest)?;
+        rest = &rest[end..];
+    }
+    match element(rest)? {
+        (0x30, _, end) => Some(&rest[..end]),
+        _ => None,
+    }
+}
+
+/// Verifies the chain like the other clients, then checks its keys against
+/// the pins, recording them for `pin`.
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    /// No pin only records the chain.
+    pins: Vec<SpkiPin>,
+    chain: Mutex<Vec<Option<SpkiPin>>>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let chain: Vec<Option<SpkiPin>> = std::iter::once(end_entity)
+            .chain(intermediates)
+            .map(|cert| SpkiPin::of(cert))
+            .collect();
+        if let Ok(mut recorded) = self.chain.lock() {
+            *recorded = chain.clone();
+        }
+        if self.pins.is_empty() || chain.iter().flatten().any(|pin| self.pins.contains(pin)) {
+            return Ok(verified);
+        }
+        let presented: Vec<String> = chain
+            .iter()
+            .map(|pin| pin.map_or_else(|| "(unparsable)".to_string(), |pin| pin.to_string()))
+            .collect();
+        error!(
+            "!!! The certificate of {} matches none of HTTP.gandi_spki_pins !!!",
+            server_name.to_str()
+        );
+        error!(
+            "!!! The chain holds the keys {}: a rogue certificate or TLS interception, or Gandi rotated its keys !!!",
+            presented.join(", ")
+        );
+        Err(rustls::Error::General(MISMATCH.to_string()))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+impl PinningVerifier {
+    fn new(tls: &TlsConfig, pins: Vec<SpkiPin>) -> Result<Self, String> {
+        let mut roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        for root in tls.root_ders() {
+            roots.add(root.clone()).map_err(|e| e.to_string())?;
+        }
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(PinningVerifier {
+            inner,
+            pins,
+            chain: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn verifying_config(verifier: Arc<PinningVerifier>) -> Result<ClientConfig, String> {
+    Ok(ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth())
+}
+
+/// The TLS settings of the client of the API, verifying its chain with the
+/// roots of `tls` and checking it holds one of `pins`.
+pub fn client_config(tls: &TlsConfig, pins: Vec<SpkiPin>) -> Result<ClientConfig, String> {
+    verifying_config(Arc::new(PinningVerifier::new(tls, pins)?))
+}
+
+/// Whether a request failed since the certificate matched none of the
+/// pins. The handshake failed then, before the credential was sent.
+pub fn is_mismatch(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(rustls::Error::General(message)) = error.downcast_ref::<rustls::Error>() {
+            return message == MISMATCH;
+        }
+        // The source of an I/O error is that of the error it wraps, which
+        // is skipped otherwise.
+        source = match error.downcast_ref::<std::io::Error>() {
+            Some(io) => io.get_ref().map(|inner| inner as &dyn std::error::Error),
+            None => error.source(),
+        };
+    }
+    false
+}
+
+/// Connects to the API without a credential and prints the pins of the
+/// chain it presents, or checks them against `HTTP.gandi_spki_pins`.
+/// Returns whether they match.
+pub async fn run(
+    args: &PinArgs,
+    tls: &TlsConfig,
+    timeouts: Timeouts,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let verifier = Arc::new(PinningVerifier::new(tls, Vec::new())?);
+    let client = timeouts
+        .client(timeouts.api_call)
+        .use_preconfigured_tls(verifying_config(verifier.clone())?)
+        .build()?;
+    // Any response will do, the certificates are what matters.
+    client.get(REST_URL).send().await?;
+    let chain = verifier
+        .chain
+        .lock()
+        .map(|chain| chain.clone())
+        .unwrap_or_default();
+    if args.show {
+        for (depth, pin) in chain.iter().enumerate() {
+            let role = if depth == 0 { "leaf" } else { "intermediate" };
+            match pin {
+                Some(pin) => println!("{} ({}, depth {})", pin, role, depth),
+                None => println!("(unparsable key) ({}, depth {})", role, depth),
+            }
+        }
+        return Ok(true);
+    }
+    if tls.gandi_pins.is_empty() {
+        return Err("HTTP.gandi_spki_pins is empty, pass --show to print the keys".into());
+    }
+    match chain
+        .iter()
+        .flatten()
+        .find(|pin| tls.gandi_pins.contains(pin))
+    {
+        Some(pin) => {
+            println!("The chain matches the pin {}", pin);
+            Ok(true)
+        }
+        None => {
+            println!(
+                "The chain matches none of HTTP.gandi_spki_pins, pass --show to print its keys"
+            );
+            Ok(false)
+        }
+    }
+}
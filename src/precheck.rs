@@ -0,0 +1,172 @@
+//! Checking the published records over DNS before asking the API.
+
+use crate::dns_type::DnsType;
+use hickory_resolver::config::{NameServerConfig, ResolverConfig};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::net::NetError;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::{Resolver, TokioResolver};
+use log::debug;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// How to tell whether a record needs an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precheck {
+    /// Always ask the LiveDNS API.
+    #[default]
+    Api,
+    /// Resolve the record first and only ask the API if it differs.
+    Dns,
+}
+
+impl Precheck {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Precheck::Api => "api",
+            Precheck::Dns => "dns",
+        }
+    }
+}
+
+impl FromStr for Precheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "api" => Ok(Precheck::Api),
+            "dns" => Ok(Precheck::Dns),
+            other => Err(format!("unknown precheck mode '{}'", other)),
+        }
+    }
+}
+
+/// The published answer for an rrset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub values: Vec<IpAddr>,
+    pub ttl: u32,
+}
+
+/// Resolves records directly against the authoritative name servers of the
+/// domain (or a configured resolver), without caching, so that a stale
+/// cached answer cannot hide a needed update.
+pub struct DnsPrecheck {
+    resolver: TokioResolver,
+}
+
+impl DnsPrecheck {
+    pub async fn new(domain: &str, resolver: Option<IpAddr>) -> Result<Self, NetError> {
+        let servers = match resolver {
+            Some(ip) => vec![ip],
+            None => authoritative_servers(domain).await?,
+        };
+        if servers.is_empty() {
+            return Err(NetError::Msg(format!(
+                "no name servers found for {}",
+                domain
+            )));
+        }
+
+        let config = ResolverConfig::from_name_servers(
+            servers
+                .into_iter()
+                .map(NameServerConfig::udp_and_tcp)
+                .collect(),
+        );
+        let mut builder = Resolver::builder_with_config(config, TokioRuntimeProvider::default());
+        builder.options_mut().cache_size = 0;
+        Ok(DnsPrecheck {
+            resolver: builder.build()?,
+        })
+    }
+
+    /// Resolves `record` of `domain`. Returns `None` if the name or the
+    /// rrset does not exist (NXDOMAIN or NODATA).
+    pub async fn lookup(
+        &self,
+        domain: &str,
+        record: &str,
+        dns_type: DnsType,
+    ) -> Result<Option<Answer>, NetError> {
+        let record_type = match dns_type {
+            DnsType::A => RecordType::A,
+            DnsType::Aaaa => RecordType::AAAA,
+            other => return Err(NetError::Msg(format!("unsupported record type {}", other))),
+        };
+
+        let lookup = match self
+            .resolver
+            .lookup(fqdn(domain, record), record_type)
+            .await
+        {
+            Ok(lookup) => lookup,
+            Err(e) if e.is_no_records_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut answer = Answer {
+            values: Vec::new(),
+            ttl: u32::MAX,
+        };
+        for record in lookup.answers() {
+            let value = match &record.data {
+                RData::A(a) => IpAddr::V4(a.0),
+                RData::AAAA(aaaa) => IpAddr::V6(aaaa.0),
+                _ => continue,
+            };
+            answer.values.push(value);
+            answer.ttl = answer.ttl.min(record.ttl);
+        }
+        if answer.values.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(answer))
+    }
+
+    /// Resolves the SOA record of `domain`, returning it in zone file
+    /// notation along with its TTL.
+    pub async fn soa(&self, domain: &str) -> Result<Option<(String, u32)>, NetError> {
+        let lookup = match self
+            .resolver
+            .lookup(format!("{}.", domain), RecordType::SOA)
+            .await
+        {
+            Ok(lookup) => lookup,
+            Err(e) if e.is_no_records_found() => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(lookup
+            .answers()
+            .iter()
+            .find_map(|record| match &record.data {
+                RData::SOA(soa) => Some((soa.to_string(), record.ttl)),
+                _ => None,
+            }))
+    }
+}
+
+fn fqdn(domain: &str, record: &str) -> String {
+    match record {
+        "@" => format!("{}.", domain),
+        record => format!("{}.{}.", record, domain),
+    }
+}
+
+/// Looks up the addresses of the name servers of `domain` with the system
+/// resolver.
+async fn authoritative_servers(domain: &str) -> Result<Vec<IpAddr>, NetError> {
+    let system = TokioResolver::builder_tokio()?.build()?;
+    let ns = system.ns_lookup(format!("{}.", domain)).await?;
+
+    let mut servers = Vec::new();
+    for record in ns.answers() {
+        if let RData::NS(name) = &record.data {
+            match system.lookup_ip(name.0.clone()).await {
+                Ok(ips) => servers.extend(ips.iter()),
+                Err(e) => debug!("Unable to resolve the name server {}: {}", name.0, e),
+            }
+        }
+    }
+    Ok(servers)
+}
@@ -0,0 +1,57 @@
+//! Fetching the rrsets of the records in the background while the
+//! addresses are being detected, hiding the latency of the API behind that
+//! of the detection.
+
+use crate::dns_type::DnsType;
+use crate::error::DdnsError;
+use crate::gandi::{get_gandi_rrsets, GandiApi, GandiRrset};
+use std::collections::HashMap;
+use tokio::task::JoinHandle;
+
+type Rrsets = Result<HashMap<DnsType, GandiRrset>, DdnsError>;
+
+/// The fetches started for a pass, by record name. Those not taken are
+/// aborted when it is dropped, e.g. once the deadline of the pass expired.
+#[derive(Debug, Default)]
+pub struct Prefetch {
+    fetches: HashMap<String, JoinHandle<Rrsets>>,
+}
+
+impl Prefetch {
+    /// Starts fetching the rrsets of every name in `names`.
+    pub fn start<'a>(
+        domain: &str,
+        names: impl IntoIterator<Item = &'a str>,
+        api: &GandiApi,
+    ) -> Self {
+        let fetches = names
+            .into_iter()
+            .map(|name| {
+                let (domain, owned, api) = (domain.to_string(), name.to_string(), api.clone());
+                let fetch =
+                    tokio::spawn(async move { get_gandi_rrsets(&domain, &owned, &api).await });
+                (name.to_string(), fetch)
+            })
+            .collect();
+        Prefetch { fetches }
+    }
+
+    /// The rrsets of `name`, fetched now if they were not prefetched or the
+    /// prefetch did not finish.
+    pub async fn take(&mut self, domain: &str, name: &str, api: &GandiApi) -> Rrsets {
+        if let Some(fetch) = self.fetches.remove(name) {
+            if let Ok(rrsets) = fetch.await {
+                return rrsets;
+            }
+        }
+        get_gandi_rrsets(domain, name, api).await
+    }
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        for fetch in self.fetches.values() {
+            fetch.abort();
+        }
+    }
+}
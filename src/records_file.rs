@@ -0,0 +1,133 @@
+//! Reading the record names listed in the files of `DNS.records_file`, e.g.
+//! generated by an inventory script.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Whether `name` matches `pattern`, where `*` stands for any number of
+/// characters and `?` for a single one.
+fn glob_matches(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            glob_matches(&pattern[1..], name)
+                || (!name.is_empty() && glob_matches(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => glob_matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Whether the record `name` matches `pattern`, with the wildcards of
+/// [`glob_matches`].
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_matches(&pattern, &name)
+}
+
+fn is_pattern(component: &str) -> bool {
+    component.contains(['*', '?'])
+}
+
+/// Expands the wildcards of `pattern` into the matching files, sorted by
+/// path. Like a shell, wildcards do not match hidden files. A path without
+/// wildcards has to exist.
+fn expand(pattern: &str) -> Result<Vec<PathBuf>, String> {
+    if !is_pattern(pattern) {
+        let path = PathBuf::from(pattern);
+        if !path.is_file() {
+            return Err(format!("{}: no such file", pattern));
+        }
+        return Ok(vec![path]);
+    }
+
+    let mut candidates = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let Component::Normal(part) = component else {
+            candidates.iter_mut().for_each(|c| c.push(component));
+            continue;
+        };
+        let part = part.to_string_lossy();
+        if !is_pattern(&part) {
+            candidates.iter_mut().for_each(|c| c.push(&*part));
+            continue;
+        }
+
+        let wanted: Vec<char> = part.chars().collect();
+        let mut expanded = Vec::new();
+        for dir in &candidates {
+            let listed = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            let Ok(entries) = fs::read_dir(listed) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let name: Vec<char> = name.chars().collect();
+                if name.first() == Some(&'.') && wanted.first() != Some(&'.') {
+                    continue;
+                }
+                if glob_matches(&wanted, &name) {
+                    expanded.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+        candidates = expanded;
+    }
+
+    candidates.retain(|path| path.is_file());
+    candidates.sort();
+    Ok(candidates)
+}
+
+/// Whether `name` can be the name of a record, e.g. `www`, `@` or
+/// `*.lab`.
+fn valid_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '*' | '@'))
+}
+
+/// Parses a file with one record name per line. `#` starts a comment and
+/// blank lines are ignored.
+fn parse(path: &Path, contents: &str) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let name = line.split('#').next().unwrap_or_default().trim();
+        if name.is_empty() {
+            continue;
+        }
+        if !valid_name(name) {
+            return Err(format!(
+                "{}:{}: invalid record name '{}'",
+                path.display(),
+                index + 1,
+                name
+            ));
+        }
+        names.push(name.to_string());
+    }
+    Ok(names)
+}
+
+/// Reads the record names of the files matching `patterns`, in the order of
+/// the patterns and then of the paths, without duplicates.
+pub fn read(patterns: &[String]) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = Vec::new();
+    for pattern in patterns {
+        for path in expand(pattern)? {
+            let contents =
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            for name in parse(&path, &contents)? {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    Ok(names)
+}
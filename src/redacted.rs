@@ -0,0 +1,23 @@
+//! Secrets kept out of the debug output of the configuration and of the
+//! channels holding them.
+
+use std::fmt;
+use std::ops::Deref;
+
+/// A secret such as a token, or a URL holding one, shown as `****`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Redacted<T>(pub T);
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt("****", f)
+    }
+}
+
+impl<T> Deref for Redacted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,146 @@
+//! Fetching the configuration from a URL instead of a local file, e.g. from
+//! a central configuration server.
+
+use crate::error::DdnsError;
+use crate::tls;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, IF_MODIFIED_SINCE, LAST_MODIFIED};
+use reqwest::StatusCode;
+use std::env;
+use std::time::Duration;
+
+const DEFAULT_CONSUL_ADDR: &str = "http://127.0.0.1:8500";
+
+/// Whether `location` names a URL rather than a local path.
+pub fn is_url(location: &str) -> bool {
+    ["file://", "http://", "https://", "consul://"]
+        .iter()
+        .any(|scheme| location.starts_with(scheme))
+}
+
+fn fetch_error(url: &str, message: impl std::fmt::Display) -> DdnsError {
+    DdnsError::ConfigFetch(format!("{}: {}", url, message))
+}
+
+fn header(url: &str, value: &str) -> Result<HeaderValue, DdnsError> {
+    HeaderValue::from_str(value).map_err(|e| fetch_error(url, e))
+}
+
+/// What to request for `url`: the HTTP URL with the headers carrying the
+/// credentials from the environment.
+fn request_target(url: &str) -> Result<(String, HeaderMap), DdnsError> {
+    let mut headers = HeaderMap::new();
+    if let Some(key) = url.strip_prefix("consul://kv/") {
+        let addr = env::var("CONSUL_HTTP_ADDR").unwrap_or_else(|_| DEFAULT_CONSUL_ADDR.into());
+        let addr = if addr.contains("://") {
+            addr
+        } else {
+            format!("http://{}", addr)
+        };
+        if let Ok(token) = env::var("CONSUL_HTTP_TOKEN") {
+            headers.insert("X-Consul-Token", header(url, &token)?);
+        }
+        let target = format!("{}/v1/kv/{}?raw", addr.trim_end_matches('/'), key);
+        return Ok((target, headers));
+    }
+    if url.starts_with("consul://") {
+        return Err(fetch_error(url, "only consul://kv/<key> is supported"));
+    }
+    if let Ok(token) = env::var("DDNS_CONFIG_TOKEN") {
+        let value = header(url, &format!("Bearer {}", token))?;
+        headers.insert(AUTHORIZATION, value);
+    }
+    Ok((url.to_string(), headers))
+}
+
+/// A configuration document at a URL, fetched again as it changes.
+#[derive(Debug)]
+pub struct RemoteConfig {
+    url: String,
+    /// The HTTP URL requested, `None` for a `file://` URL.
+    target: Option<String>,
+    headers: HeaderMap,
+    client: reqwest::Client,
+    last_modified: Option<String>,
+    /// The contents last fetched, since not every server answers
+    /// `If-Modified-Since`.
+    contents: Option<Vec<u8>>,
+}
+
+impl RemoteConfig {
+    /// `headers` are sent in addition to the credentials taken from the
+    /// environment: `DDNS_CONFIG_TOKEN` as a bearer token over HTTP(S),
+    /// `CONSUL_HTTP_TOKEN` for Consul at `CONSUL_HTTP_ADDR`.
+    pub fn new(
+        url: &str,
+        headers: Option<&HeaderMap>,
+        timeout: Duration,
+    ) -> Result<Self, DdnsError> {
+        let mut extra = headers.cloned().unwrap_or_default();
+        let target = if url.starts_with("file://") {
+            None
+        } else {
+            let (target, credentials) = request_target(url)?;
+            extra.extend(credentials);
+            Some(target)
+        };
+        Ok(RemoteConfig {
+            url: url.to_string(),
+            target,
+            headers: extra,
+            // The settings of the configuration fetched before, if any.
+            client: tls::configure(reqwest::Client::builder())
+                .timeout(timeout)
+                .build()?,
+            last_modified: None,
+            contents: None,
+        })
+    }
+
+    #[cfg_attr(not(feature = "daemon-mode"), allow(dead_code))]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Fetches the document, returning `None` if it did not change since
+    /// the last fetch.
+    pub async fn fetch(&mut self) -> Result<Option<Vec<u8>>, DdnsError> {
+        let contents = match self.target.clone() {
+            Some(target) => match self.get(target).await? {
+                Some(contents) => contents,
+                None => return Ok(None),
+            },
+            None => {
+                let path = self.url.trim_start_matches("file://");
+                std::fs::read(path).map_err(|e| fetch_error(&self.url, e))?
+            }
+        };
+        if self.contents.as_ref() == Some(&contents) {
+            return Ok(None);
+        }
+        self.contents = Some(contents.clone());
+        Ok(Some(contents))
+    }
+
+    async fn get(&mut self, target: String) -> Result<Option<Vec<u8>>, DdnsError> {
+        let mut request = self.client.get(target).headers(self.headers.clone());
+        if let Some(since) = &self.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, since);
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(fetch_error(
+                &self.url,
+                format!("the server responded with status {}", response.status()),
+            ));
+        }
+        self.last_modified = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok(Some(response.bytes().await?.to_vec()))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::dns_type::DnsType;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const QUEUE_FILE: &str = "retry-queue.json";
+
+/// A record update that failed and should be attempted again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedUpdate {
+    pub domain: String,
+    pub record: String,
+    pub dns_type: DnsType,
+    pub target_ip: String,
+    /// Detection source the target was detected through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl QueuedUpdate {
+    fn same_rrset(&self, other: &QueuedUpdate) -> bool {
+        self.domain == other.domain
+            && self.record == other.record
+            && self.dns_type == other.dns_type
+    }
+}
+
+/// Failed updates persisted as JSON in the state directory, so that an IP
+/// change is not lost when the Gandi API is unavailable during a run.
+#[derive(Debug)]
+pub struct RetryQueue {
+    path: PathBuf,
+    depth: usize,
+    entries: Vec<QueuedUpdate>,
+}
+
+impl RetryQueue {
+    /// Loads the queue from `state_dir`. A missing file yields an empty
+    /// queue, an unreadable one is discarded with a warning.
+    pub fn load(state_dir: &Path, depth: usize) -> Self {
+        let path = state_dir.join(QUEUE_FILE);
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Discarding corrupt retry queue {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!("Unable to read retry queue {}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+
+        let mut queue = RetryQueue {
+            path,
+            depth,
+            entries,
+        };
+        queue.truncate();
+        queue
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Queues an update, replacing any older entry for the same rrset since
+    /// only the most recent target matters.
+    pub fn push(&mut self, update: QueuedUpdate) {
+        self.entries.retain(|e| !e.same_rrset(&update));
+        self.entries.push(update);
+        self.truncate();
+    }
+
+    /// The queued updates, oldest first.
+    pub fn entries(&self) -> &[QueuedUpdate] {
+        &self.entries
+    }
+
+    /// Removes an update once it has been applied or superseded.
+    pub fn remove(&mut self, update: &QueuedUpdate) {
+        self.entries.retain(|e| e != update);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.entries)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn truncate(&mut self) {
+        if self.entries.len() > self.depth {
+            let excess = self.entries.len() - self.depth;
+            warn!(
+                "Retry queue is full, dropping the {} oldest entries",
+                excess
+            );
+            self.entries.drain(..excess);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! The `rollback` subcommand, putting back the values the rrsets held
+//! before the changes recorded in the audit log, e.g. after a wrong `set`.
+
+use crate::audit::{AuditEntry, AuditLog, Outcome};
+use crate::config::{normalize_record_name, DnsConfig};
+use crate::dns_type::DnsType;
+use crate::drift::{same_set, WrittenValues};
+use crate::gandi::{get_gandi_record, update_gandi_rrset, GandiApi, RRSET_TTL};
+use clap::Args;
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    /// Only roll back the rrsets of this record
+    #[arg(long)]
+    record: Option<String>,
+    /// Number of changes of each rrset to undo
+    #[arg(long, value_name = "N", default_value_t = 1,
+          value_parser = clap::value_parser!(u32).range(1..))]
+    steps: u32,
+    /// Roll back even rrsets someone else changed since the client last
+    /// wrote them
+    #[arg(long)]
+    force: bool,
+}
+
+/// The values to put back into one rrset.
+#[derive(Debug)]
+struct Restore {
+    record: String,
+    dns_type: DnsType,
+    /// What the client last wrote, and the rrset should thus hold.
+    written: String,
+    values: Vec<String>,
+    ttl: Option<u32>,
+}
+
+/// What undoing `steps` changes of each rrset of `domain` puts back, from
+/// the entries of the audit log in file order. Earlier rollbacks do not
+/// count as changes.
+fn plan(
+    entries: Vec<AuditEntry>,
+    domain: &str,
+    record: Option<&str>,
+    steps: usize,
+) -> Result<Vec<Restore>, String> {
+    let mut history: BTreeMap<(String, String), Vec<AuditEntry>> = BTreeMap::new();
+    for entry in entries {
+        let relevant = entry.domain == domain
+            && record.is_none_or(|record| entry.record == record)
+            && matches!(entry.outcome, Outcome::Changed | Outcome::RolledBack);
+        if relevant {
+            history
+                .entry((entry.record.clone(), entry.dns_type.clone()))
+                .or_default()
+                .push(entry);
+        }
+    }
+    if history.is_empty() {
+        return Err(match record {
+            Some(record) => format!("the audit log holds no change of {}", record),
+            None => format!("the audit log holds no change of {}", domain),
+        });
+    }
+
+    let mut restores = Vec::new();
+    for ((record, dns_type), entries) in history {
+        let written = entries.last().map(|e| e.new.clone()).unwrap_or_default();
+        let changes: Vec<&AuditEntry> = entries
+            .iter()
+            .filter(|e| e.outcome == Outcome::Changed)
+            .collect();
+        let Some(change) = changes.len().checked_sub(steps).map(|index| changes[index]) else {
+            warn!(
+                "Skipping {}/{}, the audit log only holds {} change(s) of it",
+                record,
+                dns_type,
+                changes.len()
+            );
+            continue;
+        };
+        let Some(values) = change.old.clone().filter(|old| !old.is_empty()) else {
+            warn!(
+                "Skipping {}/{}, the audit log does not tell what it held before {}",
+                record, dns_type, change.timestamp
+            );
+            continue;
+        };
+        let Ok(dns_type) = dns_type.parse() else {
+            warn!("Skipping {}/{}, an unknown type", record, dns_type);
+            continue;
+        };
+        restores.push(Restore {
+            record,
+            dns_type,
+            written,
+            values,
+            ttl: change.old_ttl,
+        });
+    }
+    if restores.is_empty() {
+        return Err("there is nothing to roll back".to_string());
+    }
+    Ok(restores)
+}
+
+/// Asks `question` on the terminal, returning whether it was answered yes.
+pub fn confirm(question: &str) -> io::Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Shows what the rollback puts back and, once confirmed or if `yes`,
+/// writes it, recording it in the audit log.
+pub async fn run(
+    args: &RollbackArgs,
+    config: &DnsConfig,
+    api: &GandiApi,
+    yes: bool,
+) -> Result<(), Box<dyn Error>> {
+    let log = AuditLog::new(&config.state_dir);
+    let Some(lines) = log.read()? else {
+        return Err(format!("no audit log exists yet at {}", log.path().display()).into());
+    };
+    let mut entries = Vec::new();
+    for line in lines {
+        match line? {
+            Ok(entry) => entries.push(entry),
+            Err(corrupt) => warn!(
+                "Skipping corrupt line {} of {}: {}",
+                corrupt.line,
+                log.path().display(),
+                corrupt.error
+            ),
+        }
+    }
+    let record = args
+        .record
+        .as_deref()
+        .map(|record| normalize_record_name(record, &config.domain));
+    let restores = plan(
+        entries,
+        &config.domain,
+        record.as_deref(),
+        args.steps as usize,
+    )?;
+
+    // The rrsets are checked to still hold what the client last wrote.
+    let mut steps = Vec::new();
+    let mut drifted = 0;
+    for restore in restores {
+        let current =
+            get_gandi_record(&config.domain, &restore.record, restore.dns_type, api).await?;
+        let (values, ttl) = match current {
+            Some(rrset) => (rrset.values, Some(rrset.ttl)),
+            None => (Vec::new(), None),
+        };
+        if !same_set(&values, &restore.written) {
+            let shown = if values.is_empty() {
+                "nothing".to_string()
+            } else {
+                values.join(",")
+            };
+            if args.force {
+                warn!(
+                    "{}/{} holds {} instead of {}, rolling it back anyway",
+                    restore.record, restore.dns_type, shown, restore.written
+                );
+            } else {
+                error!(
+                    "{}/{} holds {} instead of {}, which the client last wrote",
+                    restore.record, restore.dns_type, shown, restore.written
+                );
+                drifted += 1;
+            }
+        }
+        steps.push((restore, values, ttl));
+    }
+    if drifted > 0 {
+        return Err(format!(
+            "{} rrset(s) changed since the client last wrote them; pass --force to roll back anyway",
+            drifted
+        )
+        .into());
+    }
+
+    for (restore, current, current_ttl) in &steps {
+        println!(
+            "{}/{}: {} -> {} (TTL {}s)",
+            restore.record,
+            restore.dns_type,
+            current.join(","),
+            restore.values.join(","),
+            restore.ttl.or(*current_ttl).unwrap_or(RRSET_TTL)
+        );
+    }
+    if !yes && !confirm("Roll back these rrsets?")? {
+        info!("Nothing rolled back");
+        return Ok(());
+    }
+
+    let mut audit = Vec::new();
+    let mut restored = Vec::new();
+    let mut failed = 0;
+    for (restore, current, current_ttl) in steps {
+        let ttl = restore.ttl.or(current_ttl).unwrap_or(RRSET_TTL);
+        let value = restore.values.join(",");
+        let result = update_gandi_rrset(
+            &config.domain,
+            &restore.record,
+            restore.dns_type,
+            &restore.values,
+            ttl,
+            api,
+        )
+        .await;
+        let outcome = match &result {
+            Ok(_) => Outcome::RolledBack,
+            Err(_) => Outcome::Failed,
+        };
+        let mut entry = AuditEntry::new(
+            &config.domain,
+            &restore.record,
+            restore.dns_type.as_str(),
+            Some(current),
+            &value,
+            outcome,
+        );
+        entry.old_ttl = current_ttl;
+        entry.comment = config.comment(&restore.record).map(str::to_string);
+        match result {
+            Ok(_) => {
+                info!(
+                    "Rolled back {}/{} to {}",
+                    restore.record, restore.dns_type, value
+                );
+                restored.push((restore.record, restore.dns_type, value));
+            }
+            Err(e) => {
+                error!(
+                    "Unable to roll back {}/{}: {}",
+                    restore.record, restore.dns_type, e
+                );
+                entry.error = Some(e.to_string());
+                failed += 1;
+            }
+        }
+        audit.push(entry);
+    }
+
+    if let Err(e) = log.append(&audit) {
+        warn!(
+            "Unable to write the audit log {}: {}",
+            log.path().display(),
+            e
+        );
+    }
+    let mut written = WrittenValues::load(&config.state_dir);
+    written.record(&restored, false);
+    if let Err(e) = written.save() {
+        warn!("Unable to save the values written: {}", e);
+    }
+    if failed > 0 {
+        return Err(format!("{} rollback(s) failed", failed).into());
+    }
+    Ok(())
+}
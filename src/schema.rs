@@ -0,0 +1,516 @@
+//! Checking the configuration file for unknown and misplaced keys, which
+//! would otherwise be silently ignored.
+
+use crate::message_templates;
+use std::fmt;
+use toml::de::{DeTable, DeValue};
+
+/// The sections of the configuration file and their keys.
+pub const SECTIONS: &[(&str, &[&str])] = &[
+    (
+        "GANDI",
+        &[
+            "key",
+            "key_file",
+            "key_source",
+            "keyring_service",
+            "keyring_account",
+            "sharing_id",
+            "auth_method",
+            "auth_scheme",
+            "key_expires",
+            "expiry_warning_days",
+        ],
+    ),
+    (
+        "DNS",
+        &[
+            "domain",
+            "domains",
+            "records",
+            "records_file",
+            "ip_version",
+            "update_types",
+            "remove",
+            "confirm_deletes",
+            "heartbeat_record",
+            "include_apex",
+            "exclude_apex",
+        ],
+    ),
+    ("RUN", &["max_run_duration"]),
+    (
+        "HTTP",
+        &[
+            "gandi_request_interval",
+            "max_concurrent_requests",
+            "quota_warning_threshold",
+            "rate_limit_header_prefix",
+            "connect_timeout",
+            "request_timeout",
+            "ip_detection_timeout",
+            "api_call_timeout",
+            "ca_cert",
+            "danger_accept_invalid_certs",
+            "gandi_spki_pins",
+        ],
+    ),
+    ("STATE", &["dir", "metrics_file"]),
+    ("RETRY", &["queue_depth"]),
+    (
+        "UPDATE",
+        &[
+            "use_batch",
+            "on_drift",
+            "change_debounce_s",
+            "precheck",
+            "precheck_resolver",
+            "ttl_min",
+            "ttl_max",
+            "ttl_window",
+        ],
+    ),
+    (
+        "DAEMON",
+        &[
+            "interval",
+            "config_poll_interval",
+            "control_socket",
+            "pause_file",
+            "healthy_while_paused",
+        ],
+    ),
+    ("GUARD", &["gateway_mac", "prefixes", "ssids"]),
+    (
+        "NOTIFY",
+        &[
+            "webhook_url",
+            "webhook_on_change",
+            "webhook_on_failure",
+            "webhook_on_error",
+            "ntfy",
+            "gotify",
+            "push",
+            "telegram",
+            "discord",
+            "matrix",
+            "apprise",
+            "desktop",
+            "digest_interval",
+            "throttle_interval",
+            "templates",
+        ],
+    ),
+    ("WATCHDOG", &["interval", "grace", "resolver", "reassert"]),
+    ("TELEMETRY", &["otlp_endpoint"]),
+    ("DETECTION", DETECTION_KEYS),
+];
+
+/// Other names of the sections, e.g. the `[notifications]` of the
+/// documentation of the channels.
+pub const SECTION_ALIASES: &[(&str, &str)] = &[("notifications", "NOTIFY")];
+
+/// The tables nested in the sections and their keys.
+const TABLES: &[(&str, &[&str])] = &[
+    ("DNS.domains", DOMAINS_KEYS),
+    ("NOTIFY.ntfy", NTFY_KEYS),
+    ("NOTIFY.gotify", GOTIFY_KEYS),
+    ("NOTIFY.push", PUSH_KEYS),
+    ("NOTIFY.telegram", TELEGRAM_KEYS),
+    ("NOTIFY.discord", DISCORD_KEYS),
+    ("NOTIFY.matrix", MATRIX_KEYS),
+    ("NOTIFY.apprise", APPRISE_KEYS),
+    ("NOTIFY.templates", message_templates::KINDS),
+];
+
+/// Keys of `DETECTION`, which its named sources share.
+const DETECTION_KEYS: &[&str] = &[
+    "ipv4_bind_address",
+    "ipv4_bind_interface",
+    "ipv6_bind_address",
+    "ipv6_bind_interface",
+    "fritzbox_address",
+    "fritzbox_username",
+    "fritzbox_password",
+    "fritzbox_ipv6_suffix",
+    "gateway",
+    "gateway_address",
+    "cloud",
+    "cloud_metadata_url",
+    "ipv4",
+    "ipv6",
+];
+
+/// Keys of the `ipv4` and `ipv6` tables of a detection source.
+const FAMILY_KEYS: &[&str] = &[
+    "providers",
+    "method",
+    "timeout",
+    "bind_address",
+    "bind_interface",
+    "headers",
+    "prefix_len",
+    "prefix_lease_file",
+    "prefix_interface",
+];
+
+/// Keys of the `NOTIFY.ntfy` table.
+const NTFY_KEYS: &[&str] = &[
+    "server",
+    "topic",
+    "token",
+    "priority",
+    "on_change",
+    "on_failure",
+    "on_error",
+];
+
+/// Keys of the `NOTIFY.gotify` table.
+const GOTIFY_KEYS: &[&str] = &[
+    "server",
+    "token",
+    "priority",
+    "on_change",
+    "on_failure",
+    "on_error",
+];
+
+/// Keys of the `NOTIFY.push` table, for either an ntfy or a Gotify server.
+const PUSH_KEYS: &[&str] = &[
+    "type",
+    "url",
+    "token",
+    "priority",
+    "on_change",
+    "on_failure",
+    "on_error",
+];
+
+/// Keys of the `DNS.domains` table.
+const DOMAINS_KEYS: &[&str] = &["discover", "include", "exclude"];
+
+/// Keys of the `NOTIFY.discord` table.
+const DISCORD_KEYS: &[&str] = &[
+    "webhook_url",
+    "username",
+    "avatar_url",
+    "on_change",
+    "on_failure",
+    "on_error",
+];
+
+/// Keys of the `NOTIFY.matrix` table.
+const MATRIX_KEYS: &[&str] = &[
+    "homeserver_url",
+    "access_token",
+    "room_id",
+    "on_change",
+    "on_failure",
+    "on_error",
+];
+
+/// Keys of the `NOTIFY.apprise` table.
+const APPRISE_KEYS: &[&str] = &["urls", "apprise_url", "on_change", "on_failure", "on_error"];
+
+/// Keys of the `NOTIFY.telegram` table.
+const TELEGRAM_KEYS: &[&str] = &[
+    "bot_token",
+    "chat_id",
+    "on_change",
+    "on_failure",
+    "on_error",
+];
+
+/// Keys of the table entries of `DNS.records`.
+const RECORD_KEYS: &[&str] = &[
+    "name",
+    "source",
+    "sources",
+    "on_source_failure",
+    "ip_version",
+    "comment",
+    "allow_overwrite",
+    "host_part",
+    "type",
+    "template",
+];
+
+/// A key of the configuration file that is not read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub path: String,
+    pub line: usize,
+    hint: Option<String>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: unknown key {}", self.line, self.path)?;
+        if let Some(hint) = &self.hint {
+            write!(f, " ({})", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of single-character edits turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The candidate closest to `key`, if it is close enough to be a typo.
+fn closest<'a>(key: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let key = key.to_ascii_lowercase();
+    candidates
+        .iter()
+        .map(|candidate| {
+            (
+                edit_distance(&key, &candidate.to_ascii_lowercase()),
+                *candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= (key.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Suggests what was meant by an unknown key of `section`: a similar key
+/// of the same section, or the section the key belongs in.
+fn key_hint(section: &str, key: &str, keys: &[&str]) -> Option<String> {
+    if let Some(candidate) = closest(key, keys) {
+        return Some(format!("did you mean {}.{}?", section, candidate));
+    }
+    SECTIONS
+        .iter()
+        .find(|(_, keys)| keys.contains(&key))
+        .map(|(other, _)| format!("it belongs in [{}]", other))
+}
+
+struct Checker<'a> {
+    contents: &'a str,
+    unknown: Vec<UnknownKey>,
+}
+
+impl Checker<'_> {
+    fn line(&self, span: std::ops::Range<usize>) -> usize {
+        self.contents[..span.start.min(self.contents.len())]
+            .matches('\n')
+            .count()
+            + 1
+    }
+
+    fn report(&mut self, path: String, span: std::ops::Range<usize>, hint: Option<String>) {
+        let line = self.line(span);
+        self.unknown.push(UnknownKey { path, line, hint });
+    }
+
+    /// Checks the keys of `table`, found at `path`.
+    fn check_table(&mut self, path: &str, table: &DeTable<'_>, keys: &[&str]) {
+        for (key, value) in table {
+            let name: &str = key.get_ref();
+            if !keys.contains(&name) {
+                let hint = key_hint(path, name, keys);
+                self.report(format!("{}.{}", path, name), key.span(), hint);
+                continue;
+            }
+
+            let nested = format!("{}.{}", path, name);
+            if let (true, "ipv4" | "ipv6", DeValue::Table(family)) =
+                (path.starts_with("DETECTION"), name, value.get_ref())
+            {
+                self.check_table(&nested, family, FAMILY_KEYS);
+            }
+            if let (Some((_, keys)), DeValue::Table(table)) = (
+                TABLES.iter().find(|(table, _)| *table == nested),
+                value.get_ref(),
+            ) {
+                self.check_table(&nested, table, keys);
+            }
+            if let ("NOTIFY.templates", DeValue::Table(parts)) = (path, value.get_ref()) {
+                self.check_table(&nested, parts, message_templates::PARTS);
+            }
+            if let ("DNS", "records", DeValue::Array(entries)) = (path, name, value.get_ref()) {
+                for (index, entry) in entries.iter().enumerate() {
+                    if let DeValue::Table(entry) = entry.get_ref() {
+                        let path = format!("DNS.records[{}]", index);
+                        self.check_table(&path, entry, RECORD_KEYS);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_detection(&mut self, table: &DeTable<'_>) {
+        for (key, value) in table {
+            let name: &str = key.get_ref();
+            match value.get_ref() {
+                DeValue::Table(family) if name == "ipv4" || name == "ipv6" => {
+                    self.check_table(&format!("DETECTION.{}", name), family, FAMILY_KEYS)
+                }
+                DeValue::Table(source) => {
+                    self.check_table(&format!("DETECTION.{}", name), source, DETECTION_KEYS)
+                }
+                _ if DETECTION_KEYS.contains(&name) => {}
+                _ => {
+                    let hint = key_hint("DETECTION", name, DETECTION_KEYS);
+                    self.report(format!("DETECTION.{}", name), key.span(), hint);
+                }
+            }
+        }
+    }
+}
+
+/// Lists the keys of a TOML configuration file that are not read. Returns
+/// an empty list if the file cannot be parsed, leaving syntax errors to the
+/// configuration reader.
+pub fn unknown_keys(contents: &str) -> Vec<UnknownKey> {
+    let Ok(root) = DeTable::parse(contents) else {
+        return Vec::new();
+    };
+    let sections: Vec<&str> = SECTIONS.iter().map(|(name, _)| *name).collect();
+    let mut checker = Checker {
+        contents,
+        unknown: Vec::new(),
+    };
+
+    for (key, value) in root.get_ref() {
+        let name: &str = key.get_ref();
+        let name = SECTION_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map_or(name, |(_, section)| section);
+        let section = SECTIONS.iter().find(|(section, _)| *section == name);
+        match (section, value.get_ref()) {
+            (Some(("DETECTION", _)), DeValue::Table(table)) => checker.check_detection(table),
+            (Some((section, keys)), DeValue::Table(table)) => {
+                checker.check_table(section, table, keys)
+            }
+            // A value of the wrong type is reported by the reader.
+            (Some(_), _) => {}
+            (None, DeValue::Table(_)) => {
+                let hint = closest(name, &sections).map(|s| format!("did you mean [{}]?", s));
+                checker.report(name.to_string(), key.span(), hint);
+            }
+            (None, _) => {
+                let hint = SECTIONS
+                    .iter()
+                    .find(|(_, keys)| keys.contains(&name))
+                    .map(|(section, _)| format!("it belongs in [{}]", section));
+                checker.report(name.to_string(), key.span(), hint);
+            }
+        }
+    }
+    checker.unknown.sort_by_key(|key| key.line);
+    checker.unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the schema knows the key at `path`, e.g. `NOTIFY.ntfy.topic`.
+    fn known(path: &str) -> bool {
+        let (table, key) = path.rsplit_once('.').unwrap();
+        SECTIONS
+            .iter()
+            .chain(TABLES)
+            .find(|(name, _)| *name == table)
+            .is_some_and(|(_, keys)| keys.contains(&key))
+    }
+
+    #[test]
+    fn knows_every_key_the_configuration_reads() {
+        let source = include_str!("config.rs");
+        let mut read = 0;
+        for literal in source.split('"').skip(1).step_by(2) {
+            let Some((section, _)) = literal.split_once('.') else {
+                continue;
+            };
+            let is_key = literal
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+            if !is_key || !SECTIONS.iter().any(|(name, _)| *name == section) {
+                continue;
+            }
+            assert!(known(literal), "{} is read but not in the schema", literal);
+            read += 1;
+        }
+        assert!(read > 50, "only found {} keys", read);
+
+        // The keys of the detection sources are read under their names.
+        for literal in source.split("\"{}.").skip(1) {
+            let key: String = literal
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect();
+            if key.is_empty() || key.ends_with('_') {
+                continue;
+            }
+            assert!(
+                DETECTION_KEYS.contains(&key.as_str()) || FAMILY_KEYS.contains(&key.as_str()),
+                "DETECTION.*.{} is read but not in the schema",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn takes_the_other_names_of_the_sections_and_keys() {
+        let unknown = unknown_keys(
+            r#"
+[notifications.push]
+type = "ntfy"
+url = "https://ntfy.sh/ddns"
+on_error = false
+
+[notifications.telegram]
+bot_token = "123456:ABC"
+chat_id = 1
+on_eror = false
+"#,
+        );
+        let found: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            found,
+            ["line 10: unknown key NOTIFY.telegram.on_eror (did you mean NOTIFY.telegram.on_error?)"]
+        );
+    }
+
+    #[test]
+    fn reports_unknown_and_misplaced_keys() {
+        let unknown = unknown_keys(
+            r#"
+[GANDI]
+Key = "0123456789abcdef01234567"
+records = ["www"]
+
+[NOTIFY.ntfy]
+topik = "ddns"
+
+[DNS]
+domain = "example.com"
+records = [{ name = "www", sorce = "lan" }]
+"#,
+        );
+        let found: Vec<String> = unknown.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            found,
+            [
+                "line 3: unknown key GANDI.Key (did you mean GANDI.key?)",
+                "line 4: unknown key GANDI.records (it belongs in [DNS])",
+                "line 7: unknown key NOTIFY.ntfy.topik (did you mean NOTIFY.ntfy.topic?)",
+                "line 11: unknown key DNS.records[0].sorce (did you mean DNS.records[0].source?)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Keeping the API key in the secret store of the OS: the Secret Service
+//! (e.g. GNOME Keyring) on Linux, the Keychain on macOS and the Credential
+//! Manager on Windows.
+
+use std::error::Error;
+
+pub const DEFAULT_SERVICE: &str = "ddns-gandi";
+pub const DEFAULT_ACCOUNT: &str = "api-key";
+
+/// Where the key is stored in the secret store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyringEntry {
+    pub service: String,
+    pub account: String,
+}
+
+impl Default for KeyringEntry {
+    fn default() -> Self {
+        KeyringEntry {
+            service: DEFAULT_SERVICE.to_string(),
+            account: DEFAULT_ACCOUNT.to_string(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const BACKEND: &str = "Secret Service";
+#[cfg(target_os = "macos")]
+const BACKEND: &str = "macOS Keychain";
+#[cfg(windows)]
+const BACKEND: &str = "Windows Credential Manager";
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+const BACKEND: &str = "secret store";
+
+impl KeyringEntry {
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    fn entry(&self) -> Result<keyring::Entry, String> {
+        keyring::Entry::new(&self.service, &self.account).map_err(|e| self.error(e))
+    }
+
+    /// Without a native store, the keyring crate would only keep the key
+    /// in memory.
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    fn entry(&self) -> Result<keyring::Entry, String> {
+        Err("no secret store is supported on this platform".to_string())
+    }
+
+    fn error(&self, e: keyring::Error) -> String {
+        match e {
+            keyring::Error::NoEntry => format!(
+                "the {} has no key for service '{}' and account '{}'; store it with `ddns-gandi set-key`",
+                BACKEND, self.service, self.account
+            ),
+            e => format!("unable to use the {}: {}", BACKEND, e),
+        }
+    }
+
+    pub fn read(&self) -> Result<String, String> {
+        let key = self.entry()?.get_password().map_err(|e| self.error(e))?;
+        Ok(key.trim().to_string())
+    }
+
+    pub fn store(&self, key: &str) -> Result<(), String> {
+        self.entry()?.set_password(key).map_err(|e| self.error(e))
+    }
+}
+
+/// Handles the `set-key` subcommand, prompting for the key without echoing
+/// it.
+pub fn handle(entry: &KeyringEntry) -> Result<(), Box<dyn Error>> {
+    let key = rpassword::prompt_password("Gandi API key: ")
+        .map_err(|e| format!("unable to read the key from the terminal: {}", e))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("the key is empty".into());
+    }
+    entry.store(key)?;
+    log::info!(
+        "Stored the key for service '{}' and account '{}'",
+        entry.service,
+        entry.account
+    );
+    Ok(())
+}
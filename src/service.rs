@@ -0,0 +1,275 @@
+//! Running as a native Windows service under the Service Control Manager.
+
+use crate::config::program_data_dir;
+use crate::wakeup;
+use crate::{create_api, load_config, run_daemon, Cli};
+use clap::{Parser, Subcommand};
+use log::{error, info, LevelFilter, Log, Metadata, Record};
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use windows_service::service::{
+    ServiceAccess, ServiceAction as FailureAction, ServiceActionType, ServiceControl,
+    ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceFailureActions,
+    ServiceFailureResetPeriod, ServiceInfo, ServiceStartType, ServiceState, ServiceStatus,
+    ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "ddns-gandi";
+const SERVICE_DISPLAY_NAME: &str = "Gandi DDNS client";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+/// How long after a failure the SCM restarts the service, the first three
+/// times in a day.
+const RESTART_DELAY: Duration = Duration::from_secs(60);
+const RESTART_RESET_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long the SCM is told a stop may take, the current pass finishing
+/// first.
+const STOP_WAIT_HINT: Duration = Duration::from_secs(60);
+/// The log of the service, which has no console, in the state directory.
+const LOG_FILE: &str = "ddns-gandi.log";
+
+#[derive(Debug, Subcommand)]
+pub enum ServiceAction {
+    /// Register the service, starting automatically with the configuration
+    /// file given with --config
+    Install,
+    /// Stop and remove the service
+    Uninstall,
+    /// Start the service
+    Start,
+    /// Stop the service
+    Stop,
+    /// Print the current state of the service
+    Status,
+    /// Run the daemon as the service (used by the Service Control Manager)
+    Run,
+}
+
+/// Runs `action`, setting up the logging first: to the console, or for
+/// `run` to the log file in `%ProgramData%\ddns-gandi`.
+pub fn handle(action: &ServiceAction, config: &Path) -> Result<(), Box<dyn Error>> {
+    match action {
+        ServiceAction::Run => FileLogger::install()?,
+        _ => simple_logger::init_with_level(log::Level::Info)?,
+    }
+    match action {
+        ServiceAction::Install => install(config),
+        ServiceAction::Uninstall => uninstall(),
+        ServiceAction::Start => {
+            let manager =
+                ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+            let service = manager.open_service(SERVICE_NAME, ServiceAccess::START)?;
+            service.start::<&OsStr>(&[])?;
+            Ok(())
+        }
+        ServiceAction::Stop => {
+            let manager =
+                ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+            let service = manager.open_service(SERVICE_NAME, ServiceAccess::STOP)?;
+            service.stop()?;
+            Ok(())
+        }
+        ServiceAction::Status => {
+            let manager =
+                ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+            let service = manager.open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS)?;
+            println!("{:?}", service.query_status()?.current_state);
+            Ok(())
+        }
+        ServiceAction::Run => {
+            // The dispatcher blocks until the service has stopped.
+            tokio::task::block_in_place(|| {
+                service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            })?;
+            Ok(())
+        }
+    }
+}
+
+fn install(config: &Path) -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    // Services start in the system directory, so the paths must be absolute.
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from(SERVICE_DISPLAY_NAME),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            std::path::absolute(config)?.into_os_string(),
+            OsString::from("service"),
+            OsString::from("run"),
+        ],
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+
+    let service =
+        manager.create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+    service.set_description("Keeps Gandi LiveDNS records pointed at the public IP addresses")?;
+    // Restarted after crashes and after stopping with an error alike.
+    service.update_failure_actions(ServiceFailureActions {
+        reset_period: ServiceFailureResetPeriod::After(RESTART_RESET_PERIOD),
+        reboot_msg: None,
+        command: None,
+        actions: Some(vec![
+            FailureAction {
+                action_type: ServiceActionType::Restart,
+                delay: RESTART_DELAY,
+            };
+            3
+        ]),
+    })?;
+    service.set_failure_actions_on_non_crash_failures(true)?;
+    info!("Installed the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+fn uninstall() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        SERVICE_NAME,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    info!("Uninstalled the {} service", SERVICE_NAME);
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Critical Error: The service failed: {}", e);
+    }
+}
+
+fn status(state: ServiceState, controls_accepted: ServiceControlAccept) -> ServiceStatus {
+    ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::NO_ERROR,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+fn run_service() -> Result<(), Box<dyn Error>> {
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown | ServiceControl::Preshutdown => {
+                // Like SIGTERM: the daemon stops once the current pass is
+                // over.
+                wakeup::request_stop();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+
+    status_handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+    ))?;
+
+    // The launch arguments registered by `install` name the configuration.
+    let cli = Cli::parse();
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(async {
+        let config = load_config(&cli).await;
+        let api = create_api(&config)?;
+        run_daemon(&cli, config, api).await;
+        Ok::<_, Box<dyn Error>>(())
+    });
+    info!("Stopping the service ...");
+
+    status_handle.set_service_status(ServiceStatus {
+        wait_hint: STOP_WAIT_HINT,
+        ..status(ServiceState::StopPending, ServiceControlAccept::empty())
+    })?;
+    drop(runtime);
+    // A service stopping with an error is restarted by the SCM.
+    let exit_code = match &result {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+    status_handle.set_service_status(ServiceStatus {
+        exit_code,
+        ..status(ServiceState::Stopped, ServiceControlAccept::empty())
+    })?;
+    result
+}
+
+/// Appends the records to the log file of the service.
+struct FileLogger {
+    file: Mutex<File>,
+}
+
+impl FileLogger {
+    fn install() -> Result<(), Box<dyn Error>> {
+        let dir = program_data_dir().ok_or("%ProgramData% is not set")?;
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        log::set_boxed_logger(Box::new(FileLogger {
+            file: Mutex::new(file),
+        }))?;
+        log::set_max_level(LevelFilter::Info);
+        Ok(())
+    }
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(
+                file,
+                "{} {:<5} [{}] {}",
+                now,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
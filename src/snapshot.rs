@@ -0,0 +1,82 @@
+//! The `list-snapshots` and `restore-snapshot` subcommands, for the zone
+//! snapshots Gandi keeps.
+
+use crate::gandi::{get_snapshot, list_snapshots, restore_snapshot, GandiApi};
+use crate::rollback::confirm;
+use clap::Args;
+use log::{info, warn};
+use std::error::Error;
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// ID of the snapshot, as printed by `list-snapshots`
+    #[arg(long, value_name = "ID")]
+    snapshot_id: String,
+    /// Replace the zone without asking
+    #[arg(long)]
+    confirm: bool,
+}
+
+/// Prints the snapshots of the zone of `domain`, oldest first.
+pub async fn list(domain: &str, api: &GandiApi) -> Result<(), Box<dyn Error>> {
+    let mut snapshots = list_snapshots(domain, api).await?;
+    if snapshots.is_empty() {
+        info!("{} has no snapshot", domain);
+        return Ok(());
+    }
+    snapshots.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+
+    println!("{:<36}  {:<25}  {:>7}  NAME", "ID", "CREATED", "RECORDS");
+    for snapshot in snapshots {
+        // The listing leaves the rrsets out, so each snapshot is fetched.
+        let count = match get_snapshot(domain, &snapshot.id, api).await {
+            Ok(Some(full)) => full.record_count(),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Unable to get the snapshot {}: {}", snapshot.id, e);
+                None
+            }
+        };
+        let name = match (&snapshot.name, snapshot.automatic) {
+            (Some(name), _) => name.clone(),
+            (None, true) => "(automatic)".to_string(),
+            (None, false) => String::new(),
+        };
+        println!(
+            "{:<36}  {:<25}  {:>7}  {}",
+            snapshot.id,
+            snapshot.created_at,
+            count.map_or("?".to_string(), |count| count.to_string()),
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Replaces the zone of `domain` with a snapshot, once confirmed by
+/// `--confirm`, `yes` or on the terminal.
+pub async fn restore(
+    args: &RestoreArgs,
+    domain: &str,
+    api: &GandiApi,
+    yes: bool,
+) -> Result<(), Box<dyn Error>> {
+    let Some(snapshot) = get_snapshot(domain, &args.snapshot_id, api).await? else {
+        return Err(format!("{} has no snapshot {}", domain, args.snapshot_id).into());
+    };
+    let Some(count) = snapshot.record_count().filter(|count| *count > 0) else {
+        return Err(format!("the snapshot {} holds no rrset", snapshot.id).into());
+    };
+
+    println!(
+        "Replacing all rrsets of {} with the {} of the snapshot {} from {}",
+        domain, count, snapshot.id, snapshot.created_at
+    );
+    if !(args.confirm || yes || confirm("Restore this snapshot?")?) {
+        info!("Nothing restored");
+        return Ok(());
+    }
+    restore_snapshot(domain, &snapshot, api).await?;
+    info!("Restored the snapshot {} of {}", snapshot.id, domain);
+    Ok(())
+}
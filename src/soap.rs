@@ -0,0 +1,148 @@
+//! Building the SOAP requests of UPnP and TR-064 actions and reading their
+//! responses, which are simple enough not to need an XML parser.
+
+/// The SOAP request calling `action` of `service`, without arguments.
+pub fn envelope(service: &str, action: &str) -> String {
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>",
+            "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" ",
+            "s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">",
+            "<s:Body><u:{action} xmlns:u=\"{service}\"/></s:Body>",
+            "</s:Envelope>"
+        ),
+        action = action,
+        service = service
+    )
+}
+
+/// The text of the first element called `name` in `xml`, whatever its
+/// namespace prefix.
+pub fn element(xml: &str, name: &str) -> Result<String, String> {
+    find_element(xml, name).ok_or_else(|| format!("the response has no {}", name))
+}
+
+pub fn find_element(xml: &str, name: &str) -> Option<String> {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>')?;
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        let tag_name = tag.split_whitespace().next().unwrap_or_default();
+        let local = tag_name.rsplit(':').next().unwrap_or_default();
+        if local.trim_end_matches('/') != name || tag.starts_with('/') {
+            continue;
+        }
+        if tag.ends_with('/') {
+            return Some(String::new());
+        }
+        let close = rest.find("</")?;
+        return Some(unescape(rest[..close].trim()));
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The error described by a SOAP fault, if `xml` is one.
+pub fn fault(xml: &str) -> Option<String> {
+    let code = find_element(xml, "errorCode");
+    let description = find_element(xml, "errorDescription");
+    match (code, description) {
+        (Some(code), Some(description)) => Some(format!("{} ({})", description, code)),
+        (Some(code), None) => Some(format!("error {}", code)),
+        (None, _) => find_element(xml, "faultstring"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The answer of a FRITZ!Box to `GetExternalIPAddress`.
+    const EXTERNAL_IP: &str = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<u:GetExternalIPAddressResponse xmlns:u="urn:schemas-upnp-org:service:WANIPConnection:1">
+<NewExternalIPAddress>203.0.113.7</NewExternalIPAddress>
+</u:GetExternalIPAddressResponse>
+</s:Body>
+</s:Envelope>"#;
+
+    /// The answer of a FRITZ!Box to an action it does not know.
+    const FAULT: &str = r#"<?xml version="1.0"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">
+<s:Body>
+<s:Fault>
+<faultcode>s:Client</faultcode>
+<faultstring>UPnPError</faultstring>
+<detail>
+<UPnPError xmlns="urn:schemas-upnp-org:control-1-0">
+<errorCode>401</errorCode>
+<errorDescription>Invalid Action</errorDescription>
+</UPnPError>
+</detail>
+</s:Fault>
+</s:Body>
+</s:Envelope>"#;
+
+    #[test]
+    fn builds_the_envelope_of_an_action() {
+        let envelope = envelope(
+            "urn:schemas-upnp-org:service:WANIPConnection:1",
+            "GetExternalIPAddress",
+        );
+        assert!(envelope.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?><s:Envelope "));
+        assert!(envelope.contains(
+            "<s:Body><u:GetExternalIPAddress xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\"/></s:Body>"
+        ));
+        assert!(envelope.ends_with("</s:Envelope>"));
+    }
+
+    #[test]
+    fn reads_an_element_whatever_its_prefix() {
+        assert_eq!(
+            element(EXTERNAL_IP, "NewExternalIPAddress").unwrap(),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            find_element("<a:Value>1</a:Value><b:Value>2</b:Value>", "Value").as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            element(EXTERNAL_IP, "NewIPv6Prefix").unwrap_err(),
+            "the response has no NewIPv6Prefix"
+        );
+    }
+
+    #[test]
+    fn reads_empty_and_escaped_elements() {
+        assert_eq!(find_element("<Name/>", "Name").as_deref(), Some(""));
+        assert_eq!(
+            find_element("<Name>a &lt;b&gt; &amp;amp;</Name>", "Name").as_deref(),
+            Some("a <b> &amp;")
+        );
+        // Not the closing tag of another element of that name.
+        assert_eq!(
+            find_element("<Other></Name><Name>x</Name></Other>", "Name").as_deref(),
+            Some("x")
+        );
+    }
+
+    #[test]
+    fn describes_faults() {
+        assert_eq!(fault(FAULT).as_deref(), Some("Invalid Action (401)"));
+        assert_eq!(
+            fault("<s:Fault><faultstring>UPnPError</faultstring></s:Fault>").as_deref(),
+            Some("UPnPError")
+        );
+        assert_eq!(fault(EXTERNAL_IP), None);
+    }
+}
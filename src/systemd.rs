@@ -0,0 +1,147 @@
+//! Generating and installing systemd units for Linux.
+
+use clap::Args;
+use log::info;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const UNIT_NAME: &str = "ddns-gandi";
+const DEFAULT_UNIT_DIR: &str = "/etc/systemd/system";
+
+const SERVICE_TEMPLATE: &str = "[Unit]
+Description=Gandi LiveDNS dynamic DNS update
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+Type=simple
+EnvironmentFile=-{environment_file}
+ExecStart={binary_path} --config {config_path}
+Restart=on-failure
+RestartSec=30
+NoNewPrivileges=true
+ProtectSystem=strict
+PrivateTmp=true
+ReadWritePaths={state_dir}
+";
+
+const TIMER_TEMPLATE: &str = "[Unit]
+Description=Periodic Gandi LiveDNS dynamic DNS update
+
+[Timer]
+OnBootSec=1min
+OnUnitActiveSec={interval}s
+Unit={service_unit}
+
+[Install]
+WantedBy=timers.target
+";
+
+#[derive(Debug, Args)]
+pub struct SystemdArgs {
+    /// Seconds between two runs
+    #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+    interval: u32,
+    /// Where to write the service unit instead of printing both units; the
+    /// timer is written next to it (defaults to
+    /// /etc/systemd/system/ddns-gandi.service with --install)
+    #[arg(long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Optional file with environment variables for the service
+    #[arg(long, value_name = "FILE", default_value = "/etc/default/ddns-gandi")]
+    environment_file: PathBuf,
+    /// Write the units and enable the timer with `systemctl enable --now`
+    #[arg(long)]
+    install: bool,
+}
+
+/// Quotes a path for a unit file if it contains characters systemd would
+/// otherwise split or expand.
+fn quote(path: &Path) -> Result<String, Box<dyn Error>> {
+    let path = std::path::absolute(path)?.to_string_lossy().into_owned();
+    if path.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '%') {
+        let escaped = path
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('%', "%%");
+        Ok(format!("\"{}\"", escaped))
+    } else {
+        Ok(path)
+    }
+}
+
+fn render_service(
+    args: &SystemdArgs,
+    config: &Path,
+    state_dir: &Path,
+) -> Result<String, Box<dyn Error>> {
+    Ok(SERVICE_TEMPLATE
+        .replace(
+            "{environment_file}",
+            &std::path::absolute(&args.environment_file)?.to_string_lossy(),
+        )
+        .replace("{binary_path}", &quote(&env::current_exe()?)?)
+        .replace("{config_path}", &quote(config)?)
+        .replace("{state_dir}", &quote(state_dir)?))
+}
+
+fn render_timer(args: &SystemdArgs, service_unit: &str) -> String {
+    TIMER_TEMPLATE
+        .replace("{interval}", &args.interval.to_string())
+        .replace("{service_unit}", service_unit)
+}
+
+fn unit_file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn systemctl(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("systemctl").args(args).status()?;
+    if !status.success() {
+        return Err(format!("systemctl {} failed with {}", args.join(" "), status).into());
+    }
+    Ok(())
+}
+
+pub fn handle(args: &SystemdArgs, config: &Path, state_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let service = render_service(args, config, state_dir)?;
+
+    let service_path = match (&args.output, args.install) {
+        (Some(path), _) => path.clone(),
+        (None, true) => Path::new(DEFAULT_UNIT_DIR).join(format!("{}.service", UNIT_NAME)),
+        (None, false) => {
+            let timer = render_timer(args, &format!("{}.service", UNIT_NAME));
+            println!("# {}.service\n{}", UNIT_NAME, service);
+            print!("# {}.timer\n{}", UNIT_NAME, timer);
+            return Ok(());
+        }
+    };
+    let timer_path = service_path.with_extension("timer");
+    let timer = render_timer(args, &unit_file_name(&service_path));
+
+    if let Some(dir) = service_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&service_path, service)?;
+    fs::write(&timer_path, timer)?;
+    info!(
+        "Wrote {} and {}",
+        service_path.display(),
+        timer_path.display()
+    );
+
+    if args.install {
+        // ReadWritePaths= fails the unit if the directory does not exist.
+        fs::create_dir_all(state_dir)?;
+        let timer_unit = unit_file_name(&timer_path);
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", &timer_unit])?;
+        info!("Enabled {}", timer_unit);
+    }
+    Ok(())
+}
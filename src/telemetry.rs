@@ -0,0 +1,317 @@
+//! `TELEMETRY.otlp_endpoint`: a trace of every pass exported with OTLP over
+//! HTTP, e.g. to Tempo, if built with the `otlp` feature. The pass is the
+//! root span, with one span per detection attempt and Gandi API call below
+//! it. Without an endpoint, nothing is installed and the spans cost nothing.
+
+use std::future::Future;
+
+/// The collector the traces are exported to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelemetryConfig {
+    /// E.g. `http://tempo:4318`, the traces being posted to `/v1/traces`
+    /// unless the URL has a path of its own.
+    pub otlp_endpoint: String,
+}
+
+impl TelemetryConfig {
+    /// The URL the traces are posted to.
+    #[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+    fn traces_url(&self) -> String {
+        match reqwest::Url::parse(&self.otlp_endpoint) {
+            Ok(url) if url.path() == "/" && url.query().is_none() => {
+                format!("{}/v1/traces", self.otlp_endpoint.trim_end_matches('/'))
+            }
+            _ => self.otlp_endpoint.clone(),
+        }
+    }
+}
+
+/// The value of a span attribute.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "otlp"), allow(dead_code))]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<u16> for AttributeValue {
+    fn from(value: u16) -> Self {
+        AttributeValue::Int(i64::from(value))
+    }
+}
+
+impl From<usize> for AttributeValue {
+    fn from(value: usize) -> Self {
+        AttributeValue::Int(i64::try_from(value).unwrap_or(i64::MAX))
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use super::{AttributeValue, TelemetryConfig};
+    use log::{info, warn};
+    use opentelemetry::trace::{FutureExt, Status, TraceContextExt, Tracer};
+    use opentelemetry::{global, Context, KeyValue, Value};
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use std::future::Future;
+    use std::sync::OnceLock;
+
+    const TRACER: &str = env!("CARGO_PKG_NAME");
+
+    /// Set once the exporter is installed, which is also what tells whether
+    /// the spans are recorded at all.
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    impl From<AttributeValue> for Value {
+        fn from(value: AttributeValue) -> Self {
+            match value {
+                AttributeValue::String(value) => value.into(),
+                AttributeValue::Int(value) => value.into(),
+            }
+        }
+    }
+
+    pub fn init(config: &TelemetryConfig) {
+        let url = config.traces_url();
+        let exporter = match SpanExporter::builder()
+            .with_http()
+            .with_endpoint(url.as_str())
+            .build()
+        {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                warn!("Unable to export the traces to {}: {}", url, e);
+                return;
+            }
+        };
+        // The batches are exported from a thread of their own, so that a
+        // slow collector does not hold up the passes.
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(TRACER).build())
+            .build();
+        global::set_tracer_provider(provider.clone());
+        if PROVIDER.set(provider).is_ok() {
+            info!("Exporting the traces to {}", url);
+        }
+    }
+
+    pub async fn shutdown() {
+        let Some(provider) = PROVIDER.get().cloned() else {
+            return;
+        };
+        // Waits for the last batch to be exported.
+        match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+            Ok(Err(e)) => warn!("Unable to export the last traces: {}", e),
+            Err(e) => warn!("Unable to export the last traces: {}", e),
+            Ok(Ok(())) => {}
+        }
+    }
+
+    pub async fn in_span<F: Future>(
+        name: &'static str,
+        attributes: Vec<(&'static str, AttributeValue)>,
+        future: F,
+    ) -> F::Output {
+        if PROVIDER.get().is_none() {
+            return future.await;
+        }
+        let tracer = global::tracer(TRACER);
+        let span = tracer
+            .span_builder(name)
+            .with_attributes(
+                attributes
+                    .into_iter()
+                    .map(|(key, value)| KeyValue::new(key, value)),
+            )
+            .start(&tracer);
+        // The span ends once the future holding the context is dropped.
+        future.with_context(Context::current_with_span(span)).await
+    }
+
+    pub fn record(key: &'static str, value: AttributeValue) {
+        if PROVIDER.get().is_some() {
+            Context::current()
+                .span()
+                .set_attribute(KeyValue::new(key, value));
+        }
+    }
+
+    pub fn fail(description: String) {
+        if PROVIDER.get().is_some() {
+            Context::current()
+                .span()
+                .set_status(Status::error(description));
+        }
+    }
+}
+
+/// Installs the exporter if `config` is set.
+pub fn init(config: Option<&TelemetryConfig>) {
+    let Some(config) = config else {
+        return;
+    };
+    #[cfg(feature = "otlp")]
+    otlp::init(config);
+    #[cfg(not(feature = "otlp"))]
+    log::warn!(
+        "TELEMETRY.otlp_endpoint is set to {}, but ddns-gandi was built without the otlp feature",
+        config.otlp_endpoint
+    );
+}
+
+/// Exports the spans not exported yet, which is to be waited for before
+/// the process exits, whether the pass succeeded or not.
+pub async fn shutdown() {
+    #[cfg(feature = "otlp")]
+    otlp::shutdown().await;
+}
+
+/// Runs `future` in a span named `name`, below the span it is run in.
+pub async fn in_span<F: Future>(
+    name: &'static str,
+    attributes: Vec<(&'static str, AttributeValue)>,
+    future: F,
+) -> F::Output {
+    #[cfg(feature = "otlp")]
+    return otlp::in_span(name, attributes, future).await;
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = (name, attributes);
+        future.await
+    }
+}
+
+/// Sets an attribute of the span this is run in. No header of a request
+/// is ever recorded, the credentials being among them.
+pub fn record(key: &'static str, value: impl Into<AttributeValue>) {
+    #[cfg(feature = "otlp")]
+    otlp::record(key, value.into());
+    #[cfg(not(feature = "otlp"))]
+    let _ = (key, value);
+}
+
+/// Marks the span this is run in as failed.
+pub fn fail(description: impl std::fmt::Display) {
+    #[cfg(feature = "otlp")]
+    otlp::fail(description.to_string());
+    #[cfg(not(feature = "otlp"))]
+    let _ = description;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn traces_url(endpoint: &str) -> String {
+        TelemetryConfig {
+            otlp_endpoint: endpoint.to_string(),
+        }
+        .traces_url()
+    }
+
+    #[test]
+    fn posts_the_traces_to_the_path_of_the_endpoint() {
+        assert_eq!(
+            traces_url("http://tempo:4318"),
+            "http://tempo:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("http://tempo:4318/"),
+            "http://tempo:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_url("https://otlp.example.com/otlp/v1/traces"),
+            "https://otlp.example.com/otlp/v1/traces"
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_the_spans_without_an_exporter() {
+        let output = in_span("pass", vec![("domain", "example.com".into())], async {
+            record("http.response.status_code", 200u16);
+            fail("unreachable");
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+    }
+
+    /// Exports a pass to a local collector, which is the only test
+    /// installing the exporter since it stays for the whole process.
+    #[cfg(feature = "otlp")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_the_spans_to_the_collector() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            // The body follows the head, whose end tells its length.
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                let length = text
+                    .to_ascii_lowercase()
+                    .split("content-length:")
+                    .nth(1)
+                    .and_then(|rest| rest.lines().next())
+                    .and_then(|value| value.trim().parse::<usize>().ok());
+                let whole = match (text.find("\r\n\r\n"), length) {
+                    (Some(end), Some(length)) => request.len() >= end + 4 + length,
+                    _ => false,
+                };
+                if n == 0 || whole {
+                    let _ = sender.send(text);
+                    break;
+                }
+            }
+            let response = "HTTP/1.1 200 OK\r\ncontent-type: application/x-protobuf\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+
+        init(Some(&TelemetryConfig {
+            otlp_endpoint: format!("http://{}", addr),
+        }));
+        in_span("pass", vec![("domain", "example.com".into())], async {
+            in_span("gandi_api", Vec::new(), async {
+                record("http.response.status_code", 201u16);
+            })
+            .await;
+        })
+        .await;
+        shutdown().await;
+
+        let request = tokio::time::timeout(std::time::Duration::from_secs(5), receiver)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(request.starts_with("POST /v1/traces "), "{}", request);
+        assert!(request.contains("application/x-protobuf"), "{}", request);
+        // The names of the spans and the service are strings of the body.
+        assert!(request.contains("gandi_api"), "{}", request);
+        assert!(request.contains(env!("CARGO_PKG_NAME")), "{}", request);
+    }
+}
@@ -0,0 +1,262 @@
+//! TXT rrsets rendered from a template embedding the detected addresses,
+//! e.g. an SPF record `v=spf1 ip4:{ipv4} ~all`, and with the
+//! `extended-records` feature SRV, TLSA and SSHFP rrsets.
+
+use crate::dns_type::DnsType;
+use crate::ip::{IpVersion, IpVersionFilter};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Longest character-string of a TXT record, in bytes.
+const MAX_STRING_LEN: usize = 255;
+
+/// The types rendered from a template besides TXT, with the
+/// `extended-records` feature.
+pub const EXTENDED_TYPES: [DnsType; 3] = [DnsType::Srv, DnsType::Tlsa, DnsType::Sshfp];
+
+/// A value of a template replaced when it is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placeholder {
+    Ipv4,
+    Ipv6,
+    /// When the value was written, which alone does not cause an update.
+    Timestamp,
+}
+
+impl Placeholder {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ipv4" => Some(Placeholder::Ipv4),
+            "ipv6" => Some(Placeholder::Ipv6),
+            "timestamp" => Some(Placeholder::Timestamp),
+            _ => None,
+        }
+    }
+}
+
+/// A piece of a template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// The addresses a template is rendered with.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Addresses<'a> {
+    pub ipv4: Option<&'a str>,
+    pub ipv6: Option<&'a str>,
+}
+
+/// An rrset of `DNS.records` whose value is rendered from `template`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateRecord {
+    pub name: String,
+    /// TXT, or one of [`EXTENDED_TYPES`].
+    pub dns_type: DnsType,
+    /// Subsection of `DETECTION` the addresses are detected with, the
+    /// default detection settings if `None`.
+    pub source: Option<String>,
+    pub template: String,
+    /// What the record is for, only shown to the operators.
+    pub comment: Option<String>,
+}
+
+/// Splits `template` into its text and placeholders, `{{` and `}}` being
+/// literal braces.
+fn parse(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let name: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                let placeholder = Placeholder::parse(name.trim())
+                    .ok_or_else(|| format!("unknown placeholder {{{}}}", name))?;
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Placeholder(placeholder));
+            }
+            '}' => return Err("unmatched '}', write '}}' for a brace".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    Ok(parts)
+}
+
+impl TemplateRecord {
+    /// Checks the template, returning the placeholders it uses.
+    pub fn placeholders(&self) -> Result<Vec<Placeholder>, String> {
+        Ok(parse(&self.template)?
+            .into_iter()
+            .filter_map(|part| match part {
+                Part::Placeholder(placeholder) => Some(placeholder),
+                Part::Text(_) => None,
+            })
+            .collect())
+    }
+
+    /// The families whose addresses the template embeds.
+    pub fn versions(&self) -> IpVersionFilter {
+        let placeholders = self.placeholders().unwrap_or_default();
+        IpVersionFilter::covering(placeholders.into_iter().filter_map(|p| match p {
+            Placeholder::Ipv4 => Some(IpVersionFilter::Only(IpVersion::V4)),
+            Placeholder::Ipv6 => Some(IpVersionFilter::Only(IpVersion::V6)),
+            Placeholder::Timestamp => None,
+        }))
+    }
+
+    /// The text between the timestamps, rendered with `addresses`, or the
+    /// family whose address is missing.
+    fn segments(&self, addresses: Addresses<'_>) -> Result<Vec<String>, IpVersion> {
+        let mut segments = vec![String::new()];
+        for part in parse(&self.template).unwrap_or_default() {
+            let segment = segments.last_mut().expect("there is always a segment");
+            match part {
+                Part::Text(text) => segment.push_str(&text),
+                Part::Placeholder(Placeholder::Ipv4) => {
+                    segment.push_str(addresses.ipv4.ok_or(IpVersion::V4)?)
+                }
+                Part::Placeholder(Placeholder::Ipv6) => {
+                    segment.push_str(addresses.ipv6.ok_or(IpVersion::V6)?)
+                }
+                Part::Placeholder(Placeholder::Timestamp) => segments.push(String::new()),
+            }
+        }
+        Ok(segments)
+    }
+
+    /// The text of the record written at `now`, or the family whose
+    /// address is missing.
+    pub fn render(
+        &self,
+        addresses: Addresses<'_>,
+        now: OffsetDateTime,
+    ) -> Result<String, IpVersion> {
+        let now = now.replace_nanosecond(0).unwrap_or(now);
+        let timestamp = now.format(&Rfc3339).unwrap_or_default();
+        Ok(self.segments(addresses)?.join(&timestamp))
+    }
+
+    /// Whether `text` is what the template renders with `addresses`,
+    /// whenever it was written.
+    pub fn matches(&self, text: &str, addresses: Addresses<'_>) -> bool {
+        let Ok(segments) = self.segments(addresses) else {
+            return false;
+        };
+        let (first, rest) = segments.split_first().expect("there is always a segment");
+        let Some(mut rest_of_text) = text.strip_prefix(first.as_str()) else {
+            return false;
+        };
+        let Some((last, middle)) = rest.split_last() else {
+            return rest_of_text.is_empty();
+        };
+        for segment in middle {
+            // A timestamp is never empty.
+            match rest_of_text.get(1..).and_then(|t| t.find(segment.as_str())) {
+                Some(index) => rest_of_text = &rest_of_text[index + 1 + segment.len()..],
+                None => return false,
+            }
+        }
+        rest_of_text.len() > last.len() && rest_of_text.ends_with(last.as_str())
+    }
+
+    /// The value of the rrset holding `text`, which is quoted for TXT.
+    pub fn value(&self, text: &str) -> String {
+        match self.dns_type {
+            DnsType::Txt => txt_value(text),
+            _ => text.to_string(),
+        }
+    }
+
+    /// Whether a value of the rrset was rendered from the template, telling
+    /// by the text before the first placeholder. The other values, e.g. a
+    /// domain verification next to an SPF record, are kept.
+    pub fn owns(&self, text: &str) -> bool {
+        match parse(&self.template).unwrap_or_default().first() {
+            Some(Part::Text(prefix)) => text.starts_with(prefix.as_str()),
+            _ => true,
+        }
+    }
+}
+
+/// Checks that `text` is a value of an rrset of `dns_type`, one of
+/// [`EXTENDED_TYPES`], as Gandi would otherwise reject it only once written.
+#[cfg(feature = "extended-records")]
+pub fn check_value(dns_type: DnsType, text: &str) -> Result<(), String> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    let (numbers, hex, format) = match dns_type {
+        DnsType::Srv => (3, false, "priority weight port target"),
+        DnsType::Tlsa => (3, true, "usage selector matching-type data"),
+        DnsType::Sshfp => (2, true, "algorithm type fingerprint"),
+        other => return Err(format!("{} is not rendered from a template", other)),
+    };
+    let valid = fields.len() == numbers + 1
+        && fields[..numbers].iter().all(|field| match dns_type {
+            DnsType::Srv => field.parse::<u16>().is_ok(),
+            _ => field.parse::<u8>().is_ok(),
+        })
+        && (!hex || fields[numbers].chars().all(|c| c.is_ascii_hexdigit()));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' is not a {} value, which is '{}'",
+            text, dns_type, format
+        ))
+    }
+}
+
+/// The value of a TXT rrset holding `text`, split into quoted strings of
+/// at most 255 bytes, which is what the escapes do not count towards.
+pub fn txt_value(text: &str) -> String {
+    let mut chunks = vec![String::new()];
+    for c in text.chars() {
+        let chunk = chunks
+            .last_mut()
+            .filter(|chunk| chunk.len() + c.len_utf8() <= MAX_STRING_LEN);
+        match chunk {
+            Some(chunk) => chunk.push(c),
+            None => chunks.push(c.to_string()),
+        }
+    }
+    chunks
+        .iter()
+        .map(|chunk| format!("\"{}\"", chunk.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The text a TXT value holds, its quoted strings joined and unescaped.
+/// Unquoted values are taken as they are.
+pub fn txt_text(value: &str) -> String {
+    let value = value.trim();
+    if !value.starts_with('"') {
+        return value.to_string();
+    }
+    let mut text = String::new();
+    let mut chars = value.chars();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => text.extend(chars.next()),
+            c if quoted => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
@@ -0,0 +1,501 @@
+//! `NOTIFY.throttle_interval`: at most one notification of the same event
+//! per channel and window, so that a daemon retrying against an API that is
+//! down does not notify every pass. The next one sent counts the ones held
+//! back, and a channel told about a failure is told when it is over. The
+//! windows are kept in the state directory, so a restart does not send the
+//! failure again.
+
+use crate::health::unix_secs;
+use crate::notify::DdnsEvent;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const THROTTLE_FILE: &str = "throttle.json";
+
+/// What makes events the same for a channel.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct NotificationKey {
+    pub channel: String,
+    pub domain: String,
+    pub kind: String,
+    /// The class of a failure, e.g. `network` or `auth`, so that a revoked
+    /// credential is told even during an outage. The drifts and watchdog
+    /// reports are told apart by what they report.
+    pub class: String,
+}
+
+impl NotificationKey {
+    /// The key of `event` sent to `channel`, `None` if it is never
+    /// throttled: the changes each tell something new, and the digests,
+    /// recoveries and tests are sent once anyway.
+    pub fn of(channel: &str, event: &DdnsEvent) -> Option<Self> {
+        let (domain, class) = match event {
+            DdnsEvent::UpdateFailed { domain, class, .. } => (domain, class.to_string()),
+            DdnsEvent::Drift {
+                domain,
+                drifts: subjects,
+            }
+            | DdnsEvent::Watchdog {
+                domain,
+                reports: subjects,
+            } => {
+                let mut subjects = subjects.clone();
+                subjects.sort();
+                (domain, subjects.join("\n"))
+            }
+            DdnsEvent::RecordsChanged { .. }
+            | DdnsEvent::Recovered { .. }
+            | DdnsEvent::Digest { .. }
+            | DdnsEvent::Test { .. } => return None,
+        };
+        Some(NotificationKey {
+            channel: channel.to_string(),
+            domain: domain.clone(),
+            kind: event.kind().to_string(),
+            class,
+        })
+    }
+}
+
+/// The window of a key, in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Window {
+    /// When the first of the events came, i.e. the condition started.
+    pub first: u64,
+    /// When the last one was sent.
+    pub sent: u64,
+    /// Events held back since the last one sent.
+    pub repeats: u32,
+    /// Events held back since the first.
+    pub suppressed: u32,
+}
+
+/// What becomes of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Sent, telling how many of the same events were held back since the
+    /// last one sent.
+    Send {
+        repeats: u32,
+    },
+    Suppress,
+}
+
+/// The decision for an event coming at `now`, given the window of its key,
+/// and the window after it.
+pub fn decide(window: Option<Window>, now: u64, interval: u64) -> (Decision, Window) {
+    match window {
+        Some(window) if now.saturating_sub(window.sent) < interval => (
+            Decision::Suppress,
+            Window {
+                repeats: window.repeats + 1,
+                suppressed: window.suppressed + 1,
+                ..window
+            },
+        ),
+        Some(window) => (
+            Decision::Send {
+                repeats: window.repeats,
+            },
+            Window {
+                sent: now,
+                repeats: 0,
+                ..window
+            },
+        ),
+        None => (
+            Decision::Send { repeats: 0 },
+            Window {
+                first: now,
+                sent: now,
+                repeats: 0,
+                suppressed: 0,
+            },
+        ),
+    }
+}
+
+/// Whether the window no longer matters at `now`. The drifts and watchdog
+/// reports have no end of their own, so their windows go once they are
+/// over with nothing held back. The failures last until resolved.
+pub fn is_stale(key: &NotificationKey, window: &Window, now: u64, interval: u64) -> bool {
+    key.kind != "failure" && window.repeats == 0 && now.saturating_sub(window.sent) >= interval
+}
+
+/// A failure that is over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recovery {
+    /// Since the first failure.
+    pub lasted: Duration,
+    /// Failures held back in all.
+    pub suppressed: u32,
+}
+
+impl Recovery {
+    pub fn of(window: &Window, now: u64) -> Self {
+        Recovery {
+            lasted: Duration::from_secs(now.saturating_sub(window.first)),
+            suppressed: window.suppressed,
+        }
+    }
+}
+
+/// `event` telling that it was held back `repeats` times.
+pub fn with_repeats(event: &DdnsEvent, repeats: u32) -> DdnsEvent {
+    let mut event = event.clone();
+    let summary = format!("Repeated {} times since the last notification.", repeats);
+    match &mut event {
+        DdnsEvent::UpdateFailed { reason, .. } => {
+            *reason = format!("{}. {}", reason, summary);
+        }
+        DdnsEvent::Drift { drifts: lines, .. } | DdnsEvent::Watchdog { reports: lines, .. } => {
+            lines.push(summary);
+        }
+        DdnsEvent::RecordsChanged { .. }
+        | DdnsEvent::Recovered { .. }
+        | DdnsEvent::Digest { .. }
+        | DdnsEvent::Test { .. } => {}
+    }
+    event
+}
+
+/// A window as written to the state file.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    key: NotificationKey,
+    #[serde(flatten)]
+    window: Window,
+}
+
+/// The windows of the notifications, kept in the state directory.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    path: PathBuf,
+    interval: Duration,
+}
+
+impl Throttle {
+    pub fn new(state_dir: &Path, interval: Duration) -> Self {
+        Throttle {
+            path: state_dir.join(THROTTLE_FILE),
+            interval,
+        }
+    }
+
+    /// Decides whether `event` is sent to each of `channels`, counting it
+    /// towards the windows of those it is held back from.
+    pub fn admit(&self, channels: &[String], event: &DdnsEvent) -> Vec<Decision> {
+        let now = unix_secs(SystemTime::now());
+        let interval = self.interval.as_secs();
+        let mut windows = self.load();
+        windows.retain(|key, window| !is_stale(key, window, now, interval));
+        let decisions = channels
+            .iter()
+            .map(|channel| {
+                let Some(key) = NotificationKey::of(channel, event) else {
+                    return Decision::Send { repeats: 0 };
+                };
+                let (decision, window) = decide(windows.get(&key).copied(), now, interval);
+                windows.insert(key, window);
+                decision
+            })
+            .collect();
+        self.save_or_warn(&windows);
+        decisions
+    }
+
+    /// Forgets the windows of the `kind` events of `domain` once what they
+    /// were about is over, e.g. a pass succeeds after failures, returning
+    /// the channels that were told about them.
+    pub fn resolve(&self, domain: &str, kind: &str) -> Vec<(String, Recovery)> {
+        let now = unix_secs(SystemTime::now());
+        let mut windows = self.load();
+        let mut recoveries = Vec::new();
+        windows.retain(|key, window| {
+            let resolved = key.domain == domain && key.kind == kind;
+            if resolved {
+                recoveries.push((key.channel.clone(), Recovery::of(window, now)));
+            }
+            !resolved
+        });
+        if !recoveries.is_empty() {
+            self.save_or_warn(&windows);
+        }
+        recoveries
+    }
+
+    /// The windows in the state file. A missing or unreadable file starts
+    /// them all over.
+    fn load(&self) -> BTreeMap<NotificationKey, Window> {
+        let entries: Vec<Entry> = match fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "Discarding corrupt notification throttle {}: {}",
+                    self.path.display(),
+                    e
+                );
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!(
+                    "Unable to read the notification throttle {}: {}",
+                    self.path.display(),
+                    e
+                );
+                Vec::new()
+            }
+        };
+        entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.window))
+            .collect()
+    }
+
+    fn save_or_warn(&self, windows: &BTreeMap<NotificationKey, Window>) {
+        if let Err(e) = self.save(windows) {
+            warn!("Unable to save the notification throttle: {}", e);
+        }
+    }
+
+    fn save(&self, windows: &BTreeMap<NotificationKey, Window>) -> io::Result<()> {
+        if windows.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let entries: Vec<Entry> = windows
+            .iter()
+            .map(|(key, window)| Entry {
+                key: key.clone(),
+                window: *window,
+            })
+            .collect();
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&entries)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: u64 = 3600;
+
+    fn failure(domain: &str, class: &'static str) -> DdnsEvent {
+        DdnsEvent::UpdateFailed {
+            domain: domain.to_string(),
+            reason: "connection refused".to_string(),
+            class,
+            failures: Vec::new(),
+        }
+    }
+
+    fn drift(drifts: &[&str]) -> DdnsEvent {
+        DdnsEvent::Drift {
+            domain: "example.com".to_string(),
+            drifts: drifts.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn keys_failures_by_channel_domain_and_class() {
+        let key = NotificationKey::of("ntfy", &failure("example.com", "network")).unwrap();
+        assert_eq!(
+            key,
+            NotificationKey {
+                channel: "ntfy".to_string(),
+                domain: "example.com".to_string(),
+                kind: "failure".to_string(),
+                class: "network".to_string(),
+            }
+        );
+        let other =
+            |channel, domain, class| NotificationKey::of(channel, &failure(domain, class)).unwrap();
+        assert_ne!(key, other("webhook", "example.com", "network"));
+        assert_ne!(key, other("ntfy", "example.org", "network"));
+        assert_ne!(key, other("ntfy", "example.com", "auth"));
+    }
+
+    #[test]
+    fn keys_drifts_by_what_they_report_in_any_order() {
+        let key = NotificationKey::of("ntfy", &drift(&["www A", "@ A"])).unwrap();
+        assert_eq!(key.kind, "drift");
+        assert_eq!(
+            Some(key.clone()),
+            NotificationKey::of("ntfy", &drift(&["@ A", "www A"]))
+        );
+        assert_ne!(Some(key), NotificationKey::of("ntfy", &drift(&["@ A"])));
+    }
+
+    #[test]
+    fn never_throttles_changes_recoveries_digests_and_tests() {
+        let events = [
+            DdnsEvent::RecordsChanged {
+                domain: "example.com".to_string(),
+                changes: Vec::new(),
+            },
+            DdnsEvent::Recovered {
+                domain: "example.com".to_string(),
+                lasted: Duration::from_secs(60),
+                suppressed: 3,
+            },
+            DdnsEvent::Digest {
+                title: "Daily digest".to_string(),
+                text: String::new(),
+            },
+            DdnsEvent::Test {
+                domain: "example.com".to_string(),
+            },
+        ];
+        for event in &events {
+            assert_eq!(NotificationKey::of("ntfy", event), None, "{}", event.kind());
+        }
+    }
+
+    #[test]
+    fn sends_the_first_event_and_holds_back_repeats_within_the_window() {
+        let (decision, window) = decide(None, 1000, INTERVAL);
+        assert_eq!(decision, Decision::Send { repeats: 0 });
+        let expected = Window {
+            first: 1000,
+            sent: 1000,
+            repeats: 0,
+            suppressed: 0,
+        };
+        assert_eq!(window, expected);
+
+        let (decision, window) = decide(Some(window), 1000 + INTERVAL - 1, INTERVAL);
+        assert_eq!(decision, Decision::Suppress);
+        let (decision, window) = decide(Some(window), 1000 + INTERVAL - 1, INTERVAL);
+        assert_eq!(decision, Decision::Suppress);
+        assert_eq!(
+            window,
+            Window {
+                repeats: 2,
+                suppressed: 2,
+                ..expected
+            }
+        );
+
+        // Once the window is over, telling how many were held back.
+        let (decision, window) = decide(Some(window), 1000 + INTERVAL, INTERVAL);
+        assert_eq!(decision, Decision::Send { repeats: 2 });
+        assert_eq!(
+            window,
+            Window {
+                first: 1000,
+                sent: 1000 + INTERVAL,
+                repeats: 0,
+                suppressed: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn sends_every_event_without_an_interval() {
+        let (_, window) = decide(None, 1000, 0);
+        let (decision, _) = decide(Some(window), 1000, 0);
+        assert_eq!(decision, Decision::Send { repeats: 0 });
+    }
+
+    #[test]
+    fn withstands_a_clock_going_back() {
+        let (_, window) = decide(None, 1000, INTERVAL);
+        let (decision, _) = decide(Some(window), 10, INTERVAL);
+        assert_eq!(decision, Decision::Suppress);
+        assert_eq!(Recovery::of(&window, 10).lasted, Duration::ZERO);
+    }
+
+    #[test]
+    fn forgets_windows_once_over_with_nothing_held_back() {
+        let drift = NotificationKey::of("ntfy", &drift(&["@ A"])).unwrap();
+        let failure = NotificationKey::of("ntfy", &failure("example.com", "network")).unwrap();
+        let window = Window {
+            first: 1000,
+            sent: 1000,
+            repeats: 0,
+            suppressed: 0,
+        };
+        assert!(!is_stale(&drift, &window, 1000 + INTERVAL - 1, INTERVAL));
+        assert!(is_stale(&drift, &window, 1000 + INTERVAL, INTERVAL));
+        let held_back = Window {
+            repeats: 1,
+            ..window
+        };
+        assert!(!is_stale(&drift, &held_back, 1000 + INTERVAL, INTERVAL));
+        // Failures last until resolved.
+        assert!(!is_stale(&failure, &window, 1000 + INTERVAL * 10, INTERVAL));
+    }
+
+    #[test]
+    fn tells_how_long_a_failure_lasted() {
+        let window = Window {
+            first: 1000,
+            sent: 2000,
+            repeats: 1,
+            suppressed: 4,
+        };
+        assert_eq!(
+            Recovery::of(&window, 4600),
+            Recovery {
+                lasted: Duration::from_secs(3600),
+                suppressed: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_the_windows_in_the_state_directory() {
+        let dir = std::env::temp_dir().join(format!("ddns-gandi-throttle-{}", std::process::id()));
+        let throttle = Throttle::new(&dir, Duration::from_secs(INTERVAL));
+        let channels = ["ntfy".to_string(), "webhook".to_string()];
+        let event = failure("example.com", "network");
+        assert_eq!(
+            throttle.admit(&channels, &event),
+            [Decision::Send { repeats: 0 }, Decision::Send { repeats: 0 }]
+        );
+        // Read back from the file, as after a restart.
+        let throttle = Throttle::new(&dir, Duration::from_secs(INTERVAL));
+        assert_eq!(throttle.admit(&channels[..1], &event), [Decision::Suppress]);
+
+        let recoveries = throttle.resolve("example.com", "failure");
+        let channels: Vec<&str> = recoveries.iter().map(|(c, _)| c.as_str()).collect();
+        assert_eq!(channels, ["ntfy", "webhook"]);
+        assert_eq!(recoveries[0].1.suppressed, 1);
+        assert!(!dir.join(THROTTLE_FILE).exists());
+        assert!(throttle.resolve("example.com", "failure").is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tells_the_repeats_in_the_event() {
+        let DdnsEvent::UpdateFailed { reason, .. } =
+            with_repeats(&failure("example.com", "network"), 3)
+        else {
+            unreachable!()
+        };
+        assert_eq!(
+            reason,
+            "connection refused. Repeated 3 times since the last notification."
+        );
+        let DdnsEvent::Drift { drifts, .. } = with_repeats(&drift(&["@ A"]), 2) else {
+            unreachable!()
+        };
+        assert_eq!(
+            drifts,
+            ["@ A", "Repeated 2 times since the last notification."]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Timeouts of the HTTP requests, set in the `HTTP` section or all at once
+//! with `--timeout`.
+
+use crate::config::parse_duration;
+use crate::tls;
+use std::time::Duration;
+
+/// Bound of the requests that had a fixed one before it was configurable,
+/// e.g. the notifications.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeouts of the HTTP requests, `None` leaving them to the HTTP client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Establishing a connection, for every request.
+    pub connect: Option<Duration>,
+    /// Whole requests other than those below: the notifications and
+    /// fetching the configuration from a URL.
+    pub request: Duration,
+    /// Whole requests detecting the public addresses.
+    pub ip_detection: Option<Duration>,
+    /// Whole requests to the Gandi API.
+    pub api_call: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: None,
+            request: DEFAULT_REQUEST_TIMEOUT,
+            ip_detection: None,
+            api_call: None,
+        }
+    }
+}
+
+impl Timeouts {
+    /// Every timeout set to `timeout`.
+    pub fn all(timeout: Duration) -> Self {
+        Timeouts {
+            connect: Some(timeout),
+            request: timeout,
+            ip_detection: Some(timeout),
+            api_call: Some(timeout),
+        }
+    }
+
+    /// A client builder with the connect timeout and the TLS settings,
+    /// bounding whole requests by `timeout`.
+    pub fn client(self, timeout: Option<Duration>) -> reqwest::ClientBuilder {
+        let mut builder = tls::configure(reqwest::Client::builder());
+        if let Some(connect) = self.connect {
+            builder = builder.connect_timeout(connect);
+        }
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder
+    }
+}
+
+/// Parses the value of `--timeout`: milliseconds, or a duration such as
+/// `5s` or `500ms`.
+pub fn parse_timeout(value: &str) -> Result<Duration, String> {
+    let timeout = match value.trim().parse::<u64>() {
+        Ok(millis) => Duration::from_millis(millis),
+        Err(_) => parse_duration(value)?,
+    };
+    if timeout.is_zero() {
+        return Err("the timeout must not be zero".to_string());
+    }
+    Ok(timeout)
+}
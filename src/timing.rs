@@ -0,0 +1,170 @@
+//! How long the detection attempts and the Gandi API calls of a pass take,
+//! logged one by one at debug level and summed up once the pass is over.
+//! Each of them is also a span of the trace of the pass.
+
+use crate::telemetry;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The classes of timed operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    /// Asking a web service, the FRITZ!Box or the gateway for an address.
+    Detection,
+    /// A GET request to the Gandi API.
+    Get,
+    /// A PUT request to the Gandi API.
+    Put,
+    /// A DELETE request to the Gandi API.
+    Delete,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Detection => "detection",
+            Operation::Get => "get",
+            Operation::Put => "put",
+            Operation::Delete => "delete",
+        }
+    }
+
+    fn span_name(self) -> &'static str {
+        match self {
+            Operation::Detection => "ddns.detection",
+            Operation::Get => "ddns.get",
+            Operation::Put => "ddns.put",
+            Operation::Delete => "ddns.delete",
+        }
+    }
+}
+
+/// Durations of the operations of one class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    pub count: u32,
+    pub min_ms: u64,
+    pub avg_ms: u64,
+    pub max_ms: u64,
+    #[serde(skip)]
+    total: Duration,
+}
+
+impl Stats {
+    fn add(&mut self, elapsed: Duration) {
+        let ms = millis(elapsed);
+        self.min_ms = if self.count == 0 {
+            ms
+        } else {
+            self.min_ms.min(ms)
+        };
+        self.max_ms = self.max_ms.max(ms);
+        self.count += 1;
+        self.total += elapsed;
+        self.avg_ms = millis(self.total / self.count);
+    }
+}
+
+/// The durations of the operations of one pass and of the pass itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timings {
+    pub total_ms: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub operations: BTreeMap<String, Stats>,
+    /// The lowest quota of API requests the API reported during the pass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_quota_remaining: Option<u64>,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms in total", self.total_ms)?;
+        for (operation, stats) in &self.operations {
+            write!(
+                f,
+                ", {} {}x min/avg/max {}/{}/{}ms",
+                operation, stats.count, stats.min_ms, stats.avg_ms, stats.max_ms
+            )?;
+        }
+        if let Some(remaining) = self.api_quota_remaining {
+            write!(f, ", API quota {} remaining", remaining)?;
+        }
+        Ok(())
+    }
+}
+
+/// The operations since the last call to [`start`].
+static RECORDED: Mutex<BTreeMap<Operation, Stats>> = Mutex::new(BTreeMap::new());
+/// The lowest quota reported since the last call to [`start`].
+static QUOTA: Mutex<Option<u64>> = Mutex::new(None);
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Starts timing a pass, forgetting the operations made before it.
+pub fn start() -> Instant {
+    if let Ok(mut recorded) = RECORDED.lock() {
+        recorded.clear();
+    }
+    if let Ok(mut quota) = QUOTA.lock() {
+        *quota = None;
+    }
+    Instant::now()
+}
+
+/// The durations recorded since `started`, the return value of [`start`].
+pub fn finish(started: Instant) -> Timings {
+    let operations = RECORDED
+        .lock()
+        .map(|mut recorded| std::mem::take(&mut *recorded))
+        .unwrap_or_default();
+    Timings {
+        total_ms: millis(started.elapsed()),
+        api_quota_remaining: QUOTA.lock().ok().and_then(|mut quota| quota.take()),
+        operations: operations
+            .into_iter()
+            .map(|(operation, stats)| (operation.as_str().to_string(), stats))
+            .collect(),
+    }
+}
+
+/// Counts the quota of API requests `remaining` to the pass.
+pub fn record_quota(remaining: u64) {
+    if let Ok(mut quota) = QUOTA.lock() {
+        *quota = Some(quota.map_or(remaining, |lowest| lowest.min(remaining)));
+    }
+}
+
+/// Runs `future` in a span, logging how long it took as `label` and
+/// counting it to the operations of its class.
+pub async fn timed<T, E, F>(operation: Operation, label: &str, future: F) -> Result<T, E>
+where
+    E: fmt::Display,
+    F: Future<Output = Result<T, E>>,
+{
+    let attributes = vec![("ddns.operation", label.into())];
+    telemetry::in_span(operation.span_name(), attributes, async {
+        let started = Instant::now();
+        let output = future.await;
+        let elapsed = started.elapsed();
+        debug!("{} took {}ms", label, millis(elapsed));
+        if let Ok(mut recorded) = RECORDED.lock() {
+            recorded.entry(operation).or_default().add(elapsed);
+        }
+        match &output {
+            Ok(_) => telemetry::record("ddns.outcome", "ok"),
+            Err(e) => {
+                telemetry::record("ddns.outcome", "error");
+                telemetry::fail(e);
+            }
+        }
+        output
+    })
+    .await
+}
@@ -0,0 +1,124 @@
+//! TLS settings of every HTTP client, from the `HTTP` section: further root
+//! certificates, e.g. of a corporate proxy intercepting TLS, and turning
+//! the verification of certificates off in lab setups.
+
+use crate::pinning::{self, SpkiPin};
+use log::warn;
+use reqwest::{Certificate, ClientBuilder};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM files of further root certificates.
+    pub ca_certs: Vec<PathBuf>,
+    roots: Vec<Certificate>,
+    /// The same certificates, for the client of the Gandi API when its
+    /// keys are pinned.
+    root_ders: Vec<CertificateDer<'static>>,
+    /// Whether any certificate is accepted, expired, self-signed or for
+    /// another host.
+    pub accept_invalid_certs: bool,
+    /// Keys one of which the chain of the Gandi API must hold, not checked
+    /// for the other clients.
+    pub gandi_pins: Vec<SpkiPin>,
+    gandi_tls: Option<rustls::ClientConfig>,
+}
+
+impl TlsConfig {
+    /// Reads the certificates of the files `ca_certs`, each of which may
+    /// hold several.
+    pub fn load(ca_certs: Vec<PathBuf>, accept_invalid_certs: bool) -> Result<Self, String> {
+        let mut roots = Vec::new();
+        let mut root_ders = Vec::new();
+        for path in &ca_certs {
+            let pem = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if certs.is_empty() {
+                return Err(format!("{}: no certificate", path.display()));
+            }
+            roots.extend(certs);
+            for der in CertificateDer::pem_slice_iter(&pem) {
+                root_ders.push(der.map_err(|e| format!("{}: {}", path.display(), e))?);
+            }
+        }
+        Ok(TlsConfig {
+            ca_certs,
+            roots,
+            root_ders,
+            accept_invalid_certs,
+            gandi_pins: Vec::new(),
+            gandi_tls: None,
+        })
+    }
+
+    /// Checks the chain of the Gandi API against `pins` from now on.
+    pub fn pin_gandi(&mut self, pins: Vec<SpkiPin>) -> Result<(), String> {
+        if pins.is_empty() {
+            return Ok(());
+        }
+        if self.accept_invalid_certs {
+            return Err(
+                "pinning the keys makes no sense along with danger_accept_invalid_certs"
+                    .to_string(),
+            );
+        }
+        self.gandi_tls = Some(pinning::client_config(self, pins.clone())?);
+        self.gandi_pins = pins;
+        Ok(())
+    }
+
+    pub fn root_ders(&self) -> &[CertificateDer<'static>] {
+        &self.root_ders
+    }
+}
+
+/// The settings the clients are built with, those of the configuration
+/// last loaded.
+static INSTALLED: RwLock<Option<TlsConfig>> = RwLock::new(None);
+
+/// Builds the clients with `config` from now on, warning if it turns the
+/// verification of certificates off.
+pub fn install(config: &TlsConfig) {
+    if config.accept_invalid_certs {
+        warn!("!!! HTTP.danger_accept_invalid_certs is set: TLS certificates are NOT verified !!!");
+        warn!("!!! Anyone on the network path can read and change the requests, including the API key !!!");
+    }
+    if let Ok(mut installed) = INSTALLED.write() {
+        *installed = Some(config.clone());
+    }
+}
+
+/// `builder` with the installed settings.
+pub fn configure(mut builder: ClientBuilder) -> ClientBuilder {
+    let Ok(installed) = INSTALLED.read() else {
+        return builder;
+    };
+    if let Some(config) = installed.as_ref() {
+        for root in &config.roots {
+            builder = builder.add_root_certificate(root.clone());
+        }
+        builder = builder.danger_accept_invalid_certs(config.accept_invalid_certs);
+    }
+    builder
+}
+
+/// `builder` with the installed settings and the pins of the Gandi API,
+/// for its client only.
+pub fn configure_gandi(builder: ClientBuilder) -> ClientBuilder {
+    let builder = configure(builder);
+    let Ok(installed) = INSTALLED.read() else {
+        return builder;
+    };
+    match installed
+        .as_ref()
+        .and_then(|config| config.gandi_tls.clone())
+    {
+        Some(tls) => builder.use_preconfigured_tls(tls),
+        None => builder,
+    }
+}
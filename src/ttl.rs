@@ -0,0 +1,246 @@
+use crate::gandi::RRSET_TTL;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HISTORY_FILE: &str = "ttl-history.json";
+
+/// Lowest and highest TTL accepted by LiveDNS, in seconds.
+pub const GANDI_MIN_TTL: u32 = 300;
+pub const GANDI_MAX_TTL: u32 = 2_592_000;
+
+/// Shortens the TTL of records whose address changes frequently and
+/// lengthens it again while the address stays the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptiveTtl {
+    pub min: u32,
+    pub max: u32,
+    /// How long a value has to stay the same before the TTL is raised.
+    pub window: Duration,
+}
+
+impl AdaptiveTtl {
+    /// TTL of an rrset whose value has not changed for `stable_for`. It
+    /// starts at `min` and doubles for every full window, up to `max`.
+    pub fn ttl_for(&self, stable_for: Duration) -> u32 {
+        let windows = stable_for
+            .as_secs()
+            .checked_div(self.window.as_secs())
+            .unwrap_or(0);
+        if windows >= u64::from(u32::BITS) {
+            return self.max;
+        }
+        self.min.saturating_mul(1 << windows).min(self.max)
+    }
+}
+
+/// When the value of an rrset last changed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RrsetHistory {
+    domain: String,
+    record: String,
+    dns_type: String,
+    /// Seconds since the Unix epoch.
+    last_change: u64,
+}
+
+/// The change history adaptive TTLs are derived from, persisted as JSON in
+/// the state directory. Without an adaptive policy every record simply
+/// gets the default TTL and nothing is stored.
+#[derive(Debug)]
+pub struct TtlHistory {
+    path: PathBuf,
+    policy: Option<AdaptiveTtl>,
+    entries: Vec<RrsetHistory>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl TtlHistory {
+    /// Loads the history from `state_dir`. A missing file yields an empty
+    /// history, an unreadable one is discarded with a warning.
+    pub fn load(state_dir: &Path, policy: Option<AdaptiveTtl>) -> Self {
+        let path = state_dir.join(HISTORY_FILE);
+        let entries = match policy {
+            None => Vec::new(),
+            Some(_) => match fs::read_to_string(&path) {
+                Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                    warn!("Discarding corrupt TTL history {}: {}", path.display(), e);
+                    Vec::new()
+                }),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => {
+                    warn!("Unable to read TTL history {}: {}", path.display(), e);
+                    Vec::new()
+                }
+            },
+        };
+
+        TtlHistory {
+            path,
+            policy,
+            entries,
+        }
+    }
+
+    /// TTL written along with a new value.
+    pub fn change_ttl(&self) -> u32 {
+        self.policy.map_or(RRSET_TTL, |policy| policy.min)
+    }
+
+    /// TTL an rrset whose value stays the same should have, or `None` when
+    /// TTLs are not adaptive. An rrset seen for the first time counts as
+    /// having just changed.
+    pub fn steady_ttl(
+        &mut self,
+        domain: &str,
+        record: &str,
+        dns_type: &str,
+        now: SystemTime,
+    ) -> Option<u32> {
+        let policy = self.policy?;
+        let now = unix_secs(now);
+        let last_change = match self.find(domain, record, dns_type) {
+            Some(entry) => entry.last_change,
+            None => {
+                self.set_last_change(domain, record, dns_type, now);
+                now
+            }
+        };
+        Some(policy.ttl_for(Duration::from_secs(now.saturating_sub(last_change))))
+    }
+
+    /// Remembers that the value of an rrset changed at `now`.
+    pub fn record_change(&mut self, domain: &str, record: &str, dns_type: &str, now: SystemTime) {
+        if self.policy.is_some() {
+            self.set_last_change(domain, record, dns_type, unix_secs(now));
+        }
+    }
+
+    fn set_last_change(&mut self, domain: &str, record: &str, dns_type: &str, now: u64) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.domain == domain && e.record == record && e.dns_type == dns_type)
+        {
+            Some(entry) => entry.last_change = now,
+            None => self.entries.push(RrsetHistory {
+                domain: domain.to_string(),
+                record: record.to_string(),
+                dns_type: dns_type.to_string(),
+                last_change: now,
+            }),
+        }
+    }
+
+    fn find(&self, domain: &str, record: &str, dns_type: &str) -> Option<&RrsetHistory> {
+        self.entries
+            .iter()
+            .find(|e| e.domain == domain && e.record == record && e.dns_type == dns_type)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if self.policy.is_none() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.entries)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn policy() -> AdaptiveTtl {
+        AdaptiveTtl {
+            min: 300,
+            max: 86400,
+            window: HOUR,
+        }
+    }
+
+    #[test]
+    fn doubles_the_ttl_for_every_full_window() {
+        let policy = policy();
+        assert_eq!(policy.ttl_for(Duration::ZERO), 300);
+        assert_eq!(policy.ttl_for(HOUR - Duration::from_secs(1)), 300);
+        assert_eq!(policy.ttl_for(HOUR), 600);
+        assert_eq!(policy.ttl_for(HOUR * 2), 1200);
+        assert_eq!(policy.ttl_for(HOUR * 3 + HOUR / 2), 2400);
+    }
+
+    #[test]
+    fn caps_the_ttl_at_the_maximum() {
+        let policy = policy();
+        assert_eq!(policy.ttl_for(HOUR * 8), 76800);
+        assert_eq!(policy.ttl_for(HOUR * 9), 86400);
+        assert_eq!(policy.ttl_for(HOUR * 31), 86400);
+        assert_eq!(policy.ttl_for(HOUR * 32), 86400);
+        assert_eq!(policy.ttl_for(Duration::from_secs(u64::MAX)), 86400);
+    }
+
+    #[test]
+    fn keeps_the_minimum_without_a_window() {
+        let policy = AdaptiveTtl {
+            window: Duration::ZERO,
+            ..policy()
+        };
+        assert_eq!(policy.ttl_for(HOUR * 1000), 300);
+    }
+
+    #[test]
+    fn derives_the_ttl_from_the_last_change() {
+        let dir = Path::new("/nonexistent/ddns-gandi");
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut history = TtlHistory::load(dir, Some(policy()));
+        assert_eq!(history.change_ttl(), 300);
+        // Seen for the first time, as if it had just changed.
+        assert_eq!(
+            history.steady_ttl("example.com", "@", "A", start),
+            Some(300)
+        );
+        assert_eq!(
+            history.steady_ttl("example.com", "@", "A", start + HOUR * 2),
+            Some(1200)
+        );
+        assert_eq!(
+            history.steady_ttl("example.com", "@", "AAAA", start + HOUR * 2),
+            Some(300)
+        );
+
+        history.record_change("example.com", "@", "A", start + HOUR * 3);
+        assert_eq!(
+            history.steady_ttl("example.com", "@", "A", start + HOUR * 3),
+            Some(300)
+        );
+        assert_eq!(
+            history.steady_ttl("example.com", "@", "A", start + HOUR * 4),
+            Some(600)
+        );
+    }
+
+    #[test]
+    fn uses_the_default_ttl_without_a_policy() {
+        let mut history = TtlHistory::load(Path::new("/nonexistent/ddns-gandi"), None);
+        assert_eq!(history.change_ttl(), RRSET_TTL);
+        assert_eq!(
+            history.steady_ttl("example.com", "@", "A", SystemTime::now()),
+            None
+        );
+        assert!(history.save().is_ok());
+    }
+}
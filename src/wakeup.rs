@@ -0,0 +1,154 @@
+//! Waiting for the next pass of the daemon.
+
+#[cfg(unix)]
+use log::warn;
+use std::fmt;
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::Notify;
+
+/// Stops asked for other than by a signal, e.g. by the Windows Service
+/// Control Manager. One asked for during a pass ends the next wait.
+static STOP: Notify = Notify::const_new();
+
+/// Stops the daemon like SIGTERM, once the current pass is over.
+#[cfg(windows)]
+pub fn request_stop() {
+    STOP.notify_one();
+}
+
+/// Why the daemon starts a pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Interval,
+    /// SIGUSR1 was received.
+    Signal,
+    /// `update-now` was sent to the control socket.
+    Request,
+    /// The watchdog found a record answered wrongly.
+    Watchdog,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Interval => write!(f, "scheduled"),
+            Trigger::Signal => write!(f, "signal-triggered"),
+            Trigger::Request => write!(f, "requested"),
+            Trigger::Watchdog => write!(f, "watchdog-triggered"),
+        }
+    }
+}
+
+/// What ended the wait for the next pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Woken {
+    Pass(Trigger),
+    /// SIGHUP was received.
+    Reload,
+    Stop,
+}
+
+/// Sleeps between the passes of the daemon, cut short by SIGUSR1.
+///
+/// SIGHUP asks for the configuration to be read again.
+///
+/// SIGTERM and SIGINT stop the daemon once the current pass is over, so
+/// that it can remove its control socket. As PID 1, e.g. in a container,
+/// SIGTERM would otherwise even be ignored.
+///
+/// Elsewhere Ctrl+C and `request_stop` stop the daemon the same way.
+#[derive(Debug)]
+pub struct Wakeup {
+    #[cfg(unix)]
+    usr1: Option<Signal>,
+    #[cfg(unix)]
+    term: Option<Signal>,
+    #[cfg(unix)]
+    int: Option<Signal>,
+    #[cfg(unix)]
+    hup: Option<Signal>,
+}
+
+impl Wakeup {
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        let usr1 = signal(SignalKind::user_defined1())
+            .map_err(|e| warn!("Unable to listen for SIGUSR1: {}", e))
+            .ok();
+        #[cfg(unix)]
+        let term = signal(SignalKind::terminate())
+            .map_err(|e| warn!("Unable to listen for SIGTERM: {}", e))
+            .ok();
+        #[cfg(unix)]
+        let int = signal(SignalKind::interrupt())
+            .map_err(|e| warn!("Unable to listen for SIGINT: {}", e))
+            .ok();
+        #[cfg(unix)]
+        let hup = signal(SignalKind::hangup())
+            .map_err(|e| warn!("Unable to listen for SIGHUP: {}", e))
+            .ok();
+
+        Wakeup {
+            #[cfg(unix)]
+            usr1,
+            #[cfg(unix)]
+            term,
+            #[cfg(unix)]
+            int,
+            #[cfg(unix)]
+            hup,
+        }
+    }
+
+    /// Waits for `interval` unless SIGUSR1 arrives first. Signals received
+    /// while a pass was running end the wait immediately, and any number of
+    /// them only trigger a single pass.
+    pub async fn sleep(&mut self, interval: Duration) -> Woken {
+        #[cfg(unix)]
+        {
+            let usr1 = async {
+                match &mut self.usr1 {
+                    Some(usr1) => usr1.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let term = async {
+                match &mut self.term {
+                    Some(term) => term.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let int = async {
+                match &mut self.int {
+                    Some(int) => int.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let hup = async {
+                match &mut self.hup {
+                    Some(hup) => hup.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => Woken::Pass(Trigger::Interval),
+                _ = usr1 => Woken::Pass(Trigger::Signal),
+                _ = hup => Woken::Reload,
+                _ = term => Woken::Stop,
+                _ = int => Woken::Stop,
+                _ = STOP.notified() => Woken::Stop,
+            }
+        }
+
+        #[cfg(not(unix))]
+        {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => Woken::Pass(Trigger::Interval),
+                _ = tokio::signal::ctrl_c() => Woken::Stop,
+                _ = STOP.notified() => Woken::Stop,
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Checking between the passes of the daemon that the public DNS still
+//! answers with the addresses last pushed, e.g. to notice another tool
+//! overwriting a record. It only sends DNS queries, no API calls.
+
+use crate::dns_type::DnsType;
+#[cfg(feature = "daemon-mode")]
+use crate::health::unix_secs;
+#[cfg(feature = "daemon-mode")]
+use crate::notify::{DdnsEvent, Notifications};
+#[cfg(feature = "daemon-mode")]
+use crate::precheck::DnsPrecheck;
+use log::warn;
+#[cfg(feature = "daemon-mode")]
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+#[cfg(feature = "daemon-mode")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(feature = "daemon-mode")]
+use std::time::{Instant, SystemTime};
+
+#[cfg(feature = "daemon-mode")]
+const WATCHDOG_FILE: &str = "watchdog.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    pub interval: Duration,
+    /// How long a wrong answer may persist, e.g. while caches expire,
+    /// before it is reported.
+    pub grace: Duration,
+    /// Resolver asked, without caching on our side.
+    pub resolver: IpAddr,
+    /// Whether a reported mismatch also starts a pass writing the records
+    /// again.
+    pub reassert: bool,
+}
+
+/// A record that has been answered wrongly since `since`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Mismatch {
+    /// Seconds since the Unix epoch.
+    since: u64,
+    reported: bool,
+}
+
+/// The addresses last pushed and the ongoing mismatches, by `record/TYPE`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct WatchdogState {
+    #[serde(default)]
+    expected: BTreeMap<String, String>,
+    #[serde(default)]
+    mismatches: BTreeMap<String, Mismatch>,
+}
+
+/// What the resolver answered for a record.
+#[cfg(feature = "daemon-mode")]
+enum Observation {
+    Matches,
+    Differs(Vec<IpAddr>),
+    /// NXDOMAIN or no record of the type, e.g. after it was deleted.
+    Missing,
+}
+
+#[derive(Debug)]
+pub struct Watchdog {
+    #[cfg(feature = "daemon-mode")]
+    config: WatchdogConfig,
+    path: PathBuf,
+    state: WatchdogState,
+    #[cfg(feature = "daemon-mode")]
+    next: Instant,
+}
+
+/// Whether `values` are the expected addresses, several of them separated
+/// by commas for the records following several sources.
+#[cfg(feature = "daemon-mode")]
+fn answers(values: &[IpAddr], expected: &str) -> bool {
+    let expected: Vec<IpAddr> = expected
+        .split(',')
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    !values.is_empty()
+        && values.iter().all(|value| expected.contains(value))
+        && expected.iter().all(|value| values.contains(value))
+}
+
+fn key(record: &str, dns_type: &str) -> String {
+    format!("{}/{}", record, dns_type)
+}
+
+impl Watchdog {
+    /// Loads the addresses last pushed from `state_dir`. A missing or
+    /// corrupt file starts without any.
+    #[cfg(feature = "daemon-mode")]
+    pub fn load(state_dir: &Path, config: WatchdogConfig) -> Self {
+        let path = state_dir.join(WATCHDOG_FILE);
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "Discarding corrupt watchdog state {}: {}",
+                    path.display(),
+                    e
+                );
+                WatchdogState::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => WatchdogState::default(),
+            Err(e) => {
+                warn!("Unable to read watchdog state {}: {}", path.display(), e);
+                WatchdogState::default()
+            }
+        };
+        Watchdog {
+            config,
+            path,
+            state,
+            next: Instant::now() + config.interval,
+        }
+    }
+
+    /// When the next check is due.
+    #[cfg(feature = "daemon-mode")]
+    pub fn next_check(&self) -> Instant {
+        self.next
+    }
+
+    /// Remembers the addresses the records hold after a pass, clearing
+    /// their mismatches.
+    pub fn expect(&mut self, published: &[(String, DnsType, String)]) {
+        if published.is_empty() {
+            return;
+        }
+        for (record, dns_type, ip) in published {
+            let key = key(record, dns_type.as_str());
+            self.state.mismatches.remove(&key);
+            self.state.expected.insert(key, ip.clone());
+        }
+        self.save_or_warn();
+    }
+
+    /// Resolves every record last pushed and reports the mismatches that
+    /// outlasted the grace period. Returns whether to write the records
+    /// again.
+    #[cfg(feature = "daemon-mode")]
+    pub async fn check(&mut self, domain: &str, notifications: Option<&Notifications>) -> bool {
+        self.next = Instant::now() + self.config.interval;
+        let resolver = match DnsPrecheck::new(domain, Some(self.config.resolver)).await {
+            Ok(resolver) => resolver,
+            Err(e) => {
+                warn!("Unable to set up the watchdog resolver: {}", e);
+                return false;
+            }
+        };
+
+        let now = unix_secs(SystemTime::now());
+        let mut reports = Vec::new();
+        for (key, expected) in &self.state.expected {
+            let Some((record, dns_type)) = key
+                .rsplit_once('/')
+                .and_then(|(record, dns_type)| Some((record, dns_type.parse::<DnsType>().ok()?)))
+            else {
+                continue;
+            };
+            let observation = match resolver.lookup(domain, record, dns_type).await {
+                Ok(Some(answer)) if answers(&answer.values, expected) => Observation::Matches,
+                Ok(Some(answer)) => Observation::Differs(answer.values),
+                Ok(None) => Observation::Missing,
+                Err(e) => {
+                    warn!("The watchdog is unable to resolve {}: {}", key, e);
+                    continue;
+                }
+            };
+
+            if let Observation::Matches = observation {
+                if self
+                    .state
+                    .mismatches
+                    .remove(key)
+                    .is_some_and(|m| m.reported)
+                {
+                    info!("{} is answered with {} again", key, expected);
+                }
+                continue;
+            }
+            let mismatch = self
+                .state
+                .mismatches
+                .entry(key.clone())
+                .or_insert(Mismatch {
+                    since: now,
+                    reported: false,
+                });
+            if mismatch.reported || now.saturating_sub(mismatch.since) < self.config.grace.as_secs()
+            {
+                continue;
+            }
+            mismatch.reported = true;
+            let minutes = now.saturating_sub(mismatch.since) / 60;
+            reports.push(match observation {
+                Observation::Differs(values) => {
+                    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                    format!(
+                        "{} is answered with {} instead of {} for {} minutes",
+                        key,
+                        values.join(","),
+                        expected,
+                        minutes
+                    )
+                }
+                _ => format!(
+                    "{} does not resolve any more (deleted?) for {} minutes, expected {}",
+                    key, minutes, expected
+                ),
+            });
+        }
+        self.save_or_warn();
+
+        if reports.is_empty() {
+            return false;
+        }
+        for report in &reports {
+            error!("Watchdog: {}", report);
+        }
+        if let Some(notifications) = notifications {
+            let event = DdnsEvent::Watchdog {
+                domain: domain.to_string(),
+                reports,
+            };
+            notifications.send(&event).await;
+        }
+        self.config.reassert
+    }
+
+    fn save_or_warn(&self) {
+        if let Err(e) = self.save() {
+            warn!("Unable to save the watchdog state: {}", e);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.state)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
@@ -0,0 +1,122 @@
+//! The `axfr` subcommand, writing the zone as an RFC 1035 zone file, e.g.
+//! for backups kept in git.
+
+use crate::dns_type::DnsType;
+use crate::gandi::{get_zone_records, GandiApi, GandiRrset};
+use crate::health::unix_secs;
+use crate::precheck::DnsPrecheck;
+use clap::Args;
+use log::{info, warn};
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Default TTL of Gandi's SOA records, used when the SOA cannot be
+/// resolved.
+const SOA_TTL: u32 = 10800;
+
+#[derive(Debug, Args)]
+pub struct AxfrArgs {
+    /// Write the zone to this file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+/// The owner name of a record relative to the zone, as an absolute name.
+fn owner(domain: &str, name: &str) -> String {
+    match name {
+        "@" => format!("{}.", domain),
+        name => format!("{}.{}.", name, domain),
+    }
+}
+
+/// The SOA record of the zone. Gandi does not expose it in the API, so it
+/// is resolved from the authoritative name servers, or made up from
+/// Gandi's defaults with the current time as the serial if they cannot be
+/// reached.
+async fn soa(domain: &str) -> (String, u32) {
+    let resolved = match DnsPrecheck::new(domain, None).await {
+        Ok(resolver) => resolver.soa(domain).await,
+        Err(e) => Err(e),
+    };
+    match resolved {
+        Ok(Some(soa)) => soa,
+        Ok(None) => {
+            warn!("{} has no SOA record, writing Gandi's default one", domain);
+            default_soa()
+        }
+        Err(e) => {
+            warn!(
+                "Unable to resolve the SOA record of {}, writing Gandi's default one: {}",
+                domain, e
+            );
+            default_soa()
+        }
+    }
+}
+
+fn default_soa() -> (String, u32) {
+    let serial = unix_secs(SystemTime::now()) as u32;
+    (
+        format!(
+            "ns1.gandi.net. hostmaster.gandi.net. {} 10800 3600 604800 10800",
+            serial
+        ),
+        SOA_TTL,
+    )
+}
+
+/// Renders the zone with the SOA record first, then the rrsets sorted by
+/// name and type so that snapshots diff cleanly.
+fn render(domain: &str, soa: &(String, u32), mut rrsets: Vec<GandiRrset>) -> String {
+    rrsets.retain(|rrset| rrset.record_type != DnsType::Soa);
+    rrsets.sort_by(|a, b| {
+        (a.name != "@", &a.name, a.record_type.as_str()).cmp(&(
+            b.name != "@",
+            &b.name,
+            b.record_type.as_str(),
+        ))
+    });
+
+    let mut zone = String::new();
+    let _ = writeln!(zone, "$ORIGIN {}.", domain);
+    let _ = writeln!(
+        zone,
+        "{}\t{}\tIN\tSOA\t{}",
+        owner(domain, "@"),
+        soa.1,
+        soa.0
+    );
+    for rrset in &rrsets {
+        let mut values = rrset.values.clone();
+        values.sort();
+        for value in values {
+            let _ = writeln!(
+                zone,
+                "{}\t{}\tIN\t{}\t{}",
+                owner(domain, &rrset.name),
+                rrset.ttl,
+                rrset.record_type,
+                value
+            );
+        }
+    }
+    zone
+}
+
+pub async fn run(args: &AxfrArgs, domain: &str, api: &GandiApi) -> Result<(), Box<dyn Error>> {
+    let rrsets = get_zone_records(domain, api).await?;
+    let zone = render(domain, &soa(domain).await, rrsets);
+    match &args.output {
+        Some(path) => {
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, &zone)?;
+            fs::rename(&tmp, path)?;
+            info!("Wrote the zone of {} to {}", domain, path.display());
+        }
+        None => print!("{}", zone),
+    }
+    Ok(())
+}